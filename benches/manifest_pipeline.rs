@@ -129,6 +129,7 @@ fn generate_ad_segments(duration: f32, segment_duration: f32) -> Vec<AdSegment>
         .map(|i| AdSegment {
             uri: format!("ad-segment-{}.ts", i),
             duration: segment_duration,
+            init: None,
             tracking: None,
         })
         .collect()
@@ -196,6 +197,7 @@ fn bench_interleave_ads(c: &mut Criterion) {
             .iter()
             .map(|ab| generate_ad_segments(ab.duration, 6.0))
             .collect();
+        let break_ids: Vec<String> = (0..breaks.len()).map(|i| i.to_string()).collect();
 
         group.bench_with_input(
             BenchmarkId::new("ad_breaks", label),
@@ -206,6 +208,7 @@ fn bench_interleave_ads(c: &mut Criterion) {
                         black_box(media.clone()),
                         black_box(breaks),
                         black_box(ads),
+                        &break_ids,
                         "bench-session",
                         "http://stitcher.example.com",
                     );
@@ -371,11 +374,13 @@ fn full_pipeline(playlist_str: &str) -> String {
             .iter()
             .map(|ab| generate_ad_segments(ab.duration, 6.0))
             .collect();
+        let break_ids: Vec<String> = (0..ad_breaks.len()).map(|i| i.to_string()).collect();
 
         media = interleaver::interleave_ads(
             media,
             &ad_breaks,
             &ad_segments,
+            &break_ids,
             "bench-session",
             "http://stitcher.example.com",
         );
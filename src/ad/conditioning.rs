@@ -1,4 +1,7 @@
-use crate::ad::vast::MediaFile;
+use crate::ad::vast::{self, MediaFile};
+use crate::metrics;
+use crate::session::ContentProfile;
+use crate::session::content::codec_family;
 use tracing::warn;
 
 /// Known HLS-compatible MIME types for ad creatives
@@ -7,15 +10,169 @@ const HLS_MIME_TYPES: &[&str] = &["application/x-mpegURL", "application/vnd.appl
 /// Known progressive video MIME types
 const PROGRESSIVE_MIME_TYPES: &[&str] = &["video/mp4", "video/webm", "video/3gpp"];
 
+/// What happens to a creative that does not conform to the content
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ConditioningAction {
+    /// Log a warning and stitch it anyway
+    #[default]
+    Warn,
+    /// Drop it; other ads or slate fill its time
+    Reject,
+    /// Use another of its media files that conforms, else drop it
+    Substitute,
+    /// Keep its place in the pod but play slate instead
+    Slate,
+}
+
+/// Why a media file cannot be stitched into the content
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Nonconformance {
+    /// Interactive unit (VPAID), which SSAI cannot execute
+    Vpaid,
+    /// Neither HLS nor progressive video
+    Mime,
+    /// Video codec the content's renditions do not use
+    Codec,
+    /// Resolution outside the content's ladder
+    Resolution,
+}
+
+impl Nonconformance {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Nonconformance::Vpaid => "vpaid",
+            Nonconformance::Mime => "mime",
+            Nonconformance::Codec => "codec",
+            Nonconformance::Resolution => "resolution",
+        }
+    }
+}
+
+/// What to do with one linear creative
+#[derive(Debug, Clone, Copy)]
+pub enum Verdict<'a> {
+    /// Stitch this media file
+    Play(&'a MediaFile),
+    /// Hold the creative's place in the pod, but play slate
+    Slate(&'a MediaFile),
+    /// Leave the creative out
+    Reject(Nonconformance),
+}
+
+/// Check a media file against the content it would be stitched into
+///
+/// Codec and resolution are only compared when the content profile knows
+/// them (and the media file declares them).
+pub fn check_media_file(
+    media_file: &MediaFile,
+    content: &ContentProfile,
+) -> Option<Nonconformance> {
+    let vpaid = media_file
+        .api_framework
+        .as_deref()
+        .is_some_and(|f| f.eq_ignore_ascii_case("vpaid"))
+        || media_file
+            .codec
+            .as_deref()
+            .is_some_and(|c| c.to_lowercase().contains("vpaid"));
+    if vpaid {
+        return Some(Nonconformance::Vpaid);
+    }
+    if !is_hls_mime(&media_file.mime_type) && !is_progressive_mime(&media_file.mime_type) {
+        return Some(Nonconformance::Mime);
+    }
+    if !content.video_codecs.is_empty()
+        && let Some(family) = media_file.codec.as_deref().and_then(codec_family)
+        && !content.video_codecs.iter().any(|c| c == family)
+    {
+        return Some(Nonconformance::Codec);
+    }
+    if !content.resolutions.is_empty()
+        && media_file.width > 0
+        && media_file.height > 0
+        && !content
+            .resolutions
+            .contains(&(media_file.width, media_file.height))
+    {
+        return Some(Nonconformance::Resolution);
+    }
+    None
+}
+
+/// Pick the media file to stitch for one linear creative
+///
+/// Starts from [`vast::select_best_media_file`] and applies `action` if it
+/// does not conform to `content`. Returns `None` when the creative has no
+/// usable media file at all.
+pub fn condition<'a>(
+    media_files: &'a [MediaFile],
+    action: ConditioningAction,
+    content: &ContentProfile,
+    session_id: &str,
+) -> Option<Verdict<'a>> {
+    let best = vast::select_best_media_file(media_files)?;
+    let Some(issue) = check_media_file(best, content) else {
+        check_creative(best, session_id);
+        return Some(Verdict::Play(best));
+    };
+
+    let verdict = match action {
+        ConditioningAction::Warn => {
+            check_creative(best, session_id);
+            Verdict::Play(best)
+        }
+        ConditioningAction::Reject => Verdict::Reject(issue),
+        ConditioningAction::Substitute => {
+            // Same preference as select_best_media_file, among conforming files
+            let mut conforming: Vec<&MediaFile> = media_files
+                .iter()
+                .filter(|f| {
+                    is_hls_mime(&f.mime_type)
+                        || (f.delivery == "progressive" && f.mime_type == "video/mp4")
+                })
+                .filter(|f| check_media_file(f, content).is_none())
+                .collect();
+            conforming.sort_by(|a, b| {
+                is_hls_mime(&b.mime_type)
+                    .cmp(&is_hls_mime(&a.mime_type))
+                    .then(b.bitrate.cmp(&a.bitrate))
+            });
+            match conforming.first() {
+                Some(substitute) => Verdict::Play(substitute),
+                None => Verdict::Reject(issue),
+            }
+        }
+        ConditioningAction::Slate => Verdict::Slate(best),
+    };
+
+    let outcome = match verdict {
+        Verdict::Play(file) if std::ptr::eq(file, best) => "warn",
+        Verdict::Play(_) => "substitute",
+        Verdict::Slate(_) => "slate",
+        Verdict::Reject(_) => "reject",
+    };
+    warn!(
+        session_id = session_id,
+        url = best.url,
+        "Ad conditioning: creative does not conform ({}), outcome: {}",
+        issue.as_str(),
+        outcome
+    );
+    metrics::record_creative_conditioning(issue.as_str(), outcome);
+    Some(verdict)
+}
+
 /// Validate ad creative compatibility and log warnings
 ///
-/// Phase 1: warning-only. Does not block ad insertion.
-/// Checks for common issues that may cause playback problems:
+/// Does not block ad insertion; enforcement against the content's
+/// renditions is done by [`condition`]. Checks for common issues that may
+/// cause playback problems:
 /// - Non-HLS ad creative in HLS stream (codec mismatch)
 /// - Resolution mismatches (if detectable)
 /// - Missing or unknown MIME types
 ///
-/// Future: integrate with Eyevinn Ad Normalizer for transcoding
+/// Progressive creatives are packaged for stitching when an ad normalizer
+/// is configured (see [`crate::ad::normalizer`]).
 pub fn check_creative(media_file: &MediaFile, session_id: &str) {
     let mime = &media_file.mime_type;
 
@@ -28,7 +185,7 @@ pub fn check_creative(media_file: &MediaFile, session_id: &str) {
                 url = media_file.url,
                 "Ad conditioning: Progressive MP4 creative detected — \
                  may cause playback issues in HLS stream. \
-                 Consider using Eyevinn Ad Normalizer for transcoding."
+                 Configure AD_NORMALIZER_URL to stitch a packaged rendition."
             );
         } else {
             warn!(
@@ -121,9 +278,71 @@ mod tests {
             height,
             bitrate: Some(2000),
             codec: None,
+            api_framework: None,
+        }
+    }
+
+    fn ladder() -> ContentProfile {
+        ContentProfile {
+            video_codecs: vec!["avc".to_string()],
+            resolutions: vec![(1920, 1080), (1280, 720)],
         }
     }
 
+    #[test]
+    fn test_check_media_file_against_content() {
+        let mut file = create_media_file("video/mp4", 1280, 720);
+        file.codec = Some("H.264".to_string());
+        assert_eq!(check_media_file(&file, &ladder()), None);
+
+        file.width = 640;
+        file.height = 360;
+        assert_eq!(
+            check_media_file(&file, &ladder()),
+            Some(Nonconformance::Resolution)
+        );
+        // Without a known ladder only the creative itself is checked
+        assert_eq!(check_media_file(&file, &ContentProfile::default()), None);
+
+        file.codec = Some("hvc1.1.6.L93.B0".to_string());
+        assert_eq!(
+            check_media_file(&file, &ladder()),
+            Some(Nonconformance::Codec)
+        );
+
+        file.api_framework = Some("VPAID".to_string());
+        assert_eq!(
+            check_media_file(&file, &ContentProfile::default()),
+            Some(Nonconformance::Vpaid)
+        );
+    }
+
+    #[test]
+    fn test_condition_actions() {
+        let mut small = create_media_file("video/mp4", 640, 360);
+        small.bitrate = Some(4000);
+        let fitting = create_media_file("video/mp4", 1280, 720);
+        let files = vec![small, fitting];
+        let content = ladder();
+
+        // The highest bitrate file is out of the ladder
+        let verdict = condition(&files, ConditioningAction::Warn, &content, "s");
+        assert!(matches!(verdict, Some(Verdict::Play(f)) if f.width == 640));
+        let verdict = condition(&files, ConditioningAction::Substitute, &content, "s");
+        assert!(matches!(verdict, Some(Verdict::Play(f)) if f.width == 1280));
+        let verdict = condition(&files, ConditioningAction::Slate, &content, "s");
+        assert!(matches!(verdict, Some(Verdict::Slate(f)) if f.width == 640));
+        let verdict = condition(&files, ConditioningAction::Reject, &content, "s");
+        assert!(matches!(
+            verdict,
+            Some(Verdict::Reject(Nonconformance::Resolution))
+        ));
+
+        // Nothing conforms: substitution falls back to rejection
+        let verdict = condition(&files[..1], ConditioningAction::Substitute, &content, "s");
+        assert!(matches!(verdict, Some(Verdict::Reject(_))));
+    }
+
     #[test]
     fn test_hls_mime_detection() {
         assert!(is_hls_mime("application/x-mpegURL"));
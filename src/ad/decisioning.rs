@@ -0,0 +1,785 @@
+use crate::ad::provider::{self, AdCreative, AdProvider, AdSegment};
+use crate::metrics;
+use crate::session::{SessionManager, ViewerContext};
+use dashmap::DashMap;
+use futures::future::join_all;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tokio::time::{Instant, timeout_at};
+use tracing::{debug, info, warn};
+
+/// One ad opportunity to decide: a rendition-independent key and its length
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakRequest {
+    /// Identifies the break across renditions and manifest refreshes
+    pub key: String,
+    /// Break duration in seconds
+    pub duration: f32,
+}
+
+impl BreakRequest {
+    /// URL-safe id the break's ad segments are served under
+    pub fn id(&self) -> String {
+        provider::break_id(&self.key)
+    }
+}
+
+/// A decision shared by every request for the same (session, break)
+struct SharedDecision<T> {
+    cell: Arc<OnceCell<T>>,
+    last_used: std::time::Instant,
+    /// Leading segments of the break published before the decision arrived
+    late_segments: usize,
+}
+
+/// Shared decisions keyed by (session id, break key)
+type DecisionMap<T> = DashMap<(String, String), SharedDecision<T>>;
+
+/// Ad decisions made once per (session, break) and shared by all renditions
+///
+/// The video variant and each audio rendition of a session request their
+/// playlists independently. The first request for a break asks the ad
+/// provider; concurrent requests for the same break wait on that decision
+/// and later ones reuse it, so every rendition plays the same ads in the
+/// same order and the ad server sees a single request per break.
+///
+/// Decisions run in the background: one that misses a request's deadline
+/// keeps going and is stored when it completes, so later requests for the
+/// break get its ads (see [`decide_breaks`]). Decisions can also be started
+/// ahead of a break (see [`DecisionCache::prefetch_segments`]).
+///
+/// With a session store, SSAI breaks already decided for the session — by
+/// any instance — are published as stored rather than decided again.
+#[derive(Clone)]
+pub struct DecisionCache {
+    segments: Arc<DecisionMap<Vec<AdSegment>>>,
+    creatives: Arc<DecisionMap<Vec<AdCreative>>>,
+    ttl: Duration,
+    sessions: Option<SessionManager>,
+}
+
+impl DecisionCache {
+    /// Create a cache that forgets decisions unused for `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            segments: Arc::new(DashMap::new()),
+            creatives: Arc::new(DashMap::new()),
+            ttl,
+            sessions: None,
+        }
+    }
+
+    /// Publish breaks stored in the session instead of deciding them again
+    pub fn with_sessions(mut self, sessions: SessionManager) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    /// SSAI segments for a break, calling `decide` only if no decision exists
+    ///
+    /// A decision stored in the session counts: its published segments are
+    /// used as they are.
+    pub async fn segments<F, Fut>(&self, session_id: &str, key: &str, decide: F) -> Vec<AdSegment>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Vec<AdSegment>>,
+    {
+        single_flight(&self.segments, session_id, key, || async {
+            match self.stored_segments(session_id, key).await {
+                Some(published) => (published, "stored"),
+                None => (decide().await, "decided"),
+            }
+        })
+        .await
+    }
+
+    /// SGAI creatives for a break, calling `decide` only if no decision exists
+    pub async fn creatives<F, Fut>(&self, session_id: &str, key: &str, decide: F) -> Vec<AdCreative>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Vec<AdCreative>>,
+    {
+        single_flight(&self.creatives, session_id, key, || async {
+            (decide().await, "decided")
+        })
+        .await
+    }
+
+    /// Segments published for a break the session has a decision for
+    async fn stored_segments(&self, session_id: &str, key: &str) -> Option<Vec<AdSegment>> {
+        let decision = self
+            .sessions
+            .as_ref()?
+            .break_decision(session_id, &provider::break_id(key))
+            .await?;
+        Some(decision)
+            .filter(|decision| !decision.is_expired(self.ttl))
+            .map(|decision| decision.published)
+            .filter(|published| !published.is_empty())
+    }
+
+    /// Start deciding an upcoming break's SSAI segments in the background
+    ///
+    /// Does nothing if the break already has a decision, finished or not.
+    /// The decision runs to completion independently of any request
+    /// deadline, and requests for the break once it is published share it.
+    pub fn prefetch_segments(
+        &self,
+        provider: Arc<dyn AdProvider>,
+        session_id: &str,
+        request: BreakRequest,
+        viewer: ViewerContext,
+    ) {
+        if !claim(&self.segments, session_id, &request.key) {
+            return;
+        }
+        let decisions = self.clone();
+        let session_id = session_id.to_string();
+        tokio::spawn(async move {
+            metrics::record_decision("ahead");
+            let break_id = request.id();
+            decisions
+                .segments(&session_id, &request.key, || {
+                    provider.get_ad_segments(request.duration, &break_id, &session_id, &viewer)
+                })
+                .await;
+        });
+    }
+
+    /// Start deciding an upcoming break's SGAI creatives in the background
+    ///
+    /// Same as [`DecisionCache::prefetch_segments`], for interstitials.
+    pub fn prefetch_creatives(
+        &self,
+        provider: Arc<dyn AdProvider>,
+        session_id: &str,
+        request: BreakRequest,
+        viewer: ViewerContext,
+    ) {
+        if !claim(&self.creatives, session_id, &request.key) {
+            return;
+        }
+        let decisions = self.clone();
+        let session_id = session_id.to_string();
+        tokio::spawn(async move {
+            metrics::record_decision("ahead");
+            let break_id = request.id();
+            decisions
+                .creatives(&session_id, &request.key, || {
+                    provider.get_ad_creatives(request.duration, &break_id, &session_id, &viewer)
+                })
+                .await;
+        });
+    }
+
+    /// Record that the first `due` segments of a break were published
+    /// before its decision arrived
+    ///
+    /// Returns how many leading segments are late: every refresh that misses
+    /// the decision adds the segments that became due since.
+    pub fn publish_late(&self, session_id: &str, key: &str, due: usize) -> usize {
+        let mut entry = self
+            .segments
+            .entry((session_id.to_string(), key.to_string()))
+            .or_insert_with(new_decision);
+        entry.late_segments = entry.late_segments.max(due);
+        entry.late_segments
+    }
+
+    /// Record that a break was published without its ads where it cannot
+    /// be backfilled, so it stays without ads for the session
+    ///
+    /// Every segment of the break then counts as late.
+    pub fn publish_without_ads(&self, session_id: &str, key: &str) {
+        self.publish_late(session_id, key, usize::MAX);
+    }
+
+    /// Leading segments of a break published before its decision arrived
+    pub fn late_segments(&self, session_id: &str, key: &str) -> usize {
+        self.segments
+            .get(&(session_id.to_string(), key.to_string()))
+            .map_or(0, |entry| entry.late_segments)
+    }
+
+    /// Evict decisions not used within the TTL
+    pub fn cleanup(&self) {
+        let ttl = self.ttl;
+        let before = self.segments.len() + self.creatives.len();
+        self.segments.retain(|_, d| d.last_used.elapsed() < ttl);
+        self.creatives.retain(|_, d| d.last_used.elapsed() < ttl);
+        let evicted = before - (self.segments.len() + self.creatives.len());
+        if evicted > 0 {
+            info!("Evicted {} expired ad decision(s)", evicted);
+        }
+    }
+}
+
+fn new_decision<T>() -> SharedDecision<T> {
+    SharedDecision {
+        cell: Arc::new(OnceCell::new()),
+        last_used: std::time::Instant::now(),
+        late_segments: 0,
+    }
+}
+
+/// Create the entry for a key; `false` if it already exists
+fn claim<T>(map: &DecisionMap<T>, session_id: &str, key: &str) -> bool {
+    match map.entry((session_id.to_string(), key.to_string())) {
+        dashmap::Entry::Occupied(_) => false,
+        dashmap::Entry::Vacant(entry) => {
+            entry.insert(new_decision());
+            true
+        }
+    }
+}
+
+/// Run `decide` at most once per key; every caller gets its result
+///
+/// `decide` also names where its result came from, for metrics.
+async fn single_flight<T, F, Fut>(map: &DecisionMap<T>, session_id: &str, key: &str, decide: F) -> T
+where
+    T: Clone,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = (T, &'static str)>,
+{
+    // Clone the cell out so no map shard lock is held across the await
+    let cell = {
+        let mut entry = map
+            .entry((session_id.to_string(), key.to_string()))
+            .or_insert_with(new_decision);
+        entry.last_used = std::time::Instant::now();
+        entry.cell.clone()
+    };
+
+    let mut source = None;
+    let value = cell
+        .get_or_init(|| async {
+            let (value, label) = decide().await;
+            source = Some(label);
+            value
+        })
+        .await
+        .clone();
+
+    if let Some(label) = source {
+        metrics::record_decision(label);
+    } else {
+        debug!(
+            "Reusing ad decision for break {} of session {}",
+            key, session_id
+        );
+        metrics::record_decision("shared");
+    }
+    value
+}
+
+/// Decide every ad break of one manifest concurrently, within a deadline
+///
+/// All breaks share a single decision budget measured from the call, so a
+/// slow ad server cannot hold the manifest response longer than `timeout`.
+/// A break whose decision misses the deadline gets `None`: its decision
+/// keeps running in the background and is stored when it completes, so a
+/// later refresh can fill the rest of the break with its ads (see
+/// [`interleaver::backfill_break`](crate::ad::interleaver::backfill_break)).
+///
+/// Decisions go through `decisions`, so a break already decided for this
+/// session — by another rendition or an earlier refresh — is reused.
+///
+/// Returns one decision per entry in `breaks`, in the same order.
+pub async fn decide_breaks(
+    provider: &Arc<dyn AdProvider>,
+    decisions: &DecisionCache,
+    breaks: &[BreakRequest],
+    session_id: &str,
+    viewer: &ViewerContext,
+    timeout: Duration,
+) -> Vec<Option<Vec<AdSegment>>> {
+    let deadline = Instant::now() + timeout;
+    let pending = breaks.iter().map(|request| {
+        // Spawned so that missing the deadline does not cancel the decision
+        let decision = {
+            let provider = provider.clone();
+            let decisions = decisions.clone();
+            let request = request.clone();
+            let session_id = session_id.to_string();
+            let viewer = viewer.clone();
+            tokio::spawn(async move {
+                let break_id = request.id();
+                decisions
+                    .segments(&session_id, &request.key, || {
+                        provider.get_ad_segments(request.duration, &break_id, &session_id, &viewer)
+                    })
+                    .await
+            })
+        };
+        async move {
+            match timeout_at(deadline, decision).await {
+                Ok(Ok(segments)) => Some(segments),
+                Ok(Err(e)) => {
+                    warn!(
+                        "Ad decision for break {} of session {} failed: {}",
+                        request.key, session_id, e
+                    );
+                    Some(Vec::new())
+                }
+                Err(_) => {
+                    warn!(
+                        "Ad decision for break {} ({}s) missed the {}ms deadline for session {}, \
+                         continuing in the background",
+                        request.key,
+                        request.duration,
+                        timeout.as_millis(),
+                        session_id
+                    );
+                    metrics::record_decision_timeout();
+                    None
+                }
+            }
+        }
+    });
+
+    let results = join_all(pending).await;
+    info!(
+        "Decided {} ad break(s) for session {}",
+        results.len(),
+        session_id
+    );
+    results
+}
+
+/// Decide ad breaks that are published whole, within a deadline
+///
+/// Like [`decide_breaks`], for DASH: an ad Period replaces its whole break,
+/// so it cannot be inserted once the break went out as content without
+/// rewriting the timeline players already have. A break whose decision
+/// misses the deadline is therefore published without ads for the session
+/// from then on, even after its decision arrives. Returns an empty list
+/// for such breaks.
+pub async fn decide_whole_breaks(
+    provider: &Arc<dyn AdProvider>,
+    decisions: &DecisionCache,
+    breaks: &[BreakRequest],
+    session_id: &str,
+    viewer: &ViewerContext,
+    timeout: Duration,
+) -> Vec<Vec<AdSegment>> {
+    decide_breaks(provider, decisions, breaks, session_id, viewer, timeout)
+        .await
+        .into_iter()
+        .zip(breaks)
+        .map(|(decided, request)| match decided {
+            Some(segments) if decisions.late_segments(session_id, &request.key) == 0 => segments,
+            Some(_) => Vec::new(),
+            None => {
+                decisions.publish_without_ads(session_id, &request.key);
+                Vec::new()
+            }
+        })
+        .collect()
+}
+
+/// Decide SGAI creatives for one break within a deadline
+///
+/// An empty list on timeout lets the player skip the interstitial.
+pub async fn decide_creatives(
+    provider: &dyn AdProvider,
+    decisions: &DecisionCache,
+    request: &BreakRequest,
+    session_id: &str,
+    viewer: &ViewerContext,
+    timeout: Duration,
+) -> Vec<AdCreative> {
+    let break_id = request.id();
+    let decision = decisions.creatives(session_id, &request.key, || {
+        provider.get_ad_creatives(request.duration, &break_id, session_id, viewer)
+    });
+    match tokio::time::timeout(timeout, decision).await {
+        Ok(creatives) => creatives,
+        Err(_) => {
+            warn!(
+                "Ad creative decision for break {} ({}s) missed the {}ms deadline for session {}",
+                request.key,
+                request.duration,
+                timeout.as_millis(),
+                session_id
+            );
+            metrics::record_decision_timeout();
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ad::StaticAdProvider;
+    use crate::session::BreakDecision;
+    use futures::future::BoxFuture;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Provider whose decisions take `delay_per_sec` per second of break
+    #[derive(Default)]
+    struct SlowProvider {
+        delay_per_sec: Duration,
+        calls: AtomicUsize,
+    }
+
+    impl AdProvider for SlowProvider {
+        fn get_ad_segments<'a>(
+            &'a self,
+            duration: f32,
+            _break_id: &'a str,
+            _session_id: &'a str,
+            _viewer: &'a ViewerContext,
+        ) -> BoxFuture<'a, Vec<AdSegment>> {
+            Box::pin(async move {
+                let call = self.calls.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(self.delay_per_sec.mul_f32(duration)).await;
+                vec![AdSegment {
+                    uri: format!("ad-{}-call-{}.ts", duration, call),
+                    duration,
+                    init: None,
+                    tracking: None,
+                }]
+            })
+        }
+
+        fn resolve_segment_url(&self, _ad_name: &str) -> Option<String> {
+            None
+        }
+    }
+
+    fn slow(delay_ms: u64) -> Arc<SlowProvider> {
+        Arc::new(SlowProvider {
+            delay_per_sec: Duration::from_millis(delay_ms),
+            ..Default::default()
+        })
+    }
+
+    fn shared(provider: &Arc<SlowProvider>) -> Arc<dyn AdProvider> {
+        provider.clone()
+    }
+
+    fn static_provider() -> Arc<dyn AdProvider> {
+        Arc::new(StaticAdProvider::new(
+            "https://ads.example.com".to_string(),
+            10.0,
+        ))
+    }
+
+    fn requests(breaks: &[(&str, f32)]) -> Vec<BreakRequest> {
+        breaks
+            .iter()
+            .map(|(key, duration)| BreakRequest {
+                key: key.to_string(),
+                duration: *duration,
+            })
+            .collect()
+    }
+
+    fn viewer() -> ViewerContext {
+        ViewerContext::default()
+    }
+
+    fn cache() -> DecisionCache {
+        DecisionCache::new(Duration::from_secs(300))
+    }
+
+    #[tokio::test]
+    async fn test_decide_breaks_preserves_order() {
+        let provider = static_provider();
+        let breaks = requests(&[("a", 10.0), ("b", 30.0)]);
+        let results = decide_breaks(
+            &provider,
+            &cache(),
+            &breaks,
+            "s",
+            &viewer(),
+            Duration::from_secs(1),
+        )
+        .await;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap().len(), 1);
+        assert_eq!(results[1].as_ref().unwrap().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_decide_breaks_concurrently_within_deadline() {
+        let provider = shared(&slow(10));
+        let start = Instant::now();
+
+        // 30s + 60s breaks take 300ms + 600ms; concurrently that fits in 700ms
+        let breaks = requests(&[("a", 30.0), ("b", 60.0)]);
+        let results = decide_breaks(
+            &provider,
+            &cache(),
+            &breaks,
+            "s",
+            &viewer(),
+            Duration::from_millis(700),
+        )
+        .await;
+        assert_eq!(results[0].as_ref().unwrap().len(), 1);
+        assert_eq!(results[1].as_ref().unwrap().len(), 1);
+        assert!(start.elapsed() < Duration::from_millis(700));
+
+        // The 90s break misses a 700ms deadline; the 30s break is kept
+        let breaks = requests(&[("a", 30.0), ("b", 90.0)]);
+        let results = decide_breaks(
+            &provider,
+            &cache(),
+            &breaks,
+            "s",
+            &viewer(),
+            Duration::from_millis(700),
+        )
+        .await;
+        assert_eq!(results[0].as_ref().unwrap().len(), 1);
+        assert!(results[1].is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_renditions_share_one_decision() {
+        let slow = slow(10);
+        let provider = shared(&slow);
+        let decisions = cache();
+        let timeout = Duration::from_secs(1);
+        let breaks = requests(&[("103", 30.0)]);
+
+        // Video and audio playlists request the same break at the same time
+        let viewer = viewer();
+        let (video, audio) = tokio::join!(
+            decide_breaks(&provider, &decisions, &breaks, "s", &viewer, timeout),
+            decide_breaks(&provider, &decisions, &breaks, "s", &viewer, timeout),
+        );
+        assert_eq!(slow.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(video, audio);
+
+        // A later refresh reuses it; another session decides on its own
+        let refresh = decide_breaks(&provider, &decisions, &breaks, "s", &viewer, timeout).await;
+        assert_eq!(refresh, video);
+        decide_breaks(&provider, &decisions, &breaks, "other", &viewer, timeout).await;
+        assert_eq!(slow.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timed_out_decision_keeps_running() {
+        let slow = slow(100);
+        let provider = shared(&slow);
+        let decisions = cache();
+        let breaks = requests(&[("1", 30.0)]);
+
+        let missed = decide_breaks(
+            &provider,
+            &decisions,
+            &breaks,
+            "s",
+            &viewer(),
+            Duration::from_secs(1),
+        )
+        .await;
+        assert!(missed[0].is_none());
+
+        // The 3s decision completes in the background and is reused
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let ready = decide_breaks(
+            &provider,
+            &decisions,
+            &breaks,
+            "s",
+            &viewer(),
+            Duration::from_millis(1),
+        )
+        .await;
+        assert_eq!(ready[0].as_ref().unwrap().len(), 1);
+        assert_eq!(slow.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_late_whole_break_stays_without_ads() {
+        let provider = shared(&slow(100));
+        let decisions = cache();
+        let breaks = requests(&[("early", 5.0), ("late", 30.0)]);
+
+        let first = decide_whole_breaks(
+            &provider,
+            &decisions,
+            &breaks,
+            "s",
+            &viewer(),
+            Duration::from_secs(1),
+        )
+        .await;
+        assert_eq!(first[0].len(), 1);
+        assert!(first[1].is_empty());
+
+        // The late decision completes, but the break already went out as content
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        let refresh = decide_whole_breaks(
+            &provider,
+            &decisions,
+            &breaks,
+            "s",
+            &viewer(),
+            Duration::from_secs(1),
+        )
+        .await;
+        assert_eq!(refresh, first);
+        assert!(
+            decide_breaks(
+                &provider,
+                &decisions,
+                &breaks[1..],
+                "s",
+                &viewer(),
+                Duration::from_secs(1)
+            )
+            .await[0]
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_late_segments_grow_until_decided() {
+        let decisions = cache();
+        assert_eq!(decisions.late_segments("s", "1"), 0);
+        assert_eq!(decisions.publish_late("s", "1", 1), 1);
+        assert_eq!(decisions.publish_late("s", "1", 2), 2);
+        // A rendition behind the others does not shrink it
+        assert_eq!(decisions.publish_late("s", "1", 1), 2);
+        assert_eq!(decisions.late_segments("s", "1"), 2);
+        assert_eq!(decisions.late_segments("other", "1"), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_prefetched_decision_is_shared() {
+        let slow = slow(100);
+        let provider = shared(&slow);
+        let decisions = cache();
+        let breaks = requests(&[("1", 30.0)]);
+
+        decisions.prefetch_segments(provider.clone(), "s", breaks[0].clone(), viewer());
+        // A second prefetch on the next refresh is a no-op
+        decisions.prefetch_segments(provider.clone(), "s", breaks[0].clone(), viewer());
+
+        // The break is published a moment later, before the 3s decision is ready
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let missed = decide_breaks(
+            &provider,
+            &decisions,
+            &breaks,
+            "s",
+            &viewer(),
+            Duration::from_secs(1),
+        )
+        .await;
+        assert!(missed[0].is_none());
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let ready = decide_breaks(
+            &provider,
+            &decisions,
+            &breaks,
+            "s",
+            &viewer(),
+            Duration::from_millis(1),
+        )
+        .await;
+        assert_eq!(ready[0].as_ref().unwrap().len(), 1);
+        assert_eq!(slow.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_decide_creatives_timeout() {
+        let provider = slow(100);
+        let provider = provider.as_ref();
+        let decisions = cache();
+        let long = requests(&[("a", 30.0)]);
+        let short = requests(&[("b", 5.0)]);
+        assert!(
+            decide_creatives(
+                provider,
+                &decisions,
+                &long[0],
+                "s",
+                &viewer(),
+                Duration::from_secs(1)
+            )
+            .await
+            .is_empty()
+        );
+        assert_eq!(
+            decide_creatives(
+                provider,
+                &decisions,
+                &short[0],
+                "s",
+                &viewer(),
+                Duration::from_secs(1)
+            )
+            .await
+            .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stored_decision_is_published_without_deciding() {
+        let sessions = SessionManager::new_memory(Duration::from_secs(300));
+        sessions
+            .get_or_create("s".to_string(), "https://origin/p.m3u8".to_string())
+            .await;
+        let published = vec![AdSegment {
+            uri: "stored-ad.ts".to_string(),
+            duration: 10.0,
+            init: None,
+            tracking: None,
+        }];
+        sessions
+            .record_break_decision(
+                "s",
+                &provider::break_id("a"),
+                BreakDecision::segments(Vec::new()).with_published(&published),
+                Duration::from_secs(300),
+            )
+            .await;
+
+        let provider = slow(0);
+        let decisions = cache().with_sessions(sessions);
+        let results = decide_breaks(
+            &shared(&provider),
+            &decisions,
+            &requests(&[("a", 10.0)]),
+            "s",
+            &viewer(),
+            Duration::from_secs(1),
+        )
+        .await;
+
+        assert_eq!(results[0].as_ref().unwrap()[0].uri, "stored-ad.ts");
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_evicts_idle_decisions() {
+        let provider = static_provider();
+        let decisions = DecisionCache::new(Duration::ZERO);
+        let breaks = requests(&[("a", 10.0)]);
+        decide_breaks(
+            &provider,
+            &decisions,
+            &breaks,
+            "s",
+            &viewer(),
+            Duration::from_secs(1),
+        )
+        .await;
+        assert_eq!(decisions.segments.len(), 1);
+
+        decisions.cleanup();
+        assert!(decisions.segments.is_empty());
+    }
+}
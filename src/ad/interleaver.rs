@@ -1,17 +1,24 @@
-use crate::ad::provider::AdSegment;
+use crate::ad::provider::{self, AdSegment};
+use crate::ad::slate::{self, SlateProvider, SlateReason};
 use crate::hls::cue::AdBreak;
-use m3u8_rs::{MediaPlaylist, MediaSegment};
+use m3u8_rs::{Map, MediaPlaylist, MediaSegment};
 use tracing::{info, warn};
 
+/// Rounding slack when fitting ads into what is left of a break
+const FILL_EPSILON: f32 = 0.001;
+
 /// Interleave ad segments into a playlist based on detected ad breaks
 ///
 /// Replaces content segments within ad break windows with ad segments,
 /// adding proper `#EXT-X-DISCONTINUITY` tags before and after each ad break.
+/// fMP4 ad segments get their `#EXT-X-MAP`, and the content's map is
+/// repeated after the break so content playback resumes with it.
 ///
 /// # Arguments
 /// * `playlist` - The parsed MediaPlaylist to modify
 /// * `ad_breaks` - Detected ad break positions from CUE tags
 /// * `ad_segments` - Ad segments to insert (one vec per ad break)
+/// * `break_ids` - Stable id of each ad break, naming its ad segments
 /// * `session_id` - Session ID for URL generation
 /// * `base_url` - Base URL for the stitcher
 ///
@@ -21,6 +28,7 @@ pub fn interleave_ads(
     mut playlist: MediaPlaylist,
     ad_breaks: &[AdBreak],
     ad_segments_per_break: &[Vec<AdSegment>],
+    break_ids: &[String],
     session_id: &str,
     base_url: &str,
 ) -> MediaPlaylist {
@@ -29,11 +37,12 @@ pub fn interleave_ads(
         return playlist;
     }
 
-    if ad_breaks.len() != ad_segments_per_break.len() {
+    if ad_breaks.len() != ad_segments_per_break.len() || ad_breaks.len() != break_ids.len() {
         warn!(
-            "Mismatch between ad breaks ({}) and ad segment sets ({})",
+            "Mismatch between ad breaks ({}), ad segment sets ({}) and break ids ({})",
             ad_breaks.len(),
-            ad_segments_per_break.len()
+            ad_segments_per_break.len(),
+            break_ids.len()
         );
         return playlist;
     }
@@ -41,11 +50,17 @@ pub fn interleave_ads(
     let mut new_segments = Vec::new();
     let mut segment_index = 0;
     let original_segments = std::mem::take(&mut playlist.segments);
+    // The content's EXT-X-MAP in effect at segment_index
+    let mut content_map: Option<Map> = None;
 
     for (break_idx, ad_break) in ad_breaks.iter().enumerate() {
         // Add content segments before this ad break
         while segment_index < ad_break.start_index && segment_index < original_segments.len() {
-            new_segments.push(original_segments[segment_index].clone());
+            let segment = &original_segments[segment_index];
+            if segment.map.is_some() {
+                content_map = segment.map.clone();
+            }
+            new_segments.push(segment.clone());
             segment_index += 1;
         }
 
@@ -60,25 +75,45 @@ pub fn interleave_ads(
                 ad_breaks.len()
             );
 
-            // Add discontinuity before first ad segment
-            let mut first_ad_segment =
-                create_media_segment_from_ad(&ad_segments[0], session_id, base_url, break_idx, 0);
-            first_ad_segment.discontinuity = true;
-            new_segments.push(first_ad_segment);
-
-            // Add remaining ad segments
-            for (idx, ad_segment) in ad_segments.iter().skip(1).enumerate() {
-                let media_segment = create_media_segment_from_ad(
+            // Ads are named by their position in the pod, slate by itself
+            let mut ad_idx = 0;
+            for (idx, ad_segment) in ad_segments.iter().enumerate() {
+                let mut media_segment = create_media_segment_from_ad(
                     ad_segment,
                     session_id,
                     base_url,
                     break_idx,
-                    idx + 1,
+                    &break_ids[break_idx],
+                    ad_idx,
                 );
+                if !slate::is_slate_segment(&ad_segment.uri) {
+                    ad_idx += 1;
+                }
+                // Add discontinuity before first ad segment
+                media_segment.discontinuity = idx == 0;
+                // Each fMP4 ad starts with its initialization segment
+                let previous_init = idx
+                    .checked_sub(1)
+                    .and_then(|i| ad_segments[i].init.as_ref());
+                if let Some(init) = &ad_segment.init
+                    && (idx == 0 || previous_init != Some(init))
+                {
+                    media_segment.map = Some(Map {
+                        uri: init.clone(),
+                        ..Default::default()
+                    });
+                }
                 new_segments.push(media_segment);
             }
+            let ads_have_maps = ad_segments.iter().any(|s| s.init.is_some());
 
             // Skip the original content segments that were in the ad break window
+            let skip_to = ad_break.end_index.min(original_segments.len());
+            for segment in &original_segments[segment_index.min(skip_to)..skip_to] {
+                if segment.map.is_some() {
+                    content_map = segment.map.clone();
+                }
+            }
             segment_index = ad_break.end_index;
 
             // Add discontinuity after last ad segment (if there are more content segments)
@@ -87,6 +122,13 @@ pub fn interleave_ads(
                 if let Some(next_segment) = original_segments.get(segment_index) {
                     let mut next = next_segment.clone();
                     next.discontinuity = true;
+                    // Back to the content's initialization segment
+                    if next.map.is_none() && ads_have_maps {
+                        next.map = content_map.clone();
+                    }
+                    if next.map.is_some() {
+                        content_map = next.map.clone();
+                    }
                     new_segments.push(next);
                     segment_index += 1;
                 }
@@ -110,20 +152,101 @@ pub fn interleave_ads(
     playlist
 }
 
+/// Fit a break's ads around the segments published before its decision
+///
+/// The first `late_segments` content segments of the break went out while
+/// its decision was still pending. They keep playing what they played then —
+/// slate per content segment when `slate` is configured, the content itself
+/// otherwise. Once decided, `ads` follow from the start of the pod, as many
+/// whole segments as fit the time left in the break, with slate covering
+/// the rest.
+///
+/// Returns the break to interleave and its ad segments.
+pub fn backfill_break(
+    segments: &[MediaSegment],
+    ad_break: &AdBreak,
+    late_segments: usize,
+    ads: Option<Vec<AdSegment>>,
+    slate: Option<&SlateProvider>,
+    session_id: &str,
+) -> (AdBreak, Vec<AdSegment>) {
+    if late_segments == 0 {
+        return (ad_break.clone(), ads.unwrap_or_default());
+    }
+
+    let published_end = ad_break.end_index.min(segments.len());
+    let late_end = (ad_break.start_index + late_segments).min(published_end);
+    let late = &segments[ad_break.start_index.min(late_end)..late_end];
+    let late_duration: f32 = late.iter().map(|s| s.duration).sum();
+    let remaining = ad_break.duration - late_duration;
+
+    // Ads follow the late segments once this rendition has them all
+    let caught_up =
+        late_end == ad_break.start_index + late_segments && late_end < ad_break.end_index;
+    let mut fill = Vec::new();
+    if let Some(ads) = ads.filter(|_| caught_up) {
+        let mut filled = 0.0;
+        for ad in ads {
+            if filled + ad.duration > remaining + FILL_EPSILON {
+                break;
+            }
+            filled += ad.duration;
+            fill.push(ad);
+        }
+        if let Some(slate) = slate
+            && remaining - filled > FILL_EPSILON
+        {
+            fill.extend(slate.fill_duration(remaining - filled, SlateReason::Error, session_id));
+        }
+        info!(
+            "Backfilling ad break at segment #{} after {} late segment(s) ({:.1}s) for session {}",
+            ad_break.start_index,
+            late.len(),
+            late_duration,
+            session_id
+        );
+    }
+
+    match slate {
+        // Slate stood in for each late content segment
+        Some(slate) => {
+            let mut filler: Vec<AdSegment> = late
+                .iter()
+                .flat_map(|s| slate.fill_duration(s.duration, SlateReason::Error, session_id))
+                .collect();
+            filler.extend(fill);
+            (ad_break.clone(), filler)
+        }
+        // The late content segments stay; ads replace the rest of the break
+        None => (
+            AdBreak {
+                start_index: late_end,
+                end_index: ad_break.end_index,
+                duration: remaining,
+            },
+            fill,
+        ),
+    }
+}
+
 /// Create a MediaSegment from an AdSegment
 fn create_media_segment_from_ad(
     ad_segment: &AdSegment,
     session_id: &str,
     base_url: &str,
     break_idx: usize,
+    break_id: &str,
     segment_idx: usize,
 ) -> MediaSegment {
     // Route ad segment through the stitcher's ad handler
-    // Format: /stitch/{session_id}/ad/break-{break_idx}-seg-{segment_idx}.ts
-    let stitcher_uri = format!(
-        "{}/stitch/{}/ad/break-{}-seg-{}.ts",
-        base_url, session_id, break_idx, segment_idx
-    );
+    // Format: /stitch/{session_id}/ad/break-{break_id}-seg-{segment_idx}.ts
+    // Slate segments keep their own name, which says what they play
+    let name = if slate::is_slate_segment(&ad_segment.uri) {
+        ad_segment.uri.clone()
+    } else {
+        provider::ad_segment_name(break_id, segment_idx)
+    };
+    let stitcher_uri = format!("{}/stitch/{}/ad/{}", base_url, session_id, name);
 
     MediaSegment {
         uri: stitcher_uri,
@@ -181,11 +304,13 @@ mod tests {
             AdSegment {
                 uri: "ad1.ts".to_string(),
                 duration: 15.0,
+                init: None,
                 tracking: None,
             },
             AdSegment {
                 uri: "ad2.ts".to_string(),
                 duration: 15.0,
+                init: None,
                 tracking: None,
             },
         ]];
@@ -194,6 +319,7 @@ mod tests {
             playlist,
             &ad_breaks,
             &ad_segments,
+            &ids(ad_breaks.len()),
             "test-session",
             "http://localhost",
         );
@@ -241,11 +367,13 @@ mod tests {
             vec![AdSegment {
                 uri: "ad1.ts".to_string(),
                 duration: 15.0,
+                init: None,
                 tracking: None,
             }],
             vec![AdSegment {
                 uri: "ad2.ts".to_string(),
                 duration: 15.0,
+                init: None,
                 tracking: None,
             }],
         ];
@@ -254,6 +382,7 @@ mod tests {
             playlist,
             &ad_breaks,
             &ad_segments,
+            &ids(ad_breaks.len()),
             "test-session",
             "http://localhost",
         );
@@ -284,6 +413,7 @@ mod tests {
             playlist.clone(),
             &[],
             &[],
+            &[],
             "test-session",
             "http://localhost",
         );
@@ -292,4 +422,228 @@ mod tests {
         assert_eq!(result.segments[0].uri, "seg0.ts");
         assert_eq!(result.segments[1].uri, "seg1.ts");
     }
+
+    #[test]
+    fn test_interleave_fmp4_ads_switch_init_segments() {
+        let content_map = Map {
+            uri: "init.mp4".to_string(),
+            ..Default::default()
+        };
+        let mut first = create_test_segment("seg0.m4s", 10.0);
+        first.map = Some(content_map.clone());
+        let playlist = MediaPlaylist {
+            segments: vec![
+                first,
+                create_test_segment("seg1.m4s", 10.0),
+                create_test_segment("seg2.m4s", 10.0),
+                create_test_segment("seg3.m4s", 10.0),
+            ],
+            ..Default::default()
+        };
+        let ad_breaks = vec![AdBreak {
+            start_index: 1,
+            end_index: 3,
+            duration: 20.0,
+        }];
+        let ad = |uri: &str, init: &str| AdSegment {
+            uri: uri.to_string(),
+            duration: 5.0,
+            init: Some(init.to_string()),
+            tracking: None,
+        };
+        let ad_segments = vec![vec![
+            ad("a0.m4s", "https://ads/a-init.mp4"),
+            ad("a1.m4s", "https://ads/a-init.mp4"),
+            ad("b0.m4s", "https://ads/b-init.mp4"),
+            ad("b1.m4s", "https://ads/b-init.mp4"),
+        ]];
+
+        let result = interleave_ads(
+            playlist,
+            &ad_breaks,
+            &ad_segments,
+            &ids(ad_breaks.len()),
+            "test-session",
+            "http://localhost",
+        );
+
+        let maps: Vec<Option<&str>> = result
+            .segments
+            .iter()
+            .map(|s| s.map.as_ref().map(|m| m.uri.as_str()))
+            .collect();
+        assert_eq!(
+            maps,
+            [
+                Some("init.mp4"),
+                Some("https://ads/a-init.mp4"),
+                None,
+                Some("https://ads/b-init.mp4"),
+                None,
+                // Content resumes with its own initialization segment
+                Some("init.mp4"),
+            ]
+        );
+        assert!(result.segments[5].discontinuity);
+    }
+
+    #[test]
+    fn test_interleave_routes_slate_segments_by_name() {
+        let playlist = MediaPlaylist {
+            segments: vec![
+                create_test_segment("seg0.ts", 10.0),
+                create_test_segment("seg1.ts", 10.0),
+                create_test_segment("seg2.ts", 10.0),
+            ],
+            ..Default::default()
+        };
+        let ad_breaks = vec![AdBreak {
+            start_index: 1,
+            end_index: 2,
+            duration: 10.0,
+        }];
+        let segment = |uri: &str, duration: f32| AdSegment {
+            uri: uri.to_string(),
+            duration,
+            init: None,
+            tracking: None,
+        };
+        let ad_segments = vec![vec![
+            segment("break-0-seg-0.ts", 6.0),
+            segment("slate-error-seg-2.ts", 4.0),
+        ]];
+
+        let result = interleave_ads(
+            playlist,
+            &ad_breaks,
+            &ad_segments,
+            &ids(ad_breaks.len()),
+            "test-session",
+            "http://localhost",
+        );
+
+        assert_eq!(
+            result.segments[1].uri,
+            "http://localhost/stitch/test-session/ad/break-0-seg-0.ts"
+        );
+        assert_eq!(
+            result.segments[2].uri,
+            "http://localhost/stitch/test-session/ad/slate-error-seg-2.ts"
+        );
+        assert_eq!(result.segments[2].duration, 4.0);
+    }
+
+    fn ids(count: usize) -> Vec<String> {
+        (0..count).map(|i| i.to_string()).collect()
+    }
+
+    fn plain_segments(count: usize) -> Vec<MediaSegment> {
+        (0..count)
+            .map(|i| create_test_segment(&format!("seg{}.ts", i), 10.0))
+            .collect()
+    }
+
+    fn ads(durations: &[f32]) -> Vec<AdSegment> {
+        durations
+            .iter()
+            .enumerate()
+            .map(|(i, duration)| AdSegment {
+                uri: format!("ad{}.ts", i),
+                duration: *duration,
+                init: None,
+                tracking: None,
+            })
+            .collect()
+    }
+
+    const BREAK: AdBreak = AdBreak {
+        start_index: 1,
+        end_index: 4,
+        duration: 30.0,
+    };
+
+    #[test]
+    fn test_backfill_passes_late_content_through() {
+        let segments = plain_segments(4);
+
+        // Pending: the two published break segments stay content
+        let (pending, fill) = backfill_break(&segments, &BREAK, 2, None, None, "s");
+        assert_eq!(pending.start_index, 3);
+        assert!(fill.is_empty());
+
+        // Decided: whole ads fill the 10s left
+        let (late, fill) =
+            backfill_break(&segments, &BREAK, 2, Some(ads(&[10.0, 10.0])), None, "s");
+        assert_eq!(
+            late,
+            AdBreak {
+                start_index: 3,
+                end_index: 4,
+                duration: 10.0
+            }
+        );
+        assert_eq!(fill, ads(&[10.0]));
+
+        // On time: the break is untouched
+        let (on_time, fill) =
+            backfill_break(&segments, &BREAK, 0, Some(ads(&[10.0, 10.0])), None, "s");
+        assert_eq!(on_time, BREAK);
+        assert_eq!(fill.len(), 2);
+    }
+
+    #[test]
+    fn test_backfill_keeps_slate_for_late_segments() {
+        let segments = plain_segments(4);
+        let slate = SlateProvider::new("https://slate.example.com".to_string(), 10.0);
+
+        let (_, pending) = backfill_break(&segments, &BREAK, 2, None, Some(&slate), "s");
+        assert_eq!(pending.len(), 2);
+        assert!(
+            pending
+                .iter()
+                .all(|s| s.uri.starts_with("slate-error-") || s.uri.starts_with("slate-seg-"))
+        );
+
+        // The same slate, then ads and slate for the 10s left
+        let (ad_break, fill) = backfill_break(
+            &segments,
+            &BREAK,
+            2,
+            Some(ads(&[6.0, 6.0])),
+            Some(&slate),
+            "s",
+        );
+        assert_eq!(ad_break, BREAK);
+        assert_eq!(&fill[..2], &pending[..]);
+        assert_eq!(fill[2].uri, "ad0.ts");
+        assert!(slate::is_slate_segment(&fill[3].uri));
+        assert_eq!(fill[3].duration, 4.0);
+
+        // Ads keep their pod position behind the slate
+        let playlist = MediaPlaylist {
+            segments,
+            ..Default::default()
+        };
+        let result = interleave_ads(
+            playlist,
+            &[ad_break],
+            &[fill],
+            &ids(1),
+            "s",
+            "http://localhost",
+        );
+        assert_eq!(
+            result.segments[3].uri,
+            "http://localhost/stitch/s/ad/break-0-seg-0.ts"
+        );
+    }
+
+    #[test]
+    fn test_backfill_waits_for_lagging_rendition() {
+        // Three segments went out late elsewhere; this rendition has two
+        let segments = plain_segments(3);
+        let (ad_break, fill) = backfill_break(&segments, &BREAK, 3, Some(ads(&[10.0])), None, "s");
+        assert_eq!(ad_break.start_index, 3);
+        assert!(fill.is_empty());
+    }
 }
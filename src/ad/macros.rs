@@ -0,0 +1,350 @@
+//! IAB VAST 4.1 macro expansion
+//!
+//! Ad request URLs, wrapper ad tag URIs and every impression, tracking and
+//! error URL may contain macros such as `[CACHEBUSTING]` or `[ERRORCODE]`
+//! (also in percent-encoded form, `%5BERRORCODE%5D`). `expand` replaces the
+//! macros it knows with URL-encoded values from a `MacroContext`.
+//!
+//! Following the IAB guidance, a known macro whose value is not available
+//! is replaced with `-1`. Unknown macros are left untouched so ad servers
+//! can use their own.
+//!
+//! Besides the IAB list, `[LANGUAGE]` expands to the viewer's primary
+//! Accept-Language and `[ads.<name>]` to the viewer's `ads.<name>` stitch
+//! URL parameter.
+
+use crate::session::{ViewerContext, viewer::AD_PARAM_PREFIX};
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Value for a known macro whose value is not available
+const UNKNOWN: &str = "-1";
+
+/// Where a break sits in the content (`[BREAKPOSITION]`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BreakPosition {
+    Preroll = 1,
+    Midroll = 2,
+    Postroll = 3,
+    Standalone = 4,
+}
+
+/// Values available for macro substitution
+///
+/// Built when a break is decided and carried with each decided ad, so
+/// beacons fired later report the same context as the ad request.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MacroContext {
+    /// `[ERRORCODE]`
+    pub error_code: Option<u16>,
+    /// `[ASSETURI]`: media file of the ad being played
+    pub asset_uri: Option<String>,
+    /// `[CONTENTPLAYHEAD]` / `[MEDIAPLAYHEAD]` in seconds
+    pub content_playhead: Option<f64>,
+    /// `[ADPLAYHEAD]` in seconds
+    pub ad_playhead: Option<f64>,
+    /// `[BREAKPOSITION]`
+    pub break_position: Option<BreakPosition>,
+    /// `[BREAKMAXDURATION]` and `[DURATION]` in seconds
+    pub break_duration: Option<f32>,
+    /// `[PODSEQUENCE]`: `Ad@sequence` of the ad being played
+    pub pod_sequence: Option<u32>,
+    /// `[ADCOUNT]`: ads played in the break so far, including this one
+    pub ad_count: Option<usize>,
+    /// `[UNIVERSALADID]` as `registry value` pairs
+    pub universal_ad_ids: Vec<String>,
+    /// `[TRANSACTIONID]`: shared by the ad request and its beacons
+    pub transaction_id: Option<String>,
+    /// `[CLIENTUA]`
+    pub client_ua: Option<String>,
+    /// `[DEVICEIP]`
+    pub device_ip: Option<String>,
+    /// `[IFA]`
+    pub ifa: Option<String>,
+    /// `[IFATYPE]`
+    pub ifa_type: Option<String>,
+    /// `[LIMITADTRACKING]`
+    pub limit_ad_tracking: Option<bool>,
+    /// `[REGULATIONS]`, e.g. `gdpr:1,coppa:0`
+    pub regulations: Option<String>,
+    /// `[GDPRCONSENT]`
+    pub gdpr_consent: Option<String>,
+    /// `[LANGUAGE]`
+    pub language: Option<String>,
+    /// `[ads.<name>]`: the viewer's `ads.*` parameters by full name
+    pub ad_params: BTreeMap<String, String>,
+}
+
+impl MacroContext {
+    /// Add what is known about the viewer
+    ///
+    /// Device and privacy values are read from the viewer's `ads.ifa`,
+    /// `ads.ifa_type`, `ads.lmt`, `ads.gdpr`, `ads.gdpr_consent`,
+    /// `ads.coppa` and `ads.us_privacy` parameters.
+    pub fn with_viewer(self, viewer: &ViewerContext) -> Self {
+        let param = |name: &str| viewer.ad_param(name).map(str::to_string);
+        let regulations: Vec<String> = ["gdpr", "coppa", "us_privacy"]
+            .iter()
+            .filter_map(|name| viewer.ad_param(name).map(|v| format!("{}:{}", name, v)))
+            .collect();
+
+        Self {
+            client_ua: viewer.user_agent.clone(),
+            device_ip: viewer.client_ip.clone(),
+            ifa: param("ifa"),
+            ifa_type: param("ifa_type"),
+            limit_ad_tracking: viewer.ad_param("lmt").map(|v| matches!(v, "1" | "true")),
+            regulations: (!regulations.is_empty()).then(|| regulations.join(",")),
+            gdpr_consent: param("gdpr_consent"),
+            language: viewer.language(),
+            ad_params: viewer.ad_params.clone(),
+            ..self
+        }
+    }
+
+    /// Value of one macro, `None` if the macro is unknown
+    fn value(&self, name: &str) -> Option<String> {
+        if name.starts_with(AD_PARAM_PREFIX) {
+            return Some(
+                self.ad_params
+                    .get(name)
+                    .cloned()
+                    .unwrap_or_else(|| UNKNOWN.to_string()),
+            );
+        }
+        let value = match name {
+            "TIMESTAMP" => Some(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+            "CACHEBUSTING" => Some(cachebuster()),
+            "ERRORCODE" => self.error_code.map(|c| c.to_string()),
+            "ASSETURI" => self.asset_uri.clone(),
+            "CONTENTPLAYHEAD" | "MEDIAPLAYHEAD" => self.content_playhead.map(playhead),
+            "ADPLAYHEAD" => self.ad_playhead.map(playhead),
+            "BREAKPOSITION" => self.break_position.map(|p| (p as u8).to_string()),
+            "BREAKMAXDURATION" | "DURATION" => self.break_duration.map(|d| (d as u32).to_string()),
+            "PODSEQUENCE" => self.pod_sequence.map(|s| s.to_string()),
+            "ADCOUNT" => self.ad_count.map(|c| c.to_string()),
+            "UNIVERSALADID" => {
+                (!self.universal_ad_ids.is_empty()).then(|| self.universal_ad_ids.join(","))
+            }
+            "TRANSACTIONID" => self.transaction_id.clone(),
+            "CLIENTUA" => self.client_ua.clone(),
+            "SERVERUA" => Some(format!("ritcher/{}", env!("CARGO_PKG_VERSION"))),
+            "DEVICEIP" => self.device_ip.clone(),
+            "IFA" => self.ifa.clone(),
+            "IFATYPE" => self.ifa_type.clone(),
+            "LIMITADTRACKING" => self.limit_ad_tracking.map(|l| u8::from(l).to_string()),
+            "REGULATIONS" => self.regulations.clone(),
+            "GDPRCONSENT" => self.gdpr_consent.clone(),
+            // 1: server-side with client details forwarded, 2: without
+            "SERVERSIDE" => Some(
+                if self.client_ua.is_some() || self.device_ip.is_some() {
+                    "1"
+                } else {
+                    "2"
+                }
+                .to_string(),
+            ),
+            "ADTYPE" => Some("video".to_string()),
+            "LANGUAGE" => self.language.clone(),
+            _ => return None,
+        };
+        Some(value.unwrap_or_else(|| UNKNOWN.to_string()))
+    }
+}
+
+/// Replace every known macro in `template` with its URL-encoded value
+pub fn expand(template: &str, ctx: &MacroContext) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some((start, open_len)) = find_open(rest) {
+        let after_open = &rest[start + open_len..];
+        let Some((name_len, close_len)) = find_close(after_open) else {
+            // Not a macro: keep the bracket and look further
+            out.push_str(&rest[..start + open_len]);
+            rest = after_open;
+            continue;
+        };
+        let name = &after_open[..name_len];
+        out.push_str(&rest[..start]);
+        match ctx.value(name) {
+            Some(value) => out.push_str(&encode(&value)),
+            None => out.push_str(&rest[start..start + open_len + name_len + close_len]),
+        }
+        rest = &after_open[name_len + close_len..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Position and length of the next `[` or `%5B`
+fn find_open(s: &str) -> Option<(usize, usize)> {
+    let bytes = s.as_bytes();
+    (0..bytes.len()).find_map(|i| match bytes[i] {
+        b'[' => Some((i, 1)),
+        b'%' if bytes
+            .get(i + 1..i + 3)
+            .is_some_and(|b| b.eq_ignore_ascii_case(b"5B")) =>
+        {
+            Some((i, 3))
+        }
+        _ => None,
+    })
+}
+
+/// Length of a macro name and of the `]` or `%5D` that closes it
+fn find_close(s: &str) -> Option<(usize, usize)> {
+    let name_len = s
+        .bytes()
+        .take_while(|b| b.is_ascii_alphanumeric() || matches!(b, b'_' | b'.' | b'-'))
+        .count();
+    if name_len == 0 {
+        return None;
+    }
+    let tail = &s.as_bytes()[name_len..];
+    if tail.first() == Some(&b']') {
+        Some((name_len, 1))
+    } else if tail
+        .get(..3)
+        .is_some_and(|b| b.eq_ignore_ascii_case(b"%5D"))
+    {
+        Some((name_len, 3))
+    } else {
+        None
+    }
+}
+
+/// Percent-encode everything but RFC 3986 unreserved characters
+fn encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// Playhead as `HH:MM:SS.mmm`
+fn playhead(seconds: f64) -> String {
+    let ms = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Random 8-digit number
+fn cachebuster() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!("{:08}", nanos % 100_000_000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_known_macros_url_encoded() {
+        let ctx = MacroContext {
+            asset_uri: Some("https://ads.example.com/a.mp4?x=1".to_string()),
+            ad_playhead: Some(3725.5),
+            break_position: Some(BreakPosition::Midroll),
+            break_duration: Some(30.0),
+            pod_sequence: Some(2),
+            ad_count: Some(1),
+            client_ua: Some("Mozilla/5.0 (X11)".to_string()),
+            ..Default::default()
+        };
+        let url = expand(
+            "https://t.example.com/e?asset=[ASSETURI]&ph=[ADPLAYHEAD]&bp=[BREAKPOSITION]\
+             &d=[DURATION]&seq=[PODSEQUENCE]&n=[ADCOUNT]&ua=[CLIENTUA]&ss=[SERVERSIDE]",
+            &ctx,
+        );
+        assert_eq!(
+            url,
+            "https://t.example.com/e?asset=https%3A%2F%2Fads.example.com%2Fa.mp4%3Fx%3D1\
+             &ph=01%3A02%3A05.500&bp=2&d=30&seq=2&n=1&ua=Mozilla%2F5.0%20%28X11%29&ss=1"
+        );
+    }
+
+    #[test]
+    fn test_expand_unknown_values_and_macros() {
+        let url = expand(
+            "https://t.example.com/e?ip=[DEVICEIP]&gdpr=[GDPRCONSENT]&custom=[MYMACRO]&a[]=1&ifa=[IFA]",
+            &MacroContext::default(),
+        );
+        assert_eq!(
+            url,
+            "https://t.example.com/e?ip=-1&gdpr=-1&custom=[MYMACRO]&a[]=1&ifa=-1"
+        );
+    }
+
+    #[test]
+    fn test_expand_error_code_in_both_bracket_forms() {
+        let ctx = MacroContext {
+            error_code: Some(303),
+            ..Default::default()
+        };
+        assert_eq!(
+            expand("https://e.example.com/err?code=[ERRORCODE]", &ctx),
+            "https://e.example.com/err?code=303"
+        );
+        assert_eq!(
+            expand(
+                "https://e.example.com/err?code=%5BERRORCODE%5D&t=%5bTIMESTAMP",
+                &ctx
+            ),
+            "https://e.example.com/err?code=303&t=%5bTIMESTAMP"
+        );
+    }
+
+    #[test]
+    fn test_viewer_values() {
+        let viewer = ViewerContext {
+            client_ip: Some("203.0.113.7".to_string()),
+            user_agent: Some("AppleCoreMedia/1.0".to_string()),
+            accept_language: Some("sv-SE,en;q=0.8".to_string()),
+            ad_params: [
+                ("ads.genre", "sports"),
+                ("ads.ifa", "6D92078A-8246-4BA4-AE5B-76104861E7DC"),
+                ("ads.gdpr", "1"),
+                ("ads.us_privacy", "1YNN"),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        };
+        let ctx = MacroContext::default().with_viewer(&viewer);
+        assert_eq!(
+            expand(
+                "https://ads.example.com/vast?ip=[DEVICEIP]&lang=[LANGUAGE]&ifa=[IFA]\
+                 &reg=[REGULATIONS]&genre=[ads.genre]&tier=[ads.tier]&ss=[SERVERSIDE]",
+                &ctx
+            ),
+            "https://ads.example.com/vast?ip=203.0.113.7&lang=sv\
+             &ifa=6D92078A-8246-4BA4-AE5B-76104861E7DC&reg=gdpr%3A1%2Cus_privacy%3A1YNN\
+             &genre=sports&tier=-1&ss=1"
+        );
+    }
+
+    #[test]
+    fn test_generated_values() {
+        let ctx = MacroContext::default();
+        assert_eq!(expand("[CACHEBUSTING]", &ctx).len(), 8);
+        let ts = expand("[TIMESTAMP]", &ctx);
+        assert!(ts.ends_with('Z') && ts.contains('T'), "{}", ts);
+        assert_eq!(expand("[SERVERSIDE]", &ctx), "2");
+    }
+}
@@ -1,11 +1,22 @@
 pub mod conditioning;
+pub mod decisioning;
 pub mod interleaver;
+pub mod macros;
+pub mod normalizer;
+pub mod openrtb;
+pub mod playlist;
+pub mod pod;
+pub mod policy;
 pub mod provider;
 pub mod slate;
+pub mod sources;
 pub mod tracking;
 pub mod vast;
 pub mod vast_provider;
 
+pub use normalizer::AdNormalizer;
+pub use openrtb::{OpenRtbAdProvider, OpenRtbBidder};
 pub use provider::{AdProvider, StaticAdProvider};
 pub use slate::SlateProvider;
+pub use sources::{AdSource, AdSourceStrategy, MultiSourceAdProvider};
 pub use vast_provider::VastAdProvider;
@@ -0,0 +1,315 @@
+//! Client for an ad normalizer that packages progressive creatives
+//!
+//! Progressive MP4 creatives cannot be stitched into HLS or DASH content.
+//! An ad normalizer (such as the Eyevinn Ad Normalizer) transcodes them
+//! into a packaged HLS/CMAF rendition. Packaging takes longer than an ad
+//! decision may, so the normalizer is asked once per creative and the
+//! answer is cached: until the rendition is ready the creative is left
+//! out, and other ads of the pod or slate fill its time.
+//!
+//! The normalizer is asked with `GET {endpoint}?key={key}&url={media url}`
+//! and answers:
+//! - `200` with `{"url": "<HLS/CMAF playlist>"}` when the rendition is ready
+//! - `202` while the creative is being packaged
+//!
+//! Any other answer is an error, retried like a pending creative.
+
+use crate::ad::vast::UniversalAdId;
+use crate::metrics;
+use dashmap::DashMap;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Packaged renditions are looked up again after this long
+const READY_TTL: Duration = Duration::from_secs(3600);
+
+/// Most creatives remembered at once
+const MAX_ENTRIES: usize = 10_000;
+
+/// Normalizer answer for a packaged rendition
+#[derive(Debug, Deserialize)]
+struct NormalizedAsset {
+    url: String,
+}
+
+/// What is known about one creative's packaged rendition
+#[derive(Debug, Clone)]
+enum AssetState {
+    /// Packaged; the URL of its HLS/CMAF playlist
+    Ready(String),
+    /// Being packaged, or the normalizer could not be asked
+    Pending,
+}
+
+#[derive(Debug, Clone)]
+struct CachedAsset {
+    state: AssetState,
+    checked_at: Instant,
+}
+
+/// Ad normalizer client with a cache of packaged renditions
+///
+/// Clones share the cache, so one normalizer can serve several ad sources.
+#[derive(Clone)]
+pub struct AdNormalizer {
+    endpoint: String,
+    http_client: Client,
+    /// Packaging state per creative key (see [`asset_key`])
+    assets: Arc<DashMap<String, CachedAsset>>,
+    /// Normalizer request timeout
+    timeout: Duration,
+    /// How long a pending creative waits before the normalizer is asked again
+    retry_interval: Duration,
+}
+
+impl AdNormalizer {
+    /// Create a client for the normalizer at `endpoint`
+    pub fn new(endpoint: String, http_client: Client) -> Self {
+        Self {
+            endpoint,
+            http_client,
+            assets: Arc::new(DashMap::new()),
+            timeout: Duration::from_millis(1000),
+            retry_interval: Duration::from_secs(10),
+        }
+    }
+
+    /// Configure the normalizer request timeout (default: 1s)
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Configure how often a pending creative is checked again (default: 10s)
+    pub fn with_retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    /// The packaged rendition of a progressive creative, if it is ready
+    ///
+    /// Asks the normalizer for creatives it has not seen, and again for
+    /// pending ones once the retry interval has passed. `None` means the
+    /// creative cannot be stitched yet.
+    pub async fn normalized_url(
+        &self,
+        universal_ad_ids: &[UniversalAdId],
+        media_url: &str,
+    ) -> Option<String> {
+        let key = asset_key(universal_ad_ids, media_url);
+        if let Some(cached) = self.assets.get(&key) {
+            match &cached.state {
+                AssetState::Ready(url) => {
+                    metrics::record_ad_normalizer_lookup("hit");
+                    return Some(url.clone());
+                }
+                AssetState::Pending if cached.checked_at.elapsed() < self.retry_interval => {
+                    metrics::record_ad_normalizer_lookup("pending");
+                    return None;
+                }
+                AssetState::Pending => {}
+            }
+        }
+
+        // Mark as pending first so concurrent lookups don't ask again
+        self.remember(&key, AssetState::Pending);
+        let state = self.request(&key, media_url).await;
+        self.remember(&key, state.clone());
+        match state {
+            AssetState::Ready(url) => Some(url),
+            AssetState::Pending => None,
+        }
+    }
+
+    /// Ask the normalizer for one creative's packaged rendition
+    async fn request(&self, key: &str, media_url: &str) -> AssetState {
+        let response = self
+            .http_client
+            .get(&self.endpoint)
+            .query(&[("key", key), ("url", media_url)])
+            .timeout(self.timeout)
+            .send()
+            .await;
+
+        match response {
+            Ok(resp) if resp.status() == StatusCode::OK => {
+                match resp.json::<NormalizedAsset>().await {
+                    Ok(asset) => {
+                        info!("AdNormalizer: {} is packaged as {}", media_url, asset.url);
+                        metrics::record_ad_normalizer_lookup("ready");
+                        AssetState::Ready(asset.url)
+                    }
+                    Err(e) => {
+                        warn!("AdNormalizer: invalid answer for {}: {}", media_url, e);
+                        metrics::record_ad_normalizer_lookup("error");
+                        AssetState::Pending
+                    }
+                }
+            }
+            Ok(resp) if resp.status() == StatusCode::ACCEPTED => {
+                info!("AdNormalizer: {} is being packaged", media_url);
+                metrics::record_ad_normalizer_lookup("pending");
+                AssetState::Pending
+            }
+            Ok(resp) => {
+                warn!(
+                    "AdNormalizer: returned status {} for {}",
+                    resp.status(),
+                    media_url
+                );
+                metrics::record_ad_normalizer_lookup("error");
+                AssetState::Pending
+            }
+            Err(e) => {
+                warn!("AdNormalizer: request failed for {}: {}", media_url, e);
+                metrics::record_ad_normalizer_lookup("error");
+                AssetState::Pending
+            }
+        }
+    }
+
+    fn remember(&self, key: &str, state: AssetState) {
+        self.assets.insert(
+            key.to_string(),
+            CachedAsset {
+                state,
+                checked_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Forget stale entries, keeping the cache bounded
+    pub fn cleanup(&self) {
+        self.assets.retain(|_, asset| match asset.state {
+            AssetState::Ready(_) => asset.checked_at.elapsed() < READY_TTL,
+            AssetState::Pending => asset.checked_at.elapsed() < self.retry_interval,
+        });
+
+        if self.assets.len() > MAX_ENTRIES {
+            let mut entries: Vec<(String, Instant)> = self
+                .assets
+                .iter()
+                .map(|e| (e.key().clone(), e.value().checked_at))
+                .collect();
+            entries.sort_unstable_by_key(|(_, checked_at)| *checked_at);
+            let to_remove = entries.len() - MAX_ENTRIES;
+            for (key, _) in entries.iter().take(to_remove) {
+                self.assets.remove(key);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for AdNormalizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdNormalizer")
+            .field("endpoint", &self.endpoint)
+            .field("timeout", &self.timeout)
+            .field("cached_assets", &self.assets.len())
+            .finish()
+    }
+}
+
+/// Cache key of a creative: its UniversalAdId, else its media file URL
+pub fn asset_key(universal_ad_ids: &[UniversalAdId], media_url: &str) -> String {
+    universal_ad_ids
+        .iter()
+        .find(|id| !id.value.is_empty() && id.value != "unknown")
+        .map(|id| format!("{}:{}", id.id_registry, id.value))
+        .unwrap_or_else(|| media_url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Query;
+    use axum::http::StatusCode;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Stand-in normalizer: creatives are packaged on their second lookup
+    async fn mock_normalizer() -> (String, Arc<Mutex<Vec<String>>>) {
+        let lookups = Arc::new(Mutex::new(Vec::new()));
+        let recorded = lookups.clone();
+        let app =
+            axum::Router::new().fallback(move |Query(query): Query<HashMap<String, String>>| {
+                let lookups = recorded.clone();
+                async move {
+                    let key = query.get("key").cloned().unwrap_or_default();
+                    let mut lookups = lookups.lock().unwrap();
+                    let seen = lookups.contains(&key);
+                    lookups.push(key.clone());
+                    if seen {
+                        let url = format!("https://cdn.example.com/{key}/master.m3u8");
+                        (StatusCode::OK, format!(r#"{{"url":"{url}"}}"#))
+                    } else {
+                        (StatusCode::ACCEPTED, String::new())
+                    }
+                }
+            });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/normalize", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (base, lookups)
+    }
+
+    #[test]
+    fn test_asset_key_prefers_universal_ad_id() {
+        let ids = vec![
+            UniversalAdId {
+                id_registry: "unknown".into(),
+                value: "unknown".into(),
+            },
+            UniversalAdId {
+                id_registry: "ad-id.org".into(),
+                value: "CNPA0484000H".into(),
+            },
+        ];
+        assert_eq!(
+            asset_key(&ids, "https://ads.example.com/a.mp4"),
+            "ad-id.org:CNPA0484000H"
+        );
+        assert_eq!(
+            asset_key(&[], "https://ads.example.com/a.mp4"),
+            "https://ads.example.com/a.mp4"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pending_creative_is_retried_then_cached() {
+        let (endpoint, lookups) = mock_normalizer().await;
+        let normalizer = AdNormalizer::new(endpoint, Client::new())
+            .with_retry_interval(Duration::from_millis(50));
+        let media = "https://ads.example.com/a.mp4";
+
+        // First sight: packaging starts, nothing to stitch yet
+        assert_eq!(normalizer.normalized_url(&[], media).await, None);
+        // Within the retry interval the normalizer is not asked again
+        assert_eq!(normalizer.normalized_url(&[], media).await, None);
+        assert_eq!(lookups.lock().unwrap().len(), 1);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let expected = format!("https://cdn.example.com/{media}/master.m3u8");
+        assert_eq!(
+            normalizer.normalized_url(&[], media).await,
+            Some(expected.clone())
+        );
+        // Ready renditions come from the cache
+        assert_eq!(normalizer.normalized_url(&[], media).await, Some(expected));
+        assert_eq!(lookups.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_normalizer_is_pending() {
+        let normalizer = AdNormalizer::new("http://127.0.0.1:1/normalize".into(), Client::new());
+        assert_eq!(
+            normalizer
+                .normalized_url(&[], "https://ads.example.com/a.mp4")
+                .await,
+            None
+        );
+    }
+}
@@ -0,0 +1,936 @@
+//! OpenRTB 2.6 programmatic video demand
+//!
+//! Each ad break is offered to the configured bidders as one bid request
+//! whose video object describes the break as a dynamic ad pod (`poddur`,
+//! `maxseq`). Bids that arrive within `tmax` enter a first-price auction,
+//! and the winning bid's VAST — its `adm` markup, or the document its
+//! `nurl` returns — is resolved by the VAST pipeline like any ad server
+//! response. Win and loss notices are fired through [`tracking`].
+
+use crate::ad::normalizer::AdNormalizer;
+use crate::ad::pod::PodConstraints;
+use crate::ad::policy::{AdPolicy, PolicyState};
+use crate::ad::provider::{AdCreative, AdProvider, AdSegment, ResolvedSegment};
+use crate::ad::slate::{self, SlateProvider, SlateReason};
+use crate::ad::tracking::{self, AuctionMacros};
+use crate::ad::vast_provider::VastAdProvider;
+use crate::metrics;
+use crate::session::{SessionManager, ViewerContext};
+use futures::future::{BoxFuture, join_all};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Version sent in the `x-openrtb-version` header
+const OPENRTB_VERSION: &str = "2.6";
+
+/// VAST 2.0 through 4.2, inline and wrapper
+const VAST_PROTOCOLS: [u8; 10] = [2, 3, 5, 6, 7, 8, 11, 12, 13, 14];
+
+/// Creative types that can be stitched, directly or through the ad normalizer
+const AD_MIMES: [&str; 2] = ["application/x-mpegURL", "video/mp4"];
+
+/// Each bid request offers the break as a single impression
+const IMP_ID: &str = "1";
+
+/// Loss reason code: the bid was below the auction floor
+const LOSS_BELOW_FLOOR: u32 = 100;
+
+/// Loss reason code: the bid lost to a higher bid
+const LOSS_HIGHER_BID: u32 = 102;
+
+/// OpenRTB 2.6 bid request for one ad break
+#[derive(Debug, Serialize)]
+struct BidRequest {
+    id: String,
+    imp: Vec<Imp>,
+    site: Site,
+    device: Device,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<User>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    regs: Option<Regs>,
+    /// Auction type, 1 = first price
+    at: u8,
+    /// Milliseconds bidders have to answer
+    tmax: u64,
+    cur: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Imp {
+    id: &'static str,
+    video: Video,
+    bidfloor: f64,
+    bidfloorcur: String,
+}
+
+/// The break as a dynamic ad pod
+#[derive(Debug, Serialize)]
+struct Video {
+    mimes: [&'static str; 2],
+    protocols: [u8; 10],
+    /// 1 = linear
+    linearity: u8,
+    /// 1 = instream
+    plcmt: u8,
+    /// -1 = generic mid-roll
+    startdelay: i32,
+    /// Seconds of the break to fill
+    poddur: u32,
+    /// Most ads in the pod
+    maxseq: usize,
+    podid: String,
+    /// 0 = any pod position
+    podseq: u8,
+    /// 0 = any slot in the pod
+    slotinpod: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    minduration: Option<u32>,
+    maxduration: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct Site {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    domain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Device {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ua: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv6: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ifa: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lmt: Option<u8>,
+}
+
+#[derive(Debug, Serialize)]
+struct User {
+    /// TCF consent string
+    consent: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Regs {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    coppa: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gdpr: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    us_privacy: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct BidResponse {
+    id: String,
+    seatbid: Vec<SeatBid>,
+    cur: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SeatBid {
+    seat: Option<String>,
+    bid: Vec<Bid>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct Bid {
+    id: String,
+    impid: String,
+    price: f64,
+    /// VAST markup
+    adm: Option<String>,
+    /// Win notice URL; returns the VAST markup when `adm` is absent
+    nurl: Option<String>,
+    /// Loss notice URL
+    lurl: Option<String>,
+    adid: Option<String>,
+}
+
+/// A valid bid and the bidder and seat it came from
+#[derive(Debug)]
+struct Offer {
+    bidder: usize,
+    seat: String,
+    bid: Bid,
+}
+
+/// One demand partner of an [`OpenRtbAdProvider`]
+#[derive(Debug, Clone)]
+pub struct OpenRtbBidder {
+    /// Name used in logs and metric labels
+    pub name: String,
+    /// Endpoint bid requests are POSTed to
+    pub url: String,
+}
+
+/// Ad provider that runs a first-price OpenRTB auction for each break
+///
+/// Like [`MultiSourceAdProvider`](crate::ad::MultiSourceAdProvider), slate
+/// fills breaks no bid won and the gap the winning pod leaves.
+pub struct OpenRtbAdProvider {
+    bidders: Vec<OpenRtbBidder>,
+    http_client: Client,
+    /// Resolves winning markup; its own endpoint is never asked
+    vast: VastAdProvider,
+    /// How long bidders have to answer
+    tmax: Duration,
+    /// Lowest CPM accepted
+    bid_floor: f64,
+    currency: String,
+    /// `site.domain` of bid requests
+    site_domain: Option<String>,
+    pod_constraints: PodConstraints,
+    slate: Option<SlateProvider>,
+    /// Frequency caps and separation applied to the winning pod
+    policy: Option<AdPolicy>,
+}
+
+impl OpenRtbAdProvider {
+    /// Create a provider that asks `bidders` for every break
+    pub fn new(bidders: Vec<OpenRtbBidder>, http_client: Client) -> Self {
+        Self {
+            bidders,
+            vast: VastAdProvider::new(String::new(), http_client.clone()),
+            http_client,
+            tmax: Duration::from_millis(500),
+            bid_floor: 0.0,
+            currency: "USD".to_string(),
+            site_domain: None,
+            pod_constraints: PodConstraints::default(),
+            slate: None,
+            policy: None,
+        }
+    }
+
+    /// Configure how long bidders have to answer (default: 500ms)
+    pub fn with_tmax(mut self, tmax: Duration) -> Self {
+        self.tmax = tmax;
+        self
+    }
+
+    /// Configure the lowest CPM accepted and the auction currency
+    /// (default: 0 USD)
+    pub fn with_bid_floor(mut self, bid_floor: f64, currency: String) -> Self {
+        self.bid_floor = bid_floor;
+        self.currency = currency;
+        self
+    }
+
+    /// Configure the site domain sent in bid requests
+    pub fn with_site_domain(mut self, domain: String) -> Self {
+        self.site_domain = Some(domain);
+        self
+    }
+
+    /// Configure the limits of the pod requested and built for each break
+    pub fn with_pod_constraints(mut self, constraints: PodConstraints) -> Self {
+        self.vast = self.vast.with_pod_constraints(constraints.clone());
+        self.pod_constraints = constraints;
+        self
+    }
+
+    /// Stitch progressive creatives of winning bids through an ad normalizer
+    pub fn with_normalizer(mut self, normalizer: AdNormalizer) -> Self {
+        self.vast = self.vast.with_normalizer(normalizer);
+        self
+    }
+
+    /// Apply frequency caps and competitive separation to winning pods
+    pub fn with_policy(mut self, policy: AdPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Bound the cache of winning creatives (see [`VastAdProvider::with_cache_limits`])
+    pub fn with_cache_limits(mut self, ttl: Duration, max_creatives: usize) -> Self {
+        self.vast = self.vast.with_cache_limits(ttl, max_creatives);
+        self
+    }
+
+    /// Keep winning creatives in the session store (see [`VastAdProvider::with_sessions`])
+    pub fn with_sessions(mut self, sessions: SessionManager) -> Self {
+        self.vast = self.vast.with_sessions(sessions);
+        self
+    }
+
+    /// Configure the slate used when no bid wins a break
+    pub fn with_slate(mut self, slate: SlateProvider) -> Self {
+        self.slate = Some(slate);
+        self
+    }
+
+    /// The session's policy snapshot, or no policy if none is configured
+    async fn policy_state(&self, session_id: &str, viewer: &ViewerContext) -> PolicyState {
+        match &self.policy {
+            Some(policy) => policy.load(session_id, viewer).await,
+            None => PolicyState::default(),
+        }
+    }
+
+    /// Bid request offering one break, targeted with the session's viewer
+    fn build_request(&self, duration: f32, session_id: &str, viewer: &ViewerContext) -> BidRequest {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let flag = |name: &str| {
+            viewer
+                .ad_param(name)
+                .map(|v| matches!(v, "1" | "true") as u8)
+        };
+        let ip = viewer.client_ip.as_deref().and_then(|ip| ip.parse().ok());
+        let poddur = duration.floor() as u32;
+
+        let regs = Regs {
+            coppa: flag("coppa"),
+            gdpr: flag("gdpr"),
+            us_privacy: viewer.ad_param("us_privacy").map(str::to_string),
+        };
+        let has_regs = regs.coppa.is_some() || regs.gdpr.is_some() || regs.us_privacy.is_some();
+
+        BidRequest {
+            id: format!("{}-{}", session_id, timestamp),
+            imp: vec![Imp {
+                id: IMP_ID,
+                video: Video {
+                    mimes: AD_MIMES,
+                    protocols: VAST_PROTOCOLS,
+                    linearity: 1,
+                    plcmt: 1,
+                    startdelay: -1,
+                    poddur,
+                    maxseq: self.pod_constraints.max_ads,
+                    podid: IMP_ID.to_string(),
+                    podseq: 0,
+                    slotinpod: 0,
+                    minduration: (self.pod_constraints.min_ad_duration > 0.0)
+                        .then(|| self.pod_constraints.min_ad_duration.ceil() as u32),
+                    maxduration: poddur,
+                },
+                bidfloor: self.bid_floor,
+                bidfloorcur: self.currency.clone(),
+            }],
+            site: Site {
+                domain: self.site_domain.clone(),
+                page: viewer.ad_param("page").map(str::to_string),
+            },
+            device: Device {
+                ua: viewer.user_agent.clone(),
+                ip: ip.filter(IpAddr::is_ipv4).map(|ip: IpAddr| ip.to_string()),
+                ipv6: ip.filter(IpAddr::is_ipv6).map(|ip: IpAddr| ip.to_string()),
+                language: viewer.language(),
+                ifa: viewer.ad_param("ifa").map(str::to_string),
+                lmt: flag("lmt"),
+            },
+            user: viewer.ad_param("gdpr_consent").map(|consent| User {
+                consent: consent.to_string(),
+            }),
+            regs: has_regs.then_some(regs),
+            at: 1,
+            tmax: self.tmax.as_millis() as u64,
+            cur: vec![self.currency.clone()],
+        }
+    }
+
+    /// Send the bid request to one bidder, bounded by `tmax`
+    ///
+    /// `None` means the bidder failed or answered with an invalid response;
+    /// an empty list is a no-bid.
+    async fn ask(&self, idx: usize, request: &BidRequest) -> Option<Vec<Offer>> {
+        let bidder = &self.bidders[idx];
+        let start = Instant::now();
+        let exchange = async {
+            let resp = self
+                .http_client
+                .post(&bidder.url)
+                .header("x-openrtb-version", OPENRTB_VERSION)
+                .json(request)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+            if resp.status() == StatusCode::NO_CONTENT {
+                return Ok(None);
+            }
+            let body = resp
+                .error_for_status()
+                .map_err(|e| e.to_string())?
+                .bytes()
+                .await
+                .map_err(|e| e.to_string())?;
+            if body.is_empty() {
+                return Ok(None);
+            }
+            serde_json::from_slice::<BidResponse>(&body)
+                .map(Some)
+                .map_err(|e| format!("invalid bid response: {}", e))
+        };
+        let result = tokio::time::timeout(self.tmax, exchange).await;
+        metrics::record_ad_source_latency(&bidder.name, start);
+
+        let (outcome, offers) = match result {
+            Ok(Ok(Some(response))) => match self.offers(idx, request, response) {
+                Ok(offers) if !offers.is_empty() => ("fill", Some(offers)),
+                Ok(_) => ("nofill", Some(Vec::new())),
+                Err(e) => {
+                    warn!("OpenRtbAdProvider: Bidder {} answered {}", bidder.name, e);
+                    ("error", None)
+                }
+            },
+            Ok(Ok(None)) => ("nofill", Some(Vec::new())),
+            Ok(Err(e)) => {
+                warn!("OpenRtbAdProvider: Bidder {} failed: {}", bidder.name, e);
+                ("error", None)
+            }
+            Err(_) => ("timeout", None),
+        };
+        metrics::record_ad_source_request(&bidder.name, outcome);
+        info!(
+            "OpenRtbAdProvider: Bidder {} answered {} in {}ms",
+            bidder.name,
+            outcome,
+            start.elapsed().as_millis()
+        );
+        offers
+    }
+
+    /// The usable bids of one bidder's response
+    fn offers(
+        &self,
+        bidder: usize,
+        request: &BidRequest,
+        response: BidResponse,
+    ) -> Result<Vec<Offer>, String> {
+        if response.id != request.id {
+            return Err(format!("a response to bid request {}", response.id));
+        }
+        if let Some(cur) = response.cur.filter(|cur| *cur != self.currency) {
+            return Err(format!("bids in {}, expected {}", cur, self.currency));
+        }
+        Ok(response
+            .seatbid
+            .into_iter()
+            .flat_map(|seatbid| {
+                let seat = seatbid.seat.unwrap_or_default();
+                seatbid.bid.into_iter().map(move |bid| (seat.clone(), bid))
+            })
+            .filter(|(_, bid)| {
+                let usable = bid.impid == IMP_ID
+                    && bid.price > 0.0
+                    && (bid.adm.is_some() || bid.nurl.is_some());
+                if !usable {
+                    warn!(
+                        "OpenRtbAdProvider: Ignoring bid {} of bidder {}",
+                        bid.id, self.bidders[bidder].name
+                    );
+                }
+                usable
+            })
+            .map(|(seat, bid)| Offer { bidder, seat, bid })
+            .collect())
+    }
+
+    /// Auction macro values for one offer
+    fn auction_macros(
+        &self,
+        request: &BidRequest,
+        offer: &Offer,
+        price: f64,
+        loss: Option<u32>,
+    ) -> AuctionMacros {
+        AuctionMacros {
+            auction_id: request.id.clone(),
+            bid_id: offer.bid.id.clone(),
+            imp_id: offer.bid.impid.clone(),
+            seat_id: offer.seat.clone(),
+            ad_id: offer.bid.adid.clone().unwrap_or_default(),
+            price,
+            currency: self.currency.clone(),
+            loss,
+        }
+    }
+
+    /// Tell a losing bidder why its bid lost
+    fn notify_loss(&self, request: &BidRequest, offer: &Offer, price: f64, reason: u32) {
+        if let Some(lurl) = &offer.bid.lurl {
+            let macros = self.auction_macros(request, offer, price, Some(reason));
+            tracking::fire_loss_notice(self.http_client.clone(), lurl, &macros);
+        }
+    }
+
+    /// Offer a break to every bidder and run a first-price auction
+    ///
+    /// Returns the winning bid's VAST: its `adm` markup, or a wrapper
+    /// around its `nurl` when the bidder returns the markup from the win
+    /// notice. If no bid won, returns why: no fill if any bidder answered,
+    /// else an error.
+    async fn auction(
+        &self,
+        duration: f32,
+        session_id: &str,
+        viewer: &ViewerContext,
+    ) -> Result<String, SlateReason> {
+        let request = self.build_request(duration, session_id, viewer);
+        let answers = join_all((0..self.bidders.len()).map(|idx| self.ask(idx, &request))).await;
+        let answered = answers.iter().any(Option::is_some);
+
+        let mut offers: Vec<Offer> = answers.into_iter().flatten().flatten().collect();
+        // Highest price first; the sort is stable, so ties go to the earlier bidder
+        offers.sort_by(|a, b| {
+            b.bid
+                .price
+                .partial_cmp(&a.bid.price)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let (offers, below_floor): (Vec<_>, Vec<_>) = offers
+            .into_iter()
+            .partition(|offer| offer.bid.price >= self.bid_floor);
+        let mut offers = offers.into_iter();
+
+        let Some(winner) = offers.next() else {
+            for offer in &below_floor {
+                self.notify_loss(&request, offer, self.bid_floor, LOSS_BELOW_FLOOR);
+            }
+            metrics::record_openrtb_auction(if answered { "nobid" } else { "error" });
+            warn!(
+                "OpenRtbAdProvider: No bid won the break for session {}",
+                session_id
+            );
+            return Err(if answered {
+                SlateReason::NoFill
+            } else {
+                SlateReason::Error
+            });
+        };
+
+        // First price: the winner pays its bid
+        let price = winner.bid.price;
+        info!(
+            "OpenRtbAdProvider: Bid {} of bidder {} won the break for session {} at {} {}",
+            winner.bid.id, self.bidders[winner.bidder].name, session_id, price, self.currency
+        );
+        metrics::record_openrtb_auction("won");
+        for offer in offers {
+            self.notify_loss(&request, &offer, price, LOSS_HIGHER_BID);
+        }
+        for offer in &below_floor {
+            self.notify_loss(&request, offer, price, LOSS_BELOW_FLOOR);
+        }
+
+        let macros = self.auction_macros(&request, &winner, price, None);
+        Ok(match (&winner.bid.adm, &winner.bid.nurl) {
+            (Some(adm), nurl) => {
+                if let Some(nurl) = nurl {
+                    tracking::fire_win_notice(self.http_client.clone(), nurl, &macros);
+                }
+                macros.expand(adm)
+            }
+            // Fetching the nurl is the win notice
+            (None, Some(nurl)) => nurl_wrapper(&winner.bid.id, &macros.expand(nurl)),
+            (None, None) => unreachable!("bids without adm or nurl are not offers"),
+        })
+    }
+
+    /// SSAI decision: the winning pod, completed with slate when configured
+    async fn decide_segments(
+        &self,
+        duration: f32,
+        break_id: &str,
+        session_id: &str,
+        viewer: &ViewerContext,
+    ) -> Vec<AdSegment> {
+        let policy = self.policy_state(session_id, viewer).await;
+        let decided = match self.auction(duration, session_id, viewer).await {
+            Ok(markup) => self
+                .vast
+                .segments_from_markup(&markup, duration, break_id, session_id, viewer, &policy)
+                .await
+                .ok_or(SlateReason::Error),
+            Err(reason) => Err(reason),
+        };
+        let (segments, reason) = match decided {
+            Ok(segments) => (segments, SlateReason::NoFill),
+            Err(reason) => (Vec::new(), reason),
+        };
+        let reason = match reason {
+            SlateReason::NoFill if policy.has_rejections() => SlateReason::Policy,
+            reason => reason,
+        };
+        if let Some(policy) = &self.policy {
+            let ads = segments.iter().filter_map(|s| s.tracking.as_ref());
+            policy.record(session_id, viewer, ads.map(|t| &t.ad)).await;
+        }
+        let segments = match &self.slate {
+            Some(slate) => slate.complete_break(
+                segments,
+                duration,
+                self.pod_constraints.overrun_tolerance,
+                reason,
+                session_id,
+            ),
+            None => segments,
+        };
+        self.vast
+            .publish_break(session_id, break_id, segments)
+            .await
+    }
+}
+
+/// VAST wrapper around a win notice URL that returns the bid's markup
+fn nurl_wrapper(bid_id: &str, nurl: &str) -> String {
+    format!(
+        r#"<VAST version="4.2"><Ad id="{}"><Wrapper allowMultipleAds="true"><AdSystem>OpenRTB</AdSystem><VASTAdTagURI><![CDATA[{}]]></VASTAdTagURI></Wrapper></Ad></VAST>"#,
+        quick_xml::escape::escape(bid_id),
+        nurl.replace("]]>", "]]]]><![CDATA[>")
+    )
+}
+
+impl std::fmt::Debug for OpenRtbAdProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OpenRtbAdProvider")
+            .field(
+                "bidders",
+                &self.bidders.iter().map(|b| &b.name).collect::<Vec<_>>(),
+            )
+            .field("tmax", &self.tmax)
+            .field("bid_floor", &self.bid_floor)
+            .field("currency", &self.currency)
+            .field("has_slate", &self.slate.is_some())
+            .finish()
+    }
+}
+
+impl AdProvider for OpenRtbAdProvider {
+    fn get_ad_segments<'a>(
+        &'a self,
+        duration: f32,
+        break_id: &'a str,
+        session_id: &'a str,
+        viewer: &'a ViewerContext,
+    ) -> BoxFuture<'a, Vec<AdSegment>> {
+        Box::pin(self.decide_segments(duration, break_id, session_id, viewer))
+    }
+
+    fn resolve_segment_url(&self, ad_name: &str) -> Option<String> {
+        if slate::is_slate_segment(ad_name) {
+            return self.slate.as_ref()?.resolve_segment_url(ad_name);
+        }
+        self.vast.resolve_segment_url(ad_name)
+    }
+
+    fn resolve_segment_with_tracking(
+        &self,
+        ad_name: &str,
+        session_id: &str,
+    ) -> Option<ResolvedSegment> {
+        if slate::is_slate_segment(ad_name) {
+            return self
+                .slate
+                .as_ref()?
+                .resolve_segment_url(ad_name)
+                .map(|url| ResolvedSegment {
+                    url,
+                    tracking: None,
+                });
+        }
+        self.vast.resolve_segment_with_tracking(ad_name, session_id)
+    }
+
+    fn get_ad_creatives<'a>(
+        &'a self,
+        duration: f32,
+        _break_id: &'a str,
+        session_id: &'a str,
+        viewer: &'a ViewerContext,
+    ) -> BoxFuture<'a, Vec<AdCreative>> {
+        Box::pin(async move {
+            let policy = self.policy_state(session_id, viewer).await;
+            let Ok(markup) = self.auction(duration, session_id, viewer).await else {
+                return Vec::new();
+            };
+            let creatives = self
+                .vast
+                .creatives_from_markup(&markup, duration, session_id, viewer, &policy)
+                .await
+                .unwrap_or_default();
+            if let Some(policy) = &self.policy {
+                let ads = creatives.iter().filter_map(|c| c.ad.as_ref());
+                policy.record(session_id, viewer, ads).await;
+            }
+            creatives
+        })
+    }
+
+    fn slate(&self) -> Option<&SlateProvider> {
+        self.slate.as_ref()
+    }
+
+    fn cleanup_cache(&self) {
+        self.vast.cleanup_cache();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Stand-in exchange: serves one (status, body) per path and records
+    /// every request's path and query. `REQUEST_ID` in a body is replaced
+    /// with the id of the bid request it answers.
+    async fn mock_exchange(routes: Vec<(&str, u16, String)>) -> (String, Arc<Mutex<Vec<String>>>) {
+        use axum::http::Uri;
+
+        let routes: Arc<std::collections::HashMap<String, (u16, String)>> = Arc::new(
+            routes
+                .into_iter()
+                .map(|(path, status, body)| (path.to_string(), (status, body)))
+                .collect(),
+        );
+        let hits = Arc::new(Mutex::new(Vec::new()));
+        let recorded = hits.clone();
+        let app = axum::Router::new().fallback(move |uri: Uri, body: String| {
+            let routes = routes.clone();
+            let hits = recorded.clone();
+            async move {
+                hits.lock().unwrap().push(uri.to_string());
+                let request_id = serde_json::from_str::<serde_json::Value>(&body)
+                    .ok()
+                    .and_then(|req| req["id"].as_str().map(str::to_string))
+                    .unwrap_or_default();
+                match routes.get(uri.path()) {
+                    Some((status, body)) => (
+                        StatusCode::from_u16(*status).unwrap(),
+                        body.replace("REQUEST_ID", &request_id),
+                    ),
+                    None => (StatusCode::NOT_FOUND, String::new()),
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (base, hits)
+    }
+
+    fn vast_with_ad(id: &str, seconds: u32) -> String {
+        format!(
+            r#"<VAST version="4.2"><Ad id="{id}"><InLine><AdSystem>S</AdSystem><AdTitle>{id}</AdTitle>
+<Creatives><Creative id="c-{id}"><Linear><Duration>00:00:{seconds:02}</Duration>
+<MediaFiles><MediaFile delivery="progressive" type="video/mp4" width="1280" height="720">https://ads.example.com/{id}.mp4</MediaFile></MediaFiles>
+</Linear></Creative></Creatives></InLine></Ad></VAST>"#
+        )
+    }
+
+    fn bid_response(bids: serde_json::Value) -> String {
+        serde_json::json!({
+            "id": "REQUEST_ID",
+            "cur": "USD",
+            "seatbid": [{"seat": "dsp", "bid": bids}]
+        })
+        .to_string()
+    }
+
+    fn bidder(name: &str, url: String) -> OpenRtbBidder {
+        OpenRtbBidder {
+            name: name.to_string(),
+            url,
+        }
+    }
+
+    fn ad_ids(segments: &[AdSegment]) -> Vec<String> {
+        segments
+            .iter()
+            .filter_map(|s| s.tracking.as_ref())
+            .map(|t| t.ad.ad_id.clone())
+            .collect()
+    }
+
+    /// Wait for fire-and-forget notices to reach the mock exchange
+    async fn notices(hits: &Mutex<Vec<String>>, prefix: &str, expected: usize) -> Vec<String> {
+        for _ in 0..50 {
+            let found: Vec<String> = hits
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|hit| hit.starts_with(prefix))
+                .cloned()
+                .collect();
+            if found.len() >= expected {
+                return found;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        Vec::new()
+    }
+
+    #[test]
+    fn test_bid_request_describes_pod_and_viewer() {
+        let provider = OpenRtbAdProvider::new(Vec::new(), Client::new())
+            .with_tmax(Duration::from_millis(300))
+            .with_bid_floor(2.5, "EUR".to_string())
+            .with_site_domain("stream.example.com".to_string())
+            .with_pod_constraints(PodConstraints {
+                max_ads: 4,
+                ..Default::default()
+            });
+        let viewer = ViewerContext {
+            client_ip: Some("203.0.113.7".to_string()),
+            user_agent: Some("TestPlayer/1.0".to_string()),
+            accept_language: Some("sv-SE,en;q=0.8".to_string()),
+            ad_params: BTreeMap::from([
+                ("ads.ifa".to_string(), "ifa-1".to_string()),
+                ("ads.gdpr".to_string(), "1".to_string()),
+                ("ads.gdpr_consent".to_string(), "CONSENT".to_string()),
+            ]),
+        };
+
+        let request = serde_json::to_value(provider.build_request(30.4, "s", &viewer)).unwrap();
+        assert_eq!(request["at"], 1);
+        assert_eq!(request["tmax"], 300);
+        assert_eq!(request["cur"][0], "EUR");
+        let imp = &request["imp"][0];
+        assert_eq!(imp["bidfloor"], 2.5);
+        assert_eq!(imp["video"]["poddur"], 30);
+        assert_eq!(imp["video"]["maxseq"], 4);
+        assert_eq!(imp["video"]["plcmt"], 1);
+        assert_eq!(request["site"]["domain"], "stream.example.com");
+        assert_eq!(request["device"]["ip"], "203.0.113.7");
+        assert!(request["device"].get("ipv6").is_none());
+        assert_eq!(request["device"]["ua"], "TestPlayer/1.0");
+        assert_eq!(request["device"]["language"], "sv");
+        assert_eq!(request["device"]["ifa"], "ifa-1");
+        assert_eq!(request["regs"]["gdpr"], 1);
+        assert_eq!(request["user"]["consent"], "CONSENT");
+    }
+
+    #[tokio::test]
+    async fn test_highest_bid_wins_with_win_and_loss_notices() {
+        let (base, hits) = mock_exchange(vec![]).await;
+        let (bidders, _) = mock_exchange(vec![
+            (
+                "/a",
+                200,
+                bid_response(serde_json::json!([{
+                    "id": "bid-a", "impid": "1", "price": 5.0,
+                    "adm": vast_with_ad("low", 30),
+                    "lurl": format!("{base}/loss/a?reason=${{AUCTION_LOSS}}&price=${{AUCTION_PRICE}}")
+                }])),
+            ),
+            (
+                "/b",
+                200,
+                bid_response(serde_json::json!([{
+                    "id": "bid-b", "impid": "1", "price": 8.0,
+                    "adm": vast_with_ad("high", 30),
+                    "nurl": format!("{base}/win/b?price=${{AUCTION_PRICE}}&bid=${{AUCTION_BID_ID}}")
+                }])),
+            ),
+            ("/c", 204, String::new()),
+        ])
+        .await;
+        let provider = OpenRtbAdProvider::new(
+            vec![
+                bidder("a", format!("{bidders}/a")),
+                bidder("b", format!("{bidders}/b")),
+                bidder("c", format!("{bidders}/c")),
+            ],
+            Client::new(),
+        );
+
+        let segments = provider
+            .get_ad_segments(30.0, "1", "s", &ViewerContext::default())
+            .await;
+        assert_eq!(ad_ids(&segments), vec!["high"]);
+        assert_eq!(
+            notices(&hits, "/win/", 1).await,
+            vec!["/win/b?price=8&bid=bid-b"]
+        );
+        assert_eq!(
+            notices(&hits, "/loss/", 1).await,
+            vec!["/loss/a?reason=102&price=8"]
+        );
+
+        // Segments resolve through the VAST pipeline's cache
+        let resolved = provider
+            .resolve_segment_with_tracking(&segments[0].uri, "s")
+            .unwrap();
+        assert_eq!(resolved.url, "https://ads.example.com/high.mp4");
+    }
+
+    #[tokio::test]
+    async fn test_nurl_returns_markup_when_bid_has_no_adm() {
+        let (base, hits) = mock_exchange(vec![("/markup", 200, vast_with_ad("served", 15))]).await;
+        let (bidders, _) = mock_exchange(vec![(
+            "/bid",
+            200,
+            bid_response(serde_json::json!([{
+                "id": "bid-1", "impid": "1", "price": 3.0,
+                "nurl": format!("{base}/markup?price=${{AUCTION_PRICE}}")
+            }])),
+        )])
+        .await;
+        let provider =
+            OpenRtbAdProvider::new(vec![bidder("dsp", format!("{bidders}/bid"))], Client::new());
+
+        let segments = provider
+            .get_ad_segments(15.0, "1", "s", &ViewerContext::default())
+            .await;
+        assert_eq!(ad_ids(&segments), vec!["served"]);
+        assert_eq!(*hits.lock().unwrap(), vec!["/markup?price=3"]);
+    }
+
+    #[tokio::test]
+    async fn test_bids_below_floor_lose_and_slate_fills_break() {
+        let (base, hits) = mock_exchange(vec![]).await;
+        let (bidders, _) = mock_exchange(vec![
+            (
+                "/cheap",
+                200,
+                bid_response(serde_json::json!([{
+                    "id": "bid-1", "impid": "1", "price": 0.5,
+                    "adm": vast_with_ad("cheap", 5),
+                    "lurl": format!("{base}/loss?reason=${{AUCTION_LOSS}}")
+                }])),
+            ),
+            ("/empty", 204, String::new()),
+        ])
+        .await;
+        let provider = OpenRtbAdProvider::new(
+            vec![
+                bidder("cheap", format!("{bidders}/cheap")),
+                bidder("empty", format!("{bidders}/empty")),
+                bidder("broken", format!("{bidders}/missing")),
+            ],
+            Client::new(),
+        )
+        .with_bid_floor(1.0, "USD".to_string())
+        .with_slate(SlateProvider::new(
+            "https://slate.example.com".to_string(),
+            1.0,
+        ));
+
+        let segments = provider
+            .get_ad_segments(5.0, "1", "s", &ViewerContext::default())
+            .await;
+        assert_eq!(segments.len(), 5);
+        assert!(segments.iter().all(|s| s.uri.starts_with("slate-seg-")));
+        assert_eq!(notices(&hits, "/loss", 1).await, vec!["/loss?reason=100"]);
+    }
+}
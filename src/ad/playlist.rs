@@ -0,0 +1,233 @@
+use crate::error::{Result, RitcherError};
+use m3u8_rs::Playlist;
+use reqwest::Client;
+use tracing::info;
+use url::Url;
+
+/// One segment of an ad media playlist
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaylistSegment {
+    /// Absolute segment URL
+    pub url: String,
+    /// Segment duration in seconds (EXTINF)
+    pub duration: f32,
+}
+
+/// An ad creative loaded from a real HLS media playlist
+///
+/// Keeps the playlist's own segment URIs and durations, and its
+/// initialization segment (EXT-X-MAP) for fMP4 creatives.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdPlaylist {
+    /// Where the playlist was loaded from
+    pub source: String,
+    /// Absolute URL of the initialization segment of fMP4 creatives
+    pub init: Option<String>,
+    pub segments: Vec<PlaylistSegment>,
+}
+
+impl AdPlaylist {
+    /// Load a media playlist from a URL or a local file
+    ///
+    /// Segment URIs of a playlist fetched over HTTP are resolved against
+    /// its URL. A local playlist must reference its segments (and init
+    /// segment) by absolute URL, since they are fetched by URL when served.
+    pub async fn load(source: &str, http_client: &Client) -> Result<Self> {
+        let content = if source.starts_with("http://") || source.starts_with("https://") {
+            http_client
+                .get(source)
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?
+                .to_vec()
+        } else {
+            let path = source.strip_prefix("file://").unwrap_or(source);
+            tokio::fs::read(path).await.map_err(|e| {
+                RitcherError::ConfigError(format!("Cannot read ad playlist {}: {}", source, e))
+            })?
+        };
+
+        let playlist = Self::parse(source, &content)?;
+        info!(
+            "Loaded ad playlist {}: {} segment(s), {:.1}s{}",
+            source,
+            playlist.segments.len(),
+            playlist.duration(),
+            if playlist.init.is_some() {
+                ", fMP4"
+            } else {
+                ""
+            }
+        );
+        Ok(playlist)
+    }
+
+    /// Parse a media playlist loaded from `source`
+    pub fn parse(source: &str, content: &[u8]) -> Result<Self> {
+        let playlist = match m3u8_rs::parse_playlist_res(content) {
+            Ok(Playlist::MediaPlaylist(playlist)) => playlist,
+            Ok(Playlist::MasterPlaylist(_)) => {
+                return Err(RitcherError::PlaylistParseError(format!(
+                    "{} is a master playlist, expected a media playlist",
+                    source
+                )));
+            }
+            Err(e) => {
+                return Err(RitcherError::PlaylistParseError(format!(
+                    "{}: {:?}",
+                    source, e
+                )));
+            }
+        };
+
+        let base = Url::parse(source).ok().filter(|u| u.scheme() != "file");
+        let resolve = |uri: &str| -> Result<String> {
+            if let Ok(url) = Url::parse(uri) {
+                return Ok(url.to_string());
+            }
+            match &base {
+                Some(base) => base.join(uri).map(String::from).map_err(|e| {
+                    RitcherError::PlaylistParseError(format!("{}: bad URI {}: {}", source, uri, e))
+                }),
+                None => Err(RitcherError::PlaylistParseError(format!(
+                    "{}: relative URI {} needs an absolute URL in a local playlist",
+                    source, uri
+                ))),
+            }
+        };
+
+        let mut init = None;
+        let mut segments = Vec::new();
+        for segment in &playlist.segments {
+            if segment.byte_range.is_some() {
+                return Err(RitcherError::PlaylistParseError(format!(
+                    "{}: byte-range segments are not supported",
+                    source
+                )));
+            }
+            if let Some(map) = &segment.map {
+                let map_url = resolve(&map.uri)?;
+                if map.byte_range.is_some() || init.as_ref().is_some_and(|i| *i != map_url) {
+                    return Err(RitcherError::PlaylistParseError(format!(
+                        "{}: only a single whole-file EXT-X-MAP is supported",
+                        source
+                    )));
+                }
+                init = Some(map_url);
+            }
+            segments.push(PlaylistSegment {
+                url: resolve(&segment.uri)?,
+                duration: segment.duration,
+            });
+        }
+
+        if segments.is_empty() {
+            return Err(RitcherError::PlaylistParseError(format!(
+                "{} has no segments",
+                source
+            )));
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            init,
+            segments,
+        })
+    }
+
+    /// `count` numbered `out_NNN.ts` segments of `segment_duration` seconds
+    /// under `base_url`, the layout of the default test streams
+    pub fn numbered(base_url: &str, segment_duration: f32, count: usize) -> Self {
+        Self {
+            source: base_url.to_string(),
+            init: None,
+            segments: (0..count)
+                .map(|i| PlaylistSegment {
+                    url: format!("{}/out_{:03}.ts", base_url, i),
+                    duration: segment_duration,
+                })
+                .collect(),
+        }
+    }
+
+    /// Total duration in seconds
+    pub fn duration(&self) -> f32 {
+        self.segments.iter().map(|s| s.duration).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_resolves_segments_against_playlist_url() {
+        let playlist = AdPlaylist::parse(
+            "https://ads.example.com/spot/index.m3u8",
+            b"#EXTM3U
+#EXT-X-VERSION:7
+#EXT-X-TARGETDURATION:4
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXTINF:4.0,
+seg-0.m4s
+#EXTINF:3.5,
+https://cdn.example.com/seg-1.m4s
+#EXT-X-ENDLIST
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            playlist.init.as_deref(),
+            Some("https://ads.example.com/spot/init.mp4")
+        );
+        assert_eq!(
+            playlist.segments,
+            vec![
+                PlaylistSegment {
+                    url: "https://ads.example.com/spot/seg-0.m4s".into(),
+                    duration: 4.0,
+                },
+                PlaylistSegment {
+                    url: "https://cdn.example.com/seg-1.m4s".into(),
+                    duration: 3.5,
+                },
+            ]
+        );
+        assert_eq!(playlist.duration(), 7.5);
+    }
+
+    #[test]
+    fn test_parse_rejects_unusable_playlists() {
+        let relative = b"#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXTINF:4.0,\nseg-0.ts\n";
+        assert!(AdPlaylist::parse("/ads/spot.m3u8", relative).is_err());
+
+        let master = b"#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=1000000\nlow.m3u8\n";
+        assert!(AdPlaylist::parse("https://ads.example.com/master.m3u8", master).is_err());
+
+        let empty = b"#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-ENDLIST\n";
+        assert!(AdPlaylist::parse("https://ads.example.com/empty.m3u8", empty).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_load_local_playlist() {
+        let path = std::env::temp_dir().join(format!("ritcher-ad-{}.m3u8", std::process::id()));
+        tokio::fs::write(
+            &path,
+            "#EXTM3U\n#EXT-X-TARGETDURATION:6\n#EXTINF:6.0,\nhttps://ads.example.com/a-0.ts\n#EXTINF:4.0,\nhttps://ads.example.com/a-1.ts\n#EXT-X-ENDLIST\n",
+        )
+        .await
+        .unwrap();
+
+        let playlist = AdPlaylist::load(path.to_str().unwrap(), &Client::new())
+            .await
+            .unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(playlist.init, None);
+        assert_eq!(playlist.segments.len(), 2);
+        assert_eq!(playlist.duration(), 10.0);
+    }
+}
@@ -0,0 +1,298 @@
+//! Duration-optimal ad pod selection
+//!
+//! Ad servers often return more ads than fit a break, or ads whose durations
+//! do not add up to it. `build_pod` picks the subset that fills the break
+//! best — a bounded knapsack over 100 ms steps — and orders it for playback.
+//! It is independent of where the ads came from, so any `AdProvider` can
+//! use it.
+
+use std::cmp::Ordering;
+use tracing::debug;
+
+/// Ad durations are compared in 100 ms steps
+const STEPS_PER_SEC: f32 = 10.0;
+
+/// Only this many candidates (by preference) are considered per break
+const MAX_CANDIDATES: usize = 32;
+
+/// Limits applied when building a pod
+#[derive(Debug, Clone, PartialEq)]
+pub struct PodConstraints {
+    /// Maximum number of ads in one break
+    pub max_ads: usize,
+    /// Ads shorter than this (seconds) are never selected
+    pub min_ad_duration: f32,
+    /// How far (seconds) the pod may run past the break
+    pub overrun_tolerance: f32,
+}
+
+impl Default for PodConstraints {
+    fn default() -> Self {
+        Self {
+            max_ads: 10,
+            min_ad_duration: 0.0,
+            overrun_tolerance: 0.5,
+        }
+    }
+}
+
+/// An ad that may be placed in a pod
+#[derive(Debug, Clone)]
+pub struct PodCandidate<T> {
+    pub ad: T,
+    /// Ad duration in seconds
+    pub duration: f32,
+    /// VAST `Ad@sequence`; sequenced ads are preferred and play first, in order
+    pub sequence: Option<u32>,
+    /// Higher is preferred when fills are equal (e.g. price)
+    pub priority: f64,
+}
+
+/// The ads chosen for a break, in play order
+#[derive(Debug, Clone)]
+pub struct Pod<T> {
+    pub ads: Vec<T>,
+    /// Total duration of the chosen ads in seconds
+    pub duration: f32,
+    /// Part of the break (seconds) the ads leave unfilled, for slate
+    pub gap: f32,
+}
+
+/// A partial selection in the knapsack table
+#[derive(Debug, Clone)]
+struct Selection {
+    sequenced: usize,
+    priority: f64,
+    picks: Vec<usize>,
+}
+
+impl Selection {
+    /// Preference between selections of equal fill and size
+    fn cmp_preference(&self, other: &Self) -> Ordering {
+        self.sequenced
+            .cmp(&other.sequenced)
+            .then(
+                self.priority
+                    .partial_cmp(&other.priority)
+                    .unwrap_or(Ordering::Equal),
+            )
+            // Earlier ads in the response win remaining ties
+            .then_with(|| other.picks.cmp(&self.picks))
+    }
+}
+
+fn to_steps(seconds: f32) -> usize {
+    (seconds.max(0.0) * STEPS_PER_SEC).round() as usize
+}
+
+/// Pick and order the candidates that best fill `break_duration`
+///
+/// Selection maximises fill (closest to the break, overrunning by at most
+/// the tolerance), then the number of sequenced ads, then total priority,
+/// then prefers fewer ads. Sequenced ads play first in sequence order,
+/// followed by the rest by priority.
+pub fn build_pod<T>(
+    candidates: Vec<PodCandidate<T>>,
+    break_duration: f32,
+    constraints: &PodConstraints,
+) -> Pod<T> {
+    let target = to_steps(break_duration);
+    let capacity = to_steps(break_duration + constraints.overrun_tolerance);
+
+    let mut eligible: Vec<(usize, usize)> = candidates
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.duration > 0.0 && c.duration >= constraints.min_ad_duration)
+        .map(|(i, c)| (i, to_steps(c.duration)))
+        .filter(|(_, steps)| *steps > 0 && *steps <= capacity)
+        .collect();
+
+    if eligible.len() > MAX_CANDIDATES {
+        eligible.sort_by(|(a, _), (b, _)| {
+            let (a, b) = (&candidates[*a], &candidates[*b]);
+            b.sequence.is_some().cmp(&a.sequence.is_some()).then(
+                b.priority
+                    .partial_cmp(&a.priority)
+                    .unwrap_or(Ordering::Equal),
+            )
+        });
+        eligible.truncate(MAX_CANDIDATES);
+        eligible.sort_unstable();
+    }
+
+    // best[k][u]: preferred selection of exactly k ads filling u steps
+    let max_ads = constraints.max_ads.min(eligible.len());
+    let mut best: Vec<Vec<Option<Selection>>> = vec![vec![None; capacity + 1]; max_ads + 1];
+    best[0][0] = Some(Selection {
+        sequenced: 0,
+        priority: 0.0,
+        picks: Vec::new(),
+    });
+
+    for &(idx, steps) in &eligible {
+        let candidate = &candidates[idx];
+        for k in (0..max_ads).rev() {
+            for u in (0..=capacity - steps).rev() {
+                let Some(current) = &best[k][u] else {
+                    continue;
+                };
+                let mut picks = current.picks.clone();
+                picks.push(idx);
+                let extended = Selection {
+                    sequenced: current.sequenced + usize::from(candidate.sequence.is_some()),
+                    priority: current.priority + candidate.priority,
+                    picks,
+                };
+                let slot = &mut best[k + 1][u + steps];
+                if slot
+                    .as_ref()
+                    .is_none_or(|s| extended.cmp_preference(s) == Ordering::Greater)
+                {
+                    *slot = Some(extended);
+                }
+            }
+        }
+    }
+
+    let chosen = best
+        .iter()
+        .enumerate()
+        .flat_map(|(k, row)| {
+            row.iter()
+                .enumerate()
+                .filter_map(move |(u, s)| s.as_ref().map(|s| (k, u, s)))
+        })
+        .max_by(|(ka, ua, a), (kb, ub, b)| {
+            // Closest fill first, then preference, then fewer ads
+            ub.abs_diff(target)
+                .cmp(&ua.abs_diff(target))
+                .then_with(|| a.cmp_preference(b))
+                .then(kb.cmp(ka))
+        })
+        .map(|(_, _, s)| s.picks.clone())
+        .unwrap_or_default();
+
+    let mut order = chosen;
+    order.sort_by(|&a, &b| {
+        let (ca, cb) = (&candidates[a], &candidates[b]);
+        match (ca.sequence, cb.sequence) {
+            (Some(sa), Some(sb)) => sa.cmp(&sb).then(a.cmp(&b)),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => cb
+                .priority
+                .partial_cmp(&ca.priority)
+                .unwrap_or(Ordering::Equal)
+                .then(a.cmp(&b)),
+        }
+    });
+
+    let duration: f32 = order.iter().map(|&i| candidates[i].duration).sum();
+    let gap = (break_duration - duration).max(0.0);
+    debug!(
+        "Pod: {} of {} candidate(s), {:.1}s of {:.1}s break (gap {:.1}s)",
+        order.len(),
+        candidates.len(),
+        duration,
+        break_duration,
+        gap
+    );
+
+    let mut slots: Vec<Option<T>> = candidates.into_iter().map(|c| Some(c.ad)).collect();
+    let ads = order.iter().filter_map(|&i| slots[i].take()).collect();
+
+    Pod { ads, duration, gap }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(id: &'static str, duration: f32) -> PodCandidate<&'static str> {
+        PodCandidate {
+            ad: id,
+            duration,
+            sequence: None,
+            priority: 0.0,
+        }
+    }
+
+    fn sequenced(id: &'static str, duration: f32, sequence: u32) -> PodCandidate<&'static str> {
+        PodCandidate {
+            sequence: Some(sequence),
+            ..candidate(id, duration)
+        }
+    }
+
+    #[test]
+    fn test_picks_subset_that_fills_break() {
+        // Response order would give 20 + 15 = 35s (overrun); 20 + 10 fits exactly
+        let candidates = vec![
+            candidate("a", 20.0),
+            candidate("b", 15.0),
+            candidate("c", 10.0),
+        ];
+        let pod = build_pod(candidates, 30.0, &PodConstraints::default());
+        assert_eq!(pod.ads, vec!["a", "c"]);
+        assert_eq!(pod.gap, 0.0);
+    }
+
+    #[test]
+    fn test_sequence_order_and_preference() {
+        let candidates = vec![
+            candidate("buffet", 15.0),
+            sequenced("second", 15.0, 2),
+            sequenced("first", 15.0, 1),
+        ];
+        let pod = build_pod(candidates, 30.0, &PodConstraints::default());
+        assert_eq!(pod.ads, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn test_priority_breaks_ties() {
+        let mut cheap = candidate("cheap", 30.0);
+        cheap.priority = 2.0;
+        let mut premium = candidate("premium", 30.0);
+        premium.priority = 15.0;
+        let pod = build_pod(vec![cheap, premium], 30.0, &PodConstraints::default());
+        assert_eq!(pod.ads, vec!["premium"]);
+    }
+
+    #[test]
+    fn test_max_ads_and_min_duration() {
+        let constraints = PodConstraints {
+            max_ads: 2,
+            min_ad_duration: 5.0,
+            ..Default::default()
+        };
+        let candidates = vec![
+            candidate("bumper", 2.0),
+            candidate("a", 10.0),
+            candidate("b", 10.0),
+            candidate("c", 10.0),
+        ];
+        let pod = build_pod(candidates, 30.0, &constraints);
+        assert_eq!(pod.ads, vec!["a", "b"]);
+        assert_eq!(pod.gap, 10.0);
+    }
+
+    #[test]
+    fn test_nothing_fits_leaves_whole_gap() {
+        let pod = build_pod(
+            vec![candidate("long", 60.0)],
+            30.0,
+            &PodConstraints::default(),
+        );
+        assert!(pod.ads.is_empty());
+        assert_eq!(pod.gap, 30.0);
+    }
+
+    #[test]
+    fn test_odd_durations_within_tolerance() {
+        // 15.2 + 15.2 = 30.4s overruns by less than the 0.5s tolerance
+        let candidates = vec![candidate("a", 15.2), candidate("b", 15.2)];
+        let pod = build_pod(candidates, 30.0, &PodConstraints::default());
+        assert_eq!(pod.ads.len(), 2);
+        assert_eq!(pod.gap, 0.0);
+    }
+}
@@ -0,0 +1,483 @@
+//! Frequency capping and competitive separation
+//!
+//! Every decided ad is described by policy keys: one for its creative
+//! (the `UniversalAdId`, else the creative id), one for its advertiser and
+//! one per IAB category. Before a pod is built, candidates whose creative
+//! hit a frequency cap, or whose keys appeared in the session's previous
+//! breaks, are dropped, and of the candidates sharing a key only the
+//! preferred one is kept. Counts and recent pods live in the session
+//! store, so the rules hold across stitcher instances.
+//!
+//! The policy state also carries the session's content profile, against
+//! which creatives are conditioned (see [`crate::ad::conditioning`]).
+
+use crate::ad::conditioning::ConditioningAction;
+use crate::ad::pod::PodCandidate;
+use crate::ad::provider::AdMetadata;
+use crate::metrics;
+use crate::session::{ContentProfile, SessionManager, ViewerContext};
+use dashmap::DashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::time::Duration;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tracing::debug;
+
+/// Frequency caps and separation rules
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyRules {
+    /// Times one creative may be decided per session (0: unlimited)
+    pub session_cap: u32,
+    /// Times one creative may be decided per device or household within
+    /// `device_window` (0: unlimited)
+    pub device_cap: u32,
+    pub device_window: Duration,
+    /// Keep ads of one advertiser out of the same and adjacent pods
+    pub separate_advertisers: bool,
+    /// Keep ads sharing an IAB category out of the same and adjacent pods
+    pub separate_categories: bool,
+    /// Earlier breaks of the session whose ads are kept out of the next one
+    pub separation_breaks: usize,
+    /// What happens to creatives that do not conform to the content
+    pub conditioning: ConditioningAction,
+}
+
+impl Default for PolicyRules {
+    fn default() -> Self {
+        Self {
+            session_cap: 0,
+            device_cap: 0,
+            device_window: Duration::from_secs(24 * 60 * 60),
+            separate_advertisers: true,
+            separate_categories: true,
+            separation_breaks: 1,
+            conditioning: ConditioningAction::Warn,
+        }
+    }
+}
+
+/// Policy key of an ad's creative
+///
+/// The first `UniversalAdId` identifies a creative across ad servers; the
+/// ad server's own creative ids are the fallback.
+pub fn creative_key(ad: &AdMetadata) -> String {
+    if let Some(id) = ad
+        .universal_ad_ids
+        .iter()
+        .find(|id| !id.value.is_empty() && id.value != "unknown")
+    {
+        return format!("creative:{}:{}", id.id_registry, id.value);
+    }
+    let id = ad
+        .creative_ad_id
+        .as_deref()
+        .filter(|id| !id.is_empty())
+        .or(Some(ad.creative_id.as_str()).filter(|id| !id.is_empty()))
+        .unwrap_or(&ad.ad_id);
+    format!("creative:{}:{}", ad.ad_system, id)
+}
+
+/// Device or household id for cross-session caps: `ads.hhid`, else the IFA
+fn device_id(viewer: &ViewerContext) -> Option<&str> {
+    viewer
+        .ad_param("hhid")
+        .or_else(|| viewer.ad_param("ifa"))
+        .filter(|id| !id.is_empty())
+}
+
+/// Locks serializing the decisions of each session, by session id
+type SessionLocks = DashMap<String, Arc<Mutex<()>>>;
+
+/// A session's decision lock, held from [`AdPolicy::load`] until the
+/// decision's [`PolicyState`] is dropped
+struct SessionLock {
+    guard: Option<OwnedMutexGuard<()>>,
+    locks: Arc<SessionLocks>,
+    session_id: String,
+}
+
+impl Drop for SessionLock {
+    fn drop(&mut self) {
+        self.guard.take();
+        // Forget the lock once no other decision holds or awaits it
+        self.locks
+            .remove_if(&self.session_id, |_, lock| Arc::strong_count(lock) == 1);
+    }
+}
+
+impl std::fmt::Debug for SessionLock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionLock")
+            .field("session_id", &self.session_id)
+            .finish()
+    }
+}
+
+/// Frequency caps and separation over ad decisions, backed by the session store
+///
+/// Decisions of one session run one at a time: each loads the session's
+/// state only after the previous one recorded its pod, so breaks decided
+/// concurrently (renditions, look-ahead, several breaks of one manifest)
+/// are still separated and counted against the caps.
+#[derive(Clone)]
+pub struct AdPolicy {
+    rules: PolicyRules,
+    sessions: SessionManager,
+    locks: Arc<SessionLocks>,
+}
+
+impl AdPolicy {
+    pub fn new(rules: PolicyRules, sessions: SessionManager) -> Self {
+        Self {
+            rules,
+            sessions,
+            locks: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Wait until no other decision of the session is in progress
+    async fn lock(&self, session_id: &str) -> SessionLock {
+        let lock = self
+            .locks
+            .entry(session_id.to_string())
+            .or_default()
+            .clone();
+        SessionLock {
+            guard: Some(lock.lock_owned().await),
+            locks: self.locks.clone(),
+            session_id: session_id.to_string(),
+        }
+    }
+
+    /// Policy keys of one ad under these rules
+    fn keys(&self, ad: &AdMetadata) -> Vec<String> {
+        let mut keys = vec![creative_key(ad)];
+        if self.rules.separate_advertisers
+            && let Some(advertiser) = ad.advertiser.as_deref().filter(|a| !a.is_empty())
+        {
+            keys.push(format!("advertiser:{}", advertiser.to_lowercase()));
+        }
+        if self.rules.separate_categories {
+            keys.extend(ad.categories.iter().map(|c| format!("category:{}", c.code)));
+        }
+        keys
+    }
+
+    /// Load what a session and its device were already served
+    ///
+    /// The returned state holds the session's decision lock: record the
+    /// decided pod before dropping it.
+    pub async fn load(&self, session_id: &str, viewer: &ViewerContext) -> PolicyState {
+        let lock = self.lock(session_id).await;
+        let session = self.sessions.get(session_id).await;
+        let (history, content) = session
+            .map(|s| (s.ad_history, s.content))
+            .unwrap_or_default();
+        let mut excluded = HashMap::new();
+
+        let recent = history.recent_pods.len().min(self.rules.separation_breaks);
+        for key in history.recent_pods.iter().rev().take(recent).flatten() {
+            excluded.insert(key.clone(), "recent_break");
+        }
+        if self.rules.session_cap > 0 {
+            for (creative, count) in &history.creative_counts {
+                if *count >= self.rules.session_cap {
+                    excluded.insert(creative.clone(), "session_cap");
+                }
+            }
+        }
+        if self.rules.device_cap > 0
+            && let Some(device) = device_id(viewer)
+        {
+            for (creative, count) in self.sessions.device_ad_counts(device).await {
+                if count >= self.rules.device_cap {
+                    excluded.insert(creative, "device_cap");
+                }
+            }
+        }
+
+        PolicyState {
+            policy: Some(self.clone()),
+            excluded,
+            content,
+            rejected: AtomicBool::new(false),
+            _lock: Some(lock),
+        }
+    }
+
+    /// Record a decided pod against the caps and for separation
+    pub async fn record<'a>(
+        &self,
+        session_id: &str,
+        viewer: &ViewerContext,
+        ads: impl IntoIterator<Item = &'a AdMetadata>,
+    ) {
+        let ads: Vec<&AdMetadata> = ads.into_iter().collect();
+        if ads.is_empty() {
+            return;
+        }
+        let creatives: Vec<String> = ads.iter().map(|ad| creative_key(ad)).collect();
+        let mut pod_keys: Vec<String> = ads.iter().flat_map(|ad| self.keys(ad)).collect();
+        pod_keys.sort();
+        pod_keys.dedup();
+
+        self.sessions
+            .record_ad_pod(
+                session_id,
+                pod_keys,
+                &creatives,
+                self.rules.separation_breaks,
+            )
+            .await;
+        if self.rules.device_cap > 0
+            && let Some(device) = device_id(viewer)
+        {
+            self.sessions
+                .count_device_ads(device, &creatives, self.rules.device_window)
+                .await;
+        }
+    }
+}
+
+impl std::fmt::Debug for AdPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdPolicy")
+            .field("rules", &self.rules)
+            .finish()
+    }
+}
+
+/// A session's policy snapshot for one ad decision
+///
+/// The default state applies no policy at all.
+#[derive(Debug, Default)]
+pub struct PolicyState {
+    policy: Option<AdPolicy>,
+    /// Keys no ad of this decision may carry, with the reason
+    excluded: HashMap<String, &'static str>,
+    /// Video renditions of the session's content
+    content: ContentProfile,
+    /// Whether the decision left out any ad the policy did not allow
+    rejected: AtomicBool,
+    /// Keeps the session's other decisions waiting until this one is done
+    _lock: Option<SessionLock>,
+}
+
+impl PolicyState {
+    /// How creatives that do not conform to the content are handled
+    pub fn conditioning(&self) -> ConditioningAction {
+        self.policy
+            .as_ref()
+            .map_or(ConditioningAction::Warn, |p| p.rules.conditioning)
+    }
+
+    /// Video renditions of the session's content (empty if unknown)
+    pub fn content(&self) -> &ContentProfile {
+        &self.content
+    }
+
+    /// Note that an ad was left out of the decision by policy
+    pub fn note_rejection(&self) {
+        self.rejected.store(true, AtomicOrdering::Relaxed);
+    }
+
+    /// Whether the decision left out any ad by policy, so slate filling
+    /// its time is there because of the policy
+    pub fn has_rejections(&self) -> bool {
+        self.rejected.load(AtomicOrdering::Relaxed)
+    }
+
+    /// Drop candidates the policy does not allow in this pod
+    ///
+    /// Candidates are visited in pod preference order — sequenced ads by
+    /// sequence, then by priority — so of two conflicting candidates the
+    /// one the pod builder would prefer is kept. `ads_of` lists the ads a
+    /// candidate places (a wrapper may resolve to several).
+    pub fn apply<T>(
+        &self,
+        candidates: Vec<PodCandidate<T>>,
+        ads_of: impl Fn(&T) -> Vec<&AdMetadata>,
+    ) -> Vec<PodCandidate<T>> {
+        let Some(policy) = &self.policy else {
+            return candidates;
+        };
+
+        let mut order: Vec<usize> = (0..candidates.len()).collect();
+        order.sort_by(|&a, &b| {
+            let (ca, cb) = (&candidates[a], &candidates[b]);
+            match (ca.sequence, cb.sequence) {
+                (Some(sa), Some(sb)) => sa.cmp(&sb),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => cb
+                    .priority
+                    .partial_cmp(&ca.priority)
+                    .unwrap_or(Ordering::Equal),
+            }
+            .then(a.cmp(&b))
+        });
+
+        let mut taken = HashSet::new();
+        let mut allowed = vec![false; candidates.len()];
+        for idx in order {
+            let mut keys: Vec<String> = ads_of(&candidates[idx].ad)
+                .into_iter()
+                .flat_map(|ad| policy.keys(ad))
+                .collect();
+            keys.sort();
+            keys.dedup();
+
+            let reason = keys
+                .iter()
+                .find_map(|key| self.excluded.get(key).copied())
+                .or_else(|| {
+                    keys.iter()
+                        .any(|key| taken.contains(key))
+                        .then_some("pod_conflict")
+                });
+            match reason {
+                Some(reason) => {
+                    debug!("Ad policy: dropped candidate {} ({})", idx, reason);
+                    metrics::record_policy_exclusion(reason);
+                    self.note_rejection();
+                }
+                None => {
+                    taken.extend(keys);
+                    allowed[idx] = true;
+                }
+            }
+        }
+
+        candidates
+            .into_iter()
+            .zip(allowed)
+            .filter_map(|(candidate, allowed)| allowed.then_some(candidate))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ad::vast::{Category, UniversalAdId};
+
+    fn ad(id: &str, advertiser: &str, category: &str) -> AdMetadata {
+        AdMetadata {
+            ad_id: id.to_string(),
+            ad_system: "S".to_string(),
+            creative_id: format!("c-{}", id),
+            advertiser: Some(advertiser.to_string()),
+            categories: vec![Category {
+                authority: None,
+                code: category.to_string(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn candidate(ad: AdMetadata, priority: f64) -> PodCandidate<AdMetadata> {
+        PodCandidate {
+            ad,
+            duration: 15.0,
+            sequence: None,
+            priority,
+        }
+    }
+
+    fn ids(candidates: &[PodCandidate<AdMetadata>]) -> Vec<&str> {
+        candidates.iter().map(|c| c.ad.ad_id.as_str()).collect()
+    }
+
+    fn policy(rules: PolicyRules) -> (AdPolicy, SessionManager) {
+        let sessions = SessionManager::new_memory(Duration::from_secs(300));
+        (AdPolicy::new(rules, sessions.clone()), sessions)
+    }
+
+    #[test]
+    fn test_creative_key_prefers_universal_ad_id() {
+        let mut metadata = ad("a", "acme.com", "IAB1");
+        assert_eq!(creative_key(&metadata), "creative:S:c-a");
+        metadata.universal_ad_ids.push(UniversalAdId {
+            id_registry: "ad-id.org".to_string(),
+            value: "CNPA0484000H".to_string(),
+        });
+        assert_eq!(creative_key(&metadata), "creative:ad-id.org:CNPA0484000H");
+    }
+
+    #[tokio::test]
+    async fn test_pod_keeps_preferred_of_conflicting_ads() {
+        let (policy, _) = policy(PolicyRules::default());
+        let state = policy.load("s", &ViewerContext::default()).await;
+        let candidates = vec![
+            candidate(ad("cola-1", "cola.com", "IAB8-5"), 1.0),
+            candidate(ad("cola-2", "cola.com", "IAB8-5"), 5.0),
+            candidate(ad("soda", "soda.com", "IAB8-5"), 3.0),
+            candidate(ad("car", "cars.com", "IAB2"), 0.0),
+        ];
+        // cola-2 pays most; same advertiser and same category lose to it
+        let kept = state.apply(candidates, |ad| vec![ad]);
+        assert_eq!(ids(&kept), vec!["cola-2", "car"]);
+
+        // Without a policy every candidate stays
+        let candidates = vec![
+            candidate(ad("cola-1", "cola.com", "IAB8-5"), 1.0),
+            candidate(ad("cola-2", "cola.com", "IAB8-5"), 5.0),
+        ];
+        assert_eq!(
+            PolicyState::default()
+                .apply(candidates, |ad| vec![ad])
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_previous_break_and_caps_exclude_ads() {
+        let rules = PolicyRules {
+            session_cap: 2,
+            device_cap: 3,
+            separate_categories: false,
+            ..Default::default()
+        };
+        let (policy, sessions) = policy(rules);
+        let viewer = ViewerContext {
+            ad_params: [("ads.hhid".to_string(), "household-1".to_string())].into(),
+            ..Default::default()
+        };
+        let cola = ad("cola", "cola.com", "IAB8-5");
+        let car = ad("car", "cars.com", "IAB2");
+        let pod = || vec![candidate(cola.clone(), 0.0), candidate(car.clone(), 0.0)];
+
+        sessions
+            .get_or_create("s1".to_string(), "https://example.com".to_string())
+            .await;
+        policy.record("s1", &viewer, [&cola]).await;
+        // Back-to-back: cola played in the previous break. A state holds the
+        // session's decision lock until it is dropped.
+        let state = policy.load("s1", &viewer).await;
+        assert_eq!(ids(&state.apply(pod(), |ad| vec![ad])), vec!["car"]);
+        drop(state);
+
+        // Second play hits the session cap once cola is no longer adjacent
+        policy.record("s1", &viewer, [&cola]).await;
+        policy.record("s1", &viewer, [&car]).await;
+        let state = policy.load("s1", &viewer).await;
+        assert!(state.apply(pod(), |ad| vec![ad]).is_empty());
+        drop(state);
+
+        // The household cap holds in a new session of the same household
+        sessions
+            .get_or_create("s2".to_string(), "https://example.com".to_string())
+            .await;
+        policy.record("s2", &viewer, [&cola]).await;
+        policy.record("s2", &viewer, [&car]).await;
+        let state = policy.load("s2", &viewer).await;
+        assert!(ids(&state.apply(pod(), |ad| vec![ad])).is_empty());
+        drop(state);
+        let state = policy.load("s3", &viewer).await;
+        assert_eq!(ids(&state.apply(pod(), |ad| vec![ad])), vec!["car"]);
+        drop(state);
+    }
+}
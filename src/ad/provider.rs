@@ -1,34 +1,112 @@
-use crate::ad::vast::TrackingEvent;
+use crate::ad::macros::MacroContext;
+use crate::ad::playlist::{AdPlaylist, PlaylistSegment};
+use crate::ad::slate::SlateProvider;
+use crate::ad::vast::{
+    Category, Extension, InteractiveCreativeFile, Mezzanine, Pricing, TrackingEvent, UniversalAdId,
+    Verification,
+};
+use crate::error::Result;
+use crate::session::ViewerContext;
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
 use tracing::info;
 
+/// Rounding slack when summing segment durations to a break's duration
+const FILL_EPSILON: f32 = 0.001;
+
+/// URL-safe id of an ad break, derived from its rendition-independent key
+///
+/// Alphanumeric keys (HLS media sequence numbers) are used as is; others
+/// (DASH Period ids and times) are hashed with 64-bit FNV-1a, which is
+/// stable across processes.
+pub fn break_id(key: &str) -> String {
+    if !key.is_empty() && key.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return key.to_string();
+    }
+    let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Name of the `index`-th ad segment of a break, as served under `/ad/`
+pub fn ad_segment_name(break_id: &str, index: usize) -> String {
+    format!("break-{}-seg-{}.ts", break_id, index)
+}
+
+/// Break id and segment index of an ad segment name
+/// (see [`ad_segment_name`])
+pub fn parse_ad_segment_name(ad_name: &str) -> Option<(&str, usize)> {
+    let name = ad_name.strip_suffix(".ts").unwrap_or(ad_name);
+    let (break_id, index) = name.strip_prefix("break-")?.rsplit_once("-seg-")?;
+    Some((break_id, index.parse().ok()?))
+}
+
 /// Represents a single ad segment
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AdSegment {
     /// URI of the ad segment
     pub uri: String,
     /// Duration of the segment in seconds
     pub duration: f32,
+    /// Initialization segment (EXT-X-MAP) URL of fMP4 ad segments
+    pub init: Option<String>,
     /// Tracking metadata (only present for VAST-sourced ads)
     pub tracking: Option<AdTrackingInfo>,
 }
 
 /// Tracking metadata for a single ad creative
-#[derive(Debug, Clone, Default, PartialEq)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct AdTrackingInfo {
     /// Impression URLs to fire when this ad first starts
     pub impression_urls: Vec<String>,
     /// Quartile/progress tracking events
     pub tracking_events: Vec<TrackingEvent>,
-    /// Error URL to fire on failures
-    pub error_url: Option<String>,
+    /// Error URLs of the ad and every wrapper above it, fired on failures
+    pub error_urls: Vec<String>,
     /// Total number of segments in this ad (for quartile calculation)
     pub total_segments: usize,
     /// Index of this segment within the ad
     pub segment_index: usize,
+    /// Duration of the ad in seconds
+    pub ad_duration: f32,
+    /// The ad this segment belongs to
+    pub ad: AdMetadata,
+    /// Macro values from the ad decision, for beacon URLs
+    pub macros: MacroContext,
+}
+
+/// Identity and reporting metadata of a VAST ad
+///
+/// Carried with every decided ad so policies (separation, caps) and
+/// reporting can see what was served without re-parsing VAST.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AdMetadata {
+    /// `Ad@id`
+    pub ad_id: String,
+    /// Position in the ad pod, if the ad was part of one
+    pub sequence: Option<u32>,
+    pub ad_system: String,
+    pub ad_title: String,
+    pub advertiser: Option<String>,
+    pub categories: Vec<Category>,
+    pub pricing: Option<Pricing>,
+    /// `Creative@id`
+    pub creative_id: String,
+    /// `Creative@adId`
+    pub creative_ad_id: Option<String>,
+    pub universal_ad_ids: Vec<UniversalAdId>,
+    /// Verifications of the InLine ad and every wrapper above it
+    pub ad_verifications: Vec<Verification>,
+    /// Extensions of the InLine ad and every wrapper above it
+    pub extensions: Vec<Extension>,
+    pub mezzanine: Vec<Mezzanine>,
+    pub interactive_creative_files: Vec<InteractiveCreativeFile>,
 }
 
 /// Resolved segment with optional tracking context
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ResolvedSegment {
     /// URL to the ad segment source
     pub url: String,
@@ -41,33 +119,49 @@ pub struct ResolvedSegment {
 /// Unlike `AdSegment` (single TS segment), `AdCreative` represents a complete
 /// ad unit (HLS master/media playlist or MP4 URL) as served in the
 /// HLS Interstitials asset-list JSON (`ASSETS` array).
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AdCreative {
     /// URI of the ad creative (HLS playlist URL or MP4 URL)
     pub uri: String,
     /// Duration of the creative in seconds
     pub duration: f64,
+    /// The ad this creative belongs to (only present for VAST-sourced ads)
+    pub ad: Option<AdMetadata>,
 }
 
 /// Trait for ad content providers
 ///
 /// Implementations provide ad segments to fill ad breaks of a given duration.
 /// This abstraction allows for different ad decision strategies (static, VAST, VMAP, etc.)
+///
+/// Ad decisions are async so providers can call ad servers without blocking
+/// a runtime worker. They return boxed futures to keep the trait usable as
+/// `dyn AdProvider`; callers bound them with a deadline (see
+/// [`decide_breaks`](crate::ad::decisioning::decide_breaks)).
 pub trait AdProvider: Send + Sync {
     /// Get ad segments to fill an ad break of the given duration
     ///
     /// # Arguments
     /// * `duration` - Duration of the ad break in seconds
+    /// * `break_id` - Stable id of the break (see [`break_id`]); its segments
+    ///   are served as [`ad_segment_name`]s under it
     /// * `session_id` - Session ID for tracking and personalization
+    /// * `viewer` - What is known about the session's viewer, for targeting
     ///
     /// # Returns
     /// A vector of AdSegment structs. The total duration may be less than, equal to,
     /// or slightly greater than the requested duration.
-    fn get_ad_segments(&self, duration: f32, session_id: &str) -> Vec<AdSegment>;
+    fn get_ad_segments<'a>(
+        &'a self,
+        duration: f32,
+        break_id: &'a str,
+        session_id: &'a str,
+        viewer: &'a ViewerContext,
+    ) -> BoxFuture<'a, Vec<AdSegment>>;
 
     /// Resolve an ad segment identifier to its actual source URL
     ///
-    /// The ad handler receives ad segment identifiers (e.g. "break-0-seg-3.ts")
+    /// The ad handler receives ad segment identifiers (e.g. "break-103-seg-3.ts")
     /// and uses this method to get the actual URL to fetch the segment from.
     /// This keeps the handler decoupled from ad source implementation details.
     ///
@@ -108,6 +202,14 @@ pub trait AdProvider: Send + Sync {
     /// to enforce TTL and size limits.
     fn cleanup_cache(&self) {}
 
+    /// Slate this provider plays when ads cannot fill a break
+    ///
+    /// Also covers the start of a break published before its decision
+    /// arrived. Default: none — such breaks keep their content.
+    fn slate(&self) -> Option<&SlateProvider> {
+        None
+    }
+
     /// Get ad creatives for SGAI asset-list responses.
     ///
     /// Returns a list of `AdCreative` items that map directly to entries in the
@@ -116,33 +218,45 @@ pub trait AdProvider: Send + Sync {
     ///
     /// Default implementation adapts the SSAI segment list — one creative per
     /// segment. VAST provider overrides this to return proper creative-level URLs.
-    fn get_ad_creatives(&self, duration: f32, session_id: &str) -> Vec<AdCreative> {
-        self.get_ad_segments(duration, session_id)
-            .into_iter()
-            .map(|seg| AdCreative {
-                uri: seg.uri,
-                duration: seg.duration as f64,
-            })
-            .collect()
+    fn get_ad_creatives<'a>(
+        &'a self,
+        duration: f32,
+        break_id: &'a str,
+        session_id: &'a str,
+        viewer: &'a ViewerContext,
+    ) -> BoxFuture<'a, Vec<AdCreative>> {
+        Box::pin(async move {
+            self.get_ad_segments(duration, break_id, session_id, viewer)
+                .await
+                .into_iter()
+                .map(|seg| AdCreative {
+                    uri: seg.uri,
+                    duration: seg.duration as f64,
+                    ad: seg.tracking.map(|t| t.ad),
+                })
+                .collect()
+        })
     }
 }
 
-/// Static ad provider that returns a fixed set of ad segments
+/// Static ad provider that plays a fixed rotation of ads
 ///
-/// This is the MVP implementation that uses a configured ad source URL
-/// and segment duration to generate ad segments.
+/// Ads are real HLS media playlists (see [`AdPlaylist`]). Every break plays
+/// them in turn, starting from the first, with their own segment URIs,
+/// durations and initialization segments, until the break is filled.
+/// The rotation is the same for every break, so segment names resolve
+/// without per-session state.
 #[derive(Clone, Debug)]
 pub struct StaticAdProvider {
-    /// Base URL for ad segments
-    ad_source_url: String,
-    /// Duration of each ad segment
-    segment_duration: f32,
-    /// Number of available segments in the ad source (for cycling)
-    segment_count: usize,
+    /// Ads in rotation order
+    ads: Vec<AdPlaylist>,
 }
 
 impl StaticAdProvider {
-    /// Create a new StaticAdProvider
+    /// Create a StaticAdProvider for the default test ad stream layout
+    ///
+    /// Assumes a single ad of 10 `out_NNN.ts` segments of
+    /// `segment_duration` seconds under `ad_source_url`.
     ///
     /// # Arguments
     /// * `ad_source_url` - Base URL where ad segments are hosted
@@ -151,7 +265,7 @@ impl StaticAdProvider {
         Self::with_segment_count(ad_source_url, segment_duration, 10)
     }
 
-    /// Create a new StaticAdProvider with custom segment count
+    /// Create a StaticAdProvider with custom segment count
     ///
     /// # Arguments
     /// * `ad_source_url` - Base URL where ad segments are hosted
@@ -162,114 +276,218 @@ impl StaticAdProvider {
         segment_duration: f32,
         segment_count: usize,
     ) -> Self {
-        Self {
-            ad_source_url,
+        Self::from_playlists(vec![AdPlaylist::numbered(
+            &ad_source_url,
             segment_duration,
             segment_count,
+        )])
+    }
+
+    /// Create a StaticAdProvider rotating through loaded ad playlists
+    pub fn from_playlists(ads: Vec<AdPlaylist>) -> Self {
+        let ads = ads
+            .into_iter()
+            .filter(|ad| !ad.segments.is_empty())
+            .collect();
+        Self { ads }
+    }
+
+    /// Load the ad playlists at `sources` (URLs or local files), in rotation order
+    pub async fn load(sources: &[String], http_client: &Client) -> Result<Self> {
+        let mut ads = Vec::with_capacity(sources.len());
+        for source in sources {
+            ads.push(AdPlaylist::load(source, http_client).await?);
         }
+        Ok(Self::from_playlists(ads))
+    }
+
+    /// Segments of the rotation, repeating endlessly
+    fn rotation(&self) -> impl Iterator<Item = (&AdPlaylist, &PlaylistSegment)> {
+        self.ads
+            .iter()
+            .cycle()
+            .flat_map(|ad| ad.segments.iter().map(move |segment| (ad, segment)))
     }
 
     /// Parse segment index from ad name like "break-0-seg-3.ts" → Some(3)
     fn parse_segment_index(&self, ad_name: &str) -> Option<usize> {
-        let name = ad_name.strip_suffix(".ts").unwrap_or(ad_name);
-        let parts: Vec<&str> = name.split('-').collect();
-
-        // Expected format: ["break", "0", "seg", "3"]
-        if parts.len() >= 4 && parts[0] == "break" && parts[2] == "seg" {
-            parts[3].parse().ok()
-        } else {
-            None
-        }
+        parse_ad_segment_name(ad_name).map(|(_, index)| index)
     }
 }
 
 impl AdProvider for StaticAdProvider {
-    fn get_ad_segments(&self, duration: f32, session_id: &str) -> Vec<AdSegment> {
+    fn get_ad_segments<'a>(
+        &'a self,
+        duration: f32,
+        _break_id: &'a str,
+        session_id: &'a str,
+        _viewer: &'a ViewerContext,
+    ) -> BoxFuture<'a, Vec<AdSegment>> {
+        Box::pin(std::future::ready(self.fill_duration(duration, session_id)))
+    }
+
+    fn resolve_segment_url(&self, ad_name: &str) -> Option<String> {
+        let seg_index = self.parse_segment_index(ad_name)?;
+
+        // The break's segments are the rotation's first segments
+        self.rotation()
+            .nth(seg_index)
+            .map(|(_, segment)| segment.url.clone())
+    }
+}
+
+impl StaticAdProvider {
+    /// Take segments from the rotation until `duration` is covered
+    fn fill_duration(&self, duration: f32, session_id: &str) -> Vec<AdSegment> {
         info!(
             "StaticAdProvider: Generating ad segments for session {} with duration {}s",
             session_id, duration
         );
 
-        // Calculate how many segments we need to fill the duration
-        let num_segments = (duration / self.segment_duration).ceil() as usize;
-        let num_segments = num_segments.max(1); // At least one segment
-
-        // Generate ad segments
-        let segments: Vec<AdSegment> = (0..num_segments)
-            .map(|i| AdSegment {
-                // For MVP, all segments point to the same ad source
-                // In production, this would be different ad creatives
-                uri: format!("{}/ad-segment-{}.ts", self.ad_source_url, i),
-                duration: self.segment_duration,
+        let mut segments = Vec::new();
+        let mut filled = 0.0;
+        for (ad, segment) in self.rotation() {
+            // At least one segment, even for very short breaks
+            if !segments.is_empty() && filled >= duration - FILL_EPSILON {
+                break;
+            }
+            segments.push(AdSegment {
+                uri: segment.url.clone(),
+                duration: segment.duration,
+                init: ad.init.clone(),
                 tracking: None,
-            })
-            .collect();
+            });
+            filled += segment.duration;
+        }
 
         info!(
             "StaticAdProvider: Generated {} ad segments (total duration: {}s)",
             segments.len(),
-            segments.len() as f32 * self.segment_duration
+            filled
         );
 
         segments
     }
-
-    fn resolve_segment_url(&self, ad_name: &str) -> Option<String> {
-        let seg_index = self.parse_segment_index(ad_name)?;
-
-        // Map to ad source segment name, cycling through available segments
-        // Ad source uses naming like "out_000.ts", "out_001.ts", etc.
-        let source_index = seg_index % self.segment_count;
-        let source_segment = format!("out_{:03}.ts", source_index);
-
-        Some(format!("{}/{}", self.ad_source_url, source_segment))
-    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_static_ad_provider_exact_duration() {
+    #[tokio::test]
+    async fn test_static_ad_provider_exact_duration() {
         let provider = StaticAdProvider::new("https://ads.example.com".to_string(), 10.0);
-        let segments = provider.get_ad_segments(30.0, "test-session");
+        let segments = provider
+            .get_ad_segments(30.0, "1", "test-session", &ViewerContext::default())
+            .await;
 
         assert_eq!(segments.len(), 3);
         assert_eq!(segments[0].duration, 10.0);
-        assert_eq!(segments[0].uri, "https://ads.example.com/ad-segment-0.ts");
+        assert_eq!(segments[0].uri, "https://ads.example.com/out_000.ts");
         assert_eq!(segments[0].tracking, None);
-        assert_eq!(segments[1].uri, "https://ads.example.com/ad-segment-1.ts");
-        assert_eq!(segments[2].uri, "https://ads.example.com/ad-segment-2.ts");
+        assert_eq!(segments[1].uri, "https://ads.example.com/out_001.ts");
+        assert_eq!(segments[2].uri, "https://ads.example.com/out_002.ts");
     }
 
-    #[test]
-    fn test_static_ad_provider_partial_duration() {
+    #[tokio::test]
+    async fn test_static_ad_provider_partial_duration() {
         let provider = StaticAdProvider::new("https://ads.example.com".to_string(), 10.0);
-        let segments = provider.get_ad_segments(25.0, "test-session");
+        let segments = provider
+            .get_ad_segments(25.0, "1", "test-session", &ViewerContext::default())
+            .await;
 
         // 25 / 10 = 2.5, ceiling = 3 segments
         assert_eq!(segments.len(), 3);
     }
 
-    #[test]
-    fn test_static_ad_provider_min_one_segment() {
+    #[tokio::test]
+    async fn test_static_ad_provider_min_one_segment() {
         let provider = StaticAdProvider::new("https://ads.example.com".to_string(), 10.0);
-        let segments = provider.get_ad_segments(2.0, "test-session");
+        let segments = provider
+            .get_ad_segments(2.0, "1", "test-session", &ViewerContext::default())
+            .await;
 
         // Even for very short duration, return at least 1 segment
         assert_eq!(segments.len(), 1);
     }
 
-    #[test]
-    fn test_static_ad_provider_zero_duration() {
+    #[tokio::test]
+    async fn test_static_ad_provider_zero_duration() {
         let provider = StaticAdProvider::new("https://ads.example.com".to_string(), 10.0);
-        let segments = provider.get_ad_segments(0.0, "test-session");
+        let segments = provider
+            .get_ad_segments(0.0, "1", "test-session", &ViewerContext::default())
+            .await;
 
         // Should return at least 1 segment
         assert_eq!(segments.len(), 1);
     }
 
+    fn ad(name: &str, durations: &[f32], init: bool) -> AdPlaylist {
+        let base = format!("https://ads.example.com/{name}");
+        AdPlaylist {
+            source: format!("{base}/index.m3u8"),
+            init: init.then(|| format!("{base}/init.mp4")),
+            segments: durations
+                .iter()
+                .enumerate()
+                .map(|(i, &duration)| PlaylistSegment {
+                    url: format!("{base}/{i}.m4s"),
+                    duration,
+                })
+                .collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_static_ad_provider_rotates_playlists() {
+        let provider = StaticAdProvider::from_playlists(vec![
+            ad("a", &[6.0, 4.0], true),
+            ad("b", &[5.0, 5.0, 5.0], false),
+        ]);
+        let segments = provider
+            .get_ad_segments(30.0, "1", "test-session", &ViewerContext::default())
+            .await;
+
+        let uris: Vec<&str> = segments.iter().map(|s| s.uri.as_str()).collect();
+        assert_eq!(
+            uris,
+            [
+                "https://ads.example.com/a/0.m4s",
+                "https://ads.example.com/a/1.m4s",
+                "https://ads.example.com/b/0.m4s",
+                "https://ads.example.com/b/1.m4s",
+                "https://ads.example.com/b/2.m4s",
+                "https://ads.example.com/a/0.m4s",
+            ]
+        );
+        assert_eq!(segments.iter().map(|s| s.duration).sum::<f32>(), 31.0);
+        assert_eq!(
+            segments[0].init.as_deref(),
+            Some("https://ads.example.com/a/init.mp4")
+        );
+        assert_eq!(segments[2].init, None);
+
+        // Segment names resolve to the same rotation
+        assert_eq!(
+            provider.resolve_segment_url("break-1-seg-3.ts").as_deref(),
+            Some("https://ads.example.com/b/1.m4s")
+        );
+        assert_eq!(
+            provider.resolve_segment_url("break-0-seg-5.ts").as_deref(),
+            Some("https://ads.example.com/a/0.m4s")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_static_ad_provider_without_ads_is_empty() {
+        let provider = StaticAdProvider::from_playlists(vec![ad("a", &[], false)]);
+        let segments = provider
+            .get_ad_segments(30.0, "1", "test-session", &ViewerContext::default())
+            .await;
+        assert!(segments.is_empty());
+        assert_eq!(provider.resolve_segment_url("break-0-seg-0.ts"), None);
+    }
+
     #[test]
     fn test_parse_segment_index() {
         let provider = StaticAdProvider::new("https://ads.example.com".to_string(), 1.0);
@@ -279,6 +497,24 @@ mod tests {
         assert_eq!(provider.parse_segment_index("invalid.ts"), None);
     }
 
+    #[test]
+    fn test_ad_segment_names_round_trip() {
+        assert_eq!(break_id("103"), "103");
+        let dash = break_id("ad-break-1@30.000");
+        assert_eq!(dash.len(), 16);
+        assert!(dash.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_eq!(dash, break_id("ad-break-1@30.000"));
+        assert_ne!(dash, break_id("ad-break-2@30.000"));
+
+        let name = ad_segment_name(&dash, 4);
+        assert_eq!(parse_ad_segment_name(&name), Some((dash.as_str(), 4)));
+        assert_eq!(
+            parse_ad_segment_name("break-103-seg-15.ts"),
+            Some(("103", 15))
+        );
+        assert_eq!(parse_ad_segment_name("slate-seg-1.ts"), None);
+    }
+
     #[test]
     fn test_resolve_segment_url() {
         let provider = StaticAdProvider::with_segment_count(
@@ -1,73 +1,258 @@
+use crate::ad::playlist::AdPlaylist;
 use crate::ad::provider::{AdProvider, AdSegment};
-use tracing::info;
+use crate::error::Result;
+use crate::metrics;
+use crate::session::ViewerContext;
+use futures::future::BoxFuture;
+use reqwest::Client;
+use tracing::{info, warn};
+
+/// Prefix of the ad names of slate segments
+const SLATE_PREFIX: &str = "slate-";
+
+/// Rounding slack when fitting slate to a break's duration
+const FILL_EPSILON: f32 = 0.001;
+
+/// Why a break, or part of it, plays slate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlateReason {
+    /// The ad server had no (or not enough) ads
+    NoFill,
+    /// The ad request failed or timed out
+    Error,
+    /// Ads were left out by frequency caps, separation or conditioning
+    Policy,
+}
+
+impl SlateReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SlateReason::NoFill => "nofill",
+            SlateReason::Error => "error",
+            SlateReason::Policy => "policy",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "nofill" => Some(SlateReason::NoFill),
+            "error" => Some(SlateReason::Error),
+            "policy" => Some(SlateReason::Policy),
+            _ => None,
+        }
+    }
+}
+
+/// Whether an ad name refers to a slate segment
+pub fn is_slate_segment(ad_name: &str) -> bool {
+    ad_name.starts_with(SLATE_PREFIX)
+}
 
 /// Slate provider for fallback content during ad breaks
 ///
 /// When the primary ad provider (VAST) returns no ads or fails,
-/// the slate provider fills the remaining duration with looping
-/// filler segments from a configured slate URL.
+/// the slate provider fills the remaining duration with filler
+/// segments looped from a slate media playlist.
 ///
 /// The slate is typically a short looping video ("We'll be right back",
-/// channel branding, etc.) that cycles to fill any duration.
+/// channel branding, etc.). Slate is fit to the exact duration it fills:
+/// the final segment is the slate's shortest segment that still covers
+/// the remainder, with its EXTINF shortened to it. A different slate can
+/// be played for each [`SlateReason`].
 #[derive(Clone, Debug)]
 pub struct SlateProvider {
-    /// Base URL for slate segments (HLS source with .ts segments)
-    slate_url: String,
-    /// Duration of each slate segment in seconds
-    segment_duration: f32,
-    /// Number of available segments in the slate source (for cycling)
-    segment_count: usize,
+    /// Slate for every reason without its own
+    slate: AdPlaylist,
+    /// Slates for specific reasons
+    reason_slates: Vec<(SlateReason, AdPlaylist)>,
 }
 
 impl SlateProvider {
-    /// Create a new SlateProvider
+    /// Create a SlateProvider for the default test stream layout
+    ///
+    /// Assumes 10 `out_NNN.ts` segments of `segment_duration` seconds
+    /// under `slate_url`.
     ///
     /// # Arguments
     /// * `slate_url` - Base URL where slate segments are hosted
     /// * `segment_duration` - Duration of each slate segment in seconds
     pub fn new(slate_url: String, segment_duration: f32) -> Self {
+        Self::from_playlist(AdPlaylist::numbered(&slate_url, segment_duration, 10))
+    }
+
+    /// Create a SlateProvider looping a slate media playlist
+    pub fn from_playlist(slate: AdPlaylist) -> Self {
         Self {
-            slate_url,
-            segment_duration,
-            segment_count: 10, // Default, same as static provider
+            slate,
+            reason_slates: Vec::new(),
         }
     }
 
-    /// Generate slate segments to fill the given duration
+    /// Load the slate at `source`
     ///
-    /// Used directly by VastAdProvider when VAST returns empty or fails.
-    /// Cycles through available slate segments to fill the requested duration.
-    pub fn fill_duration(&self, duration: f32, session_id: &str) -> Vec<AdSegment> {
-        let num_segments = (duration / self.segment_duration).ceil() as usize;
-        let num_segments = num_segments.max(1);
+    /// A media playlist (`.m3u8` URL or local file) is looped as is; any
+    /// other URL is the base of `out_NNN.ts` segments of `segment_duration`
+    /// seconds (see [`SlateProvider::new`]).
+    pub async fn load(source: &str, segment_duration: f32, http_client: &Client) -> Result<Self> {
+        Ok(Self::from_playlist(
+            load_slate(source, segment_duration, http_client).await?,
+        ))
+    }
+
+    /// Play another slate for one reason
+    pub fn with_reason_slate(mut self, reason: SlateReason, slate: AdPlaylist) -> Self {
+        self.reason_slates.retain(|(r, _)| *r != reason);
+        self.reason_slates.push((reason, slate));
+        self
+    }
+
+    /// Load another slate for one reason (see [`SlateProvider::load`])
+    pub async fn load_reason_slate(
+        self,
+        reason: SlateReason,
+        source: &str,
+        segment_duration: f32,
+        http_client: &Client,
+    ) -> Result<Self> {
+        let slate = load_slate(source, segment_duration, http_client).await?;
+        Ok(self.with_reason_slate(reason, slate))
+    }
+
+    /// The slate played for `reason`, and the ad name prefix of its segments
+    fn slate_for(&self, reason: SlateReason) -> (&AdPlaylist, String) {
+        match self.reason_slates.iter().find(|(r, _)| *r == reason) {
+            Some((_, slate)) => (slate, format!("{}{}-seg-", SLATE_PREFIX, reason.as_str())),
+            None => (&self.slate, format!("{}seg-", SLATE_PREFIX)),
+        }
+    }
+
+    /// Generate slate segments filling exactly `duration`
+    ///
+    /// Loops the slate's segments in order. When the next one no longer
+    /// fits, the remainder is filled with the slate's shortest segment
+    /// that covers it, signalled with a shortened EXTINF unless it fits
+    /// exactly. Segment names carry the slate segment they play.
+    pub fn fill_duration(
+        &self,
+        duration: f32,
+        reason: SlateReason,
+        session_id: &str,
+    ) -> Vec<AdSegment> {
+        let (slate, prefix) = self.slate_for(reason);
+        let segment = |index: usize, duration: f32| AdSegment {
+            uri: format!("{}{}.ts", prefix, index),
+            duration,
+            init: slate.init.clone(),
+            tracking: None,
+        };
+
+        let mut segments = Vec::new();
+        let mut remaining = duration;
+        let mut next = 0;
+        while remaining > FILL_EPSILON && !slate.segments.is_empty() {
+            let index = next % slate.segments.len();
+            let length = slate.segments[index].duration;
+            if length <= remaining + FILL_EPSILON {
+                segments.push(segment(index, length));
+                remaining -= length;
+                next += 1;
+                continue;
+            }
+
+            // Last piece: the shortest slate segment covering the remainder
+            let (filler, _) = slate
+                .segments
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.duration + FILL_EPSILON >= remaining)
+                .min_by(|(_, a), (_, b)| a.duration.total_cmp(&b.duration))
+                .unwrap_or((index, &slate.segments[index]));
+            segments.push(segment(filler, remaining));
+            break;
+        }
 
         info!(
-            "SlateProvider: Generating {} slate segments for session {} (duration: {}s)",
-            num_segments, session_id, duration
+            "SlateProvider: Generated {} {} slate segments for session {} (duration: {}s)",
+            segments.len(),
+            reason.as_str(),
+            session_id,
+            duration
         );
+        segments
+    }
 
-        (0..num_segments)
-            .map(|i| AdSegment {
-                uri: format!("slate-seg-{}.ts", i),
-                duration: self.segment_duration,
-                tracking: None,
-            })
-            .collect()
+    /// Complete a break's decided ad segments with slate
+    ///
+    /// A break left without ads is filled with slate entirely (a slate
+    /// fallback); otherwise slate covers the time the ads leave unfilled,
+    /// once that gap exceeds `tolerance` seconds. `reason` picks the slate.
+    pub fn complete_break(
+        &self,
+        mut segments: Vec<AdSegment>,
+        duration: f32,
+        tolerance: f32,
+        reason: SlateReason,
+        session_id: &str,
+    ) -> Vec<AdSegment> {
+        if segments.is_empty() {
+            warn!(
+                "SlateProvider: No ads for session {} ({}) — falling back to slate",
+                session_id,
+                reason.as_str()
+            );
+            metrics::record_slate_fallback(reason.as_str());
+            return self.fill_duration(duration, reason, session_id);
+        }
+
+        let filled: f32 = segments.iter().map(|s| s.duration).sum();
+        let gap = duration - filled;
+        if gap > tolerance {
+            info!(
+                "SlateProvider: Filling {:.1}s pod gap with slate for session {}",
+                gap, session_id
+            );
+            segments.extend(self.fill_duration(gap, reason, session_id));
+        }
+        segments
     }
 
     /// Resolve a slate segment identifier to its actual source URL
     ///
-    /// Slate segments use the naming format "slate-seg-{index}.ts"
+    /// Slate segments are named "slate-seg-{index}.ts", or
+    /// "slate-{reason}-seg-{index}.ts" for a reason's own slate.
     pub fn resolve_segment_url(&self, segment_name: &str) -> Option<String> {
-        let index = segment_name
-            .strip_prefix("slate-seg-")
-            .and_then(|s| s.strip_suffix(".ts"))
-            .and_then(|s| s.parse::<usize>().ok())?;
+        let name = segment_name
+            .strip_prefix(SLATE_PREFIX)
+            .and_then(|s| s.strip_suffix(".ts"))?;
+        let (slate, index) = match name.strip_prefix("seg-") {
+            Some(index) => (&self.slate, index),
+            None => {
+                let (reason, index) = name.split_once("-seg-")?;
+                let reason = SlateReason::parse(reason)?;
+                (self.slate_for(reason).0, index)
+            }
+        };
+        let index = index.parse::<usize>().ok()?;
+        if slate.segments.is_empty() {
+            return None;
+        }
 
-        let source_index = index % self.segment_count;
-        let source_segment = format!("out_{:03}.ts", source_index);
+        // Wrap around for names minted by an earlier slate configuration
+        Some(slate.segments[index % slate.segments.len()].url.clone())
+    }
+}
 
-        Some(format!("{}/{}", self.slate_url, source_segment))
+/// A slate media playlist, or the numbered segments under a base URL
+async fn load_slate(
+    source: &str,
+    segment_duration: f32,
+    http_client: &Client,
+) -> Result<AdPlaylist> {
+    let path = source.split(['?', '#']).next().unwrap_or(source);
+    if path.ends_with(".m3u8") {
+        AdPlaylist::load(source, http_client).await
+    } else {
+        Ok(AdPlaylist::numbered(source, segment_duration, 10))
     }
 }
 
@@ -76,8 +261,18 @@ impl SlateProvider {
 /// Used when no VAST endpoint is configured and the operator wants
 /// to serve slate content for all ad breaks. Also useful for testing.
 impl AdProvider for SlateProvider {
-    fn get_ad_segments(&self, duration: f32, session_id: &str) -> Vec<AdSegment> {
-        self.fill_duration(duration, session_id)
+    fn get_ad_segments<'a>(
+        &'a self,
+        duration: f32,
+        _break_id: &'a str,
+        session_id: &'a str,
+        _viewer: &'a ViewerContext,
+    ) -> BoxFuture<'a, Vec<AdSegment>> {
+        Box::pin(std::future::ready(self.fill_duration(
+            duration,
+            SlateReason::NoFill,
+            session_id,
+        )))
     }
 
     fn resolve_segment_url(&self, ad_name: &str) -> Option<String> {
@@ -88,11 +283,12 @@ impl AdProvider for SlateProvider {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ad::playlist::PlaylistSegment;
 
     #[test]
     fn test_fill_duration_exact() {
         let provider = SlateProvider::new("https://slate.example.com".to_string(), 2.0);
-        let segments = provider.fill_duration(10.0, "test-session");
+        let segments = provider.fill_duration(10.0, SlateReason::NoFill, "test-session");
 
         assert_eq!(segments.len(), 5);
         for (i, seg) in segments.iter().enumerate() {
@@ -105,18 +301,92 @@ mod tests {
     #[test]
     fn test_fill_duration_partial() {
         let provider = SlateProvider::new("https://slate.example.com".to_string(), 2.0);
-        let segments = provider.fill_duration(7.0, "test-session");
+        let segments = provider.fill_duration(7.0, SlateReason::NoFill, "test-session");
 
-        // 7.0 / 2.0 = 3.5, ceil = 4
+        // 3 whole segments, then one shortened to the remaining 1s
         assert_eq!(segments.len(), 4);
+        assert_eq!(segments[3].duration, 1.0);
+        assert_eq!(segments.iter().map(|s| s.duration).sum::<f32>(), 7.0);
     }
 
     #[test]
-    fn test_fill_duration_minimum_one() {
+    fn test_fill_duration_zero() {
         let provider = SlateProvider::new("https://slate.example.com".to_string(), 10.0);
-        let segments = provider.fill_duration(0.0, "test-session");
+        let segments = provider.fill_duration(0.0, SlateReason::NoFill, "test-session");
+
+        assert!(segments.is_empty());
+    }
+
+    fn slate(name: &str, durations: &[f32]) -> AdPlaylist {
+        AdPlaylist {
+            source: format!("https://slate.example.com/{name}/index.m3u8"),
+            init: Some(format!("https://slate.example.com/{name}/init.mp4")),
+            segments: durations
+                .iter()
+                .enumerate()
+                .map(|(i, &duration)| PlaylistSegment {
+                    url: format!("https://slate.example.com/{name}/{i}.m4s"),
+                    duration,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_fill_duration_fits_with_short_filler_segment() {
+        // A 4s loop with a 1s filler segment at its end
+        let provider = SlateProvider::from_playlist(slate("loop", &[4.0, 4.0, 1.0]));
+        let segments = provider.fill_duration(10.5, SlateReason::NoFill, "test-session");
 
-        assert_eq!(segments.len(), 1);
+        let fill: Vec<(&str, f32)> = segments
+            .iter()
+            .map(|s| (s.uri.as_str(), s.duration))
+            .collect();
+        assert_eq!(
+            fill,
+            [
+                ("slate-seg-0.ts", 4.0),
+                ("slate-seg-1.ts", 4.0),
+                ("slate-seg-2.ts", 1.0),
+                // The shortest segment covering the last 1.5s: the 4s one, shortened
+                ("slate-seg-0.ts", 1.5),
+            ]
+        );
+        assert!(
+            segments
+                .iter()
+                .all(|s| s.init.as_deref() == Some("https://slate.example.com/loop/init.mp4"))
+        );
+        assert_eq!(
+            provider.resolve_segment_url("slate-seg-2.ts").as_deref(),
+            Some("https://slate.example.com/loop/2.m4s")
+        );
+    }
+
+    #[test]
+    fn test_reason_slates() {
+        let provider = SlateProvider::from_playlist(slate("default", &[2.0]))
+            .with_reason_slate(SlateReason::Error, slate("error", &[1.0]));
+
+        let error = provider.fill_duration(2.0, SlateReason::Error, "s");
+        assert_eq!(error[0].uri, "slate-error-seg-0.ts");
+        assert_eq!(error.len(), 2);
+        assert_eq!(
+            provider.resolve_segment_url(&error[0].uri).as_deref(),
+            Some("https://slate.example.com/error/0.m4s")
+        );
+
+        // Reasons without their own slate play the default one
+        let policy = provider.fill_duration(2.0, SlateReason::Policy, "s");
+        assert_eq!(policy[0].uri, "slate-seg-0.ts");
+        assert_eq!(
+            provider
+                .resolve_segment_url("slate-policy-seg-0.ts")
+                .as_deref(),
+            Some("https://slate.example.com/default/0.m4s")
+        );
+        assert!(is_slate_segment("slate-error-seg-0.ts"));
+        assert!(!is_slate_segment("break-0-seg-0.ts"));
     }
 
     #[test]
@@ -146,12 +416,14 @@ mod tests {
         assert_eq!(provider.resolve_segment_url("break-0-seg-0.ts"), None);
     }
 
-    #[test]
-    fn test_ad_provider_trait() {
+    #[tokio::test]
+    async fn test_ad_provider_trait() {
         let provider = SlateProvider::new("https://slate.example.com".to_string(), 2.0);
 
         // Test via AdProvider trait
-        let segments = provider.get_ad_segments(6.0, "session-1");
+        let segments = provider
+            .get_ad_segments(6.0, "1", "session-1", &ViewerContext::default())
+            .await;
         assert_eq!(segments.len(), 3);
 
         let url = AdProvider::resolve_segment_url(&provider, "slate-seg-0.ts");
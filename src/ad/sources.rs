@@ -0,0 +1,691 @@
+use crate::ad::policy::{AdPolicy, PolicyState};
+use crate::ad::provider::{self, AdCreative, AdProvider, AdSegment, ResolvedSegment};
+use crate::ad::slate::{self, SlateProvider, SlateReason};
+use crate::ad::vast_provider::VastAdProvider;
+use crate::metrics;
+use crate::session::{SessionManager, ViewerContext};
+use dashmap::DashMap;
+use futures::StreamExt;
+use futures::future::{BoxFuture, join_all};
+use futures::stream::FuturesUnordered;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How long a session's winning source is remembered for segment routing
+const WINNER_TTL: Duration = Duration::from_secs(300);
+
+/// How a [`MultiSourceAdProvider`] combines its ad sources
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AdSourceStrategy {
+    /// Ask the sources one after another, moving on after an error,
+    /// timeout or no-fill
+    Waterfall,
+    /// Ask every source at once and take the most valuable fill
+    ParallelBest,
+    /// Ask every source at once and take the first fill
+    ParallelFirst,
+}
+
+/// One ad server in a multi-source setup
+pub struct AdSource {
+    /// Name used in logs and metric labels
+    pub name: String,
+    /// Multiplier applied to the source's bid prices by [`AdSourceStrategy::ParallelBest`]
+    pub weight: f64,
+    /// Deadline for the source's whole decision, wrapper chain included
+    pub timeout: Duration,
+    pub provider: VastAdProvider,
+}
+
+/// A decision that an ad source contributed to a break
+trait Fill {
+    /// Seconds of the break the fill covers
+    fn duration(&self) -> f64;
+    /// Price the ad server bid for it, 0 when not reported
+    fn price(&self) -> f64;
+}
+
+impl Fill for AdSegment {
+    fn duration(&self) -> f64 {
+        self.duration as f64
+    }
+
+    fn price(&self) -> f64 {
+        // Creatives are single segments, so each price is counted once
+        self.tracking
+            .as_ref()
+            .and_then(|t| t.ad.pricing.as_ref())
+            .map_or(0.0, |p| p.value)
+    }
+}
+
+impl Fill for AdCreative {
+    fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    fn price(&self) -> f64 {
+        self.ad
+            .as_ref()
+            .and_then(|ad| ad.pricing.as_ref())
+            .map_or(0.0, |p| p.value)
+    }
+}
+
+/// Ad provider that decides breaks across several VAST ad sources
+///
+/// Sources are asked as a waterfall or in parallel (see
+/// [`AdSourceStrategy`]). Each source outcome — fill, no-fill, error or
+/// timeout — and its latency are recorded per source. Slate fills breaks
+/// no source filled and, like [`VastAdProvider`], the gap a fill leaves.
+pub struct MultiSourceAdProvider {
+    sources: Vec<AdSource>,
+    strategy: AdSourceStrategy,
+    slate: Option<SlateProvider>,
+    /// Unfilled seconds tolerated before slate covers the gap
+    overrun_tolerance: f32,
+    /// Frequency caps and separation, applied across all sources
+    policy: Option<AdPolicy>,
+    /// Source that won each (session, break id), for segment routing
+    winners: DashMap<(String, String), (usize, Instant)>,
+    /// Session store receiving the winning decision of each break
+    sessions: Option<SessionManager>,
+}
+
+impl MultiSourceAdProvider {
+    /// Create a provider over `sources`, in priority order
+    pub fn new(sources: Vec<AdSource>, strategy: AdSourceStrategy) -> Self {
+        Self {
+            sources,
+            strategy,
+            slate: None,
+            overrun_tolerance: 0.5,
+            policy: None,
+            winners: DashMap::new(),
+            sessions: None,
+        }
+    }
+
+    /// Keep each break's winning decision in the session store
+    ///
+    /// Only the winner is stored, once it is chosen: sources keep their own
+    /// fills to themselves, so a losing source answering late cannot replace
+    /// the decision any instance serves.
+    pub fn with_sessions(mut self, sessions: SessionManager) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    /// Configure the slate used when no source fills a break
+    pub fn with_slate(mut self, slate: SlateProvider) -> Self {
+        self.slate = Some(slate);
+        self
+    }
+
+    /// Configure how far a fill may fall short of the break before slate
+    /// covers the gap (default: 0.5s)
+    pub fn with_overrun_tolerance(mut self, tolerance: f32) -> Self {
+        self.overrun_tolerance = tolerance;
+        self
+    }
+
+    /// Apply frequency caps and competitive separation to decided breaks
+    ///
+    /// The session's policy state is loaded once per break and shared by
+    /// every source; only the winning fill is counted.
+    pub fn with_policy(mut self, policy: AdPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// The session's policy snapshot, or no policy if none is configured
+    async fn policy_state(&self, session_id: &str, viewer: &ViewerContext) -> PolicyState {
+        match &self.policy {
+            Some(policy) => policy.load(session_id, viewer).await,
+            None => PolicyState::default(),
+        }
+    }
+
+    /// Ask one source, bounded by its timeout, and record the outcome
+    async fn ask<'a, T>(
+        &'a self,
+        idx: usize,
+        request: &(dyn Fn(&'a VastAdProvider) -> BoxFuture<'a, Option<Vec<T>>> + Sync),
+    ) -> (usize, Option<Vec<T>>) {
+        let source = &self.sources[idx];
+        let start = Instant::now();
+        let result = tokio::time::timeout(source.timeout, request(&source.provider)).await;
+        metrics::record_ad_source_latency(&source.name, start);
+
+        let (outcome, fill) = match result {
+            Ok(Some(fill)) if !fill.is_empty() => ("fill", Some(fill)),
+            Ok(Some(_)) => ("nofill", Some(Vec::new())),
+            Ok(None) => ("error", None),
+            Err(_) => ("timeout", None),
+        };
+        metrics::record_ad_source_request(&source.name, outcome);
+        info!(
+            "MultiSourceAdProvider: Source {} answered {} in {}ms",
+            source.name,
+            outcome,
+            start.elapsed().as_millis()
+        );
+        (idx, fill)
+    }
+
+    /// Run one decision across the sources according to the strategy
+    ///
+    /// Returns the winning source's index and fill. If no source filled the
+    /// break, returns why: no fill if any source answered, else an error.
+    async fn decide<'a, T: Fill>(
+        &'a self,
+        duration: f32,
+        request: &(dyn Fn(&'a VastAdProvider) -> BoxFuture<'a, Option<Vec<T>>> + Sync),
+    ) -> Result<(usize, Vec<T>), SlateReason> {
+        let unfilled = |answered: bool| {
+            if answered {
+                SlateReason::NoFill
+            } else {
+                SlateReason::Error
+            }
+        };
+        match self.strategy {
+            AdSourceStrategy::Waterfall => {
+                let mut answered = false;
+                for idx in 0..self.sources.len() {
+                    match self.ask(idx, request).await {
+                        (idx, Some(fill)) if !fill.is_empty() => return Ok((idx, fill)),
+                        (_, fill) => answered |= fill.is_some(),
+                    }
+                }
+                Err(unfilled(answered))
+            }
+            AdSourceStrategy::ParallelFirst => {
+                let mut pending: FuturesUnordered<_> = (0..self.sources.len())
+                    .map(|idx| self.ask(idx, request))
+                    .collect();
+                let mut answered = false;
+                while let Some(answer) = pending.next().await {
+                    match answer {
+                        (idx, Some(fill)) if !fill.is_empty() => return Ok((idx, fill)),
+                        (_, fill) => answered |= fill.is_some(),
+                    }
+                }
+                Err(unfilled(answered))
+            }
+            AdSourceStrategy::ParallelBest => {
+                let answers =
+                    join_all((0..self.sources.len()).map(|idx| self.ask(idx, request))).await;
+                let answered = answers.iter().any(|(_, fill)| fill.is_some());
+                let score = |(idx, fill): &(usize, Vec<T>)| {
+                    let value =
+                        self.sources[*idx].weight * fill.iter().map(Fill::price).sum::<f64>();
+                    let covered = fill
+                        .iter()
+                        .map(Fill::duration)
+                        .sum::<f64>()
+                        .min(duration as f64);
+                    (value, covered)
+                };
+                answers
+                    .into_iter()
+                    .filter_map(|(idx, fill)| fill.filter(|f| !f.is_empty()).map(|f| (idx, f)))
+                    // Ties go to the earlier source: max_by keeps the last maximum
+                    .rev()
+                    .max_by(|a, b| {
+                        score(a)
+                            .partial_cmp(&score(b))
+                            .unwrap_or(std::cmp::Ordering::Equal)
+                    })
+                    .ok_or(unfilled(answered))
+            }
+        }
+    }
+
+    /// Remember which source decided a session's break
+    fn record_winner(&self, session_id: &str, break_id: &str, idx: usize) {
+        self.winners.insert(
+            (session_id.to_string(), break_id.to_string()),
+            (idx, Instant::now()),
+        );
+    }
+
+    /// Store the winning source's decision of a break in the session,
+    /// with the break's final segments (see [`VastAdProvider::publish_break_to`])
+    async fn store_winner(
+        &self,
+        session_id: &str,
+        break_id: &str,
+        winner: Option<usize>,
+        segments: Vec<AdSegment>,
+    ) -> Vec<AdSegment> {
+        match (&self.sessions, winner) {
+            (Some(sessions), Some(idx)) => {
+                self.sources[idx]
+                    .provider
+                    .publish_break_to(sessions, session_id, break_id, segments)
+                    .await
+            }
+            _ => segments,
+        }
+    }
+
+    /// Sources to ask for a session's segment, the one that won its break
+    /// first — losing sources may have cached creatives for it too
+    fn routing_order(&self, session_id: &str, ad_name: &str) -> Vec<usize> {
+        let winner = provider::parse_ad_segment_name(ad_name).and_then(|(break_id, _)| {
+            self.winners
+                .get(&(session_id.to_string(), break_id.to_string()))
+                .map(|w| w.0)
+        });
+        winner
+            .into_iter()
+            .chain((0..self.sources.len()).filter(|idx| Some(*idx) != winner))
+            .collect()
+    }
+}
+
+/// SSAI request to one source, for [`MultiSourceAdProvider::decide`]
+fn segment_request<'r>(
+    duration: f32,
+    break_id: &'r str,
+    session_id: &'r str,
+    viewer: &'r ViewerContext,
+    policy: &'r PolicyState,
+) -> impl Fn(&'r VastAdProvider) -> BoxFuture<'r, Option<Vec<AdSegment>>> + Sync {
+    move |provider| {
+        Box::pin(provider.request_segments(duration, break_id, session_id, viewer, policy))
+    }
+}
+
+/// SGAI request to one source, for [`MultiSourceAdProvider::decide`]
+fn creative_request<'r>(
+    duration: f32,
+    session_id: &'r str,
+    viewer: &'r ViewerContext,
+    policy: &'r PolicyState,
+) -> impl Fn(&'r VastAdProvider) -> BoxFuture<'r, Option<Vec<AdCreative>>> + Sync {
+    move |provider| Box::pin(provider.request_creatives(duration, session_id, viewer, policy))
+}
+
+impl std::fmt::Debug for MultiSourceAdProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MultiSourceAdProvider")
+            .field(
+                "sources",
+                &self.sources.iter().map(|s| &s.name).collect::<Vec<_>>(),
+            )
+            .field("strategy", &self.strategy)
+            .field("has_slate", &self.slate.is_some())
+            .finish()
+    }
+}
+
+impl AdProvider for MultiSourceAdProvider {
+    fn get_ad_segments<'a>(
+        &'a self,
+        duration: f32,
+        break_id: &'a str,
+        session_id: &'a str,
+        viewer: &'a ViewerContext,
+    ) -> BoxFuture<'a, Vec<AdSegment>> {
+        Box::pin(async move {
+            let policy = self.policy_state(session_id, viewer).await;
+            let request = segment_request(duration, break_id, session_id, viewer, &policy);
+            let (winner, segments, reason) = match self.decide(duration, &request).await {
+                Ok((idx, segments)) => {
+                    info!(
+                        "MultiSourceAdProvider: Source {} won the break for session {}",
+                        self.sources[idx].name, session_id
+                    );
+                    self.record_winner(session_id, break_id, idx);
+                    if let Some(policy) = &self.policy {
+                        let ads = segments.iter().filter_map(|s| s.tracking.as_ref());
+                        policy.record(session_id, viewer, ads.map(|t| &t.ad)).await;
+                    }
+                    (Some(idx), segments, SlateReason::NoFill)
+                }
+                Err(reason) => {
+                    warn!(
+                        "MultiSourceAdProvider: No source filled the break for session {}",
+                        session_id
+                    );
+                    (None, Vec::new(), reason)
+                }
+            };
+            // Any source's policy rejections explain slate that fills their time
+            let reason = match reason {
+                SlateReason::NoFill if policy.has_rejections() => SlateReason::Policy,
+                reason => reason,
+            };
+            let segments = match &self.slate {
+                Some(slate) => slate.complete_break(
+                    segments,
+                    duration,
+                    self.overrun_tolerance,
+                    reason,
+                    session_id,
+                ),
+                None => segments,
+            };
+            self.store_winner(session_id, break_id, winner, segments)
+                .await
+        })
+    }
+
+    fn resolve_segment_url(&self, ad_name: &str) -> Option<String> {
+        if slate::is_slate_segment(ad_name) {
+            return self.slate.as_ref()?.resolve_segment_url(ad_name);
+        }
+        self.sources
+            .iter()
+            .find_map(|source| source.provider.resolve_segment_url(ad_name))
+    }
+
+    fn resolve_segment_with_tracking(
+        &self,
+        ad_name: &str,
+        session_id: &str,
+    ) -> Option<ResolvedSegment> {
+        if slate::is_slate_segment(ad_name) {
+            return self
+                .slate
+                .as_ref()?
+                .resolve_segment_url(ad_name)
+                .map(|url| ResolvedSegment {
+                    url,
+                    tracking: None,
+                });
+        }
+        self.routing_order(session_id, ad_name)
+            .into_iter()
+            .find_map(|idx| {
+                self.sources[idx]
+                    .provider
+                    .resolve_segment_with_tracking(ad_name, session_id)
+            })
+    }
+
+    fn get_ad_creatives<'a>(
+        &'a self,
+        duration: f32,
+        _break_id: &'a str,
+        session_id: &'a str,
+        viewer: &'a ViewerContext,
+    ) -> BoxFuture<'a, Vec<AdCreative>> {
+        Box::pin(async move {
+            let policy = self.policy_state(session_id, viewer).await;
+            let request = creative_request(duration, session_id, viewer, &policy);
+            let creatives = self
+                .decide(duration, &request)
+                .await
+                .map(|(_, creatives)| creatives)
+                .unwrap_or_default();
+            if let Some(policy) = &self.policy {
+                let ads = creatives.iter().filter_map(|c| c.ad.as_ref());
+                policy.record(session_id, viewer, ads).await;
+            }
+            creatives
+        })
+    }
+
+    fn slate(&self) -> Option<&SlateProvider> {
+        self.slate.as_ref()
+    }
+
+    fn cleanup_cache(&self) {
+        for source in &self.sources {
+            source.provider.cleanup_cache();
+        }
+        self.winners
+            .retain(|_, (_, decided_at)| decided_at.elapsed() < WINNER_TTL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ad::policy::PolicyRules;
+    use reqwest::Client;
+    use std::sync::{Arc, Mutex};
+
+    /// Serve one VAST document per path, each after a delay, recording hits
+    async fn mock_ad_server(docs: Vec<(&str, String, u64)>) -> (String, Arc<Mutex<Vec<String>>>) {
+        use axum::http::{StatusCode, Uri};
+
+        let docs: Arc<std::collections::HashMap<String, (String, u64)>> = Arc::new(
+            docs.into_iter()
+                .map(|(path, xml, delay)| (path.to_string(), (xml, delay)))
+                .collect(),
+        );
+        let hits = Arc::new(Mutex::new(Vec::new()));
+        let recorded = hits.clone();
+        let app = axum::Router::new().fallback(move |uri: Uri| {
+            let docs = docs.clone();
+            let hits = recorded.clone();
+            async move {
+                hits.lock().unwrap().push(uri.path().to_string());
+                match docs.get(uri.path()) {
+                    Some((xml, delay)) => {
+                        tokio::time::sleep(Duration::from_millis(*delay)).await;
+                        (StatusCode::OK, xml.clone())
+                    }
+                    None => (StatusCode::NOT_FOUND, String::new()),
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (base, hits)
+    }
+
+    fn vast_with_ad(id: &str, seconds: u32, price: f64) -> String {
+        format!(
+            r#"<VAST version="4.2"><Ad id="{id}"><InLine><AdSystem>S</AdSystem><AdTitle>{id}</AdTitle>
+<Pricing model="CPM" currency="USD">{price}</Pricing>
+<Creatives><Creative id="c-{id}"><Linear><Duration>00:00:{seconds:02}</Duration>
+<MediaFiles><MediaFile delivery="progressive" type="video/mp4" width="1280" height="720">https://ads.example.com/{id}.mp4</MediaFile></MediaFiles>
+</Linear></Creative></Creatives></InLine></Ad></VAST>"#
+        )
+    }
+
+    fn source(name: &str, url: String, weight: f64) -> AdSource {
+        AdSource {
+            name: name.to_string(),
+            weight,
+            timeout: Duration::from_secs(2),
+            provider: VastAdProvider::new(url, Client::new()),
+        }
+    }
+
+    fn ad_ids(segments: &[AdSegment]) -> Vec<String> {
+        segments
+            .iter()
+            .filter_map(|s| s.tracking.as_ref())
+            .map(|t| t.ad.ad_id.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_waterfall_moves_on_after_error_and_no_fill() {
+        let (base, hits) = mock_ad_server(vec![
+            ("/empty", r#"<VAST version="4.2"></VAST>"#.to_string(), 0),
+            ("/fill", vast_with_ad("backup", 30, 1.0), 0),
+            ("/unused", vast_with_ad("never", 30, 9.0), 0),
+        ])
+        .await;
+        let provider = MultiSourceAdProvider::new(
+            vec![
+                source("broken", format!("{base}/missing"), 1.0),
+                source("empty", format!("{base}/empty"), 1.0),
+                source("backup", format!("{base}/fill"), 1.0),
+                source("unused", format!("{base}/unused"), 1.0),
+            ],
+            AdSourceStrategy::Waterfall,
+        );
+
+        let segments = provider
+            .get_ad_segments(30.0, "1", "s", &ViewerContext::default())
+            .await;
+        assert_eq!(ad_ids(&segments), vec!["backup"]);
+        // Sources are asked in order (the failed fetch is retried once)
+        let mut asked = hits.lock().unwrap().clone();
+        asked.dedup();
+        assert_eq!(asked, vec!["/missing", "/empty", "/fill"]);
+
+        // Segments resolve through the source that won the break
+        let resolved = provider
+            .resolve_segment_with_tracking(&segments[0].uri, "s")
+            .unwrap();
+        assert_eq!(resolved.url, "https://ads.example.com/backup.mp4");
+        assert!(resolved.tracking.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_parallel_best_takes_highest_weighted_price() {
+        let (base, _) = mock_ad_server(vec![
+            ("/a", vast_with_ad("cheap", 30, 10.0), 0),
+            ("/b", vast_with_ad("rich", 30, 8.0), 0),
+            ("/c", vast_with_ad("heavy", 30, 6.0), 0),
+        ])
+        .await;
+        let provider = MultiSourceAdProvider::new(
+            vec![
+                source("a", format!("{base}/a"), 1.0),
+                source("b", format!("{base}/b"), 1.5),
+                source("c", format!("{base}/c"), 1.0),
+            ],
+            AdSourceStrategy::ParallelBest,
+        );
+
+        let segments = provider
+            .get_ad_segments(30.0, "1", "s", &ViewerContext::default())
+            .await;
+        // 8.0 × 1.5 beats 10.0 × 1.0
+        assert_eq!(ad_ids(&segments), vec!["rich"]);
+    }
+
+    #[tokio::test]
+    async fn test_only_the_winning_fill_is_stored_in_the_session() {
+        // The losing source answers last, after the winner's fill is in
+        let (base, _) = mock_ad_server(vec![
+            ("/win", vast_with_ad("winner", 30, 10.0), 0),
+            ("/lose", vast_with_ad("loser", 30, 1.0), 200),
+        ])
+        .await;
+        let sessions = SessionManager::new_memory(Duration::from_secs(300));
+        sessions
+            .get_or_create("s".to_string(), "https://example.com".to_string())
+            .await;
+        let provider = MultiSourceAdProvider::new(
+            vec![
+                source("win", format!("{base}/win"), 1.0),
+                source("lose", format!("{base}/lose"), 1.0),
+            ],
+            AdSourceStrategy::ParallelBest,
+        )
+        .with_sessions(sessions.clone());
+
+        let segments = provider
+            .get_ad_segments(30.0, "1", "s", &ViewerContext::default())
+            .await;
+        assert_eq!(ad_ids(&segments), vec!["winner"]);
+
+        let delivered = sessions.deliver_ad_segment("s", "1", 0).await.unwrap();
+        assert_eq!(delivered.url, "https://ads.example.com/winner.mp4");
+        assert_eq!(delivered.tracking.unwrap().ad.ad_id, "winner");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_breaks_of_a_session_are_separated() {
+        let ad = |id: &str, advertiser: &str, price: f64| {
+            format!(
+                r#"<Ad id="{id}"><InLine><AdSystem>S</AdSystem><AdTitle>{id}</AdTitle>
+<Advertiser>{advertiser}</Advertiser>
+<Pricing model="CPM" currency="USD">{price}</Pricing>
+<Creatives><Creative id="c-{id}"><Linear><Duration>00:00:30</Duration>
+<MediaFiles><MediaFile delivery="progressive" type="video/mp4" width="1280" height="720">https://ads.example.com/{id}.mp4</MediaFile></MediaFiles>
+</Linear></Creative></Creatives></InLine></Ad>"#
+            )
+        };
+        let vast = format!(
+            r#"<VAST version="4.2">{}{}{}</VAST>"#,
+            ad("cola-1", "cola.com", 10.0),
+            ad("cola-2", "cola.com", 9.0),
+            ad("car", "cars.com", 1.0),
+        );
+        let (base, _) = mock_ad_server(vec![("/pod", vast, 50)]).await;
+        let sessions = SessionManager::new_memory(Duration::from_secs(300));
+        sessions
+            .get_or_create("s".to_string(), "https://example.com".to_string())
+            .await;
+        let rules = PolicyRules {
+            separation_breaks: 1,
+            ..Default::default()
+        };
+        let provider = MultiSourceAdProvider::new(
+            vec![source("pod", format!("{base}/pod"), 1.0)],
+            AdSourceStrategy::Waterfall,
+        )
+        .with_policy(AdPolicy::new(rules, sessions));
+
+        // Two adjacent 30s breaks, decided at the same time
+        let viewer = ViewerContext::default();
+        let (first, second) = tokio::join!(
+            provider.get_ad_segments(30.0, "1", "s", &viewer),
+            provider.get_ad_segments(30.0, "2", "s", &viewer),
+        );
+        let mut decided = [ad_ids(&first), ad_ids(&second)];
+        decided.sort();
+        assert_eq!(decided, [vec!["car"], vec!["cola-1"]]);
+    }
+
+    #[tokio::test]
+    async fn test_parallel_first_takes_earliest_fill_within_timeout() {
+        let (base, _) = mock_ad_server(vec![
+            ("/hung", vast_with_ad("hung", 30, 50.0), 5_000),
+            ("/slow", vast_with_ad("slow", 30, 20.0), 300),
+            ("/fast", vast_with_ad("fast", 30, 1.0), 0),
+        ])
+        .await;
+        let mut hung = source("hung", format!("{base}/hung"), 1.0);
+        hung.timeout = Duration::from_millis(100);
+        let provider = MultiSourceAdProvider::new(
+            vec![
+                hung,
+                source("slow", format!("{base}/slow"), 1.0),
+                source("fast", format!("{base}/fast"), 1.0),
+            ],
+            AdSourceStrategy::ParallelFirst,
+        );
+
+        let segments = provider
+            .get_ad_segments(30.0, "1", "s", &ViewerContext::default())
+            .await;
+        assert_eq!(ad_ids(&segments), vec!["fast"]);
+    }
+
+    #[tokio::test]
+    async fn test_slate_fills_break_no_source_filled() {
+        let (base, _) = mock_ad_server(vec![]).await;
+        let provider = MultiSourceAdProvider::new(
+            vec![source("broken", format!("{base}/missing"), 1.0)],
+            AdSourceStrategy::Waterfall,
+        )
+        .with_slate(SlateProvider::new(
+            "https://slate.example.com".to_string(),
+            1.0,
+        ));
+
+        let segments = provider
+            .get_ad_segments(5.0, "1", "s", &ViewerContext::default())
+            .await;
+        assert_eq!(segments.len(), 5);
+        assert!(segments.iter().all(|s| s.uri.starts_with("slate-seg-")));
+        assert_eq!(
+            provider.resolve_segment_url("slate-seg-2.ts").as_deref(),
+            Some("https://slate.example.com/out_002.ts")
+        );
+    }
+}
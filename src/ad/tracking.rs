@@ -1,4 +1,5 @@
-use crate::ad::vast::TrackingEvent;
+use crate::ad::macros::{self, MacroContext};
+use crate::ad::vast::{TrackingEvent, VastErrorCode};
 use crate::metrics;
 use reqwest::Client;
 use std::time::Duration;
@@ -107,21 +108,138 @@ pub fn fire_beacon(client: Client, url: String, event_name: String) {
 /// # Arguments
 /// * `client` - HTTP client
 /// * `impression_urls` - List of impression tracking URLs from VAST
-pub fn fire_impressions(client: Client, impression_urls: &[String]) {
+/// * `ctx` - Values for the macros in the URLs
+pub fn fire_impressions(client: Client, impression_urls: &[String], ctx: &MacroContext) {
+    let ctx = MacroContext {
+        ad_playhead: Some(0.0),
+        ..ctx.clone()
+    };
     for url in impression_urls {
-        fire_beacon(client.clone(), url.clone(), "impression".to_string());
+        fire_beacon(
+            client.clone(),
+            macros::expand(url, &ctx),
+            "impression".to_string(),
+        );
     }
 }
 
-/// Fire error beacon
+/// Fire quartile/progress tracking events for an ad
 ///
-/// Called when VAST fetch or ad segment fetch fails.
+/// `[ADPLAYHEAD]` is the point in the ad the event stands for (e.g. half
+/// of `ad_duration` for `midpoint`).
 ///
 /// # Arguments
 /// * `client` - HTTP client
-/// * `error_url` - Error tracking URL from VAST
-pub fn fire_error(client: Client, error_url: &str) {
-    fire_beacon(client, error_url.to_string(), "error".to_string());
+/// * `events` - Events selected by [`events_for_segment`]
+/// * `ad_duration` - Duration of the ad in seconds
+/// * `ctx` - Values for the macros in the URLs
+pub fn fire_events(
+    client: Client,
+    events: &[&TrackingEvent],
+    ad_duration: f32,
+    ctx: &MacroContext,
+) {
+    for event in events {
+        let progress = match event.event.as_str() {
+            "firstQuartile" => 0.25,
+            "midpoint" => 0.5,
+            "thirdQuartile" => 0.75,
+            "complete" => 1.0,
+            _ => 0.0,
+        };
+        let ctx = MacroContext {
+            ad_playhead: Some(f64::from(ad_duration) * progress),
+            ..ctx.clone()
+        };
+        fire_beacon(
+            client.clone(),
+            macros::expand(&event.url, &ctx),
+            event.event.clone(),
+        );
+    }
+}
+
+/// Fire error beacons with a VAST error code
+///
+/// Called when a wrapper, VAST fetch or ad segment fetch fails. The code
+/// replaces the `[ERRORCODE]` macro in every URL.
+///
+/// # Arguments
+/// * `client` - HTTP client
+/// * `error_urls` - Error tracking URLs of the InLine ad and its wrappers
+/// * `code` - VAST error code describing the failure
+/// * `ctx` - Values for the other macros in the URLs
+pub fn fire_errors(client: Client, error_urls: &[String], code: VastErrorCode, ctx: &MacroContext) {
+    metrics::record_vast_error(code.code());
+    let ctx = MacroContext {
+        error_code: Some(code.code()),
+        ..ctx.clone()
+    };
+    for url in error_urls {
+        fire_beacon(
+            client.clone(),
+            macros::expand(url, &ctx),
+            "error".to_string(),
+        );
+    }
+}
+
+/// Values for the OpenRTB auction macros (`${AUCTION_PRICE}` etc.) in
+/// win and loss notice URLs and in bid markup
+#[derive(Debug, Clone, Default)]
+pub struct AuctionMacros {
+    /// `BidRequest.id`
+    pub auction_id: String,
+    /// `Bid.id`
+    pub bid_id: String,
+    /// `Bid.impid`
+    pub imp_id: String,
+    /// `SeatBid.seat`
+    pub seat_id: String,
+    /// `Bid.adid`
+    pub ad_id: String,
+    /// Clearing price of the auction
+    pub price: f64,
+    pub currency: String,
+    /// OpenRTB loss reason code, for loss notices
+    pub loss: Option<u32>,
+}
+
+impl AuctionMacros {
+    /// Substitute the auction macros in `template`
+    ///
+    /// Auctions are first-price, so the market bid ratio is always 1 and
+    /// the minimum bid to win is the clearing price.
+    pub fn expand(&self, template: &str) -> String {
+        let price = format!("{}", self.price);
+        let loss = self.loss.map(|code| code.to_string()).unwrap_or_default();
+        [
+            ("${AUCTION_ID}", self.auction_id.as_str()),
+            ("${AUCTION_BID_ID}", &self.bid_id),
+            ("${AUCTION_IMP_ID}", &self.imp_id),
+            ("${AUCTION_SEAT_ID}", &self.seat_id),
+            ("${AUCTION_AD_ID}", &self.ad_id),
+            ("${AUCTION_PRICE}", &price),
+            ("${AUCTION_CURRENCY}", &self.currency),
+            ("${AUCTION_MBR}", "1"),
+            ("${AUCTION_MIN_TO_WIN}", &price),
+            ("${AUCTION_LOSS}", &loss),
+        ]
+        .iter()
+        .fold(template.to_string(), |url, (name, value)| {
+            url.replace(name, value)
+        })
+    }
+}
+
+/// Fire an OpenRTB win notice (`Bid.nurl`) for the winning bid
+pub fn fire_win_notice(client: Client, nurl: &str, auction: &AuctionMacros) {
+    fire_beacon(client, auction.expand(nurl), "win".to_string());
+}
+
+/// Fire an OpenRTB loss notice (`Bid.lurl`) for a bid that lost
+pub fn fire_loss_notice(client: Client, lurl: &str, auction: &AuctionMacros) {
+    fire_beacon(client, auction.expand(lurl), "loss".to_string());
 }
 
 #[cfg(test)]
@@ -260,4 +378,24 @@ mod tests {
         assert!(result.iter().any(|e| e.event == "complete"));
         assert_eq!(result.len(), 5);
     }
+
+    #[test]
+    fn test_auction_macros_expand() {
+        let auction = AuctionMacros {
+            auction_id: "req-1".into(),
+            bid_id: "bid-7".into(),
+            imp_id: "1".into(),
+            seat_id: "dsp".into(),
+            ad_id: "ad-3".into(),
+            price: 12.5,
+            currency: "USD".into(),
+            loss: Some(102),
+        };
+        assert_eq!(
+            auction.expand(
+                "https://dsp.example.com/loss?a=${AUCTION_ID}&b=${AUCTION_BID_ID}&p=${AUCTION_PRICE}&c=${AUCTION_CURRENCY}&l=${AUCTION_LOSS}&m=${AUCTION_MIN_TO_WIN}"
+            ),
+            "https://dsp.example.com/loss?a=req-1&b=bid-7&p=12.5&c=USD&l=102&m=12.5"
+        );
+    }
 }
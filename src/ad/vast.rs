@@ -1,6 +1,7 @@
 use crate::error::{Result, RitcherError};
 use quick_xml::events::Event;
 use quick_xml::reader::Reader;
+use serde::{Deserialize, Serialize};
 use tracing::{info, warn};
 
 /// Parsed VAST response containing ads
@@ -14,6 +15,8 @@ pub struct VastResponse {
 #[derive(Debug, Clone)]
 pub struct VastAd {
     pub id: String,
+    /// Position in the ad pod (`Ad@sequence`); `None` for stand-alone ads
+    pub sequence: Option<u32>,
     pub ad_type: VastAdType,
 }
 
@@ -32,20 +35,38 @@ pub struct InLineAd {
     pub creatives: Vec<Creative>,
     pub impression_urls: Vec<String>,
     pub error_url: Option<String>,
+    pub advertiser: Option<String>,
+    pub categories: Vec<Category>,
+    pub pricing: Option<Pricing>,
+    pub ad_verifications: Vec<Verification>,
+    pub extensions: Vec<Extension>,
 }
 
 /// Wrapper ad that references another VAST tag
 #[derive(Debug, Clone)]
 pub struct WrapperAd {
     pub ad_tag_uri: String,
+    /// Whether the wrapped response may itself be a wrapper (default true)
+    pub follow_additional_wrappers: bool,
+    /// Whether the wrapped response may contain a pod or several ads (default false)
+    pub allow_multiple_ads: bool,
+    /// Whether buffet ads may replace this ad when it yields no ads (default true)
+    pub fallback_on_no_ad: bool,
+    pub error_urls: Vec<String>,
     pub impression_urls: Vec<String>,
     pub tracking_events: Vec<TrackingEvent>,
+    pub ad_verifications: Vec<Verification>,
+    pub extensions: Vec<Extension>,
 }
 
 /// A creative containing linear video content
 #[derive(Debug, Clone)]
 pub struct Creative {
     pub id: String,
+    /// Ad server's identifier for the creative (`Creative@adId`)
+    pub ad_id: Option<String>,
+    /// Registry-issued creative identifiers (VAST 4)
+    pub universal_ad_ids: Vec<UniversalAdId>,
     pub linear: Option<LinearAd>,
 }
 
@@ -54,6 +75,10 @@ pub struct Creative {
 pub struct LinearAd {
     pub duration: f32,
     pub media_files: Vec<MediaFile>,
+    /// High-quality source files for transcoding (VAST 4)
+    pub mezzanine: Vec<Mezzanine>,
+    /// Interactive files the player would run alongside the media (VAST 4)
+    pub interactive_creative_files: Vec<InteractiveCreativeFile>,
     pub tracking_events: Vec<TrackingEvent>,
 }
 
@@ -67,15 +92,128 @@ pub struct MediaFile {
     pub height: u32,
     pub bitrate: Option<u32>,
     pub codec: Option<String>,
+    /// `apiFramework`, e.g. "VPAID" for interactive units
+    pub api_framework: Option<String>,
+}
+
+/// Mezzanine source file for an ad creative
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Mezzanine {
+    pub url: String,
+    pub delivery: String,
+    pub mime_type: String,
+    pub width: u32,
+    pub height: u32,
+    pub codec: Option<String>,
+    pub file_size: Option<u64>,
+}
+
+/// Interactive file (e.g. SIMID) delivered alongside a linear creative
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InteractiveCreativeFile {
+    pub url: String,
+    pub mime_type: String,
+    pub api_framework: Option<String>,
+    pub variable_duration: bool,
 }
 
 /// Tracking event for ad playback reporting
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TrackingEvent {
     pub event: String,
     pub url: String,
 }
 
+/// Creative identifier from an ID registry (e.g. Ad-ID)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UniversalAdId {
+    pub id_registry: String,
+    pub value: String,
+}
+
+/// Ad category code, optionally qualified by its taxonomy authority
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Category {
+    pub authority: Option<String>,
+    pub code: String,
+}
+
+/// Price of the ad as reported by the ad server
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Pricing {
+    /// Pricing model, e.g. "CPM"
+    pub model: String,
+    /// ISO 4217 currency code
+    pub currency: String,
+    pub value: f64,
+}
+
+/// Third-party verification script to run for the ad (OMID)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Verification {
+    pub vendor: Option<String>,
+    pub javascript_resources: Vec<VerificationResource>,
+    pub tracking_events: Vec<TrackingEvent>,
+    pub parameters: Option<String>,
+}
+
+/// A verification script URL
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VerificationResource {
+    pub url: String,
+    pub api_framework: Option<String>,
+    pub browser_optional: bool,
+}
+
+/// A vendor extension, kept as raw XML
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Extension {
+    pub extension_type: Option<String>,
+    pub xml: String,
+}
+
+impl VastResponse {
+    /// Ads of the pod (those with `sequence`), in play order
+    pub fn pod(&self) -> Vec<&VastAd> {
+        let mut pod: Vec<&VastAd> = self.ads.iter().filter(|a| a.sequence.is_some()).collect();
+        pod.sort_by_key(|a| a.sequence);
+        pod
+    }
+
+    /// Stand-alone ads (no `sequence`), in document order
+    ///
+    /// When a pod is present these are the buffet: they replace pod ads that
+    /// fail and fill the time the pod leaves unused.
+    pub fn buffet(&self) -> Vec<&VastAd> {
+        self.ads.iter().filter(|a| a.sequence.is_none()).collect()
+    }
+}
+
+/// VAST error codes reported through the `[ERRORCODE]` macro of Error URLs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VastErrorCode {
+    /// XML parsing error
+    XmlParseError = 100,
+    /// General wrapper error (e.g. a wrapper where none was allowed)
+    WrapperError = 300,
+    /// Wrapped VAST URI timed out or was unavailable
+    WrapperTimeout = 301,
+    /// Wrapper limit reached
+    WrapperLimit = 302,
+    /// No ads in the VAST response after one or more wrappers
+    NoAdsAfterWrapper = 303,
+    /// No media file of a supported type
+    UnsupportedMedia = 403,
+    /// The media file could not be fetched
+    MediaFetchFailed = 405,
+}
+
+impl VastErrorCode {
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+}
+
 /// Parse VAST XML into structured data
 pub fn parse_vast(xml: &str) -> Result<VastResponse> {
     let mut reader = Reader::from_str(xml);
@@ -91,7 +229,8 @@ pub fn parse_vast(xml: &str) -> Result<VastResponse> {
             }
             Ok(Event::Start(ref e)) if e.name().as_ref() == b"Ad" => {
                 let ad_id = get_attr(e, "id").unwrap_or_default();
-                if let Some(ad) = parse_ad(&mut reader, ad_id)? {
+                let sequence = get_attr(e, "sequence").and_then(|s| s.trim().parse().ok());
+                if let Some(ad) = parse_ad(&mut reader, ad_id, sequence)? {
                     ads.push(ad);
                 }
             }
@@ -116,20 +255,32 @@ pub fn parse_vast(xml: &str) -> Result<VastResponse> {
 }
 
 /// Parse a single <Ad> element
-fn parse_ad(reader: &mut Reader<&[u8]>, id: String) -> Result<Option<VastAd>> {
+fn parse_ad(
+    reader: &mut Reader<&[u8]>,
+    id: String,
+    sequence: Option<u32>,
+) -> Result<Option<VastAd>> {
     loop {
         match reader.read_event() {
             Ok(Event::Start(ref e)) if e.name().as_ref() == b"InLine" => {
                 let inline = parse_inline(reader)?;
                 return Ok(Some(VastAd {
                     id,
+                    sequence,
                     ad_type: VastAdType::InLine(inline),
                 }));
             }
             Ok(Event::Start(ref e)) if e.name().as_ref() == b"Wrapper" => {
-                let wrapper = parse_wrapper(reader)?;
+                let flag = |name: &str, default: bool| {
+                    get_attr(e, name).map_or(default, |v| v == "true" || v == "1")
+                };
+                let mut wrapper = parse_wrapper(reader)?;
+                wrapper.follow_additional_wrappers = flag("followAdditionalWrappers", true);
+                wrapper.allow_multiple_ads = flag("allowMultipleAds", false);
+                wrapper.fallback_on_no_ad = flag("fallbackOnNoAd", true);
                 return Ok(Some(VastAd {
                     id,
+                    sequence,
                     ad_type: VastAdType::Wrapper(wrapper),
                 }));
             }
@@ -153,6 +304,11 @@ fn parse_inline(reader: &mut Reader<&[u8]>) -> Result<InLineAd> {
     let mut creatives = Vec::new();
     let mut impression_urls = Vec::new();
     let mut error_url = None;
+    let mut advertiser = None;
+    let mut categories = Vec::new();
+    let mut pricing = None;
+    let mut ad_verifications = Vec::new();
+    let mut extensions = Vec::new();
 
     loop {
         match reader.read_event() {
@@ -174,6 +330,37 @@ fn parse_inline(reader: &mut Reader<&[u8]>) -> Result<InLineAd> {
             Ok(Event::Start(ref e)) if e.name().as_ref() == b"Creatives" => {
                 creatives = parse_creatives(reader)?;
             }
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"Advertiser" => {
+                advertiser = Some(read_text(reader, "Advertiser")?).filter(|a| !a.is_empty());
+            }
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"Category" => {
+                let authority = get_attr(e, "authority");
+                let code = read_text(reader, "Category")?;
+                if !code.is_empty() {
+                    categories.push(Category { authority, code });
+                }
+            }
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"Pricing" => {
+                let model = get_attr(e, "model").unwrap_or_default();
+                let currency = get_attr(e, "currency").unwrap_or_default();
+                let text = read_text(reader, "Pricing")?;
+                match text.parse() {
+                    Ok(value) => {
+                        pricing = Some(Pricing {
+                            model,
+                            currency,
+                            value,
+                        })
+                    }
+                    Err(_) => warn!("Ignoring invalid VAST Pricing value: {}", text),
+                }
+            }
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"AdVerifications" => {
+                ad_verifications = parse_ad_verifications(reader)?;
+            }
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"Extensions" => {
+                extensions = parse_extensions(reader)?;
+            }
             Ok(Event::End(ref e)) if e.name().as_ref() == b"InLine" => break,
             Ok(Event::Eof) => break,
             Err(e) => {
@@ -192,20 +379,34 @@ fn parse_inline(reader: &mut Reader<&[u8]>) -> Result<InLineAd> {
         creatives,
         impression_urls,
         error_url,
+        advertiser,
+        categories,
+        pricing,
+        ad_verifications,
+        extensions,
     })
 }
 
 /// Parse <Wrapper> element
 fn parse_wrapper(reader: &mut Reader<&[u8]>) -> Result<WrapperAd> {
     let mut ad_tag_uri = String::new();
+    let mut error_urls = Vec::new();
     let mut impression_urls = Vec::new();
     let mut tracking_events = Vec::new();
+    let mut ad_verifications = Vec::new();
+    let mut extensions = Vec::new();
 
     loop {
         match reader.read_event() {
             Ok(Event::Start(ref e)) if e.name().as_ref() == b"VASTAdTagURI" => {
                 ad_tag_uri = read_text(reader, "VASTAdTagURI")?;
             }
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"Error" => {
+                let url = read_text(reader, "Error")?;
+                if !url.is_empty() {
+                    error_urls.push(url);
+                }
+            }
             Ok(Event::Start(ref e)) if e.name().as_ref() == b"Impression" => {
                 let url = read_text(reader, "Impression")?;
                 if !url.is_empty() {
@@ -215,6 +416,12 @@ fn parse_wrapper(reader: &mut Reader<&[u8]>) -> Result<WrapperAd> {
             Ok(Event::Start(ref e)) if e.name().as_ref() == b"TrackingEvents" => {
                 tracking_events = parse_tracking_events(reader)?;
             }
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"AdVerifications" => {
+                ad_verifications = parse_ad_verifications(reader)?;
+            }
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"Extensions" => {
+                extensions = parse_extensions(reader)?;
+            }
             Ok(Event::End(ref e)) if e.name().as_ref() == b"Wrapper" => break,
             Ok(Event::Eof) => break,
             Err(e) => {
@@ -229,8 +436,14 @@ fn parse_wrapper(reader: &mut Reader<&[u8]>) -> Result<WrapperAd> {
 
     Ok(WrapperAd {
         ad_tag_uri,
+        follow_additional_wrappers: true,
+        allow_multiple_ads: false,
+        fallback_on_no_ad: true,
+        error_urls,
         impression_urls,
         tracking_events,
+        ad_verifications,
+        extensions,
     })
 }
 
@@ -242,7 +455,8 @@ fn parse_creatives(reader: &mut Reader<&[u8]>) -> Result<Vec<Creative>> {
         match reader.read_event() {
             Ok(Event::Start(ref e)) if e.name().as_ref() == b"Creative" => {
                 let id = get_attr(e, "id").unwrap_or_default();
-                let creative = parse_creative(reader, id)?;
+                let ad_id = get_attr(e, "adId").or_else(|| get_attr(e, "AdID"));
+                let creative = parse_creative(reader, id, ad_id)?;
                 creatives.push(creative);
             }
             Ok(Event::End(ref e)) if e.name().as_ref() == b"Creatives" => break,
@@ -261,11 +475,34 @@ fn parse_creatives(reader: &mut Reader<&[u8]>) -> Result<Vec<Creative>> {
 }
 
 /// Parse a single <Creative> element
-fn parse_creative(reader: &mut Reader<&[u8]>, id: String) -> Result<Creative> {
+fn parse_creative(
+    reader: &mut Reader<&[u8]>,
+    id: String,
+    ad_id: Option<String>,
+) -> Result<Creative> {
     let mut linear = None;
+    let mut universal_ad_ids = Vec::new();
 
     loop {
         match reader.read_event() {
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"UniversalAdId" => {
+                let id_registry = get_attr(e, "idRegistry").unwrap_or_default();
+                // VAST 4.0 carries the id in @idValue, 4.1+ in the element text
+                let id_value = get_attr(e, "idValue");
+                let text = read_text(reader, "UniversalAdId")?;
+                let value = if text.is_empty() {
+                    id_value.unwrap_or_default()
+                } else {
+                    text
+                };
+                universal_ad_ids.push(UniversalAdId { id_registry, value });
+            }
+            Ok(Event::Empty(ref e)) if e.name().as_ref() == b"UniversalAdId" => {
+                universal_ad_ids.push(UniversalAdId {
+                    id_registry: get_attr(e, "idRegistry").unwrap_or_default(),
+                    value: get_attr(e, "idValue").unwrap_or_default(),
+                });
+            }
             Ok(Event::Start(ref e)) if e.name().as_ref() == b"Linear" => {
                 linear = Some(parse_linear(reader)?);
             }
@@ -281,26 +518,35 @@ fn parse_creative(reader: &mut Reader<&[u8]>, id: String) -> Result<Creative> {
         }
     }
 
-    Ok(Creative { id, linear })
+    Ok(Creative {
+        id,
+        ad_id,
+        universal_ad_ids,
+        linear,
+    })
 }
 
 /// Parse <Linear> element
 fn parse_linear(reader: &mut Reader<&[u8]>) -> Result<LinearAd> {
-    let mut duration = 0.0;
-    let mut media_files = Vec::new();
-    let mut tracking_events = Vec::new();
+    let mut linear = LinearAd {
+        duration: 0.0,
+        media_files: Vec::new(),
+        mezzanine: Vec::new(),
+        interactive_creative_files: Vec::new(),
+        tracking_events: Vec::new(),
+    };
 
     loop {
         match reader.read_event() {
             Ok(Event::Start(ref e)) if e.name().as_ref() == b"Duration" => {
                 let dur_str = read_text(reader, "Duration")?;
-                duration = parse_duration(&dur_str);
+                linear.duration = parse_duration(&dur_str);
             }
             Ok(Event::Start(ref e)) if e.name().as_ref() == b"MediaFiles" => {
-                media_files = parse_media_files(reader)?;
+                parse_media_files(reader, &mut linear)?;
             }
             Ok(Event::Start(ref e)) if e.name().as_ref() == b"TrackingEvents" => {
-                tracking_events = parse_tracking_events(reader)?;
+                linear.tracking_events = parse_tracking_events(reader)?;
             }
             Ok(Event::End(ref e)) if e.name().as_ref() == b"Linear" => break,
             Ok(Event::Eof) => break,
@@ -314,17 +560,11 @@ fn parse_linear(reader: &mut Reader<&[u8]>) -> Result<LinearAd> {
         }
     }
 
-    Ok(LinearAd {
-        duration,
-        media_files,
-        tracking_events,
-    })
+    Ok(linear)
 }
 
-/// Parse <MediaFiles> element
-fn parse_media_files(reader: &mut Reader<&[u8]>) -> Result<Vec<MediaFile>> {
-    let mut files = Vec::new();
-
+/// Parse <MediaFiles> element: media files, Mezzanine and InteractiveCreativeFile
+fn parse_media_files(reader: &mut Reader<&[u8]>, linear: &mut LinearAd) -> Result<()> {
     loop {
         match reader.read_event() {
             Ok(Event::Start(ref e)) if e.name().as_ref() == b"MediaFile" => {
@@ -338,10 +578,11 @@ fn parse_media_files(reader: &mut Reader<&[u8]>) -> Result<Vec<MediaFile>> {
                     .unwrap_or(0);
                 let bitrate = get_attr(e, "bitrate").and_then(|s| s.parse().ok());
                 let codec = get_attr(e, "codec");
+                let api_framework = get_attr(e, "apiFramework");
 
                 let url = read_text(reader, "MediaFile")?.trim().to_string();
 
-                files.push(MediaFile {
+                linear.media_files.push(MediaFile {
                     url,
                     delivery,
                     mime_type,
@@ -349,8 +590,48 @@ fn parse_media_files(reader: &mut Reader<&[u8]>) -> Result<Vec<MediaFile>> {
                     height,
                     bitrate,
                     codec,
+                    api_framework,
                 });
             }
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"Mezzanine" => {
+                let delivery = get_attr(e, "delivery").unwrap_or_default();
+                let mime_type = get_attr(e, "type").unwrap_or_default();
+                let width = get_attr(e, "width")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0);
+                let height = get_attr(e, "height")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0);
+                let codec = get_attr(e, "codec");
+                let file_size = get_attr(e, "fileSize").and_then(|s| s.parse().ok());
+                let url = read_text(reader, "Mezzanine")?;
+
+                linear.mezzanine.push(Mezzanine {
+                    url,
+                    delivery,
+                    mime_type,
+                    width,
+                    height,
+                    codec,
+                    file_size,
+                });
+            }
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"InteractiveCreativeFile" => {
+                let mime_type = get_attr(e, "type").unwrap_or_default();
+                let api_framework = get_attr(e, "apiFramework");
+                let variable_duration =
+                    get_attr(e, "variableDuration").is_some_and(|v| v == "true" || v == "1");
+                let url = read_text(reader, "InteractiveCreativeFile")?;
+
+                linear
+                    .interactive_creative_files
+                    .push(InteractiveCreativeFile {
+                        url,
+                        mime_type,
+                        api_framework,
+                        variable_duration,
+                    });
+            }
             Ok(Event::End(ref e)) if e.name().as_ref() == b"MediaFiles" => break,
             Ok(Event::Eof) => break,
             Err(e) => {
@@ -363,7 +644,7 @@ fn parse_media_files(reader: &mut Reader<&[u8]>) -> Result<Vec<MediaFile>> {
         }
     }
 
-    Ok(files)
+    Ok(())
 }
 
 /// Parse <TrackingEvents> element
@@ -392,6 +673,108 @@ fn parse_tracking_events(reader: &mut Reader<&[u8]>) -> Result<Vec<TrackingEvent
     Ok(events)
 }
 
+/// Parse <AdVerifications> element
+fn parse_ad_verifications(reader: &mut Reader<&[u8]>) -> Result<Vec<Verification>> {
+    let mut verifications = Vec::new();
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"Verification" => {
+                let vendor = get_attr(e, "vendor");
+                verifications.push(parse_verification(reader, vendor)?);
+            }
+            Ok(Event::End(ref e)) if e.name().as_ref() == b"AdVerifications" => break,
+            Ok(Event::Eof) => break,
+            Err(e) => {
+                return Err(RitcherError::InternalError(format!(
+                    "VAST XML parse error in AdVerifications: {}",
+                    e
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(verifications)
+}
+
+/// Parse a single <Verification> element
+fn parse_verification(reader: &mut Reader<&[u8]>, vendor: Option<String>) -> Result<Verification> {
+    let mut verification = Verification {
+        vendor,
+        ..Default::default()
+    };
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"JavaScriptResource" => {
+                let api_framework = get_attr(e, "apiFramework");
+                let browser_optional =
+                    get_attr(e, "browserOptional").is_some_and(|v| v == "true" || v == "1");
+                let url = read_text(reader, "JavaScriptResource")?;
+                verification
+                    .javascript_resources
+                    .push(VerificationResource {
+                        url,
+                        api_framework,
+                        browser_optional,
+                    });
+            }
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"TrackingEvents" => {
+                verification.tracking_events = parse_tracking_events(reader)?;
+            }
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"VerificationParameters" => {
+                verification.parameters = Some(read_text(reader, "VerificationParameters")?);
+            }
+            Ok(Event::End(ref e)) if e.name().as_ref() == b"Verification" => break,
+            Ok(Event::Eof) => break,
+            Err(e) => {
+                return Err(RitcherError::InternalError(format!(
+                    "VAST XML parse error in Verification: {}",
+                    e
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(verification)
+}
+
+/// Parse <Extensions> element, keeping each extension's inner XML verbatim
+fn parse_extensions(reader: &mut Reader<&[u8]>) -> Result<Vec<Extension>> {
+    let mut extensions = Vec::new();
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(ref e)) if e.name().as_ref() == b"Extension" => {
+                let extension_type = get_attr(e, "type");
+                let xml = reader.read_text(e.name()).map_err(|err| {
+                    RitcherError::InternalError(format!(
+                        "VAST XML parse error in Extension: {}",
+                        err
+                    ))
+                })?;
+                extensions.push(Extension {
+                    extension_type,
+                    xml: xml.trim().to_string(),
+                });
+            }
+            Ok(Event::End(ref e)) if e.name().as_ref() == b"Extensions" => break,
+            Ok(Event::Eof) => break,
+            Err(e) => {
+                return Err(RitcherError::InternalError(format!(
+                    "VAST XML parse error in Extensions: {}",
+                    e
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(extensions)
+}
+
 /// Parse VAST duration format "HH:MM:SS" or "HH:MM:SS.mmm" to seconds
 fn parse_duration(duration: &str) -> f32 {
     let parts: Vec<&str> = duration.trim().split(':').collect();
@@ -512,6 +895,68 @@ mod tests {
   </Ad>
 </VAST>"#;
 
+    const VAST_4_POD: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<VAST version="4.2">
+  <Ad id="second" sequence="2">
+    <InLine>
+      <AdSystem>Ads</AdSystem>
+      <AdTitle>Second</AdTitle>
+      <Advertiser>Acme Cars</Advertiser>
+      <Category authority="https://www.iab.com/categories">IAB2</Category>
+      <Pricing model="CPM" currency="USD"><![CDATA[ 12.50 ]]></Pricing>
+      <AdVerifications>
+        <Verification vendor="verifier.example.com-omid">
+          <JavaScriptResource apiFramework="omid" browserOptional="true">
+            <![CDATA[https://verifier.example.com/omid.js]]>
+          </JavaScriptResource>
+          <TrackingEvents>
+            <Tracking event="verificationNotExecuted">https://verifier.example.com/ne</Tracking>
+          </TrackingEvents>
+          <VerificationParameters><![CDATA[{"k":"v"}]]></VerificationParameters>
+        </Verification>
+      </AdVerifications>
+      <Creatives>
+        <Creative id="c2" adId="acme-30">
+          <UniversalAdId idRegistry="ad-id.org">ACME0030000H</UniversalAdId>
+          <Linear>
+            <Duration>00:00:30</Duration>
+            <MediaFiles>
+              <MediaFile delivery="progressive" type="video/mp4" width="1920" height="1080">https://example.com/c2.mp4</MediaFile>
+              <Mezzanine delivery="progressive" type="video/mp4" width="1920" height="1080" fileSize="900000000">https://example.com/c2-mezz.mov</Mezzanine>
+              <InteractiveCreativeFile type="text/html" apiFramework="SIMID" variableDuration="true">https://example.com/simid.html</InteractiveCreativeFile>
+            </MediaFiles>
+          </Linear>
+        </Creative>
+      </Creatives>
+      <Extensions>
+        <Extension type="waterfall"><Ordinal>1</Ordinal></Extension>
+      </Extensions>
+    </InLine>
+  </Ad>
+  <Ad id="buffet">
+    <InLine>
+      <AdSystem>Ads</AdSystem>
+      <AdTitle>Buffet</AdTitle>
+      <Creatives>
+        <Creative id="cb">
+          <UniversalAdId idRegistry="ad-id.org" idValue="BUFF0015000H"/>
+          <Linear><Duration>00:00:15</Duration></Linear>
+        </Creative>
+      </Creatives>
+    </InLine>
+  </Ad>
+  <Ad id="first" sequence="1">
+    <Wrapper>
+      <VASTAdTagURI>https://example.com/first.xml</VASTAdTagURI>
+      <AdVerifications>
+        <Verification vendor="wrapper-verifier">
+          <JavaScriptResource apiFramework="omid">https://wrapper.example.com/v.js</JavaScriptResource>
+        </Verification>
+      </AdVerifications>
+    </Wrapper>
+  </Ad>
+</VAST>"#;
+
     const VAST_EMPTY: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
 <VAST version="3.0">
 </VAST>"#;
@@ -573,6 +1018,128 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_pod_ordered_by_sequence_and_buffet() {
+        let result = parse_vast(VAST_4_POD).unwrap();
+        assert_eq!(result.ads.len(), 3);
+
+        let pod: Vec<&str> = result.pod().iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(pod, vec!["first", "second"]);
+        let buffet: Vec<&str> = result.buffet().iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(buffet, vec!["buffet"]);
+    }
+
+    #[test]
+    fn test_parse_vast4_inline_metadata() {
+        let result = parse_vast(VAST_4_POD).unwrap();
+        let VastAdType::InLine(inline) = &result.ads[0].ad_type else {
+            panic!("Expected InLine ad");
+        };
+
+        assert_eq!(result.ads[0].sequence, Some(2));
+        assert_eq!(inline.advertiser.as_deref(), Some("Acme Cars"));
+        assert_eq!(
+            inline.categories,
+            vec![Category {
+                authority: Some("https://www.iab.com/categories".to_string()),
+                code: "IAB2".to_string(),
+            }]
+        );
+        assert_eq!(
+            inline.pricing,
+            Some(Pricing {
+                model: "CPM".to_string(),
+                currency: "USD".to_string(),
+                value: 12.5,
+            })
+        );
+
+        let verification = &inline.ad_verifications[0];
+        assert_eq!(
+            verification.vendor.as_deref(),
+            Some("verifier.example.com-omid")
+        );
+        assert_eq!(
+            verification.javascript_resources[0],
+            VerificationResource {
+                url: "https://verifier.example.com/omid.js".to_string(),
+                api_framework: Some("omid".to_string()),
+                browser_optional: true,
+            }
+        );
+        assert_eq!(verification.tracking_events.len(), 1);
+        assert_eq!(verification.parameters.as_deref(), Some(r#"{"k":"v"}"#));
+
+        assert_eq!(inline.extensions.len(), 1);
+        assert_eq!(
+            inline.extensions[0].extension_type.as_deref(),
+            Some("waterfall")
+        );
+        assert_eq!(inline.extensions[0].xml, "<Ordinal>1</Ordinal>");
+
+        let creative = &inline.creatives[0];
+        assert_eq!(creative.ad_id.as_deref(), Some("acme-30"));
+        assert_eq!(creative.universal_ad_ids[0].id_registry, "ad-id.org");
+        assert_eq!(creative.universal_ad_ids[0].value, "ACME0030000H");
+
+        let linear = creative.linear.as_ref().unwrap();
+        assert_eq!(linear.media_files.len(), 1);
+        assert_eq!(linear.mezzanine[0].url, "https://example.com/c2-mezz.mov");
+        assert_eq!(linear.mezzanine[0].file_size, Some(900_000_000));
+        let icf = &linear.interactive_creative_files[0];
+        assert_eq!(icf.api_framework.as_deref(), Some("SIMID"));
+        assert!(icf.variable_duration);
+    }
+
+    #[test]
+    fn test_parse_vast4_universal_ad_id_attribute_and_wrapper_verifications() {
+        let result = parse_vast(VAST_4_POD).unwrap();
+
+        // VAST 4.0 style @idValue
+        let VastAdType::InLine(buffet) = &result.ads[1].ad_type else {
+            panic!("Expected InLine ad");
+        };
+        assert_eq!(
+            buffet.creatives[0].universal_ad_ids[0].value,
+            "BUFF0015000H"
+        );
+
+        let VastAdType::Wrapper(wrapper) = &result.ads[2].ad_type else {
+            panic!("Expected Wrapper ad");
+        };
+        assert_eq!(
+            wrapper.ad_verifications[0].vendor.as_deref(),
+            Some("wrapper-verifier")
+        );
+    }
+
+    #[test]
+    fn test_parse_wrapper_attributes_and_errors() {
+        let xml = r#"<VAST version="4.2"><Ad id="w">
+  <Wrapper followAdditionalWrappers="false" allowMultipleAds="true" fallbackOnNoAd="false">
+    <VASTAdTagURI>https://example.com/next.xml</VASTAdTagURI>
+    <Error><![CDATA[https://example.com/err?code=[ERRORCODE]]]></Error>
+    <Error>https://other.example.com/err</Error>
+  </Wrapper>
+</Ad></VAST>"#;
+        let result = parse_vast(xml).unwrap();
+        let VastAdType::Wrapper(wrapper) = &result.ads[0].ad_type else {
+            panic!("Expected Wrapper ad");
+        };
+        assert!(!wrapper.follow_additional_wrappers);
+        assert!(wrapper.allow_multiple_ads);
+        assert!(!wrapper.fallback_on_no_ad);
+        assert_eq!(wrapper.error_urls.len(), 2);
+
+        // Defaults when the attributes are absent
+        let VastAdType::Wrapper(plain) = &parse_vast(VAST_WRAPPER).unwrap().ads[0].ad_type else {
+            panic!("Expected Wrapper ad");
+        };
+        assert!(plain.follow_additional_wrappers);
+        assert!(!plain.allow_multiple_ads);
+        assert!(plain.fallback_on_no_ad);
+    }
+
     #[test]
     fn test_parse_empty_vast() {
         let result = parse_vast(VAST_EMPTY).unwrap();
@@ -600,6 +1167,7 @@ mod tests {
                 height: 720,
                 bitrate: Some(2000),
                 codec: Some("H.264".to_string()),
+                api_framework: None,
             },
             MediaFile {
                 url: "https://example.com/ad.m3u8".to_string(),
@@ -609,6 +1177,7 @@ mod tests {
                 height: 720,
                 bitrate: None,
                 codec: None,
+                api_framework: None,
             },
         ];
 
@@ -626,6 +1195,7 @@ mod tests {
             height: 720,
             bitrate: Some(2000),
             codec: Some("H.264".to_string()),
+            api_framework: None,
         }];
 
         let best = select_best_media_file(&files).unwrap();
@@ -1,13 +1,32 @@
-use crate::ad::conditioning;
-use crate::ad::provider::{AdCreative, AdProvider, AdSegment, AdTrackingInfo, ResolvedSegment};
-use crate::ad::slate::SlateProvider;
-use crate::ad::vast::{self, TrackingEvent, VastAdType};
+use crate::ad::conditioning::{self, Verdict};
+use crate::ad::macros::{self, BreakPosition, MacroContext};
+use crate::ad::normalizer::AdNormalizer;
+use crate::ad::pod::{self, PodCandidate, PodConstraints};
+use crate::ad::policy::{AdPolicy, PolicyState};
+use crate::ad::provider::{
+    self, AdCreative, AdMetadata, AdProvider, AdSegment, AdTrackingInfo, ResolvedSegment,
+};
+use crate::ad::slate::{self, SlateProvider, SlateReason};
+use crate::ad::tracking;
+use crate::ad::vast::{
+    self, Extension, TrackingEvent, VastAd, VastAdType, VastErrorCode, Verification, WrapperAd,
+};
 use crate::metrics;
+use crate::session::{BreakDecision, SessionManager, ViewerContext};
 use dashmap::DashMap;
+use futures::future::{BoxFuture, join_all};
 use reqwest::Client;
+use std::collections::BTreeSet;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tracing::{error, info, warn};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+use tracing::{debug, error, info, warn};
+
+/// How long decided creatives stay resolvable by default
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Most creatives cached across all sessions by default
+const DEFAULT_CACHE_MAX_CREATIVES: usize = 10_000;
 
 /// Ad creative resolved from VAST (before caching)
 #[derive(Debug, Clone)]
@@ -16,40 +35,69 @@ struct ResolvedVastCreative {
     url: String,
     /// Duration in seconds
     duration: f32,
-    /// Whether this is an HLS stream (vs progressive MP4)
-    is_hls: bool,
     /// Impression URLs to fire
     impression_urls: Vec<String>,
     /// Tracking events
     tracking_events: Vec<TrackingEvent>,
-    /// Error URL
-    error_url: Option<String>,
+    /// Error URLs of the ad and every wrapper above it
+    error_urls: Vec<String>,
+    /// The ad this creative belongs to
+    ad: AdMetadata,
+    /// Macro values for this creative's beacons
+    macros: MacroContext,
+    /// Conditioned out: holds its place in the pod, but slate plays
+    slate: bool,
 }
 
-/// Ad creative cached per session with tracking state
-#[derive(Debug, Clone)]
-#[allow(dead_code)]
-struct ResolvedCreative {
-    /// URL to the ad creative (HLS playlist or MP4)
-    url: String,
-    /// Duration in seconds
-    duration: f32,
-    /// Whether this is an HLS stream (vs progressive MP4)
-    is_hls: bool,
-    /// Impression URLs to fire
+/// Data a wrapper chain passes down to the InLine ads it resolves to
+#[derive(Debug, Clone, Default)]
+struct WrapperChain {
+    error_urls: Vec<String>,
     impression_urls: Vec<String>,
-    /// Tracking events
     tracking_events: Vec<TrackingEvent>,
-    /// Error URL
-    error_url: Option<String>,
-    /// Total segments in this ad
-    total_segments: usize,
-    /// Index of this segment
-    segment_index: usize,
-    /// Whether tracking has been returned for this segment (deduplication)
-    visited: bool,
-    /// When this entry was inserted (for TTL-based eviction)
-    inserted_at: Instant,
+    ad_verifications: Vec<Verification>,
+    extensions: Vec<Extension>,
+}
+
+/// One ad of a VAST response after resolution
+#[derive(Debug, Default)]
+struct ResolvedAd {
+    creatives: Vec<ResolvedVastCreative>,
+    /// A wrapper with `fallbackOnNoAd="false"` yielded no ads, so its pod
+    /// slot must not be refilled from the buffet
+    forbids_fallback: bool,
+}
+
+/// Decided breaks keyed by (session id, break id)
+type AdCache = DashMap<(String, String), CachedBreak>;
+
+/// A break's decision as cached by this provider
+///
+/// Which segments had their tracking handed out is local to the provider;
+/// sessions record deliveries in the store instead.
+#[derive(Debug)]
+struct CachedBreak {
+    decision: BreakDecision,
+    delivered: BTreeSet<usize>,
+}
+
+impl CachedBreak {
+    fn new(decision: BreakDecision) -> Self {
+        Self {
+            decision,
+            delivered: BTreeSet::new(),
+        }
+    }
+
+    /// Segment `index`, with its tracking on the first delivery only
+    fn deliver(&mut self, index: usize) -> Option<ResolvedSegment> {
+        let segment = self.decision.segments.get(index)?;
+        let first = self.delivered.insert(index);
+        Some(ResolvedSegment {
+            url: segment.url.clone(),
+            tracking: segment.tracking.clone().filter(|_| first),
+        })
+    }
 }
 
 /// VAST-based ad provider that fetches ads from a VAST endpoint
@@ -60,34 +108,56 @@ struct ResolvedCreative {
 /// 3. Caching resolved creatives per session for segment URL resolution
 #[derive(Clone)]
 pub struct VastAdProvider {
-    /// VAST endpoint URL (with optional macros like [DURATION])
+    /// VAST endpoint URL (with optional IAB macros like [DURATION])
     vast_endpoint: String,
     /// HTTP client for VAST requests
     http_client: Client,
-    /// Per-session ad cache: maps "session_id:break-N-seg-M" to creative URL
-    ad_cache: Arc<DashMap<String, ResolvedCreative>>,
+    /// Decided creatives of each session's breaks, for segment resolution
+    /// when no session store is attached
+    ad_cache: Arc<AdCache>,
+    /// Creatives in `ad_cache`, across all breaks
+    cached_creatives: Arc<AtomicUsize>,
+    /// How long decided creatives stay resolvable
+    cache_ttl: Duration,
+    /// Most creatives kept in `ad_cache`; the oldest breaks go first
+    cache_max_creatives: usize,
     /// Maximum number of VAST wrapper redirects to follow
     max_wrapper_depth: u32,
     /// VAST request timeout
     timeout: Duration,
     /// Optional slate provider for fallback when VAST returns no ads
     slate: Option<SlateProvider>,
+    /// Limits for building each break's ad pod
+    pod_constraints: PodConstraints,
+    /// Frequency caps and separation applied to each break's ads
+    policy: Option<AdPolicy>,
+    /// Packages progressive creatives for stitching
+    normalizer: Option<AdNormalizer>,
+    /// Session store holding decided breaks, shared by all instances
+    sessions: Option<SessionManager>,
 }
 
 impl VastAdProvider {
     /// Create a new VastAdProvider
     ///
     /// # Arguments
-    /// * `vast_endpoint` - VAST endpoint URL (supports [DURATION] and [CACHEBUSTING] macros)
+    /// * `vast_endpoint` - VAST endpoint URL (supports the macros of [`macros::expand`])
     /// * `http_client` - Shared HTTP client for VAST requests
     pub fn new(vast_endpoint: String, http_client: Client) -> Self {
         Self {
             vast_endpoint,
             http_client,
             ad_cache: Arc::new(DashMap::new()),
+            cached_creatives: Arc::new(AtomicUsize::new(0)),
+            cache_ttl: DEFAULT_CACHE_TTL,
+            cache_max_creatives: DEFAULT_CACHE_MAX_CREATIVES,
             max_wrapper_depth: 5,
             timeout: Duration::from_millis(2000),
             slate: None,
+            pod_constraints: PodConstraints::default(),
+            policy: None,
+            normalizer: None,
+            sessions: None,
         }
     }
 
@@ -100,84 +170,153 @@ impl VastAdProvider {
         self
     }
 
-    /// Replace VAST macros in the endpoint URL
-    fn resolve_endpoint(&self, duration: f32) -> String {
+    /// Bound the creative cache (default: 5 minutes, 10,000 creatives)
+    ///
+    /// Creatives stay resolvable for `ttl` after their break is decided.
+    /// Beyond `max_creatives`, the oldest breaks are evicted first.
+    pub fn with_cache_limits(mut self, ttl: Duration, max_creatives: usize) -> Self {
+        self.cache_ttl = ttl;
+        self.cache_max_creatives = max_creatives;
+        self
+    }
+
+    /// Keep decided breaks in the session store instead of this provider
+    ///
+    /// Any instance sharing the store can then serve the break's segments,
+    /// and tracking is fired once per segment across instances. Decisions
+    /// older than the cache TTL are dropped from the session.
+    pub fn with_sessions(mut self, sessions: SessionManager) -> Self {
+        self.sessions = Some(sessions);
+        self
+    }
+
+    /// Configure the VAST request timeout (default: 2s)
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Configure the limits used when selecting ads for a break
+    pub fn with_pod_constraints(mut self, constraints: PodConstraints) -> Self {
+        self.pod_constraints = constraints;
+        self
+    }
+
+    /// Apply frequency caps and competitive separation to decided breaks
+    pub fn with_policy(mut self, policy: AdPolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Stitch progressive creatives through an ad normalizer
+    ///
+    /// Progressive creatives whose packaged rendition is not ready yet are
+    /// left out of the break.
+    pub fn with_normalizer(mut self, normalizer: AdNormalizer) -> Self {
+        self.normalizer = Some(normalizer);
+        self
+    }
+
+    /// The session's policy snapshot, or no policy if none is configured
+    async fn policy_state(&self, session_id: &str, viewer: &ViewerContext) -> PolicyState {
+        match &self.policy {
+            Some(policy) => policy.load(session_id, viewer).await,
+            None => PolicyState::default(),
+        }
+    }
+
+    /// Macro values for one break's ad request and everything it leads to
+    fn request_context(
+        &self,
+        duration: f32,
+        session_id: &str,
+        viewer: &ViewerContext,
+    ) -> MacroContext {
         let timestamp = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap_or_default()
             .as_millis();
 
-        self.vast_endpoint
-            .replace("[DURATION]", &format!("{}", duration as u32))
-            .replace("[CACHEBUSTING]", &format!("{}", timestamp))
+        MacroContext {
+            // Breaks come from in-stream cues, so they are mid-rolls
+            break_position: Some(BreakPosition::Midroll),
+            break_duration: Some(duration),
+            transaction_id: Some(format!("{}-{}", session_id, timestamp)),
+            ..Default::default()
+        }
+        .with_viewer(viewer)
     }
 
-    /// Fetch and parse VAST XML, following wrapper chains
-    ///
-    /// Uses `block_in_place` to run async HTTP requests within the sync
-    /// AdProvider trait methods. This is safe because Axum uses a multi-threaded
-    /// runtime and `block_in_place` only blocks the current thread.
-    ///
-    /// Accumulates wrapper tracking data through the chain.
-    fn fetch_vast(
+    /// Replace VAST macros in the endpoint URL
+    fn resolve_endpoint(&self, ctx: &MacroContext) -> String {
+        macros::expand(&self.vast_endpoint, ctx)
+    }
+
+    /// Fetch a VAST document and resolve it to the creatives for one break
+    async fn fetch_vast(
         &self,
         url: &str,
-        depth: u32,
         session_id: &str,
-        wrapper_impressions: &[String],
-        wrapper_tracking: &[TrackingEvent],
+        break_duration: f32,
+        ctx: &MacroContext,
+        policy: &PolicyState,
     ) -> Option<Vec<ResolvedVastCreative>> {
-        if depth > self.max_wrapper_depth {
-            warn!(
-                "VAST wrapper chain exceeded max depth ({})",
-                self.max_wrapper_depth
-            );
-            return None;
-        }
+        let xml = self.fetch_vast_xml(url).await?;
+        self.resolve_vast_document(&xml, session_id, break_duration, ctx, policy)
+            .await
+    }
 
-        let client = self.http_client.clone();
-        let url = url.to_string();
-        let timeout = self.timeout;
-
-        // Run async reqwest within sync context using block_in_place
-        // Includes 1 retry with 500ms backoff on failure
-        let xml = tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(async {
-                let max_attempts = 2;
-                for attempt in 1..=max_attempts {
-                    let response = client.get(&url).timeout(timeout).send().await;
-
-                    match response {
-                        Ok(resp) if resp.status().is_success() => {
-                            return resp.text().await.ok();
-                        }
-                        Ok(resp) => {
-                            error!(
-                                "VAST endpoint returned status {} (attempt {}/{})",
-                                resp.status(),
-                                attempt,
-                                max_attempts
-                            );
-                        }
-                        Err(e) => {
-                            error!(
-                                "VAST request failed: {} (attempt {}/{})",
-                                e, attempt, max_attempts
-                            );
-                        }
-                    }
+    /// GET a VAST document, with 1 retry and 500ms backoff on failure
+    async fn fetch_vast_xml(&self, url: &str) -> Option<String> {
+        let max_attempts = 2;
+        for attempt in 1..=max_attempts {
+            let response = self.http_client.get(url).timeout(self.timeout).send().await;
 
-                    // Retry backoff (skip on last attempt)
-                    if attempt < max_attempts {
-                        warn!("Retrying VAST request in 500ms...");
-                        tokio::time::sleep(Duration::from_millis(500)).await;
-                    }
+            match response {
+                Ok(resp) if resp.status().is_success() => {
+                    return resp.text().await.ok();
                 }
-                None
-            })
-        })?;
+                Ok(resp) => {
+                    error!(
+                        "VAST endpoint returned status {} (attempt {}/{})",
+                        resp.status(),
+                        attempt,
+                        max_attempts
+                    );
+                }
+                Err(e) => {
+                    error!(
+                        "VAST request failed: {} (attempt {}/{})",
+                        e, attempt, max_attempts
+                    );
+                }
+            }
 
-        let vast_response = match vast::parse_vast(&xml) {
+            // Retry backoff (skip on last attempt)
+            if attempt < max_attempts {
+                warn!("Retrying VAST request in 500ms...");
+                tokio::time::sleep(Duration::from_millis(500)).await;
+            }
+        }
+        None
+    }
+
+    /// Extract creatives from a parsed VAST document, following wrappers
+    ///
+    /// Every ad is resolved concurrently, then the pod builder picks the ads
+    /// that best fill `break_duration`: pod ads (with `sequence`) are
+    /// preferred and play in sequence order, while stand-alone buffet ads
+    /// only replace pod ads that failed or fill time the pod leaves. Ads
+    /// the policy does not allow are dropped before the pod is built.
+    async fn resolve_vast_document(
+        &self,
+        xml: &str,
+        session_id: &str,
+        break_duration: f32,
+        ctx: &MacroContext,
+        policy: &PolicyState,
+    ) -> Option<Vec<ResolvedVastCreative>> {
+        let vast_response = match vast::parse_vast(xml) {
             Ok(r) => r,
             Err(e) => {
                 error!("Failed to parse VAST XML: {}", e);
@@ -185,109 +324,374 @@ impl VastAdProvider {
             }
         };
 
-        let mut creatives = Vec::new();
+        let mut ads = vast_response.pod();
+        ads.extend(vast_response.buffet());
+        let chain = WrapperChain::default();
+        let resolved = join_all(
+            ads.iter()
+                .map(|ad| self.resolve_ad(ad, 0, session_id, &chain, ctx, policy)),
+        )
+        .await;
+
+        // fallbackOnNoAd="false" on a failed pod wrapper: no buffet substitution
+        let buffet_allowed = !ads
+            .iter()
+            .zip(&resolved)
+            .any(|(ad, r)| ad.sequence.is_some() && r.forbids_fallback);
+        if !buffet_allowed {
+            info!(
+                "VastAdProvider: Pod wrapper forbids fallback, buffet ads not used for session {}",
+                session_id
+            );
+        }
+
+        let candidates = ads
+            .iter()
+            .zip(resolved)
+            .filter(|(ad, r)| !r.creatives.is_empty() && (ad.sequence.is_some() || buffet_allowed))
+            .map(|(ad, r)| PodCandidate {
+                duration: r.creatives.iter().map(|c| c.duration).sum(),
+                sequence: ad.sequence,
+                priority: r.creatives[0].ad.pricing.as_ref().map_or(0.0, |p| p.value),
+                ad: r.creatives,
+            })
+            .collect();
+        let candidates = policy.apply(candidates, |creatives| {
+            creatives.iter().map(|c| &c.ad).collect()
+        });
+        let selected = pod::build_pod(candidates, break_duration, &self.pod_constraints);
+        info!(
+            "VastAdProvider: Pod of {} ad(s) fills {:.1}s of {:.1}s for session {}",
+            selected.ads.len(),
+            selected.duration,
+            break_duration,
+            session_id
+        );
+
+        // Conditioned-out creatives leave their time to slate
+        Some(
+            selected
+                .ads
+                .into_iter()
+                .flatten()
+                .filter(|c| !c.slate)
+                .collect(),
+        )
+    }
+
+    /// Follow a wrapper to the ads of the VAST document it points to
+    ///
+    /// Applies the VAST 4 wrapper rules — `followAdditionalWrappers`,
+    /// `allowMultipleAds`, `fallbackOnNoAd` — and reports failures with
+    /// error codes 301 (fetch failed or timed out), 302 (wrapper limit)
+    /// and 303 (no ads) to the Error URLs of every wrapper in the chain.
+    /// Boxed because wrapper resolution recurses.
+    fn resolve_wrapper<'a>(
+        &'a self,
+        wrapper: &'a WrapperAd,
+        depth: u32,
+        session_id: &'a str,
+        chain: &'a WrapperChain,
+        ctx: &'a MacroContext,
+        policy: &'a PolicyState,
+    ) -> BoxFuture<'a, ResolvedAd> {
+        Box::pin(async move {
+            // Accumulate wrapper tracking down the chain
+            let mut next = chain.clone();
+            next.error_urls.extend(wrapper.error_urls.clone());
+            next.impression_urls.extend(wrapper.impression_urls.clone());
+            next.tracking_events.extend(wrapper.tracking_events.clone());
+            next.ad_verifications
+                .extend(wrapper.ad_verifications.clone());
+            next.extensions.extend(wrapper.extensions.clone());
 
-        for ad in &vast_response.ads {
-            match &ad.ad_type {
-                VastAdType::InLine(inline) => {
-                    for creative in &inline.creatives {
-                        if let Some(linear) = &creative.linear
-                            && let Some(media_file) =
-                                vast::select_best_media_file(&linear.media_files)
+            let failed = |code: VastErrorCode| {
+                tracking::fire_errors(self.http_client.clone(), &next.error_urls, code, ctx);
+                ResolvedAd {
+                    creatives: Vec::new(),
+                    forbids_fallback: !wrapper.fallback_on_no_ad,
+                }
+            };
+
+            if depth > self.max_wrapper_depth {
+                warn!(
+                    "VAST wrapper chain exceeded max depth ({})",
+                    self.max_wrapper_depth
+                );
+                return failed(VastErrorCode::WrapperLimit);
+            }
+
+            let ad_tag_uri = macros::expand(&wrapper.ad_tag_uri, ctx);
+            let Some(xml) = self.fetch_vast_xml(&ad_tag_uri).await else {
+                return failed(VastErrorCode::WrapperTimeout);
+            };
+            let response = match vast::parse_vast(&xml) {
+                Ok(r) => r,
+                Err(e) => {
+                    error!("Failed to parse wrapped VAST XML: {}", e);
+                    return failed(VastErrorCode::XmlParseError);
+                }
+            };
+
+            // allowMultipleAds="false": only the first stand-alone ad may be used
+            let mut ads = if wrapper.allow_multiple_ads {
+                let mut ads = response.pod();
+                ads.extend(response.buffet());
+                ads
+            } else {
+                response.buffet().into_iter().take(1).collect()
+            };
+
+            if !wrapper.follow_additional_wrappers {
+                let before = ads.len();
+                ads.retain(|ad| matches!(ad.ad_type, VastAdType::InLine(_)));
+                if ads.len() < before {
+                    warn!(
+                        "VAST wrapper {} forbids further wrappers, dropped {} wrapped wrapper(s)",
+                        wrapper.ad_tag_uri,
+                        before - ads.len()
+                    );
+                    if ads.is_empty() {
+                        return failed(VastErrorCode::WrapperError);
+                    }
+                }
+            }
+
+            if ads.is_empty() {
+                info!(
+                    "VAST wrapper {} returned no ads for session {}",
+                    wrapper.ad_tag_uri, session_id
+                );
+                return failed(VastErrorCode::NoAdsAfterWrapper);
+            }
+
+            let resolved = join_all(
+                ads.iter()
+                    .map(|ad| self.resolve_ad(ad, depth, session_id, &next, ctx, policy)),
+            )
+            .await;
+            let creatives: Vec<_> = resolved.into_iter().flat_map(|r| r.creatives).collect();
+            ResolvedAd {
+                forbids_fallback: creatives.is_empty() && !wrapper.fallback_on_no_ad,
+                creatives,
+            }
+        })
+    }
+
+    /// Resolve one ad to its usable linear creatives, following a wrapper
+    ///
+    /// Creatives are conditioned against the session's content (see
+    /// [`conditioning::condition`]). An InLine ad with a creative that was
+    /// conditioned out, or whose linear creatives have no supported media
+    /// file, is reported with error code 403. With a normalizer, progressive
+    /// creatives play their packaged rendition and are left out (without
+    /// an error) while it is not ready.
+    async fn resolve_ad(
+        &self,
+        ad: &VastAd,
+        depth: u32,
+        session_id: &str,
+        chain: &WrapperChain,
+        ctx: &MacroContext,
+        policy: &PolicyState,
+    ) -> ResolvedAd {
+        match &ad.ad_type {
+            VastAdType::InLine(inline) => {
+                let mut error_urls = chain.error_urls.clone();
+                error_urls.extend(inline.error_url.clone());
+
+                let mut creatives = Vec::new();
+                let mut nonconforming = false;
+                let mut packaging = false;
+                for creative in &inline.creatives {
+                    if let Some(linear) = &creative.linear
+                        && let Some(verdict) = conditioning::condition(
+                            &linear.media_files,
+                            policy.conditioning(),
+                            policy.content(),
+                            session_id,
+                        )
+                    {
+                        let (media_file, slate) = match verdict {
+                            Verdict::Play(file) => (file, false),
+                            Verdict::Slate(file) => (file, true),
+                            Verdict::Reject(_) => {
+                                nonconforming = true;
+                                policy.note_rejection();
+                                continue;
+                            }
+                        };
+                        if slate {
+                            nonconforming = true;
+                            policy.note_rejection();
+                        }
+
+                        let mut url = media_file.url.clone();
+                        let is_hls = media_file.mime_type == "application/x-mpegURL";
+                        if !is_hls
+                            && !slate
+                            && let Some(normalizer) = &self.normalizer
                         {
-                            // Ad conditioning: check creative compatibility (warnings only)
-                            conditioning::check_creative(media_file, session_id);
-
-                            let is_hls = media_file.mime_type == "application/x-mpegURL";
-
-                            // Merge wrapper tracking with inline tracking
-                            let mut impression_urls = wrapper_impressions.to_vec();
-                            impression_urls.extend(inline.impression_urls.clone());
-
-                            let mut tracking_events = wrapper_tracking.to_vec();
-                            tracking_events.extend(linear.tracking_events.clone());
-
-                            creatives.push(ResolvedVastCreative {
-                                url: media_file.url.clone(),
-                                duration: linear.duration,
-                                is_hls,
-                                impression_urls,
-                                tracking_events,
-                                error_url: inline.error_url.clone(),
-                            });
+                            match normalizer
+                                .normalized_url(&creative.universal_ad_ids, &media_file.url)
+                                .await
+                            {
+                                Some(normalized) => url = normalized,
+                                None => {
+                                    packaging = true;
+                                    continue;
+                                }
+                            }
                         }
+
+                        // Merge wrapper tracking with inline tracking
+                        let mut impression_urls = chain.impression_urls.clone();
+                        impression_urls.extend(inline.impression_urls.clone());
+
+                        let mut tracking_events = chain.tracking_events.clone();
+                        tracking_events.extend(linear.tracking_events.clone());
+
+                        let mut ad_verifications = chain.ad_verifications.clone();
+                        ad_verifications.extend(inline.ad_verifications.clone());
+
+                        let mut extensions = chain.extensions.clone();
+                        extensions.extend(inline.extensions.clone());
+
+                        creatives.push(ResolvedVastCreative {
+                            url: url.clone(),
+                            duration: linear.duration,
+                            impression_urls,
+                            tracking_events,
+                            error_urls: error_urls.clone(),
+                            macros: MacroContext {
+                                asset_uri: Some(url),
+                                pod_sequence: ad.sequence,
+                                universal_ad_ids: creative
+                                    .universal_ad_ids
+                                    .iter()
+                                    .map(|id| format!("{} {}", id.id_registry, id.value))
+                                    .collect(),
+                                ..ctx.clone()
+                            },
+                            slate,
+                            ad: AdMetadata {
+                                ad_id: ad.id.clone(),
+                                sequence: ad.sequence,
+                                ad_system: inline.ad_system.clone(),
+                                ad_title: inline.ad_title.clone(),
+                                advertiser: inline.advertiser.clone(),
+                                categories: inline.categories.clone(),
+                                pricing: inline.pricing.clone(),
+                                creative_id: creative.id.clone(),
+                                creative_ad_id: creative.ad_id.clone(),
+                                universal_ad_ids: creative.universal_ad_ids.clone(),
+                                ad_verifications,
+                                extensions,
+                                mezzanine: linear.mezzanine.clone(),
+                                interactive_creative_files: linear
+                                    .interactive_creative_files
+                                    .clone(),
+                            },
+                        });
                     }
                 }
-                VastAdType::Wrapper(wrapper) => {
-                    // Accumulate wrapper tracking and follow chain
-                    let mut merged_impressions = wrapper_impressions.to_vec();
-                    merged_impressions.extend(wrapper.impression_urls.clone());
-
-                    let mut merged_tracking = wrapper_tracking.to_vec();
-                    merged_tracking.extend(wrapper.tracking_events.clone());
-
-                    if let Some(mut wrapped_creatives) = self.fetch_vast(
-                        &wrapper.ad_tag_uri,
-                        depth + 1,
-                        session_id,
-                        &merged_impressions,
-                        &merged_tracking,
-                    ) {
-                        creatives.append(&mut wrapped_creatives);
-                    }
+
+                if packaging {
+                    info!(
+                        "VAST ad {} is still being normalized, left out for session {}",
+                        ad.id, session_id
+                    );
+                }
+                if nonconforming {
+                    warn!(
+                        "VAST ad {} has a creative not conforming to the content for session {}",
+                        ad.id, session_id
+                    );
+                }
+                let unsupported = creatives.is_empty()
+                    && !packaging
+                    && inline.creatives.iter().any(|c| c.linear.is_some());
+                if unsupported && !nonconforming {
+                    warn!(
+                        "VAST ad {} has no supported media file for session {}",
+                        ad.id, session_id
+                    );
+                }
+                if unsupported || nonconforming {
+                    tracking::fire_errors(
+                        self.http_client.clone(),
+                        &error_urls,
+                        VastErrorCode::UnsupportedMedia,
+                        ctx,
+                    );
+                }
+
+                ResolvedAd {
+                    creatives,
+                    forbids_fallback: false,
                 }
             }
+            VastAdType::Wrapper(wrapper) => {
+                self.resolve_wrapper(wrapper, depth + 1, session_id, chain, ctx, policy)
+                    .await
+            }
         }
-
-        Some(creatives)
     }
 
-    /// Generate slate fallback segments when VAST returns no ads
+    /// Ask the ad server for one break's SSAI segments, without slate
     ///
-    /// Slate segments use "slate-seg-N.ts" naming to distinguish them
-    /// from regular VAST ad segments ("break-N-seg-M.ts").
-    fn slate_fallback(
+    /// `None` means the VAST request failed; an empty list is a no-fill.
+    /// Decided creatives are cached under `break_id` for segment resolution.
+    /// Used directly when this provider is one of several ad sources.
+    pub async fn request_segments(
         &self,
-        slate: &SlateProvider,
         duration: f32,
+        break_id: &str,
         session_id: &str,
-    ) -> Vec<AdSegment> {
-        let segments = slate.fill_duration(duration, session_id);
+        viewer: &ViewerContext,
+        policy: &PolicyState,
+    ) -> Option<Vec<AdSegment>> {
+        let ctx = self.request_context(duration, session_id, viewer);
+        let url = self.resolve_endpoint(&ctx);
         info!(
-            "VastAdProvider: Slate fallback generated {} segments for session {}",
-            segments.len(),
-            session_id
+            "VastAdProvider: Fetching VAST for session {} (duration: {}s) from {}",
+            session_id, duration, url
         );
-        segments
-    }
 
-    /// Build cache key for ad segment lookup
-    fn cache_key(session_id: &str, ad_name: &str) -> String {
-        format!("{}:{}", session_id, ad_name)
+        let creatives = self
+            .fetch_vast(&url, session_id, duration, &ctx, policy)
+            .await;
+        self.cache_segments(creatives, break_id, session_id).await
     }
-}
 
-impl std::fmt::Debug for VastAdProvider {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("VastAdProvider")
-            .field("vast_endpoint", &self.vast_endpoint)
-            .field("max_wrapper_depth", &self.max_wrapper_depth)
-            .field("timeout", &self.timeout)
-            .field("cached_entries", &self.ad_cache.len())
-            .field("has_slate", &self.slate.is_some())
-            .finish()
+    /// Resolve VAST markup already in hand to one break's SSAI segments
+    ///
+    /// Like [`request_segments`](Self::request_segments), for markup that
+    /// came with an OpenRTB bid instead of from the VAST endpoint.
+    pub async fn segments_from_markup(
+        &self,
+        xml: &str,
+        duration: f32,
+        break_id: &str,
+        session_id: &str,
+        viewer: &ViewerContext,
+        policy: &PolicyState,
+    ) -> Option<Vec<AdSegment>> {
+        let ctx = self.request_context(duration, session_id, viewer);
+        let creatives = self
+            .resolve_vast_document(xml, session_id, duration, &ctx, policy)
+            .await;
+        self.cache_segments(creatives, break_id, session_id).await
     }
-}
-
-impl AdProvider for VastAdProvider {
-    fn get_ad_segments(&self, duration: f32, session_id: &str) -> Vec<AdSegment> {
-        let url = self.resolve_endpoint(duration);
-        info!(
-            "VastAdProvider: Fetching VAST for session {} (duration: {}s) from {}",
-            session_id, duration, url
-        );
 
-        let creatives = match self.fetch_vast(&url, 0, session_id, &[], &[]) {
+    /// Cache decided creatives for segment resolution, as ad segments
+    async fn cache_segments(
+        &self,
+        creatives: Option<Vec<ResolvedVastCreative>>,
+        break_id: &str,
+        session_id: &str,
+    ) -> Option<Vec<AdSegment>> {
+        let creatives = match creatives {
             Some(c) if !c.is_empty() => {
                 metrics::record_vast_request("success");
                 c
@@ -295,76 +699,52 @@ impl AdProvider for VastAdProvider {
             Some(_) => {
                 // VAST returned but with no creatives
                 metrics::record_vast_request("empty");
-                if let Some(slate) = &self.slate {
-                    warn!(
-                        "VastAdProvider: Empty VAST response for session {} — falling back to slate",
-                        session_id
-                    );
-                    metrics::record_slate_fallback();
-                    return self.slate_fallback(slate, duration, session_id);
-                }
                 warn!(
-                    "VastAdProvider: Empty VAST response for session {} and no slate configured",
+                    "VastAdProvider: Empty VAST response for session {}",
                     session_id
                 );
-                return Vec::new();
+                return Some(Vec::new());
             }
             None => {
-                // VAST request failed
                 metrics::record_vast_request("error");
-                if let Some(slate) = &self.slate {
-                    warn!(
-                        "VastAdProvider: VAST failed for session {} — falling back to slate",
-                        session_id
-                    );
-                    metrics::record_slate_fallback();
-                    return self.slate_fallback(slate, duration, session_id);
-                }
-                warn!(
-                    "VastAdProvider: VAST failed for session {} and no slate configured",
-                    session_id
-                );
-                return Vec::new();
+                warn!("VastAdProvider: VAST failed for session {}", session_id);
+                return None;
             }
         };
 
-        // Build ad segments and cache them for resolve_segment_url
+        // Build ad segments and keep them for segment resolution
         let mut segments = Vec::new();
-        let break_idx = 0; // TODO: track break index per session
+        let mut resolved = Vec::new();
         let total_segments = creatives.len();
 
         for (seg_idx, creative) in creatives.iter().enumerate() {
-            let ad_name = format!("break-{}-seg-{}.ts", break_idx, seg_idx);
-
-            // Cache the resolved creative with tracking metadata
-            self.ad_cache.insert(
-                Self::cache_key(session_id, &ad_name),
-                ResolvedCreative {
-                    url: creative.url.clone(),
-                    duration: creative.duration,
-                    is_hls: creative.is_hls,
-                    impression_urls: creative.impression_urls.clone(),
-                    tracking_events: creative.tracking_events.clone(),
-                    error_url: creative.error_url.clone(),
-                    total_segments,
-                    segment_index: seg_idx,
-                    visited: false,
-                    inserted_at: Instant::now(),
+            let tracking = AdTrackingInfo {
+                impression_urls: creative.impression_urls.clone(),
+                tracking_events: creative.tracking_events.clone(),
+                error_urls: creative.error_urls.clone(),
+                total_segments,
+                segment_index: seg_idx,
+                ad_duration: creative.duration,
+                ad: creative.ad.clone(),
+                macros: MacroContext {
+                    ad_count: Some(seg_idx + 1),
+                    ..creative.macros.clone()
                 },
-            );
+            };
+
+            resolved.push(ResolvedSegment {
+                url: creative.url.clone(),
+                tracking: Some(tracking.clone()),
+            });
 
             segments.push(AdSegment {
-                uri: ad_name,
+                uri: provider::ad_segment_name(break_id, seg_idx),
                 duration: creative.duration,
-                tracking: Some(AdTrackingInfo {
-                    impression_urls: creative.impression_urls.clone(),
-                    tracking_events: creative.tracking_events.clone(),
-                    error_url: creative.error_url.clone(),
-                    total_segments,
-                    segment_index: seg_idx,
-                }),
+                init: None,
+                tracking: Some(tracking),
             });
         }
+        self.cache_break(session_id, break_id, BreakDecision::segments(resolved));
 
         info!(
             "VastAdProvider: Resolved {} ad segment(s) for session {}",
@@ -372,48 +752,101 @@ impl AdProvider for VastAdProvider {
             session_id
         );
 
-        segments
+        Some(segments)
     }
 
-    fn resolve_segment_url(&self, ad_name: &str) -> Option<String> {
-        // Check if this is a slate segment
-        if ad_name.starts_with("slate-seg-") {
-            if let Some(slate) = &self.slate {
-                return slate.resolve_segment_url(ad_name);
-            }
-            warn!("VastAdProvider: Slate segment requested but no slate configured");
-            return None;
-        }
-
-        // Search across all sessions for this ad_name.
-        // Ad names include break and segment indices, making them unique enough.
-        for entry in self.ad_cache.iter() {
-            if entry.key().ends_with(&format!(":{}", ad_name)) {
-                return Some(entry.value().url.clone());
-            }
+    /// SSAI decision: ad segments, completed with slate when configured
+    async fn decide_segments(
+        &self,
+        duration: f32,
+        break_id: &str,
+        session_id: &str,
+        viewer: &ViewerContext,
+    ) -> Vec<AdSegment> {
+        let policy = self.policy_state(session_id, viewer).await;
+        let decided = self
+            .request_segments(duration, break_id, session_id, viewer, &policy)
+            .await;
+        let reason = match &decided {
+            None => SlateReason::Error,
+            Some(_) if policy.has_rejections() => SlateReason::Policy,
+            Some(_) => SlateReason::NoFill,
+        };
+        let segments = decided.unwrap_or_default();
+        if let Some(policy) = &self.policy {
+            let ads = segments.iter().filter_map(|s| s.tracking.as_ref());
+            policy.record(session_id, viewer, ads.map(|t| &t.ad)).await;
         }
-
-        warn!("VastAdProvider: No cached creative found for {}", ad_name);
-        None
+        let segments = match &self.slate {
+            Some(slate) => slate.complete_break(
+                segments,
+                duration,
+                self.pod_constraints.overrun_tolerance,
+                reason,
+                session_id,
+            ),
+            None => segments,
+        };
+        self.publish_break(session_id, break_id, segments).await
     }
 
-    fn get_ad_creatives(&self, duration: f32, session_id: &str) -> Vec<AdCreative> {
-        let url = self.resolve_endpoint(duration);
+    /// Ask the ad server for one break's SGAI creatives
+    ///
+    /// `None` means the VAST request failed; an empty list is a no-fill.
+    pub async fn request_creatives(
+        &self,
+        duration: f32,
+        session_id: &str,
+        viewer: &ViewerContext,
+        policy: &PolicyState,
+    ) -> Option<Vec<AdCreative>> {
+        let ctx = self.request_context(duration, session_id, viewer);
+        let url = self.resolve_endpoint(&ctx);
         info!(
             "VastAdProvider: Fetching VAST creatives for session {} (duration: {}s)",
             session_id, duration
         );
 
-        match self.fetch_vast(&url, 0, session_id, &[], &[]) {
+        let creatives = self
+            .fetch_vast(&url, session_id, duration, &ctx, policy)
+            .await;
+        Self::to_creatives(creatives, session_id)
+    }
+
+    /// Resolve VAST markup already in hand to one break's SGAI creatives
+    pub async fn creatives_from_markup(
+        &self,
+        xml: &str,
+        duration: f32,
+        session_id: &str,
+        viewer: &ViewerContext,
+        policy: &PolicyState,
+    ) -> Option<Vec<AdCreative>> {
+        let ctx = self.request_context(duration, session_id, viewer);
+        let creatives = self
+            .resolve_vast_document(xml, session_id, duration, &ctx, policy)
+            .await;
+        Self::to_creatives(creatives, session_id)
+    }
+
+    /// Decided creatives as SGAI asset-list entries
+    fn to_creatives(
+        creatives: Option<Vec<ResolvedVastCreative>>,
+        session_id: &str,
+    ) -> Option<Vec<AdCreative>> {
+        match creatives {
             Some(creatives) if !creatives.is_empty() => {
                 metrics::record_vast_request("success");
-                creatives
-                    .into_iter()
-                    .map(|c| AdCreative {
-                        uri: c.url,
-                        duration: c.duration as f64,
-                    })
-                    .collect()
+                Some(
+                    creatives
+                        .into_iter()
+                        .map(|c| AdCreative {
+                            uri: c.url,
+                            duration: c.duration as f64,
+                            ad: Some(c.ad),
+                        })
+                        .collect(),
+                )
             }
             Some(_) => {
                 metrics::record_vast_request("empty");
@@ -421,7 +854,7 @@ impl AdProvider for VastAdProvider {
                     "VastAdProvider: Empty VAST response for session {} (get_ad_creatives)",
                     session_id
                 );
-                Vec::new()
+                Some(Vec::new())
             }
             None => {
                 metrics::record_vast_request("error");
@@ -429,45 +862,209 @@ impl AdProvider for VastAdProvider {
                     "VastAdProvider: VAST failed for session {} (get_ad_creatives)",
                     session_id
                 );
-                Vec::new()
+                None
             }
         }
     }
 
-    fn cleanup_cache(&self) {
-        const MAX_AGE: Duration = Duration::from_secs(300);
-        const MAX_SIZE: usize = 10_000;
+    /// Store a break's decision in the session, if a store is configured
+    ///
+    /// See [`publish_break_to`](Self::publish_break_to).
+    pub(crate) async fn publish_break(
+        &self,
+        session_id: &str,
+        break_id: &str,
+        segments: Vec<AdSegment>,
+    ) -> Vec<AdSegment> {
+        match &self.sessions {
+            Some(sessions) => {
+                self.publish_break_to(sessions, session_id, break_id, segments)
+                    .await
+            }
+            None => segments,
+        }
+    }
 
-        let before = self.ad_cache.len();
+    /// Store this provider's decision of a break in the session, together
+    /// with the break's final `segments`, slate included
+    ///
+    /// Every instance then serves the break from the session. If the break
+    /// was already decided — by another instance, say — that decision is
+    /// kept, replaces this provider's own, and its segments are returned
+    /// instead of `segments`.
+    pub(crate) async fn publish_break_to(
+        &self,
+        sessions: &SessionManager,
+        session_id: &str,
+        break_id: &str,
+        segments: Vec<AdSegment>,
+    ) -> Vec<AdSegment> {
+        let Some(decision) = self.cached_break(session_id, break_id) else {
+            return segments;
+        };
+        let decision = decision.with_published(&segments);
+        match sessions
+            .record_break_decision(session_id, break_id, decision, self.cache_ttl)
+            .await
+        {
+            Some(earlier) if !earlier.published.is_empty() => {
+                let published = earlier.published.clone();
+                self.cache_break(session_id, break_id, earlier);
+                published
+            }
+            _ => segments,
+        }
+    }
 
-        // Pass 1: evict entries older than MAX_AGE
+    /// This provider's own decision of a session's break, if still cached
+    fn cached_break(&self, session_id: &str, break_id: &str) -> Option<BreakDecision> {
         self.ad_cache
-            .retain(|_, v| v.inserted_at.elapsed() < MAX_AGE);
+            .get(&(session_id.to_string(), break_id.to_string()))
+            .filter(|cached| !cached.decision.is_expired(self.cache_ttl))
+            .map(|cached| cached.decision.clone())
+    }
 
-        // Pass 2: if still over MAX_SIZE, evict the oldest entries first.
-        // Snapshot into a Vec to avoid TOCTOU issues with concurrent inserts.
-        if self.ad_cache.len() > MAX_SIZE {
-            let mut entries: Vec<(String, Duration)> = self
-                .ad_cache
-                .iter()
-                .map(|e| (e.key().clone(), e.value().inserted_at.elapsed()))
-                .collect();
+    /// Cache a break's decision, replacing an earlier decision for it
+    fn cache_break(&self, session_id: &str, break_id: &str, decision: BreakDecision) {
+        let added = decision.len();
+        let replaced = self.ad_cache.insert(
+            (session_id.to_string(), break_id.to_string()),
+            CachedBreak::new(decision),
+        );
+        self.cached_creatives.fetch_add(added, Ordering::Relaxed);
+        if let Some(replaced) = replaced {
+            self.cached_creatives
+                .fetch_sub(replaced.decision.len(), Ordering::Relaxed);
+        }
+        if self.cached_creatives.load(Ordering::Relaxed) > self.cache_max_creatives {
+            self.evict_oldest();
+        }
+    }
 
-            // Sort descending by age (oldest first)
-            entries.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+    /// Evict the oldest breaks until the cache is back within its limit
+    fn evict_oldest(&self) {
+        // Snapshot so no shard lock is held while removing
+        let mut breaks: Vec<((String, String), SystemTime)> = self
+            .ad_cache
+            .iter()
+            .map(|e| (e.key().clone(), e.value().decision.decided_at))
+            .collect();
+        breaks.sort_unstable_by_key(|(_, decided_at)| *decided_at);
 
-            let to_remove = entries.len().saturating_sub(MAX_SIZE);
-            for (key, _) in entries.iter().take(to_remove) {
-                self.ad_cache.remove(key);
+        for (key, _) in breaks {
+            if self.cached_creatives.load(Ordering::Relaxed) <= self.cache_max_creatives {
+                break;
+            }
+            if let Some((_, evicted)) = self.ad_cache.remove(&key) {
+                self.cached_creatives
+                    .fetch_sub(evicted.decision.len(), Ordering::Relaxed);
             }
         }
+    }
+}
+
+impl std::fmt::Debug for VastAdProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VastAdProvider")
+            .field("vast_endpoint", &self.vast_endpoint)
+            .field("max_wrapper_depth", &self.max_wrapper_depth)
+            .field("timeout", &self.timeout)
+            .field("cached_breaks", &self.ad_cache.len())
+            .field(
+                "cached_creatives",
+                &self.cached_creatives.load(Ordering::Relaxed),
+            )
+            .field("has_slate", &self.slate.is_some())
+            .field("normalizer", &self.normalizer)
+            .finish()
+    }
+}
+
+impl AdProvider for VastAdProvider {
+    fn get_ad_segments<'a>(
+        &'a self,
+        duration: f32,
+        break_id: &'a str,
+        session_id: &'a str,
+        viewer: &'a ViewerContext,
+    ) -> BoxFuture<'a, Vec<AdSegment>> {
+        Box::pin(self.decide_segments(duration, break_id, session_id, viewer))
+    }
+
+    fn resolve_segment_url(&self, ad_name: &str) -> Option<String> {
+        // Check if this is a slate segment
+        if slate::is_slate_segment(ad_name) {
+            if let Some(slate) = &self.slate {
+                return slate.resolve_segment_url(ad_name);
+            }
+            warn!("VastAdProvider: Slate segment requested but no slate configured");
+            return None;
+        }
+
+        // Creatives belong to one session's decision; see
+        // resolve_segment_with_tracking
+        debug!(
+            "VastAdProvider: {} can only be resolved within its session",
+            ad_name
+        );
+        None
+    }
+
+    fn get_ad_creatives<'a>(
+        &'a self,
+        duration: f32,
+        _break_id: &'a str,
+        session_id: &'a str,
+        viewer: &'a ViewerContext,
+    ) -> BoxFuture<'a, Vec<AdCreative>> {
+        Box::pin(async move {
+            let policy = self.policy_state(session_id, viewer).await;
+            let creatives = self
+                .request_creatives(duration, session_id, viewer, &policy)
+                .await
+                .unwrap_or_default();
+            if let Some(policy) = &self.policy {
+                let ads = creatives.iter().filter_map(|c| c.ad.as_ref());
+                policy.record(session_id, viewer, ads).await;
+            }
+            creatives
+        })
+    }
+
+    fn slate(&self) -> Option<&SlateProvider> {
+        self.slate.as_ref()
+    }
+
+    fn cleanup_cache(&self) {
+        if let Some(normalizer) = &self.normalizer {
+            normalizer.cleanup();
+        }
+
+        let before = self.ad_cache.len();
+
+        // Pass 1: evict breaks decided longer than the TTL ago
+        let ttl = self.cache_ttl;
+        self.ad_cache.retain(|_, cached| {
+            let keep = !cached.decision.is_expired(ttl);
+            if !keep {
+                self.cached_creatives
+                    .fetch_sub(cached.decision.len(), Ordering::Relaxed);
+            }
+            keep
+        });
+
+        // Pass 2: if still over the limit, evict the oldest breaks first
+        if self.cached_creatives.load(Ordering::Relaxed) > self.cache_max_creatives {
+            self.evict_oldest();
+        }
 
         let after = self.ad_cache.len();
         if before != after {
             info!(
-                "VastAdProvider: evicted {} stale cache entries ({} remaining)",
+                "VastAdProvider: evicted {} stale cached break(s) ({} remaining, {} creatives)",
                 before - after,
-                after
+                after,
+                self.cached_creatives.load(Ordering::Relaxed)
             );
         }
     }
@@ -478,7 +1075,7 @@ impl AdProvider for VastAdProvider {
         session_id: &str,
     ) -> Option<ResolvedSegment> {
         // Slate segments have no tracking
-        if ad_name.starts_with("slate-seg-") {
+        if slate::is_slate_segment(ad_name) {
             if let Some(slate) = &self.slate {
                 return slate
                     .resolve_segment_url(ad_name)
@@ -490,32 +1087,18 @@ impl AdProvider for VastAdProvider {
             return None;
         }
 
-        let cache_key = Self::cache_key(session_id, ad_name);
-        if let Some(mut entry) = self.ad_cache.get_mut(&cache_key) {
-            // Check if this segment has been visited (deduplication)
-            let tracking = if !entry.visited {
-                // Mark as visited
-                entry.visited = true;
-                Some(AdTrackingInfo {
-                    impression_urls: entry.impression_urls.clone(),
-                    tracking_events: entry.tracking_events.clone(),
-                    error_url: entry.error_url.clone(),
-                    total_segments: entry.total_segments,
-                    segment_index: entry.segment_index,
-                })
-            } else {
-                // Already served, don't fire tracking again
-                None
-            };
-
-            Some(ResolvedSegment {
-                url: entry.url.clone(),
-                tracking,
-            })
-        } else {
+        let Some((break_id, index)) = provider::parse_ad_segment_name(ad_name) else {
+            warn!("VastAdProvider: Invalid ad segment name {}", ad_name);
+            return None;
+        };
+        let resolved = self
+            .ad_cache
+            .get_mut(&(session_id.to_string(), break_id.to_string()))
+            .and_then(|mut cached| cached.deliver(index));
+        if resolved.is_none() {
             warn!("VastAdProvider: No cached creative found for {}", ad_name);
-            None
         }
+        resolved
     }
 }
 
@@ -531,18 +1114,136 @@ mod tests {
             client,
         );
 
-        let resolved = provider.resolve_endpoint(30.0);
+        let resolved = provider.resolve_endpoint(&provider.request_context(
+            30.0,
+            "s",
+            &ViewerContext::default(),
+        ));
         assert!(resolved.contains("dur=30"));
         assert!(!resolved.contains("[CACHEBUSTING]"));
         assert!(!resolved.contains("[DURATION]"));
     }
 
+    fn cached(url: &str) -> ResolvedSegment {
+        ResolvedSegment {
+            url: url.to_string(),
+            tracking: Some(AdTrackingInfo::default()),
+        }
+    }
+
+    fn decided(urls: &[&str]) -> BreakDecision {
+        BreakDecision::segments(urls.iter().map(|url| cached(url)).collect())
+    }
+
     #[test]
-    fn test_cache_key() {
+    fn test_cache_resolves_per_session_and_break() {
+        let provider = VastAdProvider::new("http://unused".to_string(), Client::new());
+        provider.cache_break("s1", "100", decided(&["https://ads/a.mp4"]));
+        provider.cache_break("s1", "200", decided(&["https://ads/b.mp4"]));
+        provider.cache_break("s2", "100", decided(&["https://ads/c.mp4"]));
+
+        let url = |session: &str, name: &str| {
+            provider
+                .resolve_segment_with_tracking(name, session)
+                .map(|r| r.url)
+        };
+        assert_eq!(
+            url("s1", "break-100-seg-0.ts").as_deref(),
+            Some("https://ads/a.mp4")
+        );
         assert_eq!(
-            VastAdProvider::cache_key("session-1", "break-0-seg-0.ts"),
-            "session-1:break-0-seg-0.ts"
+            url("s1", "break-200-seg-0.ts").as_deref(),
+            Some("https://ads/b.mp4")
         );
+        assert_eq!(
+            url("s2", "break-100-seg-0.ts").as_deref(),
+            Some("https://ads/c.mp4")
+        );
+        // No other session's creatives, no out-of-range segments
+        assert_eq!(url("s3", "break-100-seg-0.ts"), None);
+        assert_eq!(url("s1", "break-100-seg-1.ts"), None);
+        assert_eq!(provider.resolve_segment_url("break-100-seg-0.ts"), None);
+
+        // Tracking came with the first request only
+        let again = provider.resolve_segment_with_tracking("break-200-seg-0.ts", "s1");
+        assert!(again.unwrap().tracking.is_none());
+        let first = provider.resolve_segment_with_tracking("break-100-seg-0.ts", "s1");
+        assert!(first.is_some_and(|r| r.tracking.is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_decisions_are_stored_in_the_session() {
+        let sessions = SessionManager::new_memory(Duration::from_secs(300));
+        sessions
+            .get_or_create("s1".to_string(), "https://example.com".to_string())
+            .await;
+        let segments = |uri: &str| {
+            vec![AdSegment {
+                uri: uri.to_string(),
+                duration: 10.0,
+                init: None,
+                tracking: None,
+            }]
+        };
+        let first = VastAdProvider::new("http://unused".to_string(), Client::new())
+            .with_sessions(sessions.clone());
+        first.cache_break("s1", "100", decided(&["https://ads/a.mp4"]));
+        let published = first.publish_break("s1", "100", segments("first.ts")).await;
+        assert_eq!(published[0].uri, "first.ts");
+
+        // Another instance deciding the same break publishes the first decision
+        let second = VastAdProvider::new("http://unused".to_string(), Client::new())
+            .with_sessions(sessions.clone());
+        second.cache_break("s1", "100", decided(&["https://ads/b.mp4"]));
+        let published = second
+            .publish_break("s1", "100", segments("second.ts"))
+            .await;
+        assert_eq!(published[0].uri, "first.ts");
+        let local = second.cached_break("s1", "100").unwrap();
+        assert_eq!(local.segments[0].url, "https://ads/a.mp4");
+
+        let resolved = sessions.deliver_ad_segment("s1", "100", 0).await.unwrap();
+        assert_eq!(resolved.url, "https://ads/a.mp4");
+        assert!(resolved.tracking.is_some());
+    }
+
+    #[test]
+    fn test_tracking_is_delivered_once_per_segment() {
+        let mut cached = CachedBreak::new(decided(&["a.ts", "b.ts"]));
+
+        let first = cached.deliver(1).unwrap();
+        assert_eq!(first.url, "b.ts");
+        assert!(first.tracking.is_some());
+        assert!(cached.deliver(1).unwrap().tracking.is_none());
+        assert!(cached.deliver(0).unwrap().tracking.is_some());
+        assert!(cached.deliver(2).is_none());
+    }
+
+    #[test]
+    fn test_cache_evicts_oldest_breaks_over_limit() {
+        let provider = VastAdProvider::new("http://unused".to_string(), Client::new())
+            .with_cache_limits(Duration::from_secs(300), 3);
+        provider.cache_break("s", "1", decided(&["https://ads/1", "https://ads/1b"]));
+        provider.cache_break("s", "2", decided(&["https://ads/2"]));
+        provider.cache_break("s", "3", decided(&["https://ads/3"]));
+
+        assert!(
+            !provider
+                .ad_cache
+                .contains_key(&("s".to_string(), "1".to_string()))
+        );
+        assert_eq!(provider.cached_creatives.load(Ordering::Relaxed), 2);
+
+        // A re-decided break replaces its creatives
+        provider.cache_break("s", "3", decided(&["https://ads/3b"]));
+        assert_eq!(provider.cached_creatives.load(Ordering::Relaxed), 2);
+
+        let expired = VastAdProvider::new("http://unused".to_string(), Client::new())
+            .with_cache_limits(Duration::ZERO, 10);
+        expired.cache_break("s", "1", decided(&["https://ads/1"]));
+        expired.cleanup_cache();
+        assert!(expired.ad_cache.is_empty());
+        assert_eq!(expired.cached_creatives.load(Ordering::Relaxed), 0);
     }
 
     #[test]
@@ -583,4 +1284,403 @@ mod tests {
         assert_eq!(level2_impressions[1], "http://wrapper/imp");
         assert_eq!(level2_impressions[2], "http://inline/imp");
     }
+
+    fn inline_ad(id: &str, sequence: Option<u32>, seconds: u32, mime: &str) -> String {
+        let sequence = sequence
+            .map(|n| format!(r#" sequence="{}""#, n))
+            .unwrap_or_default();
+        format!(
+            r#"<Ad id="{id}"{sequence}><InLine><AdSystem>S</AdSystem><AdTitle>{id}</AdTitle>
+<Advertiser>adv-{id}</Advertiser>
+<Creatives><Creative id="c-{id}"><Linear><Duration>00:00:{seconds:02}</Duration>
+<MediaFiles><MediaFile delivery="progressive" type="{mime}" width="1280" height="720">https://ads.example.com/{id}.mp4</MediaFile></MediaFiles>
+</Linear></Creative></Creatives></InLine></Ad>"#
+        )
+    }
+
+    async fn resolve(ads: &[String], fill: f32) -> Vec<String> {
+        let provider = VastAdProvider::new("http://unused".to_string(), Client::new());
+        let xml = format!(r#"<VAST version="4.2">{}</VAST>"#, ads.concat());
+        provider
+            .resolve_vast_document(
+                &xml,
+                "s",
+                fill,
+                &MacroContext::default(),
+                &PolicyState::default(),
+            )
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.ad.ad_id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_pod_plays_in_sequence_order() {
+        let ads = [
+            inline_ad("b", Some(2), 15, "video/mp4"),
+            inline_ad("a", Some(1), 15, "video/mp4"),
+            inline_ad("spare", None, 15, "video/mp4"),
+        ];
+        // The pod fills the break, so the buffet ad is not used
+        assert_eq!(resolve(&ads, 30.0).await, vec!["a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_buffet_replaces_failed_pod_ad_and_fills_gap() {
+        let ads = [
+            inline_ad("a", Some(1), 15, "video/mp4"),
+            // No usable media file: the pod slot fails
+            inline_ad("b", Some(2), 15, "application/javascript"),
+            inline_ad("x", None, 15, "video/mp4"),
+            inline_ad("y", None, 15, "video/mp4"),
+            inline_ad("z", None, 15, "video/mp4"),
+        ];
+        // x replaces b, y fills the remaining 15s, z is not needed
+        assert_eq!(resolve(&ads, 45.0).await, vec!["a", "x", "y"]);
+    }
+
+    #[tokio::test]
+    async fn test_resolved_creatives_carry_ad_metadata() {
+        let provider = VastAdProvider::new("http://unused".to_string(), Client::new());
+        let xml = format!(
+            r#"<VAST version="4.2">{}</VAST>"#,
+            inline_ad("a", Some(1), 15, "video/mp4")
+        );
+        let chain = WrapperChain {
+            ad_verifications: vec![Verification {
+                vendor: Some("wrapper-vendor".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let response = vast::parse_vast(&xml).unwrap();
+        let resolved = provider
+            .resolve_ad(
+                &response.ads[0],
+                0,
+                "s",
+                &chain,
+                &MacroContext::default(),
+                &PolicyState::default(),
+            )
+            .await;
+
+        let ad = &resolved.creatives[0].ad;
+        assert_eq!(ad.sequence, Some(1));
+        assert_eq!(ad.creative_id, "c-a");
+        assert_eq!(ad.advertiser.as_deref(), Some("adv-a"));
+        assert_eq!(ad.ad_verifications.len(), 1);
+    }
+
+    /// Serve VAST documents by path and record every request
+    async fn mock_ad_server(
+        docs: Vec<(&str, String)>,
+    ) -> (String, Arc<std::sync::Mutex<Vec<String>>>) {
+        use axum::http::{StatusCode, Uri};
+
+        let docs: Arc<std::collections::HashMap<String, String>> = Arc::new(
+            docs.into_iter()
+                .map(|(path, xml)| (path.to_string(), xml))
+                .collect(),
+        );
+        let hits = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = hits.clone();
+        let app = axum::Router::new().fallback(move |uri: Uri| {
+            let docs = docs.clone();
+            let hits = recorded.clone();
+            async move {
+                hits.lock().unwrap().push(uri.to_string());
+                match docs.get(uri.path()) {
+                    Some(xml) => (StatusCode::OK, xml.clone()),
+                    None => (StatusCode::NOT_FOUND, String::new()),
+                }
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (base, hits)
+    }
+
+    /// Wait for fire-and-forget error beacons to reach the mock server
+    async fn error_beacons(hits: &std::sync::Mutex<Vec<String>>, expected: usize) -> Vec<String> {
+        for _ in 0..100 {
+            let errors: Vec<String> = hits
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|h| h.starts_with("/error"))
+                .cloned()
+                .collect();
+            if errors.len() >= expected {
+                return errors;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("expected {} error beacon(s)", expected);
+    }
+
+    fn wrapper_ad(id: &str, attrs: &str, tag: &str, error: &str) -> String {
+        format!(
+            r#"<Ad id="{id}"><Wrapper{attrs}><AdSystem>W</AdSystem>
+<VASTAdTagURI><![CDATA[{tag}]]></VASTAdTagURI>
+<Error><![CDATA[{error}]]></Error></Wrapper></Ad>"#
+        )
+    }
+
+    fn vast_doc(ads: &[String]) -> String {
+        format!(r#"<VAST version="4.2">{}</VAST>"#, ads.concat())
+    }
+
+    #[tokio::test]
+    async fn test_wrapper_allow_multiple_ads_false_keeps_first_standalone_ad() {
+        let wrapped = vast_doc(&[
+            inline_ad("pod", Some(1), 10, "video/mp4"),
+            inline_ad("first", None, 10, "video/mp4"),
+            inline_ad("second", None, 10, "video/mp4"),
+        ]);
+        let (base, _) = mock_ad_server(vec![("/wrapped", wrapped)]).await;
+        let provider = VastAdProvider::new("http://unused".to_string(), Client::new());
+
+        let single = vast_doc(&[wrapper_ad("w", "", &format!("{base}/wrapped"), "")]);
+        let ids: Vec<_> = provider
+            .resolve_vast_document(
+                &single,
+                "s",
+                30.0,
+                &MacroContext::default(),
+                &PolicyState::default(),
+            )
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.ad.ad_id)
+            .collect();
+        assert_eq!(ids, vec!["first"]);
+
+        let multiple = vast_doc(&[wrapper_ad(
+            "w",
+            r#" allowMultipleAds="true""#,
+            &format!("{base}/wrapped"),
+            "",
+        )]);
+        let ids: Vec<_> = provider
+            .resolve_vast_document(
+                &multiple,
+                "s",
+                30.0,
+                &MacroContext::default(),
+                &PolicyState::default(),
+            )
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.ad.ad_id)
+            .collect();
+        assert_eq!(ids, vec!["pod", "first", "second"]);
+    }
+
+    #[tokio::test]
+    async fn test_wrapper_errors_reported_to_whole_chain() {
+        let (base, hits) = mock_ad_server(vec![
+            (
+                "/inner",
+                vast_doc(&[wrapper_ad("w2", "", "http://127.0.0.1:1/never-fetched", "")]),
+            ),
+            ("/empty", vast_doc(&[])),
+        ])
+        .await;
+        let provider = VastAdProvider::new("http://unused".to_string(), Client::new());
+
+        // No ads behind the wrapper: 303 to the wrapper's Error URL
+        let doc = vast_doc(&[wrapper_ad(
+            "w",
+            "",
+            &format!("{base}/empty"),
+            &format!("{base}/error?code=[ERRORCODE]"),
+        )]);
+        assert!(
+            provider
+                .resolve_vast_document(
+                    &doc,
+                    "s",
+                    30.0,
+                    &MacroContext::default(),
+                    &PolicyState::default()
+                )
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(error_beacons(&hits, 1).await, vec!["/error?code=303"]);
+
+        // followAdditionalWrappers="false" with only a wrapper behind it: 300
+        let doc = vast_doc(&[wrapper_ad(
+            "w",
+            r#" followAdditionalWrappers="false""#,
+            &format!("{base}/inner"),
+            &format!("{base}/error?code=[ERRORCODE]"),
+        )]);
+        assert!(
+            provider
+                .resolve_vast_document(
+                    &doc,
+                    "s",
+                    30.0,
+                    &MacroContext::default(),
+                    &PolicyState::default()
+                )
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(error_beacons(&hits, 2).await[1], "/error?code=300");
+    }
+
+    #[tokio::test]
+    async fn test_wrapper_limit_and_unsupported_media_error_codes() {
+        let (base, hits) = mock_ad_server(vec![(
+            "/wrapped",
+            vast_doc(&[inline_ad("js", None, 10, "application/javascript")]),
+        )])
+        .await;
+        let error = format!("{base}/error?code=[ERRORCODE]");
+
+        // Wrappers disabled entirely: the first wrapper already exceeds the limit
+        let mut provider = VastAdProvider::new("http://unused".to_string(), Client::new());
+        provider.max_wrapper_depth = 0;
+        let doc = vast_doc(&[wrapper_ad("w", "", &format!("{base}/wrapped"), &error)]);
+        assert!(
+            provider
+                .resolve_vast_document(
+                    &doc,
+                    "s",
+                    30.0,
+                    &MacroContext::default(),
+                    &PolicyState::default()
+                )
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(error_beacons(&hits, 1).await, vec!["/error?code=302"]);
+
+        // The wrapped ad has no supported media file: 403 up the chain
+        let provider = VastAdProvider::new("http://unused".to_string(), Client::new());
+        assert!(
+            provider
+                .resolve_vast_document(
+                    &doc,
+                    "s",
+                    30.0,
+                    &MacroContext::default(),
+                    &PolicyState::default()
+                )
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(error_beacons(&hits, 2).await[1], "/error?code=403");
+    }
+
+    #[tokio::test]
+    async fn test_fallback_on_no_ad_false_blocks_buffet() {
+        let (base, _) = mock_ad_server(vec![("/empty", vast_doc(&[]))]).await;
+        let provider = VastAdProvider::new("http://unused".to_string(), Client::new());
+        let pod_wrapper = |attrs: &str| {
+            wrapper_ad("w", attrs, &format!("{base}/empty"), "").replacen(
+                r#"<Ad id="w">"#,
+                r#"<Ad id="w" sequence="1">"#,
+                1,
+            )
+        };
+
+        let doc = vast_doc(&[pod_wrapper(""), inline_ad("buffet", None, 30, "video/mp4")]);
+        assert_eq!(
+            provider
+                .resolve_vast_document(
+                    &doc,
+                    "s",
+                    30.0,
+                    &MacroContext::default(),
+                    &PolicyState::default()
+                )
+                .await
+                .unwrap()[0]
+                .ad
+                .ad_id,
+            "buffet"
+        );
+
+        let doc = vast_doc(&[
+            pod_wrapper(r#" fallbackOnNoAd="false""#),
+            inline_ad("buffet", None, 30, "video/mp4"),
+        ]);
+        assert!(
+            provider
+                .resolve_vast_document(
+                    &doc,
+                    "s",
+                    30.0,
+                    &MacroContext::default(),
+                    &PolicyState::default()
+                )
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_progressive_creatives_wait_for_normalizer() {
+        let (base, hits) = mock_ad_server(vec![(
+            "/normalize",
+            r#"{"url":"https://cdn.example.com/mp4/master.m3u8"}"#.to_string(),
+        )])
+        .await;
+        let doc = vast_doc(&[
+            inline_ad("mp4", Some(1), 15, "video/mp4"),
+            inline_ad("hls", None, 15, "application/x-mpegURL"),
+        ]);
+        let resolve = |provider: VastAdProvider| {
+            let doc = doc.clone();
+            async move {
+                provider
+                    .resolve_vast_document(
+                        &doc,
+                        "s",
+                        15.0,
+                        &MacroContext::default(),
+                        &PolicyState::default(),
+                    )
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // Not packaged yet: the buffet ad takes the progressive ad's place
+        let normalizer = AdNormalizer::new(format!("{base}/pending"), Client::new());
+        let provider = VastAdProvider::new("http://unused".to_string(), Client::new())
+            .with_normalizer(normalizer);
+        let creatives = resolve(provider).await;
+        assert_eq!(creatives.len(), 1);
+        assert_eq!(creatives[0].ad.ad_id, "hls");
+
+        // Packaged: the progressive ad plays its HLS rendition
+        let normalizer = AdNormalizer::new(format!("{base}/normalize"), Client::new());
+        let provider = VastAdProvider::new("http://unused".to_string(), Client::new())
+            .with_normalizer(normalizer);
+        let creatives = resolve(provider).await;
+        assert_eq!(creatives.len(), 1);
+        assert_eq!(creatives[0].ad.ad_id, "mp4");
+        assert_eq!(creatives[0].url, "https://cdn.example.com/mp4/master.m3u8");
+        assert!(
+            hits.lock()
+                .unwrap()
+                .iter()
+                .any(|h| h.starts_with("/normalize?key=https%3A%2F%2Fads.example.com%2Fmp4.mp4"))
+        );
+    }
 }
@@ -1,3 +1,9 @@
+use crate::ad::conditioning::ConditioningAction;
+use crate::ad::pod::PodConstraints;
+use crate::ad::policy::PolicyRules;
+use crate::ad::sources::AdSourceStrategy;
+use crate::session::viewer::AD_PARAM_PREFIX;
+use serde::Deserialize;
 use std::env;
 
 /// HLS stitching mode
@@ -10,11 +16,33 @@ pub enum StitchingMode {
     Sgai,
 }
 
+/// How DASH XLink remote Periods are resolved
+#[derive(Clone, Debug, PartialEq)]
+pub enum XlinkResolution {
+    /// Fill the placeholder's duration from the configured ad provider,
+    /// falling back to the remote Period URL
+    AdProvider,
+    /// Fetch the remote Period URL, falling back to the ad provider
+    Remote,
+}
+
 /// Session store type selection
 #[derive(Clone, Debug, PartialEq)]
 pub enum SessionStoreType {
     Memory,
     Valkey,
+    Sqlite,
+}
+
+/// How the Valkey session store is deployed
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValkeyMode {
+    /// One server at `VALKEY_URL`
+    Standalone,
+    /// Valkey Cluster; `VALKEY_URL` lists seed nodes
+    Cluster,
+    /// Primary/replicas behind Sentinel; `VALKEY_URL` lists the Sentinels
+    Sentinel,
 }
 
 /// Ad provider selection
@@ -24,6 +52,40 @@ pub enum AdProviderType {
     Static,
     /// VAST-based ad provider fetching from an ad server
     Vast,
+    /// OpenRTB 2.6 auction across programmatic bidders
+    OpenRtb,
+}
+
+/// One ad server of a multi-source setup, as listed in `AD_SOURCES`
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct AdSourceConfig {
+    pub name: String,
+    /// VAST endpoint URL (supports the same macros as `VAST_ENDPOINT`)
+    pub url: String,
+    /// Deadline for the source's decision, in milliseconds (default: 2000)
+    #[serde(default = "AdSourceConfig::default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Bid price multiplier for the `best` strategy (default: 1.0)
+    #[serde(default = "AdSourceConfig::default_weight")]
+    pub weight: f64,
+}
+
+/// One bidder of an OpenRTB setup, as listed in `OPENRTB_BIDDERS`
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct OpenRtbBidderConfig {
+    pub name: String,
+    /// Endpoint bid requests are POSTed to
+    pub url: String,
+}
+
+impl AdSourceConfig {
+    fn default_timeout_ms() -> u64 {
+        2000
+    }
+
+    fn default_weight() -> f64 {
+        1.0
+    }
 }
 
 /// Application configuration loaded from environment variables
@@ -41,21 +103,122 @@ pub struct Config {
     pub ad_source_url: String,
     /// Static ad segment duration (used when ad_provider_type = Static)
     pub ad_segment_duration: f32,
+    /// Ad media playlists (URLs or local files) the static provider rotates
+    /// through; when set, they replace `ad_source_url`
+    pub ad_playlists: Vec<String>,
     /// VAST endpoint URL (used when ad_provider_type = Vast)
     pub vast_endpoint: Option<String>,
+    /// Ordered ad sources; when set, they replace `vast_endpoint`
+    pub ad_sources: Vec<AdSourceConfig>,
+    /// How the ad sources are combined (default: waterfall)
+    pub ad_source_strategy: AdSourceStrategy,
+    /// OpenRTB bidders (used when ad_provider_type = OpenRtb)
+    pub openrtb_bidders: Vec<OpenRtbBidderConfig>,
+    /// Milliseconds OpenRTB bidders have to answer (default: 500)
+    pub openrtb_tmax_ms: u64,
+    /// Lowest CPM accepted in OpenRTB auctions (default: 0)
+    pub openrtb_bid_floor: f64,
+    /// Currency of OpenRTB bids and floor (default: USD)
+    pub openrtb_currency: String,
+    /// Deadline for all ad decisions of one manifest request, in milliseconds
+    pub ad_decision_timeout_ms: u64,
+    /// Seconds ahead of a splice point to start deciding its break (0: disabled)
+    pub ad_lookahead_secs: u64,
+    /// Seconds decided VAST creatives stay resolvable (default: 300)
+    pub ad_cache_ttl_secs: u64,
+    /// Maximum VAST creatives cached across sessions (default: 10000)
+    pub ad_cache_max_creatives: usize,
+    /// Maximum number of ads per break (default: 10)
+    pub pod_max_ads: usize,
+    /// Ads shorter than this many seconds are not selected (default: 0)
+    pub pod_min_ad_duration: f32,
+    /// Times one creative may play per session (0: unlimited)
+    pub freq_cap_session: u32,
+    /// Times one creative may play per device or household (0: unlimited)
+    pub freq_cap_device: u32,
+    /// Window of the device frequency cap in seconds (default: 86400)
+    pub freq_cap_window_secs: u64,
+    /// Keep ads of one advertiser apart (default: true)
+    pub separate_advertisers: bool,
+    /// Keep ads sharing an IAB category apart (default: true)
+    pub separate_categories: bool,
+    /// Earlier breaks whose ads are kept out of the next break (default: 1)
+    pub separation_breaks: usize,
+    /// Handling of creatives that do not conform to the content (default: warn)
+    pub creative_conditioning: ConditioningAction,
+    /// Ad normalizer endpoint for packaging progressive MP4 creatives
+    pub ad_normalizer_url: Option<String>,
     /// Slate URL for fallback content when no ads are available
     pub slate_url: Option<String>,
     /// Slate segment duration in seconds (default: 1.0)
     pub slate_segment_duration: f32,
+    /// Slate played when the ad server has no ads (default: `slate_url`)
+    pub slate_url_nofill: Option<String>,
+    /// Slate played when the ad request fails (default: `slate_url`)
+    pub slate_url_error: Option<String>,
+    /// Slate played for ads left out by policy (default: `slate_url`)
+    pub slate_url_policy: Option<String>,
     /// Session store backend
     pub session_store: SessionStoreType,
-    /// Valkey/Redis URL (used when session_store = Valkey)
+    /// Valkey/Redis URL (used when session_store = Valkey); comma-separated
+    /// node or Sentinel URLs in cluster and Sentinel mode
     pub valkey_url: Option<String>,
+    /// Valkey deployment (default: standalone)
+    pub valkey_mode: ValkeyMode,
+    /// Sentinel master name (used in Sentinel mode)
+    pub valkey_sentinel_master: Option<String>,
+    /// SQLite database path (used when session_store = Sqlite)
+    pub session_db_path: String,
     /// Session TTL in seconds (default: 300)
     pub session_ttl_secs: u64,
+    /// Only serve sessions created through `POST /sessions` (default: false)
+    pub require_sessions: bool,
+    /// Scan live-edge HLS TS segments for in-band SCTE-35 cues (default: false)
+    pub inband_scte35: bool,
+    /// DASH XLink Period resolution strategy (default: ad provider)
+    pub xlink_resolution: XlinkResolution,
+    /// `ads.*` stitch URL parameters passed to ad decisioning (empty: none)
+    pub ad_query_params: Vec<String>,
 }
 
 impl Config {
+    /// Ad decisioning deadline as a Duration
+    pub fn ad_decision_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.ad_decision_timeout_ms)
+    }
+
+    /// How far beyond the playlist end upcoming breaks are decided ahead
+    pub fn ad_lookahead(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.ad_lookahead_secs)
+    }
+
+    /// How long decided creatives stay resolvable in the VAST ad cache
+    pub fn ad_cache_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.ad_cache_ttl_secs)
+    }
+
+    /// Ad pod limits for VAST decisions
+    pub fn pod_constraints(&self) -> PodConstraints {
+        PodConstraints {
+            max_ads: self.pod_max_ads,
+            min_ad_duration: self.pod_min_ad_duration,
+            ..Default::default()
+        }
+    }
+
+    /// Frequency caps and competitive separation for VAST decisions
+    pub fn policy_rules(&self) -> PolicyRules {
+        PolicyRules {
+            session_cap: self.freq_cap_session,
+            device_cap: self.freq_cap_device,
+            device_window: std::time::Duration::from_secs(self.freq_cap_window_secs),
+            separate_advertisers: self.separate_advertisers,
+            separate_categories: self.separate_categories,
+            separation_breaks: self.separation_breaks,
+            conditioning: self.creative_conditioning,
+        }
+    }
+
     /// Load configuration from environment variables
     /// In DEV mode, provides sensible defaults. In PROD mode, all vars are required.
     pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
@@ -103,7 +266,49 @@ impl Config {
         // VAST endpoint URL (optional)
         let vast_endpoint = env::var("VAST_ENDPOINT").ok();
 
-        // Ad provider type: auto-detect from VAST_ENDPOINT or explicit AD_PROVIDER_TYPE
+        // Multiple ad sources (optional), as a JSON array, e.g.
+        // [{"name":"primary","url":"https://...","timeout_ms":1500,"weight":1.2}]
+        let ad_sources: Vec<AdSourceConfig> = match env::var("AD_SOURCES") {
+            Ok(json) if !json.trim().is_empty() => serde_json::from_str(&json)
+                .map_err(|e| format!("AD_SOURCES is not a valid source list: {}", e))?,
+            _ => Vec::new(),
+        };
+
+        // Ad source strategy: waterfall (default), best or first
+        let ad_source_strategy = match env::var("AD_SOURCE_STRATEGY")
+            .unwrap_or_else(|_| "waterfall".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "best" => AdSourceStrategy::ParallelBest,
+            "first" => AdSourceStrategy::ParallelFirst,
+            _ => AdSourceStrategy::Waterfall,
+        };
+
+        // OpenRTB bidders (optional), as a JSON array, e.g.
+        // [{"name":"dsp","url":"https://..."}]
+        let openrtb_bidders: Vec<OpenRtbBidderConfig> = match env::var("OPENRTB_BIDDERS") {
+            Ok(json) if !json.trim().is_empty() => serde_json::from_str(&json)
+                .map_err(|e| format!("OPENRTB_BIDDERS is not a valid bidder list: {}", e))?,
+            _ => Vec::new(),
+        };
+
+        // OpenRTB auction: 500ms tmax, no floor, bids in USD
+        let openrtb_tmax_ms = env::var("OPENRTB_TMAX_MS")
+            .unwrap_or_else(|_| "500".to_string())
+            .parse()
+            .unwrap_or(500);
+        let openrtb_bid_floor = env::var("OPENRTB_BID_FLOOR")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0.0);
+        let openrtb_currency = env::var("OPENRTB_CURRENCY")
+            .map(|cur| cur.trim().to_uppercase())
+            .ok()
+            .filter(|cur| !cur.is_empty())
+            .unwrap_or_else(|| "USD".to_string());
+
+        // Ad provider type: auto-detect from VAST_ENDPOINT/AD_SOURCES/OPENRTB_BIDDERS or explicit AD_PROVIDER_TYPE
         let ad_provider_type = match env::var("AD_PROVIDER_TYPE")
             .unwrap_or_else(|_| "auto".to_string())
             .to_lowercase()
@@ -111,10 +316,14 @@ impl Config {
         {
             "vast" => AdProviderType::Vast,
             "static" => AdProviderType::Static,
+            "openrtb" => AdProviderType::OpenRtb,
             _ => {
-                // Auto-detect: use VAST if endpoint is configured, otherwise static
-                if vast_endpoint.is_some() {
+                // Auto-detect: use VAST if an ad server is configured, then
+                // OpenRTB if bidders are, otherwise static
+                if vast_endpoint.is_some() || !ad_sources.is_empty() {
                     AdProviderType::Vast
+                } else if !openrtb_bidders.is_empty() {
+                    AdProviderType::OpenRtb
                 } else {
                     AdProviderType::Static
                 }
@@ -131,8 +340,108 @@ impl Config {
             .parse()
             .unwrap_or(1.0);
 
-        // Slate URL: optional fallback content for empty ad breaks
+        // Static ad playlists (optional): comma-separated URLs or local files
+        let ad_playlists = env::var("AD_PLAYLISTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|source| !source.is_empty())
+            .map(String::from)
+            .collect();
+
+        // Ad decisioning deadline per manifest request: defaults to 3 seconds
+        let ad_decision_timeout_ms = env::var("AD_DECISION_TIMEOUT_MS")
+            .unwrap_or_else(|_| "3000".to_string())
+            .parse()
+            .unwrap_or(3000);
+
+        // Decide breaks signalled up to 30 seconds ahead of their splice point
+        let ad_lookahead_secs = env::var("AD_LOOKAHEAD_SECS")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse()
+            .unwrap_or(30);
+
+        // VAST ad cache: creatives resolvable for 5 minutes, at most 10,000
+        let ad_cache_ttl_secs = env::var("AD_CACHE_TTL_SECS")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()
+            .unwrap_or(300);
+        let ad_cache_max_creatives = env::var("AD_CACHE_MAX_CREATIVES")
+            .unwrap_or_else(|_| "10000".to_string())
+            .parse()
+            .unwrap_or(10_000);
+
+        // Ad pod limits: at most 10 ads per break, no minimum ad length
+        let pod_max_ads = env::var("POD_MAX_ADS")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse()
+            .unwrap_or(10);
+        let pod_min_ad_duration = env::var("POD_MIN_AD_DURATION")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0.0);
+
+        // Frequency caps: off by default; the device window is one day
+        let freq_cap_session = env::var("FREQ_CAP_SESSION")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0);
+        let freq_cap_device = env::var("FREQ_CAP_DEVICE")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .unwrap_or(0);
+        let freq_cap_window_secs = env::var("FREQ_CAP_WINDOW_SECS")
+            .unwrap_or_else(|_| "86400".to_string())
+            .parse()
+            .unwrap_or(86400);
+
+        // Competitive separation: advertisers and categories kept apart
+        // within a pod and from the previous break
+        let separate_advertisers = env::var("SEPARATE_ADVERTISERS")
+            .unwrap_or_else(|_| "true".to_string())
+            .parse()
+            .unwrap_or(true);
+        let separate_categories = env::var("SEPARATE_CATEGORIES")
+            .unwrap_or_else(|_| "true".to_string())
+            .parse()
+            .unwrap_or(true);
+        let separation_breaks = env::var("SEPARATION_BREAKS")
+            .unwrap_or_else(|_| "1".to_string())
+            .parse()
+            .unwrap_or(1);
+
+        // Creative conditioning: warn (default), reject, substitute or slate
+        let creative_conditioning = match env::var("CREATIVE_CONDITIONING")
+            .unwrap_or_else(|_| "warn".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "reject" => ConditioningAction::Reject,
+            "substitute" => ConditioningAction::Substitute,
+            "slate" => ConditioningAction::Slate,
+            _ => ConditioningAction::Warn,
+        };
+
+        // Ad normalizer: optional; without it progressive creatives are stitched as-is
+        let ad_normalizer_url = env::var("AD_NORMALIZER_URL").ok();
+
+        // Slate URL: optional fallback content for empty ad breaks, either
+        // a media playlist (.m3u8) or the base URL of out_NNN.ts segments
         let slate_url = env::var("SLATE_URL").ok();
+        // Optional slates per reason, replacing SLATE_URL for that reason
+        let slate_url_nofill = env::var("SLATE_URL_NOFILL").ok();
+        let slate_url_error = env::var("SLATE_URL_ERROR").ok();
+        let slate_url_policy = env::var("SLATE_URL_POLICY").ok();
+        // They only override a reason, so without SLATE_URL they would be ignored
+        if slate_url.is_none()
+            && (slate_url_nofill.is_some()
+                || slate_url_error.is_some()
+                || slate_url_policy.is_some())
+        {
+            return Err(
+                "SLATE_URL_NOFILL, SLATE_URL_ERROR and SLATE_URL_POLICY require SLATE_URL".into(),
+            );
+        }
 
         // Slate segment duration: defaults to 1 second
         let slate_segment_duration = env::var("SLATE_SEGMENT_DURATION")
@@ -150,9 +459,62 @@ impl Config {
             .as_str()
         {
             "valkey" | "redis" => SessionStoreType::Valkey,
+            "sqlite" => SessionStoreType::Sqlite,
             _ => SessionStoreType::Memory,
         };
         let valkey_url = env::var("VALKEY_URL").ok();
+        let valkey_mode = match env::var("VALKEY_MODE")
+            .unwrap_or_else(|_| "standalone".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "cluster" => ValkeyMode::Cluster,
+            "sentinel" => ValkeyMode::Sentinel,
+            _ => ValkeyMode::Standalone,
+        };
+        let valkey_sentinel_master = env::var("VALKEY_SENTINEL_MASTER").ok();
+        let session_db_path =
+            env::var("SESSION_DB_PATH").unwrap_or_else(|_| "ritcher-sessions.db".to_string());
+
+        // Sessions are created implicitly by their first stitch request
+        // unless they must be issued by the session API
+        let require_sessions = env::var("REQUIRE_SESSIONS")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+
+        // In-band SCTE-35 detection for HLS transport streams: off by default
+        // since it costs extra origin fetches on every playlist refresh
+        let inband_scte35 = env::var("INBAND_SCTE35")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .unwrap_or(false);
+
+        // XLink Periods: resolve through the ad provider (default) or remote URL
+        let xlink_resolution = match env::var("XLINK_RESOLUTION")
+            .unwrap_or_else(|_| "provider".to_string())
+            .to_lowercase()
+            .as_str()
+        {
+            "remote" => XlinkResolution::Remote,
+            _ => XlinkResolution::AdProvider,
+        };
+
+        // Viewer targeting parameters accepted from stitch URLs, e.g.
+        // "genre,ifa" or "ads.genre,ads.ifa"; unset accepts none
+        let ad_query_params = env::var("AD_QUERY_PARAMS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                if name.starts_with(AD_PARAM_PREFIX) {
+                    name.to_string()
+                } else {
+                    format!("{}{}", AD_PARAM_PREFIX, name)
+                }
+            })
+            .collect();
 
         Ok(Config {
             port,
@@ -163,12 +525,43 @@ impl Config {
             ad_provider_type,
             ad_source_url,
             ad_segment_duration,
+            ad_playlists,
             vast_endpoint,
+            ad_sources,
+            ad_source_strategy,
+            openrtb_bidders,
+            openrtb_tmax_ms,
+            openrtb_bid_floor,
+            openrtb_currency,
+            ad_decision_timeout_ms,
+            ad_lookahead_secs,
+            ad_cache_ttl_secs,
+            ad_cache_max_creatives,
+            pod_max_ads,
+            pod_min_ad_duration,
+            freq_cap_session,
+            freq_cap_device,
+            freq_cap_window_secs,
+            separate_advertisers,
+            separate_categories,
+            separation_breaks,
+            creative_conditioning,
+            ad_normalizer_url,
             slate_url,
             slate_segment_duration,
+            slate_url_nofill,
+            slate_url_error,
+            slate_url_policy,
             session_store,
             valkey_url,
+            valkey_mode,
+            valkey_sentinel_master,
+            session_db_path,
             session_ttl_secs,
+            require_sessions,
+            inband_scte35,
+            xlink_resolution,
+            ad_query_params,
         })
     }
 }
@@ -1,4 +1,5 @@
-use dash_mpd::MPD;
+use crate::scte35::InbandCue;
+use dash_mpd::{MPD, Period, SegmentTemplate};
 use tracing::{debug, info, warn};
 
 /// Represents an ad break detected from DASH EventStream/SCTE-35 signaling
@@ -16,6 +17,20 @@ pub struct DashAdBreak {
     pub signal_type: DashSignalType,
 }
 
+impl DashAdBreak {
+    /// Identify the break across MPD refreshes of a session
+    ///
+    /// Prefers the Period id, which survives Periods rolling out of a live
+    /// MPD; the index is only a fallback for MPDs without Period ids.
+    pub fn break_key(&self) -> String {
+        let period = match &self.period_id {
+            Some(id) => id.clone(),
+            None => format!("#{}", self.period_index),
+        };
+        format!("{}@{:.3}", period, self.presentation_time)
+    }
+}
+
 /// Type of SCTE-35 signal detected in EventStream
 #[derive(Debug, Clone, PartialEq, Copy)]
 pub enum DashSignalType {
@@ -82,6 +97,94 @@ pub fn detect_dash_ad_breaks(mpd: &MPD) -> Vec<DashAdBreak> {
     ad_breaks
 }
 
+/// Convert in-band SCTE-35 cues (from `emsg` boxes) into DASH ad breaks
+///
+/// Each cue's media time is mapped to the last Period whose
+/// presentationTimeOffset does not exceed it (live edge for multi-Period
+/// manifests). Cues that duplicate a break already signalled in an MPD
+/// EventStream (same Period, within half a second) are dropped, as are cues
+/// failing the same duration bounds as EventStream signals.
+pub fn inband_ad_breaks(
+    mpd: &MPD,
+    cues: &[InbandCue],
+    signalled: &[DashAdBreak],
+) -> Vec<DashAdBreak> {
+    let mut ad_breaks = Vec::new();
+
+    if mpd.periods.is_empty() {
+        return ad_breaks;
+    }
+
+    for cue in cues {
+        if cue.duration <= 0.0 || cue.duration > 600.0 {
+            warn!(
+                "Invalid in-band ad break duration {}s (event {}), skipping (max 600s)",
+                cue.duration, cue.event_id
+            );
+            continue;
+        }
+
+        let (period_idx, offset) = mpd
+            .periods
+            .iter()
+            .enumerate()
+            .map(|(idx, period)| (idx, presentation_time_offset(period)))
+            .rfind(|(_, offset)| *offset <= cue.media_time)
+            .unwrap_or((0, 0.0));
+        let presentation_time = cue.media_time - offset;
+
+        let duplicate = signalled.iter().chain(ad_breaks.iter()).any(|b| {
+            b.period_index == period_idx && (b.presentation_time - presentation_time).abs() < 0.5
+        });
+        if duplicate {
+            debug!(
+                "In-band cue {} duplicates a signalled ad break, skipping",
+                cue.event_id
+            );
+            continue;
+        }
+
+        info!(
+            "In-band ad break at Period #{}, presentation_time: {}s, duration: {}s",
+            period_idx, presentation_time, cue.duration
+        );
+        ad_breaks.push(DashAdBreak {
+            period_index: period_idx,
+            period_id: mpd.periods[period_idx].id.clone(),
+            duration: cue.duration,
+            presentation_time,
+            signal_type: DashSignalType::SpliceInsert,
+        });
+    }
+
+    ad_breaks
+}
+
+/// Presentation time offset of a Period in seconds
+///
+/// Taken from the first SegmentTemplate found at Period, AdaptationSet or
+/// Representation level — renditions of one Period share a timeline.
+fn presentation_time_offset(period: &Period) -> f64 {
+    let templates = period
+        .SegmentTemplate
+        .iter()
+        .chain(period.adaptations.iter().flat_map(|a| {
+            a.SegmentTemplate.iter().chain(
+                a.representations
+                    .iter()
+                    .flat_map(|r| r.SegmentTemplate.iter()),
+            )
+        }));
+
+    templates
+        .filter_map(|t: &SegmentTemplate| {
+            let pto = t.presentationTimeOffset?;
+            Some(pto as f64 / t.timescale.unwrap_or(1).max(1) as f64)
+        })
+        .next()
+        .unwrap_or(0.0)
+}
+
 /// Check if schemeIdUri represents a SCTE-35 signal
 fn is_scte35_scheme(scheme_id: &str) -> bool {
     scheme_id.starts_with("urn:scte:scte35:")
@@ -208,6 +311,64 @@ mod tests {
         assert_eq!(ad_breaks.len(), 0);
     }
 
+    #[test]
+    fn test_inband_ad_breaks_map_to_period() {
+        let xml = r#"<?xml version="1.0"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="dynamic">
+  <Period id="p0" start="PT0S">
+    <AdaptationSet>
+      <SegmentTemplate timescale="1000" presentationTimeOffset="0" media="$Number$.m4s"/>
+      <Representation id="1" bandwidth="1000000"/>
+    </AdaptationSet>
+  </Period>
+  <Period id="p1" start="PT600S">
+    <AdaptationSet>
+      <SegmentTemplate timescale="1000" presentationTimeOffset="600000" media="$Number$.m4s"/>
+      <Representation id="1" bandwidth="1000000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+        let mpd = parse_mpd(xml).expect("Failed to parse MPD");
+        let cues = vec![
+            InbandCue::new(1, 120.0, 30.0),
+            InbandCue::new(2, 650.0, 15.0),
+            InbandCue::new(3, 700.0, 0.0),
+        ];
+
+        let breaks = inband_ad_breaks(&mpd, &cues, &[]);
+        assert_eq!(breaks.len(), 2);
+        assert_eq!(breaks[0].period_index, 0);
+        assert_eq!(breaks[0].presentation_time, 120.0);
+        assert_eq!(breaks[1].period_index, 1);
+        assert_eq!(breaks[1].period_id, Some("p1".to_string()));
+        assert_eq!(breaks[1].presentation_time, 50.0);
+        assert_eq!(breaks[1].duration, 15.0);
+    }
+
+    #[test]
+    fn test_inband_ad_breaks_skip_signalled_duplicates() {
+        let xml = r#"<?xml version="1.0"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="dynamic">
+  <Period id="live">
+    <EventStream schemeIdUri="urn:scte:scte35:2013:xml" timescale="1">
+      <Event presentationTime="50" duration="30" id="1"/>
+    </EventStream>
+    <AdaptationSet>
+      <Representation id="1" bandwidth="1000000">
+        <SegmentTemplate media="$Number$.m4s"/>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+        let mpd = parse_mpd(xml).expect("Failed to parse MPD");
+        let signalled = detect_dash_ad_breaks(&mpd);
+        assert_eq!(signalled.len(), 1);
+
+        // Same break as the EventStream (50s into the Period) seen in-band
+        let cues = vec![InbandCue::new(9, 50.2, 30.0)];
+        assert!(inband_ad_breaks(&mpd, &cues, &signalled).is_empty());
+    }
+
     #[test]
     fn test_is_scte35_scheme() {
         assert!(is_scte35_scheme("urn:scte:scte35:2013:xml"));
@@ -0,0 +1,362 @@
+//! In-band event message (`emsg`) extraction from fMP4/CMAF segments
+//!
+//! Packagers that signal ads with `InbandEventStream` carry SCTE-35
+//! splice_info_sections in `emsg` boxes (ISO/IEC 23009-1 §5.10.3.3) ahead of
+//! each `moof`. Segments already flow through the stitcher's segment proxy,
+//! so we scan them there and hand decoded cues to the manifest pipeline.
+
+use crate::scte35::{InbandCue, parse_splice_info_section};
+use tracing::{debug, warn};
+
+/// Scheme for binary SCTE-35 in emsg message_data
+pub const SCTE35_BIN_SCHEME: &str = "urn:scte:scte35:2013:bin";
+
+/// `event_duration` value meaning "unknown"
+const UNKNOWN_DURATION: u32 = 0xFFFF_FFFF;
+
+/// A decoded `emsg` box
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmsgEvent {
+    pub scheme_id_uri: String,
+    pub value: String,
+    pub timescale: u32,
+    /// Absolute presentation time in `timescale` units
+    ///
+    /// Version 1 boxes carry it directly. Version 0 boxes carry a delta from
+    /// the segment's earliest presentation time, resolved from `sidx` when
+    /// present, otherwise from `tfdt` (assuming the track timescale matches).
+    pub presentation_time: u64,
+    /// Event duration in `timescale` units (None = unknown)
+    pub event_duration: Option<u32>,
+    pub id: u32,
+    pub message_data: Vec<u8>,
+}
+
+/// Parse all top-level `emsg` boxes in a media segment
+///
+/// Malformed boxes end the scan; whatever was decoded before is returned.
+pub fn parse_emsg_boxes(segment: &[u8]) -> Vec<EmsgEvent> {
+    let boxes = top_level_boxes(segment);
+    let earliest = earliest_presentation_time(&boxes);
+
+    boxes
+        .iter()
+        .filter(|(kind, _)| kind == b"emsg")
+        .filter_map(|(_, body)| parse_emsg(body, earliest))
+        .collect()
+}
+
+/// Extract SCTE-35 ad break starts from a media segment's `emsg` boxes
+///
+/// Media time comes from the emsg presentation time; the break duration from
+/// the SCTE-35 payload, falling back to the emsg `event_duration`.
+pub fn scte35_cues(segment: &[u8]) -> Vec<InbandCue> {
+    parse_emsg_boxes(segment)
+        .into_iter()
+        .filter(|e| e.scheme_id_uri == SCTE35_BIN_SCHEME && e.timescale > 0)
+        .filter_map(|event| {
+            let info = match parse_splice_info_section(&event.message_data) {
+                Ok(info) => info,
+                Err(e) => {
+                    warn!("Skipping emsg {} with invalid SCTE-35: {}", event.id, e);
+                    return None;
+                }
+            };
+            let cue = info.cue_out()?;
+            let timescale = event.timescale as f64;
+            let duration = cue
+                .duration
+                .or_else(|| event.event_duration.map(|d| d as f64 / timescale))?;
+
+            debug!(
+                "emsg {} carries SCTE-35 cue-out (event {}, duration {}s)",
+                event.id, cue.event_id, duration
+            );
+            Some(InbandCue::new(
+                cue.event_id,
+                event.presentation_time as f64 / timescale,
+                duration,
+            ))
+        })
+        .collect()
+}
+
+/// Parse the body of an `emsg` full box (after the 8-byte box header)
+fn parse_emsg(body: &[u8], earliest: Option<(u64, u32)>) -> Option<EmsgEvent> {
+    let mut r = ByteReader::new(body);
+    let version = r.u8()?;
+    r.skip(3)?; // flags
+
+    let (scheme_id_uri, value, timescale, presentation_time, duration, id) = match version {
+        0 => {
+            let scheme = r.cstring()?;
+            let value = r.cstring()?;
+            let timescale = r.u32()?;
+            let delta = r.u32()? as u64;
+            let duration = r.u32()?;
+            let id = r.u32()?;
+            let base = match earliest {
+                Some((ept, ts)) if ts == timescale || ts == 0 => ept,
+                Some((ept, ts)) => (ept as u128 * timescale as u128 / ts as u128) as u64,
+                None => 0,
+            };
+            (
+                scheme,
+                value,
+                timescale,
+                base.saturating_add(delta),
+                duration,
+                id,
+            )
+        }
+        1 => {
+            let timescale = r.u32()?;
+            let presentation_time = r.u64()?;
+            let duration = r.u32()?;
+            let id = r.u32()?;
+            let scheme = r.cstring()?;
+            let value = r.cstring()?;
+            (scheme, value, timescale, presentation_time, duration, id)
+        }
+        other => {
+            debug!("Ignoring emsg box with unsupported version {}", other);
+            return None;
+        }
+    };
+
+    Some(EmsgEvent {
+        scheme_id_uri,
+        value,
+        timescale,
+        presentation_time,
+        event_duration: (duration != UNKNOWN_DURATION).then_some(duration),
+        id,
+        message_data: r.rest().to_vec(),
+    })
+}
+
+/// Earliest presentation time of a segment as (time, timescale)
+///
+/// `sidx` carries both; `tfdt` only carries the decode time, so its timescale
+/// is reported as 0 (unknown) and assumed equal to the emsg timescale.
+fn earliest_presentation_time(boxes: &[([u8; 4], &[u8])]) -> Option<(u64, u32)> {
+    for (kind, body) in boxes {
+        if kind == b"sidx" {
+            let mut r = ByteReader::new(body);
+            let version = r.u8()?;
+            r.skip(3 + 4)?; // flags, reference_ID
+            let timescale = r.u32()?;
+            let ept = if version == 0 {
+                r.u32()? as u64
+            } else {
+                r.u64()?
+            };
+            return Some((ept, timescale));
+        }
+    }
+
+    let moof = boxes.iter().find(|(k, _)| k == b"moof")?.1;
+    let traf = top_level_boxes(moof)
+        .into_iter()
+        .find(|(k, _)| k == b"traf")?
+        .1;
+    let tfdt = top_level_boxes(traf)
+        .into_iter()
+        .find(|(k, _)| k == b"tfdt")?
+        .1;
+    let mut r = ByteReader::new(tfdt);
+    let version = r.u8()?;
+    r.skip(3)?;
+    let decode_time = if version == 1 {
+        r.u64()?
+    } else {
+        r.u32()? as u64
+    };
+    Some((decode_time, 0))
+}
+
+/// Split a buffer into (type, body) pairs of consecutive ISO BMFF boxes
+fn top_level_boxes(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = Vec::new();
+    let mut pos = 0usize;
+
+    while pos + 8 <= data.len() {
+        let size = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
+        let kind = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+        let (header, size) = match size {
+            0 => (8, (data.len() - pos) as u64),
+            1 => {
+                if pos + 16 > data.len() {
+                    break;
+                }
+                let mut large = [0u8; 8];
+                large.copy_from_slice(&data[pos + 8..pos + 16]);
+                (16, u64::from_be_bytes(large))
+            }
+            n => (8, n as u64),
+        };
+
+        if size < header as u64 || pos as u64 + size > data.len() as u64 {
+            break;
+        }
+        let end = pos + size as usize;
+        boxes.push((kind, &data[pos + header..end]));
+        pos = end;
+    }
+
+    boxes
+}
+
+/// Bounds-checked big-endian reader
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let slice = self.data.get(self.pos..self.pos + n)?;
+        self.pos += n;
+        Some(slice)
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let b = self.take(8)?;
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(b);
+        Some(u64::from_be_bytes(buf))
+    }
+
+    fn cstring(&mut self) -> Option<String> {
+        let rest = &self.data[self.pos..];
+        let nul = rest.iter().position(|&b| b == 0)?;
+        let s = String::from_utf8_lossy(&rest[..nul]).into_owned();
+        self.pos += nul + 1;
+        Some(s)
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scte35::splice::tests::splice_insert_section;
+
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn emsg_v1(
+        timescale: u32,
+        pt: u64,
+        duration: u32,
+        id: u32,
+        scheme: &str,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut body = vec![1, 0, 0, 0];
+        body.extend_from_slice(&timescale.to_be_bytes());
+        body.extend_from_slice(&pt.to_be_bytes());
+        body.extend_from_slice(&duration.to_be_bytes());
+        body.extend_from_slice(&id.to_be_bytes());
+        body.extend_from_slice(scheme.as_bytes());
+        body.push(0);
+        body.push(0); // empty value
+        body.extend_from_slice(data);
+        mp4_box(b"emsg", &body)
+    }
+
+    fn emsg_v0(timescale: u32, delta: u32, id: u32, scheme: &str, data: &[u8]) -> Vec<u8> {
+        let mut body = vec![0, 0, 0, 0];
+        body.extend_from_slice(scheme.as_bytes());
+        body.push(0);
+        body.extend_from_slice(b"1\0");
+        body.extend_from_slice(&timescale.to_be_bytes());
+        body.extend_from_slice(&delta.to_be_bytes());
+        body.extend_from_slice(&UNKNOWN_DURATION.to_be_bytes());
+        body.extend_from_slice(&id.to_be_bytes());
+        body.extend_from_slice(data);
+        mp4_box(b"emsg", &body)
+    }
+
+    fn moof_with_tfdt(decode_time: u64) -> Vec<u8> {
+        let mut tfdt = vec![1, 0, 0, 0];
+        tfdt.extend_from_slice(&decode_time.to_be_bytes());
+        let traf = mp4_box(b"traf", &mp4_box(b"tfdt", &tfdt));
+        mp4_box(b"moof", &traf)
+    }
+
+    #[test]
+    fn test_scte35_cue_from_emsg_v1() {
+        let scte = splice_insert_section(100, 0, 30.0);
+        let mut segment = mp4_box(b"styp", b"msdh\0\0\0\0");
+        segment.extend(emsg_v1(1000, 120_000, 30_000, 5, SCTE35_BIN_SCHEME, &scte));
+        segment.extend(moof_with_tfdt(120_000));
+        segment.extend(mp4_box(b"mdat", &[0u8; 16]));
+
+        let cues = scte35_cues(&segment);
+        assert_eq!(cues.len(), 1);
+        assert_eq!(cues[0].event_id, 100);
+        assert_eq!(cues[0].media_time, 120.0);
+        assert_eq!(cues[0].duration, 30.0);
+    }
+
+    #[test]
+    fn test_emsg_v0_resolves_delta_against_tfdt() {
+        let scte = splice_insert_section(7, 0, 15.0);
+        let mut segment = emsg_v0(90_000, 180_000, 1, SCTE35_BIN_SCHEME, &scte);
+        segment.extend(moof_with_tfdt(900_000));
+
+        let events = parse_emsg_boxes(&segment);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].presentation_time, 1_080_000);
+        assert_eq!(events[0].event_duration, None);
+        assert_eq!(events[0].value, "1");
+
+        let cues = scte35_cues(&segment);
+        assert_eq!(cues[0].media_time, 12.0);
+        assert_eq!(cues[0].duration, 15.0);
+    }
+
+    #[test]
+    fn test_ignores_other_schemes_and_bad_payloads() {
+        let mut segment = emsg_v1(1, 10, 5, 1, "urn:mpeg:dash:event:2012", b"\x01");
+        segment.extend(emsg_v1(1, 10, 5, 2, SCTE35_BIN_SCHEME, b"garbage"));
+
+        assert_eq!(parse_emsg_boxes(&segment).len(), 2);
+        assert!(scte35_cues(&segment).is_empty());
+    }
+
+    #[test]
+    fn test_truncated_segment_does_not_panic() {
+        let scte = splice_insert_section(1, 0, 30.0);
+        let segment = emsg_v1(1000, 0, 0, 1, SCTE35_BIN_SCHEME, &scte);
+        for len in 0..segment.len() {
+            let _ = scte35_cues(&segment[..len]);
+        }
+        assert!(scte35_cues(b"\x47\x40\x00\x10 not an mp4").is_empty());
+    }
+}
@@ -1,6 +1,9 @@
-use crate::ad::provider::AdSegment;
+use crate::ad::provider::{self, AdSegment};
+use crate::ad::slate;
 use crate::dash::cue::DashAdBreak;
-use dash_mpd::{AdaptationSet, MPD, Period, Representation, SegmentList, SegmentURL};
+use dash_mpd::{
+    AdaptationSet, Initialization, MPD, Period, Representation, SegmentList, SegmentURL,
+};
 use std::time::Duration;
 use tracing::{info, warn};
 
@@ -73,6 +76,7 @@ pub fn interleave_ads_mpd(
         let ad_period = create_ad_period(
             ad_segments,
             break_idx,
+            &provider::break_id(&ad_break.break_key()),
             session_id,
             base_url,
             content_adaptations,
@@ -109,18 +113,26 @@ pub fn interleave_ads_mpd(
 /// Falls back to a single video-only AdaptationSet when no content AdaptationSets
 /// are available (backward compatibility).
 ///
+/// Low-latency signalling is deliberately not mirrored: ad creatives are
+/// pre-produced, so their segments are fully available (no
+/// `availabilityTimeOffset`, `availabilityTimeComplete` defaults to true) and
+/// carry no `ProducerReferenceTime` of the live encoder. MPD-level
+/// `ServiceDescription` still applies, so latency targets hold across breaks.
+///
 /// # Arguments
 /// * `ad_segments` - Ad segments to include in this Period
 /// * `break_idx` - Index of this ad break (for ID generation)
+/// * `break_id` - Stable id of the break its ad segments are served under
 /// * `session_id` - Session ID for URL generation
 /// * `base_url` - Stitcher base URL for proxying
 /// * `content_adaptations` - AdaptationSets from the content Period to mirror
 ///
 /// # Returns
 /// A Period with ad content matching the content track structure
-fn create_ad_period(
+pub(crate) fn create_ad_period(
     ad_segments: &[AdSegment],
     break_idx: usize,
+    break_id: &str,
     session_id: &str,
     base_url: &str,
     content_adaptations: &[AdaptationSet],
@@ -128,24 +140,46 @@ fn create_ad_period(
     // Calculate total duration
     let total_duration: f64 = ad_segments.iter().map(|s| s.duration as f64).sum();
 
-    // Create SegmentURL entries for each ad segment (shared across all tracks)
+    // Create SegmentURL entries for each ad segment (shared across all tracks).
+    // Ads are named by their position in the pod, slate by itself.
+    let mut ad_idx = 0;
     let segment_urls: Vec<SegmentURL> = ad_segments
         .iter()
-        .enumerate()
-        .map(|(seg_idx, _seg)| SegmentURL {
-            media: Some(format!(
-                "{}/stitch/{}/ad/break-{}-seg-{}.ts",
-                base_url, session_id, break_idx, seg_idx
-            )),
-            ..Default::default()
+        .map(|seg| {
+            let name = if slate::is_slate_segment(&seg.uri) {
+                seg.uri.clone()
+            } else {
+                ad_idx += 1;
+                provider::ad_segment_name(break_id, ad_idx - 1)
+            };
+            SegmentURL {
+                media: Some(format!("{}/stitch/{}/ad/{}", base_url, session_id, name)),
+                ..Default::default()
+            }
         })
         .collect();
 
+    // fMP4 ads need their initialization segment; one per Period, so only
+    // when every ad of the break shares it
+    let initialization = ad_segments
+        .first()
+        .and_then(|s| s.init.as_ref())
+        .filter(|init| ad_segments.iter().all(|s| s.init.as_ref() == Some(*init)))
+        .map(|init| Initialization {
+            sourceURL: Some(init.clone()),
+            ..Default::default()
+        });
+    let segment_list = SegmentList {
+        Initialization: initialization,
+        segment_urls,
+        ..Default::default()
+    };
+
     // Mirror content AdaptationSets, or fall back to single video
     let adaptations = if content_adaptations.is_empty() {
         vec![create_fallback_video_adaptation_set(
             break_idx,
-            segment_urls,
+            segment_list,
         )]
     } else {
         content_adaptations
@@ -161,10 +195,7 @@ fn create_ad_period(
                 let representation = Representation {
                     id: Some(format!("ad-rep-{}-{}", break_idx, as_idx)),
                     bandwidth: Some(bw),
-                    SegmentList: Some(SegmentList {
-                        segment_urls: segment_urls.clone(),
-                        ..Default::default()
-                    }),
+                    SegmentList: Some(segment_list.clone()),
                     ..Default::default()
                 };
 
@@ -191,15 +222,12 @@ fn create_ad_period(
 /// Fallback: create a single video-only AdaptationSet (backward compatibility)
 fn create_fallback_video_adaptation_set(
     break_idx: usize,
-    segment_urls: Vec<SegmentURL>,
+    segment_list: SegmentList,
 ) -> AdaptationSet {
     let representation = Representation {
         id: Some(format!("ad-rep-{}", break_idx)),
         bandwidth: Some(500_000),
-        SegmentList: Some(SegmentList {
-            segment_urls,
-            ..Default::default()
-        }),
+        SegmentList: Some(segment_list),
         ..Default::default()
     };
 
@@ -274,16 +302,19 @@ mod tests {
             AdSegment {
                 uri: "ad1.ts".to_string(),
                 duration: 10.0,
+                init: None,
                 tracking: None,
             },
             AdSegment {
                 uri: "ad2.ts".to_string(),
                 duration: 10.0,
+                init: None,
                 tracking: None,
             },
             AdSegment {
                 uri: "ad3.ts".to_string(),
                 duration: 10.0,
+                init: None,
                 tracking: None,
             },
         ]];
@@ -327,17 +358,20 @@ mod tests {
             vec![AdSegment {
                 uri: "ad1.ts".to_string(),
                 duration: 15.0,
+                init: None,
                 tracking: None,
             }],
             vec![
                 AdSegment {
                     uri: "ad2.ts".to_string(),
                     duration: 10.0,
+                    init: None,
                     tracking: None,
                 },
                 AdSegment {
                     uri: "ad3.ts".to_string(),
                     duration: 10.0,
+                    init: None,
                     tracking: None,
                 },
             ],
@@ -378,6 +412,7 @@ mod tests {
         let ad_segments = vec![vec![AdSegment {
             uri: "ad.ts".to_string(),
             duration: 30.0,
+            init: None,
             tracking: None,
         }]];
 
@@ -391,6 +426,36 @@ mod tests {
         assert_eq!(result.periods[3].id, original_periods[2].id);
     }
 
+    #[test]
+    fn test_ad_period_initialization_for_fmp4_ads() {
+        let ad = |init: &str| AdSegment {
+            uri: "ad.m4s".to_string(),
+            duration: 10.0,
+            init: Some(init.to_string()),
+            tracking: None,
+        };
+        let initialization = |segments: &[AdSegment]| {
+            let period = create_ad_period(segments, 0, "0", "test", "http://test", &[]);
+            period.adaptations[0].representations[0]
+                .SegmentList
+                .as_ref()
+                .unwrap()
+                .Initialization
+                .as_ref()
+                .and_then(|i| i.sourceURL.clone())
+        };
+
+        assert_eq!(
+            initialization(&[ad("https://ads/init.mp4"), ad("https://ads/init.mp4")]),
+            Some("https://ads/init.mp4".to_string())
+        );
+        // Ads with different initialization segments cannot share one
+        assert_eq!(
+            initialization(&[ad("https://ads/a.mp4"), ad("https://ads/b.mp4")]),
+            None
+        );
+    }
+
     #[test]
     fn test_ad_period_segment_urls() {
         let mpd = create_test_mpd_with_periods(1);
@@ -401,15 +466,18 @@ mod tests {
             AdSegment {
                 uri: "ad1.ts".to_string(),
                 duration: 10.0,
+                init: None,
                 tracking: None,
             },
             AdSegment {
                 uri: "ad2.ts".to_string(),
                 duration: 10.0,
+                init: None,
                 tracking: None,
             },
         ]];
 
+        let break_id = provider::break_id(&ad_breaks[0].break_key());
         let result = interleave_ads_mpd(
             mpd,
             &ad_breaks,
@@ -418,7 +486,7 @@ mod tests {
             "https://stitcher.example.com",
         );
 
-        // Verify segment URLs have correct format
+        // Segments are named by the break's stable id and their pod position
         let ad_period = &result.periods[1];
         let segment_list = &ad_period.adaptations[0].representations[0]
             .SegmentList
@@ -426,14 +494,15 @@ mod tests {
             .unwrap();
 
         assert_eq!(segment_list.segment_urls.len(), 2);
-        assert_eq!(
-            segment_list.segment_urls[0].media,
-            Some("https://stitcher.example.com/stitch/session123/ad/break-0-seg-0.ts".to_string())
-        );
-        assert_eq!(
-            segment_list.segment_urls[1].media,
-            Some("https://stitcher.example.com/stitch/session123/ad/break-0-seg-1.ts".to_string())
-        );
+        for (idx, url) in segment_list.segment_urls.iter().enumerate() {
+            assert_eq!(
+                url.media,
+                Some(format!(
+                    "https://stitcher.example.com/stitch/session123/ad/break-{}-seg-{}.ts",
+                    break_id, idx
+                ))
+            );
+        }
     }
 
     #[test]
@@ -461,6 +530,7 @@ mod tests {
         let ad_segments = vec![vec![AdSegment {
             uri: "ad.ts".to_string(),
             duration: 30.0,
+            init: None,
             tracking: None,
         }]];
 
@@ -501,6 +571,7 @@ mod tests {
         let ad_segments = vec![vec![AdSegment {
             uri: "ad.ts".to_string(),
             duration: 15.0,
+            init: None,
             tracking: None,
         }]];
 
@@ -521,6 +592,7 @@ mod tests {
         let ad_segments = vec![vec![AdSegment {
             uri: "ad.ts".to_string(),
             duration: 10.0,
+            init: None,
             tracking: None,
         }]];
 
@@ -543,11 +615,13 @@ mod tests {
             AdSegment {
                 uri: "ad1.ts".to_string(),
                 duration: 10.0,
+                init: None,
                 tracking: None,
             },
             AdSegment {
                 uri: "ad2.ts".to_string(),
                 duration: 10.0,
+                init: None,
                 tracking: None,
             },
         ]];
@@ -578,4 +652,51 @@ mod tests {
         assert_eq!(video_urls, audio_urls);
         assert_eq!(video_urls.len(), 2);
     }
+
+    #[test]
+    fn test_low_latency_mpd_keeps_ll_signalling() {
+        let xml = r#"<?xml version="1.0"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="dynamic" id="ll" publishTime="2025-01-01T00:00:00Z">
+  <ServiceDescription id="0">
+    <Latency target="3000" min="2000" max="6000" referenceId="0"/>
+  </ServiceDescription>
+  <Period id="live" start="PT0S">
+    <AdaptationSet contentType="video" mimeType="video/mp4">
+      <ProducerReferenceTime id="0" type="encoder" presentationTime="0" wallClockTime="2025-01-01T00:00:00Z"/>
+      <SegmentTemplate media="v_$Number$.m4s" duration="2" availabilityTimeOffset="1.8" availabilityTimeComplete="false"/>
+      <Representation id="v" bandwidth="1000000"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+        let mpd = crate::dash::parser::parse_mpd(xml).expect("Failed to parse MPD");
+        let ad_segments = vec![vec![AdSegment {
+            uri: "ad1.ts".to_string(),
+            duration: 10.0,
+            init: None,
+            tracking: None,
+        }]];
+
+        let result = interleave_ads_mpd(
+            mpd,
+            &[create_test_ad_break(0, 10.0)],
+            &ad_segments,
+            "s",
+            "http://stitcher",
+        );
+
+        let content = &result.periods[0].adaptations[0];
+        let template = content.SegmentTemplate.as_ref().unwrap();
+        assert_eq!(template.availabilityTimeOffset, Some(1.8));
+        assert_eq!(template.availabilityTimeComplete, Some(false));
+        assert!(content.ProducerReferenceTime.is_some());
+        assert!(result.ServiceDescription.is_some());
+
+        let ad = &result.periods[1].adaptations[0];
+        assert!(ad.SegmentTemplate.is_none());
+        assert!(ad.ProducerReferenceTime.is_none());
+
+        let serialized = result.to_string();
+        assert!(serialized.contains("ServiceDescription"));
+        assert!(serialized.contains(r#"availabilityTimeComplete="false""#));
+    }
 }
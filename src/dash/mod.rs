@@ -1,3 +1,6 @@
 pub mod cue;
+pub mod emsg;
 pub mod interleaver;
 pub mod parser;
+pub mod patch;
+pub mod xlink;
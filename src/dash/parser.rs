@@ -61,6 +61,9 @@ pub fn rewrite_dash_urls(
 ) -> Result<()> {
     info!("Rewriting DASH URLs for session: {}", session_id);
 
+    // BaseURLs are cleared below — keep their low-latency timing first
+    carry_availability_to_templates(mpd);
+
     // Extract MPD-level BaseURL before clearing (hierarchical inheritance)
     let mpd_base = if !mpd.base_url.is_empty() {
         compose_url(origin_base, &mpd.base_url[0].base)
@@ -112,6 +115,66 @@ pub fn rewrite_dash_urls(
     Ok(())
 }
 
+/// Low-latency availability signalled on a BaseURL: (offset, complete)
+type Availability = (Option<f64>, Option<bool>);
+
+/// Combine a parent level's availability with a child BaseURL's
+///
+/// Offsets accumulate across levels; the innermost `availabilityTimeComplete`
+/// wins (ISO/IEC 23009-1 §5.3.9.5).
+fn inherit_availability(parent: Availability, base_urls: &[dash_mpd::BaseURL]) -> Availability {
+    let Some(base) = base_urls.first() else {
+        return parent;
+    };
+    let offset = match (parent.0, base.availability_time_offset) {
+        (Some(a), Some(b)) => Some(a + b),
+        (a, b) => a.or(b),
+    };
+    (offset, base.availability_time_complete.or(parent.1))
+}
+
+/// Apply inherited availability to a SegmentTemplate
+fn apply_availability(template: &mut dash_mpd::SegmentTemplate, inherited: Availability) {
+    if let Some(offset) = inherited.0 {
+        template.availabilityTimeOffset =
+            Some(offset + template.availabilityTimeOffset.unwrap_or(0.0));
+    }
+    if template.availabilityTimeComplete.is_none() {
+        template.availabilityTimeComplete = inherited.1;
+    }
+}
+
+/// Move LL-DASH `availabilityTimeOffset`/`availabilityTimeComplete` from
+/// BaseURL elements onto the SegmentTemplates they govern
+///
+/// Low-latency packagers often signal chunked availability on BaseURL.
+/// URL rewriting replaces BaseURLs with absolute proxy URLs in the templates,
+/// so without this the player would lose the offset and request chunks late.
+fn carry_availability_to_templates(mpd: &mut MPD) {
+    let mpd_level = inherit_availability((None, None), &mpd.base_url);
+
+    for period in &mut mpd.periods {
+        let period_level = inherit_availability(mpd_level, &period.BaseURL);
+        if let Some(ref mut template) = period.SegmentTemplate {
+            apply_availability(template, period_level);
+        }
+
+        for adaptation_set in &mut period.adaptations {
+            let adaptation_level = inherit_availability(period_level, &adaptation_set.BaseURL);
+            if let Some(ref mut template) = adaptation_set.SegmentTemplate {
+                apply_availability(template, adaptation_level);
+            }
+
+            for representation in &mut adaptation_set.representations {
+                let repr_level = inherit_availability(adaptation_level, &representation.BaseURL);
+                if let Some(ref mut template) = representation.SegmentTemplate {
+                    apply_availability(template, repr_level);
+                }
+            }
+        }
+    }
+}
+
 /// Rewrite SegmentTemplate media and initialization URLs
 fn rewrite_segment_template(
     template: &mut dash_mpd::SegmentTemplate,
@@ -196,6 +259,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_rewrite_preserves_low_latency_availability() {
+        let xml = r#"<?xml version="1.0"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="dynamic">
+  <BaseURL availabilityTimeOffset="1.5" availabilityTimeComplete="false">https://origin.example.com/ll/</BaseURL>
+  <Period id="live">
+    <AdaptationSet mimeType="video/mp4">
+      <SegmentTemplate media="v_$Number$.m4s" initialization="v_init.mp4" duration="2" availabilityTimeOffset="0.5"/>
+      <Representation id="v" bandwidth="1000000"/>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/mp4">
+      <Representation id="a" bandwidth="128000">
+        <SegmentTemplate media="a_$Number$.m4s" duration="2"/>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+        let mut mpd = parse_mpd(xml).expect("Failed to parse MPD");
+
+        rewrite_dash_urls(&mut mpd, "s", "http://stitcher.local", "https://origin")
+            .expect("Failed to rewrite URLs");
+
+        assert!(mpd.base_url.is_empty());
+        let video = mpd.periods[0].adaptations[0]
+            .SegmentTemplate
+            .as_ref()
+            .unwrap();
+        assert_eq!(video.availabilityTimeOffset, Some(2.0));
+        assert_eq!(video.availabilityTimeComplete, Some(false));
+        let audio = mpd.periods[0].adaptations[1].representations[0]
+            .SegmentTemplate
+            .as_ref()
+            .unwrap();
+        assert_eq!(audio.availabilityTimeOffset, Some(1.5));
+        assert_eq!(audio.availabilityTimeComplete, Some(false));
+    }
+
     #[test]
     fn test_parse_multiperiod_mpd() {
         let xml = std::fs::read_to_string("test-data/sample_mpd_multiperiod.xml")
@@ -0,0 +1,461 @@
+use dash_mpd::MPD;
+use dashmap::DashMap;
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// MPD patch document namespace (ISO/IEC 23009-1 Annex I)
+pub const PATCH_NAMESPACE: &str = "urn:mpeg:dash:schema:mpd-patch:2020";
+
+/// MPD namespace, declared on elements embedded in patch operations
+const MPD_NAMESPACE: &str = "urn:mpeg:dash:schema:mpd:2011";
+
+/// Stitched MPD versions kept per session and origin
+const MAX_VERSIONS: usize = 8;
+
+/// Maximum age of a stitched MPD version before eviction
+const MAX_VERSION_AGE: Duration = Duration::from_secs(300);
+
+/// Recently served stitched MPDs, so patches can be computed against the
+/// exact version a client holds
+///
+/// A patch must describe the *stitched* timeline (ad Periods included), so it
+/// cannot be forwarded from the origin. Instead each served MPD is kept under
+/// its publishTime and [`fingerprint`] and diffed against the next one on
+/// request. The stitched timeline can change while the origin keeps its
+/// publishTime (e.g. a break decided between refreshes), so the publishTime
+/// alone does not identify the document a client holds.
+#[derive(Clone, Default)]
+pub struct MpdHistory {
+    versions: Arc<DashMap<String, VecDeque<MpdVersion>>>,
+}
+
+/// One served stitched MPD
+struct MpdVersion {
+    publish_time: String,
+    fingerprint: String,
+    mpd: MPD,
+    recorded_at: Instant,
+}
+
+impl MpdHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember a served MPD under its publishTime and fingerprint
+    pub fn record(&self, key: &str, publish_time: &str, fingerprint: &str, mpd: &MPD) {
+        let mut versions = self.versions.entry(key.to_string()).or_default();
+        if versions
+            .iter()
+            .any(|v| v.publish_time == publish_time && v.fingerprint == fingerprint)
+        {
+            return;
+        }
+        versions.push_back(MpdVersion {
+            publish_time: publish_time.to_string(),
+            fingerprint: fingerprint.to_string(),
+            mpd: mpd.clone(),
+            recorded_at: Instant::now(),
+        });
+        if versions.len() > MAX_VERSIONS {
+            versions.pop_front();
+        }
+    }
+
+    /// The MPD previously served with the given publishTime and fingerprint
+    pub fn get(&self, key: &str, publish_time: &str, fingerprint: &str) -> Option<MPD> {
+        self.versions.get(key).and_then(|versions| {
+            versions
+                .iter()
+                .find(|v| v.publish_time == publish_time && v.fingerprint == fingerprint)
+                .map(|v| v.mpd.clone())
+        })
+    }
+
+    /// Evict expired versions and drop empty entries
+    pub fn cleanup(&self) {
+        self.versions.retain(|_, versions| {
+            versions.retain(|v| v.recorded_at.elapsed() < MAX_VERSION_AGE);
+            !versions.is_empty()
+        });
+    }
+}
+
+/// Serialized `@publishTime` of an MPD, as it appears in the manifest
+pub fn publish_time(mpd: &MPD) -> Option<String> {
+    root_attributes(mpd)
+        .into_iter()
+        .find(|(name, _)| name == "publishTime")
+        .map(|(_, value)| value)
+}
+
+/// Hash of a stitched MPD, ignoring its `PatchLocation` (which carries it)
+pub fn fingerprint(mpd: &MPD) -> String {
+    let shell = MPD {
+        PatchLocation: Vec::new(),
+        ..mpd.clone()
+    };
+    let mut hasher = DefaultHasher::new();
+    shell.to_string().hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Build an RFC 5261 patch document transforming `old` into `new`
+///
+/// Periods are addressed by `@id`; MPD attributes and other top-level
+/// elements are replaced in place. Returns `None` when the change cannot be
+/// expressed safely (Periods without ids, reordered Periods, top-level
+/// elements added or removed) — the client then refetches the full MPD.
+pub fn build_patch(old: &MPD, new: &MPD) -> Option<String> {
+    let mpd_id = new.id.as_deref()?;
+    let original_publish_time = publish_time(old)?;
+    let new_publish_time = publish_time(new)?;
+
+    let mut ops = Vec::new();
+    diff_attributes(old, new, &mut ops);
+    for ((name, old_items), (_, new_items)) in top_level_elements(old)
+        .into_iter()
+        .zip(top_level_elements(new))
+    {
+        if old_items == new_items {
+            continue;
+        }
+        if old_items.len() != new_items.len() {
+            debug!("MPD patch: {} element count changed, cannot patch", name);
+            return None;
+        }
+        for (i, (before, after)) in old_items.iter().zip(&new_items).enumerate() {
+            if before != after {
+                ops.push(format!(
+                    r#"<replace sel="/MPD/{}[{}]">{}</replace>"#,
+                    name,
+                    i + 1,
+                    with_mpd_namespace(after, name)
+                ));
+            }
+        }
+    }
+    diff_periods(old, new, &mut ops)?;
+
+    let mut patch = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><Patch xmlns="{}" mpdId="{}" originalPublishTime="{}" publishTime="{}">"#,
+        PATCH_NAMESPACE,
+        escape(mpd_id),
+        escape(&original_publish_time),
+        escape(&new_publish_time)
+    );
+    for op in ops {
+        patch.push_str(&op);
+    }
+    patch.push_str("</Patch>");
+    Some(patch)
+}
+
+/// Replace/add/remove operations for changed MPD attributes
+fn diff_attributes(old: &MPD, new: &MPD, ops: &mut Vec<String>) {
+    let old_attrs = root_attributes(old);
+    let new_attrs = root_attributes(new);
+
+    for (name, value) in &new_attrs {
+        match old_attrs.iter().find(|(n, _)| n == name) {
+            Some((_, old_value)) if old_value == value => {}
+            Some(_) => ops.push(format!(
+                r#"<replace sel="/MPD/@{}">{}</replace>"#,
+                name,
+                escape(value)
+            )),
+            None => ops.push(format!(
+                r#"<add sel="/MPD" type="@{}">{}</add>"#,
+                name,
+                escape(value)
+            )),
+        }
+    }
+    for (name, _) in &old_attrs {
+        if !new_attrs.iter().any(|(n, _)| n == name) {
+            ops.push(format!(r#"<remove sel="/MPD/@{}"/>"#, name));
+        }
+    }
+}
+
+/// Operations for removed, changed and added Periods
+fn diff_periods(old: &MPD, new: &MPD, ops: &mut Vec<String>) -> Option<()> {
+    let old_periods = periods_by_id(old)?;
+    let new_periods = periods_by_id(new)?;
+
+    // Periods present in both versions must keep their relative order
+    let surviving_old: Vec<&str> = old_periods
+        .iter()
+        .map(|(id, _)| *id)
+        .filter(|id| new_periods.iter().any(|(n, _)| n == id))
+        .collect();
+    let surviving_new: Vec<&str> = new_periods
+        .iter()
+        .map(|(id, _)| *id)
+        .filter(|id| old_periods.iter().any(|(o, _)| o == id))
+        .collect();
+    if surviving_old != surviving_new {
+        debug!("MPD patch: Periods reordered, cannot patch");
+        return None;
+    }
+
+    for (id, _) in &old_periods {
+        if !surviving_old.contains(id) {
+            ops.push(format!(r#"<remove sel="/MPD/Period[@id='{}']"/>"#, id));
+        }
+    }
+
+    for (id, xml) in &new_periods {
+        if let Some((_, old_xml)) = old_periods.iter().find(|(o, _)| o == id)
+            && old_xml != xml
+        {
+            ops.push(format!(
+                r#"<replace sel="/MPD/Period[@id='{}']">{}</replace>"#,
+                id,
+                with_mpd_namespace(xml, "Period")
+            ));
+        }
+    }
+
+    for (index, (id, xml)) in new_periods.iter().enumerate() {
+        if surviving_new.contains(id) {
+            continue;
+        }
+        let element = with_mpd_namespace(xml, "Period");
+        let op = if let Some((prev, _)) = index.checked_sub(1).map(|i| &new_periods[i]) {
+            format!(
+                r#"<add sel="/MPD/Period[@id='{}']" pos="after">{}</add>"#,
+                prev, element
+            )
+        } else if let Some(next) = surviving_new.first() {
+            format!(
+                r#"<add sel="/MPD/Period[@id='{}']" pos="before">{}</add>"#,
+                next, element
+            )
+        } else if has_elements_after_periods(new) {
+            // Appending to /MPD would put the Period after trailing elements
+            return None;
+        } else {
+            format!(r#"<add sel="/MPD">{}</add>"#, element)
+        };
+        ops.push(op);
+    }
+
+    Some(())
+}
+
+/// Serialized Periods keyed by `@id`; `None` if any Period lacks a usable id
+fn periods_by_id(mpd: &MPD) -> Option<Vec<(&str, String)>> {
+    mpd.periods
+        .iter()
+        .map(|period| {
+            let id = period.id.as_deref().filter(|id| !id.contains('\''))?;
+            Some((id, element_xml("Period", period)?))
+        })
+        .collect()
+}
+
+/// Whether the MPD has elements that follow Period in schema order
+fn has_elements_after_periods(mpd: &MPD) -> bool {
+    !mpd.Metrics.is_empty()
+        || !mpd.essential_property.is_empty()
+        || !mpd.supplemental_property.is_empty()
+        || !mpd.UTCTiming.is_empty()
+        || mpd.LeapSecondInformation.is_some()
+}
+
+/// Serialized top-level MPD elements other than Period, grouped by name
+fn top_level_elements(mpd: &MPD) -> Vec<(&'static str, Vec<String>)> {
+    fn all<T: Serialize>(name: &'static str, items: &[T]) -> (&'static str, Vec<String>) {
+        (
+            name,
+            items
+                .iter()
+                .filter_map(|item| element_xml(name, item))
+                .collect(),
+        )
+    }
+
+    vec![
+        all("ProgramInformation", mpd.ProgramInformation.as_slice()),
+        all("BaseURL", &mpd.base_url),
+        all("Location", &mpd.locations),
+        all("PatchLocation", &mpd.PatchLocation),
+        all("ServiceDescription", mpd.ServiceDescription.as_slice()),
+        all("ContentProtection", &mpd.ContentProtection),
+        all("Metrics", &mpd.Metrics),
+        all("EssentialProperty", &mpd.essential_property),
+        all("SupplementalProperty", &mpd.supplemental_property),
+        all("UTCTiming", &mpd.UTCTiming),
+        all(
+            "LeapSecondInformation",
+            mpd.LeapSecondInformation.as_slice(),
+        ),
+    ]
+}
+
+/// Attributes of the serialized MPD element, excluding namespace declarations
+fn root_attributes(mpd: &MPD) -> Vec<(String, String)> {
+    let shell = MPD {
+        ProgramInformation: None,
+        base_url: Vec::new(),
+        locations: Vec::new(),
+        PatchLocation: Vec::new(),
+        ServiceDescription: None,
+        ContentProtection: Vec::new(),
+        periods: Vec::new(),
+        Metrics: Vec::new(),
+        essential_property: Vec::new(),
+        supplemental_property: Vec::new(),
+        UTCTiming: Vec::new(),
+        LeapSecondInformation: None,
+        ..mpd.clone()
+    };
+
+    let xml = shell.to_string();
+    let mut reader = quick_xml::Reader::from_str(&xml);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => {
+                return e
+                    .attributes()
+                    .flatten()
+                    .filter_map(|a| {
+                        let name = String::from_utf8(a.key.as_ref().to_vec()).ok()?;
+                        if name == "xmlns" || name.starts_with("xmlns:") {
+                            return None;
+                        }
+                        let value = a.unescape_value().ok()?.into_owned();
+                        Some((name, value))
+                    })
+                    .collect();
+            }
+            Ok(Event::Eof) | Err(_) => return Vec::new(),
+            _ => {}
+        }
+    }
+}
+
+fn element_xml<T: Serialize>(name: &str, item: &T) -> Option<String> {
+    quick_xml::se::to_string_with_root(name, item).ok()
+}
+
+/// Declare the MPD namespace on a serialized element embedded in a patch
+fn with_mpd_namespace(xml: &str, name: &str) -> String {
+    let open = format!("<{}", name);
+    match xml.strip_prefix(&open) {
+        Some(rest) => format!(r#"{} xmlns="{}"{}"#, open, MPD_NAMESPACE, rest),
+        None => xml.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dash::parser::parse_mpd;
+
+    fn live_mpd(publish_time: &str, periods: &[&str]) -> MPD {
+        let periods: String = periods
+            .iter()
+            .map(|id| format!(r#"<Period id="{}" start="PT0S"/>"#, id))
+            .collect();
+        parse_mpd(&format!(
+            r#"<?xml version="1.0"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" id="channel1" type="dynamic" publishTime="{}" minimumUpdatePeriod="PT2S">
+  <PatchLocation ttl="60">http://stitcher/patch?publishTime={}</PatchLocation>
+  {}
+</MPD>"#,
+            publish_time, publish_time, periods
+        ))
+        .expect("Failed to parse MPD")
+    }
+
+    #[test]
+    fn test_build_patch_describes_period_changes() {
+        let old = live_mpd("2025-01-01T00:00:00Z", &["p0", "p1"]);
+        let mut new = live_mpd("2025-01-01T00:00:10Z", &["p1", "ad-0", "p2"]);
+        new.periods[0].duration = Some(Duration::from_secs(30));
+
+        let patch = build_patch(&old, &new).expect("patch should be expressible");
+
+        assert!(patch.contains(r#"mpdId="channel1""#));
+        assert!(patch.contains(r#"originalPublishTime="2025-01-01T00:00:00Z""#));
+        assert!(
+            patch.contains(r#"<replace sel="/MPD/@publishTime">2025-01-01T00:00:10Z</replace>"#)
+        );
+        assert!(patch.contains(r#"<replace sel="/MPD/PatchLocation[1]">"#));
+        assert!(patch.contains(r#"<remove sel="/MPD/Period[@id='p0']"/>"#));
+        assert!(patch.contains(
+            r#"<replace sel="/MPD/Period[@id='p1']"><Period xmlns="urn:mpeg:dash:schema:mpd:2011""#
+        ));
+        assert!(patch.contains(r#"<add sel="/MPD/Period[@id='p1']" pos="after"><Period xmlns="urn:mpeg:dash:schema:mpd:2011" id="ad-0""#));
+        assert!(patch.contains(r#"<add sel="/MPD/Period[@id='ad-0']" pos="after">"#));
+
+        // Removes precede adds so selectors stay valid
+        let remove = patch.find("<remove").unwrap();
+        let add = patch.find("<add").unwrap();
+        assert!(remove < add);
+    }
+
+    #[test]
+    fn test_build_patch_unpatchable_changes() {
+        let old = live_mpd("2025-01-01T00:00:00Z", &["p0", "p1"]);
+        let reordered = live_mpd("2025-01-01T00:00:10Z", &["p1", "p0"]);
+        assert!(build_patch(&old, &reordered).is_none());
+
+        let mut no_id = live_mpd("2025-01-01T00:00:10Z", &["p0"]);
+        no_id.periods[0].id = None;
+        assert!(build_patch(&old, &no_id).is_none());
+    }
+
+    #[test]
+    fn test_history_lookup_by_publish_time() {
+        let history = MpdHistory::new();
+        let mpd = live_mpd("2025-01-01T00:00:00Z", &["p0"]);
+        let published = publish_time(&mpd).unwrap();
+        let version = fingerprint(&mpd);
+
+        history.record("s1", &published, &version, &mpd);
+        assert_eq!(history.get("s1", &published, &version), Some(mpd));
+        assert!(
+            history
+                .get("s1", "2024-01-01T00:00:00Z", &version)
+                .is_none()
+        );
+        assert!(history.get("s2", &published, &version).is_none());
+    }
+
+    #[test]
+    fn test_history_keeps_stitched_changes_under_one_publish_time() {
+        let history = MpdHistory::new();
+        let before = live_mpd("2025-01-01T00:00:00Z", &["p0", "p1"]);
+        // Same origin publishTime, but a break was decided in between
+        let after = live_mpd("2025-01-01T00:00:00Z", &["p0", "ad-0", "p1"]);
+        let published = publish_time(&before).unwrap();
+        assert_eq!(publish_time(&after).unwrap(), published);
+        assert_ne!(fingerprint(&before), fingerprint(&after));
+
+        history.record("s1", &published, &fingerprint(&before), &before);
+        history.record("s1", &published, &fingerprint(&after), &after);
+        assert_eq!(
+            history.get("s1", &published, &fingerprint(&before)),
+            Some(before)
+        );
+        assert_eq!(
+            history.get("s1", &published, &fingerprint(&after)),
+            Some(after.clone())
+        );
+        assert!(history.get("s1", &published, "0000000000000000").is_none());
+
+        // The PatchLocation carries the fingerprint, so it is not part of it
+        let mut relocated = after.clone();
+        relocated.PatchLocation[0].content = "http://stitcher/elsewhere".to_string();
+        assert_eq!(fingerprint(&relocated), fingerprint(&after));
+    }
+}
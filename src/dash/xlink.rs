@@ -0,0 +1,302 @@
+use crate::dash::parser;
+use crate::error::{Result, RitcherError};
+use dash_mpd::{BaseURL, MPD, Period};
+use dashmap::DashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tracing::{debug, info};
+
+/// XLink href value that removes the linking element (ISO/IEC 23009-1 §5.5.3)
+pub const RESOLVE_TO_ZERO: &str = "urn:mpeg:dash:resolve-to-zero:2013";
+
+/// How long a remote element's resolution is reused
+const REMOTE_PERIOD_TTL: Duration = Duration::from_secs(30);
+
+/// Resolution of one remote element and when it was started
+type RemoteResolution = (Arc<OnceCell<Option<Vec<Period>>>>, Instant);
+
+/// Remote Periods resolved per absolute XLink URL, shared by all sessions
+///
+/// Every session's MPD refresh carries the same placeholders, so each URL
+/// is fetched once per [`REMOTE_PERIOD_TTL`]: concurrent resolutions share
+/// a single fetch and later ones reuse its result, failures included.
+#[derive(Clone, Default)]
+pub struct RemotePeriodCache {
+    resolutions: Arc<DashMap<String, RemoteResolution>>,
+}
+
+impl RemotePeriodCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Periods of `url`, running `fetch` unless a fresh resolution exists
+    pub async fn get_or_fetch<F, Fut>(&self, url: &str, fetch: F) -> Option<Vec<Period>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Option<Vec<Period>>>,
+    {
+        // Clone the cell out so no map shard lock is held across the await
+        let cell = {
+            let mut resolution = self
+                .resolutions
+                .entry(url.to_string())
+                .or_insert_with(|| (Arc::new(OnceCell::new()), Instant::now()));
+            if resolution.1.elapsed() >= REMOTE_PERIOD_TTL {
+                *resolution = (Arc::new(OnceCell::new()), Instant::now());
+            }
+            resolution.0.clone()
+        };
+        cell.get_or_init(fetch).await.clone()
+    }
+
+    /// Evict resolutions older than the TTL
+    pub fn cleanup(&self) {
+        self.resolutions
+            .retain(|_, (_, started)| started.elapsed() < REMOTE_PERIOD_TTL);
+    }
+}
+
+/// A remote Period placeholder (`<Period xlink:href="...">`) in an MPD
+///
+/// Upstream packagers use these to mark ad opportunities; the stitcher
+/// resolves them server-side instead of leaving them to the player.
+#[derive(Debug, Clone, PartialEq)]
+pub struct XlinkPeriod {
+    /// Index of the placeholder Period in the MPD
+    pub period_index: usize,
+    /// Remote element URL, or [`RESOLVE_TO_ZERO`]
+    pub href: String,
+    /// Placeholder duration in seconds (the ad opportunity length), if given
+    pub duration: Option<f64>,
+}
+
+impl XlinkPeriod {
+    pub fn is_resolve_to_zero(&self) -> bool {
+        self.href == RESOLVE_TO_ZERO
+    }
+
+    /// Identify the placeholder across MPD refreshes of a session
+    pub fn break_key(&self, mpd: &MPD) -> String {
+        match mpd
+            .periods
+            .get(self.period_index)
+            .and_then(|p| p.id.as_ref())
+        {
+            Some(id) => format!("xlink:{}", id),
+            None => format!("xlink:#{}:{}", self.period_index, self.href),
+        }
+    }
+}
+
+/// Find every XLink Period in the MPD
+///
+/// Both `actuate="onLoad"` and `actuate="onRequest"` Periods are returned:
+/// the stitcher resolves them all before the manifest reaches the player.
+pub fn detect_xlink_periods(mpd: &MPD) -> Vec<XlinkPeriod> {
+    mpd.periods
+        .iter()
+        .enumerate()
+        .filter_map(|(period_index, period)| {
+            let href = period.href.as_deref()?.trim();
+            if href.is_empty() {
+                return None;
+            }
+            info!(
+                "Found XLink Period #{} (actuate: {}): {}",
+                period_index,
+                period.actuate.as_deref().unwrap_or("onRequest"),
+                href
+            );
+            Some(XlinkPeriod {
+                period_index,
+                href: href.to_string(),
+                duration: period.duration.map(|d| d.as_secs_f64()),
+            })
+        })
+        .collect()
+}
+
+/// Parse the body of a remote Period response
+///
+/// The response holds zero or more `<Period>` elements (optionally with an
+/// XML declaration). Relative URLs inside them resolve against the remote
+/// document, so each Period gets `remote_base` prepended to its BaseURL.
+pub fn parse_remote_periods(xml: &str, remote_base: &str) -> Result<Vec<Period>> {
+    let mut body = xml.trim();
+    if body.starts_with("<?xml")
+        && let Some(end) = body.find("?>")
+    {
+        body = body[end + 2..].trim_start();
+    }
+
+    if body.is_empty() {
+        return Ok(Vec::new());
+    }
+    if !body.starts_with("<Period") {
+        return Err(RitcherError::MpdParseError(
+            "Remote XLink element is not a Period".to_string(),
+        ));
+    }
+
+    let wrapped = format!(
+        r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" xmlns:xlink="http://www.w3.org/1999/xlink">{}</MPD>"#,
+        body
+    );
+    let mut periods = parser::parse_mpd(&wrapped)?.periods;
+
+    for period in &mut periods {
+        // Nested XLink would need another round trip — not supported
+        period.href = None;
+        period.actuate = None;
+
+        let base = match period.BaseURL.first() {
+            Some(b) if b.base.starts_with("http") => continue,
+            Some(b) => format!(
+                "{}/{}",
+                remote_base.trim_end_matches('/'),
+                b.base.trim_start_matches('/')
+            ),
+            None => remote_base.to_string(),
+        };
+        period.BaseURL = vec![BaseURL {
+            base,
+            ..Default::default()
+        }];
+    }
+
+    debug!("Parsed {} remote Period(s)", periods.len());
+    Ok(periods)
+}
+
+/// Replace XLink placeholders with their resolved Periods
+///
+/// `resolved` pairs a placeholder index with its replacement (possibly
+/// empty, which removes the placeholder). Indices refer to the MPD before
+/// any replacement.
+pub fn apply_resolutions(mpd: &mut MPD, mut resolved: Vec<(usize, Vec<Period>)>) {
+    resolved.sort_by_key(|(index, _)| *index);
+    for (index, periods) in resolved.into_iter().rev() {
+        if index < mpd.periods.len() {
+            mpd.periods.splice(index..=index, periods);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const XLINK_MPD: &str = r#"<?xml version="1.0"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" xmlns:xlink="http://www.w3.org/1999/xlink" type="static" mediaPresentationDuration="PT150S">
+  <Period id="content-0" duration="PT60S">
+    <AdaptationSet contentType="video" mimeType="video/mp4">
+      <Representation id="v" bandwidth="1000000"/>
+    </AdaptationSet>
+  </Period>
+  <Period id="ad-slot" duration="PT30S" xlink:href="https://ads.example.com/period.xml" xlink:actuate="onLoad"/>
+  <Period id="gone" xlink:href="urn:mpeg:dash:resolve-to-zero:2013"/>
+  <Period id="content-1" duration="PT60S"/>
+</MPD>"#;
+
+    #[test]
+    fn test_detect_xlink_periods() {
+        let mpd = parser::parse_mpd(XLINK_MPD).expect("Failed to parse MPD");
+        let xlinks = detect_xlink_periods(&mpd);
+
+        assert_eq!(xlinks.len(), 2);
+        assert_eq!(xlinks[0].period_index, 1);
+        assert_eq!(xlinks[0].href, "https://ads.example.com/period.xml");
+        assert_eq!(xlinks[0].duration, Some(30.0));
+        assert!(!xlinks[0].is_resolve_to_zero());
+        assert_eq!(xlinks[1].period_index, 2);
+        assert!(xlinks[1].is_resolve_to_zero());
+    }
+
+    #[test]
+    fn test_parse_remote_periods() {
+        let xml = r#"<?xml version="1.0"?>
+<Period id="remote-ad" duration="PT15S">
+  <AdaptationSet mimeType="video/mp4">
+    <Representation id="ad" bandwidth="800000">
+      <SegmentTemplate media="ad_$Number$.m4s" initialization="ad_init.mp4" duration="2" startNumber="1"/>
+    </Representation>
+  </AdaptationSet>
+</Period>"#;
+        let periods = parse_remote_periods(xml, "https://ads.example.com/creatives").unwrap();
+
+        assert_eq!(periods.len(), 1);
+        assert_eq!(periods[0].id, Some("remote-ad".to_string()));
+        assert_eq!(
+            periods[0].BaseURL[0].base,
+            "https://ads.example.com/creatives"
+        );
+        assert_eq!(periods[0].adaptations.len(), 1);
+
+        assert!(
+            parse_remote_periods("", "https://ads.example.com")
+                .unwrap()
+                .is_empty()
+        );
+        assert!(parse_remote_periods("<VAST/>", "https://ads.example.com").is_err());
+    }
+
+    #[test]
+    fn test_apply_resolutions() {
+        let mut mpd = parser::parse_mpd(XLINK_MPD).expect("Failed to parse MPD");
+        let replacement = Period {
+            id: Some("resolved".to_string()),
+            ..Default::default()
+        };
+
+        apply_resolutions(&mut mpd, vec![(2, Vec::new()), (1, vec![replacement])]);
+
+        let ids: Vec<_> = mpd.periods.iter().map(|p| p.id.clone().unwrap()).collect();
+        assert_eq!(ids, vec!["content-0", "resolved", "content-1"]);
+        assert!(mpd.periods.iter().all(|p| p.href.is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_remote_periods_fetched_once_per_url() {
+        let cache = RemotePeriodCache::new();
+        let fetches = AtomicUsize::new(0);
+        let fetch = || async {
+            fetches.fetch_add(1, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+            Some(vec![Period::default()])
+        };
+
+        // Concurrent sessions share one fetch; a later refresh reuses it
+        let (a, b) = tokio::join!(
+            cache.get_or_fetch("http://ads/p.xml", fetch),
+            cache.get_or_fetch("http://ads/p.xml", fetch),
+        );
+        assert_eq!(a.map(|p| p.len()), Some(1));
+        assert_eq!(b.map(|p| p.len()), Some(1));
+        assert!(
+            cache
+                .get_or_fetch("http://ads/p.xml", fetch)
+                .await
+                .is_some()
+        );
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+        // Failures are reused too, so a broken URL is not hammered
+        assert!(
+            cache
+                .get_or_fetch("http://ads/broken.xml", || async { None })
+                .await
+                .is_none()
+        );
+        assert!(
+            cache
+                .get_or_fetch("http://ads/broken.xml", fetch)
+                .await
+                .is_none()
+        );
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+}
@@ -28,9 +28,18 @@ pub enum RitcherError {
     #[error("Failed to convert data: {0}")]
     ConversionError(String),
 
+    #[error("MPD patch unavailable: {0}")]
+    PatchUnavailable(String),
+
     #[error("Invalid origin URL: {0}")]
     InvalidOrigin(String),
 
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Session store error: {0}")]
+    SessionStoreError(String),
+
     #[error("Internal server error: {0}")]
     InternalError(String),
 }
@@ -56,8 +65,8 @@ impl IntoResponse for RitcherError {
                 (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
             }
             RitcherError::InvalidSessionId(ref e) => {
-                tracing::error!("Invalid session ID: {}", e);
-                (StatusCode::BAD_REQUEST, self.to_string())
+                tracing::warn!("Invalid session ID: {}", e);
+                (StatusCode::NOT_FOUND, self.to_string())
             }
             RitcherError::ConfigError(ref e) => {
                 tracing::error!("Configuration error: {}", e);
@@ -67,10 +76,22 @@ impl IntoResponse for RitcherError {
                 tracing::error!("Conversion error: {}", e);
                 (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
             }
+            RitcherError::PatchUnavailable(ref e) => {
+                tracing::warn!("MPD patch unavailable: {}", e);
+                (StatusCode::NOT_FOUND, self.to_string())
+            }
             RitcherError::InvalidOrigin(ref e) => {
                 tracing::error!("Invalid origin URL: {}", e);
                 (StatusCode::BAD_REQUEST, self.to_string())
             }
+            RitcherError::InvalidRequest(ref e) => {
+                tracing::warn!("Invalid request: {}", e);
+                (StatusCode::BAD_REQUEST, self.to_string())
+            }
+            RitcherError::SessionStoreError(ref e) => {
+                tracing::error!("Session store error: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
+            }
             RitcherError::InternalError(ref e) => {
                 tracing::error!("Internal error: {}", e);
                 (StatusCode::INTERNAL_SERVER_ERROR, self.to_string())
@@ -1,5 +1,16 @@
-use m3u8_rs::MediaPlaylist;
-use tracing::{debug, info};
+use crate::scte35::{InbandCue, splice::PTS_CLOCK};
+use chrono::{DateTime, FixedOffset};
+use m3u8_rs::{DateRange, MediaPlaylist, MediaSegment};
+use tracing::{debug, info, warn};
+
+/// Length of the 33-bit PTS timeline in seconds (~26.5 hours)
+const PTS_WRAP_SECS: f64 = (1u64 << 33) as f64 / PTS_CLOCK;
+
+/// Splice points this close before a segment boundary snap to that boundary
+const SPLICE_TOLERANCE_SECS: f64 = 0.5;
+
+/// Longest ad break accepted from a splice signal, in seconds
+const MAX_BREAK_SECS: f64 = 600.0;
 
 /// Represents an ad break detected from CUE tags in the playlist
 #[derive(Debug, Clone, PartialEq)]
@@ -12,6 +23,18 @@ pub struct AdBreak {
     pub duration: f32,
 }
 
+/// An ad break signalled ahead of its splice point, beyond the playlist's
+/// last segment
+#[derive(Debug, Clone, PartialEq)]
+pub struct UpcomingBreak {
+    /// Predicted [`break_key`] of the break once its first segment is published
+    pub key: String,
+    /// Duration of the ad break in seconds
+    pub duration: f32,
+    /// Seconds from the end of the playlist to the splice point
+    pub lead_time: f64,
+}
+
 /// Detect ad breaks from SCTE-35 CUE tags in HLS playlists
 ///
 /// Scans the `unknown_tags` field of each MediaSegment for industry-standard
@@ -120,6 +143,333 @@ fn parse_cue_out(tag_name: &str, rest: Option<&str>) -> Option<f32> {
     None
 }
 
+/// Turn in-band SCTE-35 cues into ad breaks on the playlist's segments
+///
+/// `anchor` ties the playlist to the media timeline: segment `anchor.0`
+/// starts at media time `anchor.1` seconds (its first PTS). Start times of
+/// the other segments are extrapolated from EXTINF durations. A cue starts
+/// its break at the segment containing the splice point; the break spans
+/// enough segments to cover its duration, or runs to the playlist end.
+///
+/// Cues outside the playlist window and breaks overlapping `signalled`
+/// (tag-detected) breaks are skipped.
+pub fn inband_ad_breaks(
+    playlist: &MediaPlaylist,
+    cues: &[InbandCue],
+    anchor: (usize, f64),
+    signalled: &[AdBreak],
+) -> Vec<AdBreak> {
+    let mut ad_breaks: Vec<AdBreak> = Vec::new();
+    let segments = &playlist.segments;
+    let starts = segment_starts(playlist, anchor);
+    if starts.is_empty() {
+        return ad_breaks;
+    }
+
+    for cue in cues {
+        if !valid_inband_duration(cue) {
+            continue;
+        }
+
+        let Some(start_index) = segments.iter().zip(&starts).position(|(segment, start)| {
+            contains_splice(segment, pts_delta(cue.media_time, *start))
+        }) else {
+            debug!(
+                "In-band cue {} at {}s is outside the playlist window",
+                cue.event_id, cue.media_time
+            );
+            continue;
+        };
+
+        let end_index = break_end(segments, start_index, cue.duration);
+        if overlaps(signalled, &ad_breaks, start_index, end_index) {
+            debug!(
+                "In-band cue {} overlaps a signalled ad break, skipping",
+                cue.event_id
+            );
+            continue;
+        }
+
+        info!(
+            "In-band ad break at segment #{} (event {}): duration {}s",
+            start_index, cue.event_id, cue.duration
+        );
+        ad_breaks.push(AdBreak {
+            start_index,
+            end_index,
+            duration: cue.duration as f32,
+        });
+    }
+
+    ad_breaks
+}
+
+/// In-band SCTE-35 cues whose splice point lies beyond the playlist
+///
+/// Encoders signal breaks several seconds ahead of the splice (SCTE-35
+/// pre-roll), so a cue is often known before its first segment is
+/// published. Cues splicing within `horizon` seconds of the playlist end
+/// are returned with the key their break will have (see [`break_key`]).
+pub fn upcoming_inband_breaks(
+    playlist: &MediaPlaylist,
+    cues: &[InbandCue],
+    anchor: (usize, f64),
+    horizon: f64,
+) -> Vec<UpcomingBreak> {
+    let starts = segment_starts(playlist, anchor);
+    let (Some(last_start), Some(last)) = (starts.last(), playlist.segments.last()) else {
+        return Vec::new();
+    };
+    let end = last_start + last.duration as f64;
+
+    cues.iter()
+        .filter(|cue| valid_inband_duration(cue))
+        .filter_map(|cue| {
+            upcoming(
+                playlist,
+                pts_delta(cue.media_time, end),
+                cue.duration,
+                horizon,
+            )
+        })
+        .collect()
+}
+
+/// Detect ad breaks from `EXT-X-DATERANGE` tags carrying `SCTE35-OUT`
+///
+/// The break starts at the segment whose program date time range holds the
+/// DateRange's START-DATE, and lasts its DURATION (or PLANNED-DURATION).
+/// Playlists without EXT-X-PROGRAM-DATE-TIME cannot place DateRanges.
+/// Breaks overlapping `signalled` (tag-detected) breaks are skipped.
+pub fn daterange_ad_breaks(playlist: &MediaPlaylist, signalled: &[AdBreak]) -> Vec<AdBreak> {
+    let mut ad_breaks: Vec<AdBreak> = Vec::new();
+    let segments = &playlist.segments;
+    let dates = segment_dates(playlist);
+
+    for (daterange, duration) in scte35_out_dateranges(playlist) {
+        let Some(start_index) = segments.iter().zip(&dates).position(|(segment, date)| {
+            date.is_some_and(|date| {
+                contains_splice(segment, seconds_between(date, daterange.start_date))
+            })
+        }) else {
+            continue;
+        };
+
+        let end_index = break_end(segments, start_index, duration);
+        if overlaps(signalled, &ad_breaks, start_index, end_index) {
+            debug!(
+                "DateRange {} overlaps a signalled ad break, skipping",
+                daterange.id
+            );
+            continue;
+        }
+
+        info!(
+            "DateRange ad break at segment #{} ({}): duration {}s",
+            start_index, daterange.id, duration
+        );
+        ad_breaks.push(AdBreak {
+            start_index,
+            end_index,
+            duration: duration as f32,
+        });
+    }
+
+    ad_breaks
+}
+
+/// `SCTE35-OUT` DateRanges published ahead of their START-DATE
+///
+/// DateRanges starting within `horizon` seconds after the playlist end
+/// are returned with the key their break will have (see [`break_key`]).
+pub fn upcoming_daterange_breaks(playlist: &MediaPlaylist, horizon: f64) -> Vec<UpcomingBreak> {
+    let dates = segment_dates(playlist);
+    let (Some(Some(last_date)), Some(last)) = (dates.last(), playlist.segments.last()) else {
+        return Vec::new();
+    };
+    let end = *last_date + chrono::Duration::milliseconds((last.duration * 1000.0) as i64);
+
+    scte35_out_dateranges(playlist)
+        .filter_map(|(daterange, duration)| {
+            upcoming(
+                playlist,
+                seconds_between(end, daterange.start_date),
+                duration,
+                horizon,
+            )
+        })
+        .collect()
+}
+
+/// The break of a splice `ahead` seconds after the playlist end, if it is
+/// beyond the playlist and within `horizon`
+fn upcoming(
+    playlist: &MediaPlaylist,
+    ahead: f64,
+    duration: f64,
+    horizon: f64,
+) -> Option<UpcomingBreak> {
+    // Splices this close to the end belong to the last segment
+    let ahead = ahead + SPLICE_TOLERANCE_SECS;
+    if ahead < 0.0 || ahead > horizon + SPLICE_TOLERANCE_SECS {
+        return None;
+    }
+
+    // Segments yet to come are assumed as long as the published ones
+    let segments = &playlist.segments;
+    let segment_duration = match segments.iter().map(|s| s.duration as f64).sum::<f64>() {
+        total if total > 0.0 => total / segments.len() as f64,
+        _ => playlist.target_duration.max(1) as f64,
+    };
+    let offset = (ahead / segment_duration).floor() as u64;
+    let first_sequence = playlist.media_sequence + segments.len() as u64 + offset;
+
+    Some(UpcomingBreak {
+        key: first_sequence.to_string(),
+        duration: duration as f32,
+        lead_time: (ahead - SPLICE_TOLERANCE_SECS).max(0.0),
+    })
+}
+
+/// Media time of each segment's start, extrapolated from `anchor`
+///
+/// Empty if the anchor is outside the playlist.
+fn segment_starts(playlist: &MediaPlaylist, anchor: (usize, f64)) -> Vec<f64> {
+    let (anchor_index, anchor_time) = anchor;
+    let segments = &playlist.segments;
+    if anchor_index >= segments.len() {
+        return Vec::new();
+    }
+
+    let anchor_offset: f64 = segments[..anchor_index]
+        .iter()
+        .map(|s| s.duration as f64)
+        .sum();
+    let mut starts = Vec::with_capacity(segments.len());
+    let mut elapsed = 0.0;
+    for segment in segments {
+        starts.push(anchor_time - anchor_offset + elapsed);
+        elapsed += segment.duration as f64;
+    }
+    starts
+}
+
+/// Program date time of each segment, extrapolated from the nearest
+/// preceding EXT-X-PROGRAM-DATE-TIME; `None` before the first one
+fn segment_dates(playlist: &MediaPlaylist) -> Vec<Option<DateTime<FixedOffset>>> {
+    let mut current: Option<DateTime<FixedOffset>> = None;
+    playlist
+        .segments
+        .iter()
+        .map(|segment| {
+            if segment.program_date_time.is_some() {
+                current = segment.program_date_time;
+            }
+            let date = current;
+            current = current
+                .map(|d| d + chrono::Duration::milliseconds((segment.duration * 1000.0) as i64));
+            date
+        })
+        .collect()
+}
+
+/// DateRanges opening an ad break, with their break duration
+fn scte35_out_dateranges(playlist: &MediaPlaylist) -> impl Iterator<Item = (&DateRange, f64)> {
+    playlist
+        .segments
+        .iter()
+        .filter_map(|segment| segment.daterange.as_ref())
+        .filter(|daterange| {
+            daterange
+                .other_attributes
+                .as_ref()
+                .is_some_and(|attributes| attributes.contains_key("SCTE35-OUT"))
+        })
+        .filter_map(|daterange| {
+            let duration = daterange
+                .duration
+                .or(daterange.planned_duration)
+                .or_else(|| {
+                    daterange
+                        .end_date
+                        .map(|end| seconds_between(daterange.start_date, end))
+                })?;
+            if duration <= 0.0 || duration > MAX_BREAK_SECS {
+                warn!(
+                    "Invalid DateRange ad break duration {}s ({}), skipping (max {}s)",
+                    duration, daterange.id, MAX_BREAK_SECS
+                );
+                return None;
+            }
+            Some((daterange, duration))
+        })
+}
+
+/// Whether a splice `offset` seconds after the segment's start falls in it
+fn contains_splice(segment: &MediaSegment, offset: f64) -> bool {
+    let offset = offset + SPLICE_TOLERANCE_SECS;
+    offset >= 0.0 && offset < segment.duration as f64
+}
+
+/// End index (exclusive) of a break of `duration` seconds from `start_index`
+fn break_end(segments: &[MediaSegment], start_index: usize, duration: f64) -> usize {
+    let mut end_index = start_index;
+    let mut covered = 0.0;
+    while end_index < segments.len() && covered < duration - SPLICE_TOLERANCE_SECS {
+        covered += segments[end_index].duration as f64;
+        end_index += 1;
+    }
+    end_index
+}
+
+/// Whether `[start_index, end_index)` overlaps any break already found
+fn overlaps(
+    signalled: &[AdBreak],
+    found: &[AdBreak],
+    start_index: usize,
+    end_index: usize,
+) -> bool {
+    signalled
+        .iter()
+        .chain(found)
+        .any(|b| start_index < b.end_index && b.start_index < end_index)
+}
+
+fn valid_inband_duration(cue: &InbandCue) -> bool {
+    let valid = cue.duration > 0.0 && cue.duration <= MAX_BREAK_SECS;
+    if !valid {
+        warn!(
+            "Invalid in-band ad break duration {}s (event {}), skipping (max {}s)",
+            cue.duration, cue.event_id, MAX_BREAK_SECS
+        );
+    }
+    valid
+}
+
+/// Seconds from `from` to `to`
+fn seconds_between(from: DateTime<FixedOffset>, to: DateTime<FixedOffset>) -> f64 {
+    (to - from).num_milliseconds() as f64 / 1000.0
+}
+
+/// Difference `a - b` between two PTS-derived times, allowing for wrap
+fn pts_delta(a: f64, b: f64) -> f64 {
+    let delta = (a - b).rem_euclid(PTS_WRAP_SECS);
+    if delta >= PTS_WRAP_SECS / 2.0 {
+        delta - PTS_WRAP_SECS
+    } else {
+        delta
+    }
+}
+
+/// Identify an ad break independently of the rendition it was found in
+///
+/// Renditions of one stream share media sequence numbering, so the sequence
+/// number of the break's first segment names the same break in the video
+/// variant, every audio rendition and every refresh of the live window.
+pub fn break_key(playlist: &MediaPlaylist, ad_break: &AdBreak) -> String {
+    (playlist.media_sequence + ad_break.start_index as u64).to_string()
+}
+
 /// Helper to check if a segment is within an ad break
 pub fn is_in_ad_break(segment_index: usize, ad_breaks: &[AdBreak]) -> bool {
     ad_breaks
@@ -130,7 +480,7 @@ pub fn is_in_ad_break(segment_index: usize, ad_breaks: &[AdBreak]) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use m3u8_rs::{ExtTag, MediaSegment};
+    use m3u8_rs::ExtTag;
 
     fn create_segment(uri: &str) -> MediaSegment {
         MediaSegment {
@@ -297,6 +647,149 @@ mod tests {
         assert_eq!(ad_breaks[0].duration, 30.0);
     }
 
+    fn plain_playlist(count: usize) -> MediaPlaylist {
+        MediaPlaylist {
+            segments: (0..count)
+                .map(|i| create_segment(&format!("seg{}.ts", i)))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_inband_ad_breaks_map_pts_to_segments() {
+        let playlist = plain_playlist(8);
+        // seg5 starts at 1050s, so seg0 starts at 1000s
+        let cues = vec![
+            InbandCue::new(1, 1019.8, 20.0),
+            InbandCue::new(2, 1065.0, 60.0),
+            InbandCue::new(3, 900.0, 30.0),
+        ];
+
+        let breaks = inband_ad_breaks(&playlist, &cues, (5, 1050.0), &[]);
+        assert_eq!(
+            breaks,
+            vec![
+                AdBreak {
+                    start_index: 2,
+                    end_index: 4,
+                    duration: 20.0
+                },
+                AdBreak {
+                    start_index: 6,
+                    end_index: 8,
+                    duration: 60.0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inband_ad_breaks_skip_signalled_and_wrap() {
+        let playlist = plain_playlist(4);
+        let signalled = vec![AdBreak {
+            start_index: 1,
+            end_index: 3,
+            duration: 20.0,
+        }];
+        let cues = vec![InbandCue::new(1, 10.0, 20.0)];
+        assert!(inband_ad_breaks(&playlist, &cues, (0, 0.0), &signalled).is_empty());
+
+        // seg0 starts just before the PTS wrap, the cue just after it
+        let cues = vec![InbandCue::new(2, 5.0, 10.0)];
+        let breaks = inband_ad_breaks(&playlist, &cues, (0, PTS_WRAP_SECS - 15.0), &[]);
+        assert_eq!(breaks.len(), 1);
+        assert_eq!(breaks[0].start_index, 2);
+        assert_eq!(breaks[0].end_index, 3);
+    }
+
+    #[test]
+    fn test_upcoming_inband_breaks_predict_key() {
+        let playlist = MediaPlaylist {
+            media_sequence: 100,
+            ..plain_playlist(4)
+        };
+        // Playlist spans 0s..40s; seg104 would start at 40s, seg105 at 50s
+        let cues = vec![
+            InbandCue::new(1, 45.0, 30.0),
+            InbandCue::new(2, 39.8, 20.0),
+            InbandCue::new(3, 20.0, 20.0),
+            InbandCue::new(4, 120.0, 30.0),
+        ];
+
+        let upcoming = upcoming_inband_breaks(&playlist, &cues, (0, 0.0), 60.0);
+        assert_eq!(upcoming.len(), 2);
+        assert_eq!(upcoming[0].key, "104");
+        assert_eq!(upcoming[0].duration, 30.0);
+        assert!((upcoming[0].lead_time - 5.0).abs() < 1e-9);
+        // Splice just before the end snaps to the next boundary
+        assert_eq!(upcoming[1].key, "104");
+        assert_eq!(upcoming[1].lead_time, 0.0);
+    }
+
+    const DATERANGE_PLAYLIST: &str = "#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:10
+#EXT-X-MEDIA-SEQUENCE:200
+#EXT-X-PROGRAM-DATE-TIME:2026-01-01T00:00:00.000Z
+#EXTINF:10.0,
+seg0.ts
+#EXT-X-DATERANGE:ID=\"ad-1\",START-DATE=\"2026-01-01T00:00:10.000Z\",DURATION=20.0,SCTE35-OUT=0xFC30
+#EXTINF:10.0,
+seg1.ts
+#EXTINF:10.0,
+seg2.ts
+#EXT-X-DATERANGE:ID=\"ad-2\",START-DATE=\"2026-01-01T00:00:55.000Z\",PLANNED-DURATION=30.0,SCTE35-OUT=0xFC30
+#EXTINF:10.0,
+seg3.ts
+#EXT-X-DATERANGE:ID=\"promo\",START-DATE=\"2026-01-01T00:00:45.000Z\",DURATION=15.0
+#EXTINF:10.0,
+seg4.ts
+";
+
+    fn daterange_playlist() -> MediaPlaylist {
+        m3u8_rs::parse_media_playlist_res(DATERANGE_PLAYLIST.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_daterange_ad_breaks_place_by_program_date_time() {
+        let playlist = daterange_playlist();
+
+        let breaks = daterange_ad_breaks(&playlist, &[]);
+        assert_eq!(
+            breaks,
+            vec![AdBreak {
+                start_index: 1,
+                end_index: 3,
+                duration: 20.0
+            }]
+        );
+
+        let signalled = breaks.clone();
+        assert!(daterange_ad_breaks(&playlist, &signalled).is_empty());
+    }
+
+    #[test]
+    fn test_upcoming_daterange_breaks() {
+        let playlist = daterange_playlist();
+
+        // Playlist ends at 00:00:50; ad-2 splices 5s later, in seg205
+        let upcoming = upcoming_daterange_breaks(&playlist, 30.0);
+        assert_eq!(
+            upcoming,
+            vec![UpcomingBreak {
+                key: "205".to_string(),
+                duration: 30.0,
+                lead_time: 5.0,
+            }]
+        );
+        assert!(upcoming_daterange_breaks(&playlist, 2.0).is_empty());
+
+        let mut undated = playlist;
+        undated.segments[0].program_date_time = None;
+        assert!(upcoming_daterange_breaks(&undated, 30.0).is_empty());
+    }
+
     #[test]
     fn test_is_in_ad_break() {
         let ad_breaks = vec![AdBreak {
@@ -312,4 +805,26 @@ mod tests {
         assert!(is_in_ad_break(4, &ad_breaks));
         assert!(!is_in_ad_break(5, &ad_breaks));
     }
+
+    #[test]
+    fn test_break_key_follows_media_sequence() {
+        let mut playlist = MediaPlaylist {
+            media_sequence: 100,
+            ..Default::default()
+        };
+        let ad_break = AdBreak {
+            start_index: 3,
+            end_index: 6,
+            duration: 30.0,
+        };
+        assert_eq!(break_key(&playlist, &ad_break), "103");
+
+        // The window slid by two segments: same break, same key
+        playlist.media_sequence = 102;
+        let slid = AdBreak {
+            start_index: 1,
+            ..ad_break
+        };
+        assert_eq!(break_key(&playlist, &slid), "103");
+    }
 }
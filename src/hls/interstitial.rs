@@ -9,7 +9,7 @@
 //! AVPlayer) fetches ad content directly from the ad CDN via the X-ASSET-LIST
 //! URL and handles playback client-side.
 
-use crate::hls::cue::AdBreak;
+use crate::hls::cue::{self, AdBreak};
 use chrono::{DateTime, FixedOffset, TimeZone};
 use m3u8_rs::{DateRange, MediaPlaylist, QuotedOrUnquoted};
 use std::collections::HashMap;
@@ -61,7 +61,8 @@ pub fn ensure_program_date_time(playlist: &mut MediaPlaylist) {
 /// For every detected `AdBreak`:
 /// 1. Computes the START-DATE from the segment's program_date_time at `start_index`
 /// 2. Builds a DateRange with `CLASS="com.apple.hls.interstitial"` and the
-///    standard HLS Interstitials attributes
+///    standard HLS Interstitials attributes, identified by the break's
+///    media sequence number (`cue::break_key`) so all renditions agree
 /// 3. Sets the DateRange on the segment at `start_index`
 /// 4. Strips the SCTE-35 CUE-OUT/CUE-IN/CUE-OUT-CONT tags from unknown_tags
 ///    (they would confuse players that also parse DateRange interstitials)
@@ -73,7 +74,7 @@ pub fn inject_interstitials(
     session_id: &str,
     base_url: &str,
 ) {
-    for (break_idx, ad_break) in ad_breaks.iter().enumerate() {
+    for ad_break in ad_breaks {
         let start_index = ad_break.start_index;
 
         // Guard: break must reference a valid segment
@@ -93,9 +94,11 @@ pub fn inject_interstitials(
             }
         };
 
+        // Same break, same id in every rendition and refresh
+        let break_id = cue::break_key(playlist, ad_break);
         let asset_list_url = format!(
             "{}/stitch/{}/asset-list/{}?dur={}",
-            base_url, session_id, break_idx, ad_break.duration
+            base_url, session_id, break_id, ad_break.duration
         );
 
         info!(
@@ -120,7 +123,7 @@ pub fn inject_interstitials(
         );
 
         let daterange = DateRange {
-            id: format!("ad-break-{}", break_idx),
+            id: format!("ad-break-{}", break_id),
             class: Some("com.apple.hls.interstitial".to_string()),
             start_date,
             end_date: None,
@@ -283,7 +286,7 @@ mod tests {
             .as_ref()
             .expect("DateRange should be set on break-start segment");
 
-        assert_eq!(dr.id, "ad-break-0");
+        assert_eq!(dr.id, "ad-break-1");
         assert_eq!(dr.class, Some("com.apple.hls.interstitial".to_string()));
         assert_eq!(dr.duration, Some(30.0));
     }
@@ -320,11 +323,11 @@ mod tests {
 
         assert_eq!(
             playlist.segments[1].daterange.as_ref().unwrap().id,
-            "ad-break-0"
+            "ad-break-1"
         );
         assert_eq!(
             playlist.segments[4].daterange.as_ref().unwrap().id,
-            "ad-break-1"
+            "ad-break-4"
         );
         assert_eq!(
             playlist.segments[4].daterange.as_ref().unwrap().duration,
@@ -394,7 +397,7 @@ mod tests {
         let asset_list = x.get("X-ASSET-LIST").expect("X-ASSET-LIST should exist");
         let url = asset_list.as_str();
         assert!(url.contains("my-sess"), "URL should contain session_id");
-        assert!(url.contains("/asset-list/0"), "URL should contain break_id");
+        assert!(url.contains("/asset-list/1"), "URL should contain break_id");
         assert!(url.contains("dur=30"), "URL should contain duration");
 
         // X-RESUME-OFFSET should be unquoted "0"
@@ -441,7 +444,7 @@ mod tests {
 
         assert_eq!(
             asset_list_url,
-            "https://stitcher.example.com/stitch/test-session/asset-list/0?dur=30"
+            "https://stitcher.example.com/stitch/test-session/asset-list/1?dur=30"
         );
     }
 
@@ -1,3 +1,4 @@
 pub mod cue;
 pub mod interstitial;
 pub mod parser;
+pub mod ts;
@@ -0,0 +1,421 @@
+//! Minimal MPEG-TS demux for in-band SCTE-35 detection
+//!
+//! Reads the PAT and PMT to locate the SCTE-35 elementary stream
+//! (`stream_type` 0x86), reassembles its splice_info_sections, and records
+//! the first PES PTS of the segment so splice times can be mapped onto the
+//! playlist timeline. Only what ad break detection needs is decoded —
+//! this is not a general-purpose demuxer.
+
+use crate::scte35::{CueOut, parse_splice_info_section};
+use std::collections::HashMap;
+use tracing::{debug, warn};
+
+const PACKET_SIZE: usize = 188;
+const SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0x0000;
+const NULL_PID: u16 = 0x1FFF;
+
+/// `stream_type` for SCTE-35 splice_info_sections (SCTE-35 §8.1)
+pub const SCTE35_STREAM_TYPE: u8 = 0x86;
+
+/// Result of scanning one transport stream segment
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TsScan {
+    /// PTS (90kHz) of the first PES packet on the segment's main stream
+    pub first_pts: Option<u64>,
+    /// Ad break starts found on SCTE-35 PIDs, in stream order
+    pub cues: Vec<CueOut>,
+}
+
+/// Scan a TS segment for SCTE-35 cue-outs and its first PTS
+///
+/// Tolerates leading garbage before the first sync byte, truncated trailing
+/// packets and corrupt sections (which are skipped with a warning).
+pub fn scan_segment(data: &[u8]) -> TsScan {
+    let mut scan = TsScan::default();
+    let Some(start) = find_sync(data) else {
+        return scan;
+    };
+
+    let mut pmt_pids: Vec<u16> = Vec::new();
+    let mut scte35_pids: Vec<u16> = Vec::new();
+    let mut pes_pid: Option<u16> = None;
+    let mut assemblers: HashMap<u16, SectionAssembler> = HashMap::new();
+
+    for packet in data[start..].chunks_exact(PACKET_SIZE) {
+        if packet[0] != SYNC_BYTE {
+            debug!("TS: lost sync, stopping scan");
+            break;
+        }
+        let transport_error = packet[1] & 0x80 != 0;
+        let pusi = packet[1] & 0x40 != 0;
+        let pid = (((packet[1] & 0x1F) as u16) << 8) | packet[2] as u16;
+        if transport_error || pid == NULL_PID {
+            continue;
+        }
+        let Some(payload) = packet_payload(packet) else {
+            continue;
+        };
+
+        let is_psi = pid == PAT_PID || pmt_pids.contains(&pid) || scte35_pids.contains(&pid);
+        if is_psi {
+            let sections = assemblers.entry(pid).or_default().push(payload, pusi);
+            for section in sections {
+                match section.first() {
+                    Some(0x00) if pid == PAT_PID => {
+                        for pmt in parse_pat(&section) {
+                            if !pmt_pids.contains(&pmt) {
+                                pmt_pids.push(pmt);
+                            }
+                        }
+                    }
+                    Some(0x02) => {
+                        let (streams, main) = parse_pmt(&section);
+                        for sp in streams {
+                            if !scte35_pids.contains(&sp) {
+                                scte35_pids.push(sp);
+                            }
+                        }
+                        pes_pid = pes_pid.or(main);
+                    }
+                    Some(0xFC) => match parse_splice_info_section(&section) {
+                        Ok(info) => {
+                            if let Some(cue) = info.cue_out() {
+                                debug!("TS: SCTE-35 cue-out on PID {}: {:?}", pid, cue);
+                                scan.cues.push(cue);
+                            }
+                        }
+                        Err(e) => warn!("TS: skipping invalid SCTE-35 section: {}", e),
+                    },
+                    _ => {}
+                }
+            }
+        } else if pusi && scan.first_pts.is_none() && Some(pid) == pes_pid {
+            scan.first_pts = pes_pts(payload);
+        }
+    }
+
+    scan
+}
+
+/// Locate the first offset where packets are aligned on sync bytes
+fn find_sync(data: &[u8]) -> Option<usize> {
+    (0..PACKET_SIZE.min(data.len())).find(|&i| {
+        data[i] == SYNC_BYTE
+            && data
+                .get(i + PACKET_SIZE)
+                .is_none_or(|&next| next == SYNC_BYTE)
+    })
+}
+
+/// Payload bytes of a packet, skipping the adaptation field
+fn packet_payload(packet: &[u8]) -> Option<&[u8]> {
+    let adaptation_control = (packet[3] >> 4) & 0x03;
+    match adaptation_control {
+        0b01 => Some(&packet[4..]),
+        0b11 => {
+            let start = 5 + packet[4] as usize;
+            packet.get(start..).filter(|p| !p.is_empty())
+        }
+        _ => None,
+    }
+}
+
+/// PMT PIDs from a program_association_section
+fn parse_pat(section: &[u8]) -> Vec<u16> {
+    let end = section_end(section);
+    let mut pids = Vec::new();
+    let mut pos = 8;
+    while pos + 4 <= end {
+        let program_number = u16::from_be_bytes([section[pos], section[pos + 1]]);
+        let pid = (((section[pos + 2] & 0x1F) as u16) << 8) | section[pos + 3] as u16;
+        if program_number != 0 {
+            pids.push(pid);
+        }
+        pos += 4;
+    }
+    pids
+}
+
+/// SCTE-35 PIDs and the main PES PID from a TS_program_map_section
+///
+/// The main PID is the first video stream, or the first non-SCTE-35 stream
+/// when the program has no video.
+fn parse_pmt(section: &[u8]) -> (Vec<u16>, Option<u16>) {
+    let end = section_end(section);
+    let mut scte35 = Vec::new();
+    let mut video = None;
+    let mut other = None;
+
+    if section.len() < 12 {
+        return (scte35, None);
+    }
+    let program_info_length = (((section[10] & 0x0F) as usize) << 8) | section[11] as usize;
+    let mut pos = 12 + program_info_length;
+
+    while pos + 5 <= end {
+        let stream_type = section[pos];
+        let pid = (((section[pos + 1] & 0x1F) as u16) << 8) | section[pos + 2] as u16;
+        let es_info_length =
+            (((section[pos + 3] & 0x0F) as usize) << 8) | section[pos + 4] as usize;
+
+        match stream_type {
+            SCTE35_STREAM_TYPE => scte35.push(pid),
+            // MPEG-1/2, H.264, HEVC video
+            0x01 | 0x02 | 0x1B | 0x24 => video = video.or(Some(pid)),
+            _ => other = other.or(Some(pid)),
+        }
+        pos += 5 + es_info_length;
+    }
+
+    (scte35, video.or(other))
+}
+
+/// End of section data (excluding CRC_32), clamped to the buffer
+fn section_end(section: &[u8]) -> usize {
+    if section.len() < 3 {
+        return 0;
+    }
+    let length = (((section[1] & 0x0F) as usize) << 8) | section[2] as usize;
+    (3 + length).saturating_sub(4).min(section.len())
+}
+
+/// PTS from the header of a PES packet starting in this payload
+fn pes_pts(payload: &[u8]) -> Option<u64> {
+    if payload.len() < 14 || payload[..3] != [0x00, 0x00, 0x01] {
+        return None;
+    }
+    let pts_dts_flags = payload[7] >> 6;
+    if pts_dts_flags & 0b10 == 0 {
+        return None;
+    }
+    let p = &payload[9..14];
+    Some(
+        (((p[0] >> 1) & 0x07) as u64) << 30
+            | (p[1] as u64) << 22
+            | ((p[2] >> 1) as u64) << 15
+            | (p[3] as u64) << 7
+            | (p[4] >> 1) as u64,
+    )
+}
+
+/// Reassembles PSI sections that span multiple TS packets
+#[derive(Default)]
+struct SectionAssembler {
+    buffer: Vec<u8>,
+    /// Whether we are inside a section (seen its start)
+    active: bool,
+}
+
+impl SectionAssembler {
+    /// Feed one packet payload; returns every section completed by it
+    fn push(&mut self, payload: &[u8], pusi: bool) -> Vec<Vec<u8>> {
+        let mut complete = Vec::new();
+        let mut data = payload;
+
+        if pusi {
+            let Some((&pointer, rest)) = data.split_first() else {
+                return complete;
+            };
+            let pointer = pointer as usize;
+            if pointer > rest.len() {
+                self.reset();
+                return complete;
+            }
+            // Bytes before the pointer finish the previous section
+            if self.active {
+                self.buffer.extend_from_slice(&rest[..pointer]);
+                self.drain(&mut complete);
+            }
+            self.reset();
+            self.active = true;
+            data = &rest[pointer..];
+        } else if !self.active {
+            return complete;
+        }
+
+        self.buffer.extend_from_slice(data);
+        self.drain(&mut complete);
+        complete
+    }
+
+    /// Move complete sections out of the buffer
+    fn drain(&mut self, complete: &mut Vec<Vec<u8>>) {
+        loop {
+            // 0xFF table_id marks stuffing until the end of the packet
+            if self.buffer.first() == Some(&0xFF) {
+                self.reset();
+                return;
+            }
+            if self.buffer.len() < 3 {
+                return;
+            }
+            let length = 3 + ((((self.buffer[1] & 0x0F) as usize) << 8) | self.buffer[2] as usize);
+            if self.buffer.len() < length {
+                return;
+            }
+            complete.push(self.buffer.drain(..length).collect());
+            if self.buffer.is_empty() {
+                self.active = false;
+                return;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.buffer.clear();
+        self.active = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scte35::splice::{crc32_mpeg2, tests::splice_insert_section};
+
+    const PMT_PID: u16 = 0x1000;
+    const VIDEO_PID: u16 = 0x0100;
+    const SCTE_PID: u16 = 0x01F4;
+
+    /// Split a PSI section into TS packets on the given PID
+    fn psi_packets(pid: u16, section: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut data = vec![0u8]; // pointer_field
+        data.extend_from_slice(section);
+        for (i, chunk) in data.chunks(PACKET_SIZE - 4).enumerate() {
+            let mut packet = vec![
+                SYNC_BYTE,
+                (if i == 0 { 0x40 } else { 0x00 }) | (pid >> 8) as u8,
+                pid as u8,
+                0x10 | (i as u8 & 0x0F),
+            ];
+            packet.extend_from_slice(chunk);
+            packet.resize(PACKET_SIZE, 0xFF);
+            out.extend(packet);
+        }
+        out
+    }
+
+    fn with_crc(mut section: Vec<u8>) -> Vec<u8> {
+        let crc = crc32_mpeg2(&section);
+        section.extend_from_slice(&crc.to_be_bytes());
+        section
+    }
+
+    fn pat() -> Vec<u8> {
+        with_crc(vec![
+            0x00,
+            0xB0,
+            13,
+            0x00,
+            0x01,
+            0xC1,
+            0x00,
+            0x00, // header
+            0x00,
+            0x01,
+            0xE0 | (PMT_PID >> 8) as u8,
+            PMT_PID as u8,
+        ])
+    }
+
+    fn pmt() -> Vec<u8> {
+        with_crc(vec![
+            0x02,
+            0xB0,
+            23,
+            0x00,
+            0x01,
+            0xC1,
+            0x00,
+            0x00, // header
+            0xE0 | (VIDEO_PID >> 8) as u8,
+            VIDEO_PID as u8,
+            0xF0,
+            0x00, // PCR PID, no info
+            0x1B,
+            0xE0 | (VIDEO_PID >> 8) as u8,
+            VIDEO_PID as u8,
+            0xF0,
+            0x00,
+            0x86,
+            0xE0 | (SCTE_PID >> 8) as u8,
+            SCTE_PID as u8,
+            0xF0,
+            0x00,
+        ])
+    }
+
+    fn pes_packet(pts: u64) -> Vec<u8> {
+        let mut packet = vec![
+            SYNC_BYTE,
+            0x40 | (VIDEO_PID >> 8) as u8,
+            VIDEO_PID as u8,
+            0x10,
+        ];
+        packet.extend_from_slice(&[0x00, 0x00, 0x01, 0xE0, 0x00, 0x00, 0x80, 0x80, 0x05]);
+        packet.extend_from_slice(&[
+            0x21 | (((pts >> 30) & 0x07) as u8) << 1,
+            (pts >> 22) as u8,
+            0x01 | ((pts >> 15) as u8) << 1,
+            (pts >> 7) as u8,
+            0x01 | (pts as u8) << 1,
+        ]);
+        packet.resize(PACKET_SIZE, 0xFF);
+        packet
+    }
+
+    fn segment(pts: u64, scte: Option<Vec<u8>>) -> Vec<u8> {
+        let mut data = psi_packets(PAT_PID, &pat());
+        data.extend(psi_packets(PMT_PID, &pmt()));
+        data.extend(pes_packet(pts));
+        if let Some(section) = scte {
+            data.extend(psi_packets(SCTE_PID, &section));
+        }
+        data
+    }
+
+    #[test]
+    fn test_scan_finds_cue_and_first_pts() {
+        let data = segment(900_000, Some(splice_insert_section(55, 1_800_000, 30.0)));
+        let scan = scan_segment(&data);
+
+        assert_eq!(scan.first_pts, Some(900_000));
+        assert_eq!(scan.cues.len(), 1);
+        assert_eq!(scan.cues[0].event_id, 55);
+        assert_eq!(scan.cues[0].pts, Some(1_800_000));
+        assert_eq!(scan.cues[0].duration, Some(30.0));
+    }
+
+    #[test]
+    fn test_scan_without_scte35_pid() {
+        let scan = scan_segment(&segment(90_000, None));
+        assert_eq!(scan.first_pts, Some(90_000));
+        assert!(scan.cues.is_empty());
+    }
+
+    #[test]
+    fn test_section_spanning_packets() {
+        let mut assembler = SectionAssembler::default();
+        let section = splice_insert_section(1, 0, 10.0);
+        let mut first = vec![0u8];
+        first.extend_from_slice(&section[..10]);
+
+        assert!(assembler.push(&first, true).is_empty());
+        let done = assembler.push(&section[10..], false);
+        assert_eq!(done, vec![section]);
+    }
+
+    #[test]
+    fn test_scan_garbage_and_truncation() {
+        assert_eq!(scan_segment(b"not a transport stream"), TsScan::default());
+
+        let data = segment(900_000, Some(splice_insert_section(1, 0, 30.0)));
+        for len in (0..data.len()).step_by(47) {
+            let _ = scan_segment(&data[..len]);
+        }
+        let mut shifted = vec![0u8; 5];
+        shifted.extend_from_slice(&data);
+        assert_eq!(scan_segment(&shifted).cues.len(), 1);
+    }
+}
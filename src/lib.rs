@@ -9,5 +9,6 @@ pub mod dash;
 pub mod error;
 pub mod hls;
 pub mod metrics;
+pub mod scte35;
 pub mod server;
 pub mod session;
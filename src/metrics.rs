@@ -13,12 +13,43 @@ pub const ACTIVE_SESSIONS: &str = "ritcher_active_sessions";
 pub const AD_BREAKS_DETECTED: &str = "ritcher_ad_breaks_detected";
 /// VAST requests by result (success, error, timeout, empty)
 pub const VAST_REQUESTS: &str = "ritcher_vast_requests_total";
-/// Slate fallback activations
+/// Ad decisions abandoned at the per-request decisioning deadline
+pub const DECISION_TIMEOUTS: &str = "ritcher_ad_decision_timeouts_total";
+/// Requests to each configured ad source by result (fill, nofill, error, timeout)
+pub const AD_SOURCE_REQUESTS: &str = "ritcher_ad_source_requests_total";
+/// Ad source decision latency in seconds, by source
+pub const AD_SOURCE_LATENCY: &str = "ritcher_ad_source_latency_seconds";
+/// Ad break decisions by source (decided, shared, ahead)
+pub const AD_DECISIONS: &str = "ritcher_ad_decisions_total";
+/// VAST errors reported to Error URLs, by VAST error code
+pub const VAST_ERRORS: &str = "ritcher_vast_errors_total";
+/// Ad candidates dropped by frequency caps and separation, by reason
+pub const AD_POLICY_EXCLUSIONS: &str = "ritcher_ad_policy_exclusions_total";
+/// Non-conforming ad creatives by reason and outcome (warn, reject, substitute, slate)
+pub const CREATIVE_CONDITIONING: &str = "ritcher_creative_conditioning_total";
+/// Ad normalizer lookups of progressive creatives by result (hit, ready, pending, error)
+pub const AD_NORMALIZER_LOOKUPS: &str = "ritcher_ad_normalizer_lookups_total";
+/// OpenRTB auctions by result (won, nobid, error)
+pub const OPENRTB_AUCTIONS: &str = "ritcher_openrtb_auctions_total";
+/// Slate fallback activations by reason (nofill, error, policy)
 pub const SLATE_FALLBACKS: &str = "ritcher_slate_fallbacks_total";
 /// Origin fetch errors
 pub const ORIGIN_FETCH_ERRORS: &str = "ritcher_origin_fetch_errors_total";
 /// Tracking beacons fired by event type and result
 pub const TRACKING_BEACONS: &str = "ritcher_tracking_beacons_total";
+/// In-band SCTE-35 cues discovered in media segments by source (emsg, ts)
+pub const INBAND_CUES: &str = "ritcher_inband_cues_total";
+/// DASH XLink Periods resolved by result (provider, remote, zero, failed)
+pub const XLINK_RESOLUTIONS: &str = "ritcher_xlink_resolutions_total";
+/// Sessions created through the session API, and stitch requests of unknown
+/// sessions rejected, by result (created, rejected)
+pub const SESSIONS: &str = "ritcher_sessions_total";
+/// Valkey session store commands by operation, in seconds
+pub const VALKEY_COMMAND_DURATION: &str = "ritcher_valkey_command_duration_seconds";
+/// Failed Valkey session store commands by operation
+pub const VALKEY_ERRORS: &str = "ritcher_valkey_errors_total";
+/// Valkey reconnections after a lost connection, by result (ok, error)
+pub const VALKEY_RECONNECTIONS: &str = "ritcher_valkey_reconnections_total";
 
 // ── Recording helpers ───────────────────────────────────────────────────
 
@@ -49,9 +80,58 @@ pub fn record_vast_request(result: &str) {
     counter!(VAST_REQUESTS, "result" => result.to_string()).increment(1);
 }
 
+/// Record the outcome of asking one ad source for a decision
+pub fn record_ad_source_request(source: &str, result: &str) {
+    counter!(AD_SOURCE_REQUESTS, "source" => source.to_string(), "result" => result.to_string())
+        .increment(1);
+}
+
+/// Record how long an ad source took to answer
+pub fn record_ad_source_latency(source: &str, start: Instant) {
+    let duration = start.elapsed().as_secs_f64();
+    histogram!(AD_SOURCE_LATENCY, "source" => source.to_string()).record(duration);
+}
+
+/// Record an ad decision that missed its deadline
+pub fn record_decision_timeout() {
+    counter!(DECISION_TIMEOUTS).increment(1);
+}
+
+/// Record an ad break decision: newly made, shared with another rendition,
+/// or started ahead of the break
+pub fn record_decision(source: &str) {
+    counter!(AD_DECISIONS, "source" => source.to_string()).increment(1);
+}
+
+/// Record a VAST error code reported via `[ERRORCODE]`
+pub fn record_vast_error(code: u16) {
+    counter!(VAST_ERRORS, "code" => code.to_string()).increment(1);
+}
+
+/// Record an ad candidate dropped by the ad policy
+pub fn record_policy_exclusion(reason: &str) {
+    counter!(AD_POLICY_EXCLUSIONS, "reason" => reason.to_string()).increment(1);
+}
+
+/// Record a non-conforming creative and what was done with it
+pub fn record_creative_conditioning(reason: &str, outcome: &str) {
+    counter!(CREATIVE_CONDITIONING, "reason" => reason.to_string(), "outcome" => outcome.to_string())
+        .increment(1);
+}
+
+/// Record a lookup of a progressive creative's packaged rendition
+pub fn record_ad_normalizer_lookup(result: &str) {
+    counter!(AD_NORMALIZER_LOOKUPS, "result" => result.to_string()).increment(1);
+}
+
+/// Record the result of an OpenRTB auction
+pub fn record_openrtb_auction(result: &str) {
+    counter!(OPENRTB_AUCTIONS, "result" => result.to_string()).increment(1);
+}
+
 /// Record a slate fallback activation
-pub fn record_slate_fallback() {
-    counter!(SLATE_FALLBACKS).increment(1);
+pub fn record_slate_fallback(reason: &str) {
+    counter!(SLATE_FALLBACKS, "reason" => reason.to_string()).increment(1);
 }
 
 /// Record an origin fetch error
@@ -65,6 +145,35 @@ pub fn record_tracking_event(event: &str, result: &str) {
         .increment(1);
 }
 
+/// Record a newly discovered in-band SCTE-35 cue
+pub fn record_inband_cue(source: &str) {
+    counter!(INBAND_CUES, "source" => source.to_string()).increment(1);
+}
+
+/// Record the outcome of resolving a DASH XLink Period
+pub fn record_xlink_resolution(result: &str) {
+    counter!(XLINK_RESOLUTIONS, "result" => result.to_string()).increment(1);
+}
+
+/// Record a session created through the API or a rejected session id
+pub fn record_session(result: &str) {
+    counter!(SESSIONS, "result" => result.to_string()).increment(1);
+}
+
+/// Record the latency of a Valkey session store command
+pub fn record_valkey_command(op: &str, start: Instant, ok: bool) {
+    let duration = start.elapsed().as_secs_f64();
+    histogram!(VALKEY_COMMAND_DURATION, "op" => op.to_string()).record(duration);
+    if !ok {
+        counter!(VALKEY_ERRORS, "op" => op.to_string()).increment(1);
+    }
+}
+
+/// Record an attempt to reconnect to Valkey
+pub fn record_valkey_reconnection(result: &str) {
+    counter!(VALKEY_RECONNECTIONS, "result" => result.to_string()).increment(1);
+}
+
 /// SGAI: total EXT-X-DATERANGE interstitial markers injected
 pub const INTERSTITIALS_INJECTED: &str = "ritcher_interstitials_injected_total";
 /// SGAI: asset-list requests by HTTP status
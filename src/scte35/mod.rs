@@ -0,0 +1,5 @@
+pub mod splice;
+pub mod store;
+
+pub use splice::{CueOut, SpliceInfo, parse_splice_info_section};
+pub use store::{InbandCue, InbandCueStore};
@@ -0,0 +1,562 @@
+//! Binary SCTE-35 `splice_info_section` decoder
+//!
+//! Decodes the subset of SCTE-35 (2022) needed to find ad break starts in
+//! in-band signalling: `splice_insert` and `time_signal` commands plus
+//! `segmentation_descriptor`s. Encrypted sections and unknown commands are
+//! reported but not interpreted.
+
+use thiserror::Error;
+
+/// A malformed or unsupported `splice_info_section`
+///
+/// Sections come from media segments, so a bad one is skipped by the
+/// caller and never fails a request.
+#[derive(Error, Debug)]
+#[error("Failed to parse SCTE-35 section: {0}")]
+pub struct Scte35Error(String);
+
+type Result<T> = std::result::Result<T, Scte35Error>;
+
+/// SCTE-35 `table_id` for splice_info_section
+const TABLE_ID: u8 = 0xFC;
+
+/// Clock rate of SCTE-35 PTS values
+pub const PTS_CLOCK: f64 = 90_000.0;
+
+/// PTS values wrap at 2^33
+const PTS_MODULO: u64 = 1 << 33;
+
+/// `segmentation_type_id` values that open an ad opportunity
+const SEGMENTATION_STARTS: &[u8] = &[
+    0x22, // Break Start
+    0x30, // Provider Advertisement Start
+    0x32, // Distributor Advertisement Start
+    0x34, // Provider Placement Opportunity Start
+    0x36, // Distributor Placement Opportunity Start
+];
+
+/// Decoded splice_info_section
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpliceInfo {
+    /// PTS adjustment to add to every splice time (90kHz ticks)
+    pub pts_adjustment: u64,
+    /// Whether the command payload is encrypted (not interpreted)
+    pub encrypted: bool,
+    /// The splice command carried by this section
+    pub command: SpliceCommand,
+    /// Segmentation descriptors from the descriptor loop
+    pub segmentation: Vec<SegmentationDescriptor>,
+}
+
+/// Splice command carried in a splice_info_section
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpliceCommand {
+    /// splice_null (heartbeat)
+    SpliceNull,
+    /// splice_insert (command type 0x05)
+    SpliceInsert(SpliceInsert),
+    /// time_signal (command type 0x06) with optional PTS
+    TimeSignal { pts_time: Option<u64> },
+    /// Any other command type (schedule, bandwidth_reservation, private)
+    Other(u8),
+}
+
+/// splice_insert command fields relevant for ad insertion
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpliceInsert {
+    pub splice_event_id: u32,
+    pub cancel: bool,
+    pub out_of_network: bool,
+    pub splice_immediate: bool,
+    /// Splice time in 90kHz ticks (program splice mode only)
+    pub pts_time: Option<u64>,
+    /// Break duration in 90kHz ticks
+    pub break_duration: Option<u64>,
+}
+
+/// segmentation_descriptor fields relevant for ad insertion
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentationDescriptor {
+    pub event_id: u32,
+    pub cancel: bool,
+    pub type_id: u8,
+    /// Segmentation duration in 90kHz ticks
+    pub duration: Option<u64>,
+}
+
+/// Ad break start extracted from a splice_info_section
+#[derive(Debug, Clone, PartialEq)]
+pub struct CueOut {
+    /// splice_event_id or segmentation_event_id
+    pub event_id: u32,
+    /// Splice time in 90kHz ticks with pts_adjustment applied (None = immediate)
+    pub pts: Option<u64>,
+    /// Break duration in seconds (None when the signal carries no duration)
+    pub duration: Option<f64>,
+}
+
+impl SpliceInfo {
+    /// Extract an ad break start from this section, if it signals one
+    ///
+    /// - `splice_insert` with `out_of_network_indicator=1` and no cancel
+    /// - `time_signal` carrying a segmentation descriptor whose type opens
+    ///   an advertisement/placement opportunity
+    pub fn cue_out(&self) -> Option<CueOut> {
+        if self.encrypted {
+            return None;
+        }
+
+        let adjust = |pts: u64| (pts + self.pts_adjustment) % PTS_MODULO;
+
+        match &self.command {
+            SpliceCommand::SpliceInsert(insert) if insert.out_of_network && !insert.cancel => {
+                Some(CueOut {
+                    event_id: insert.splice_event_id,
+                    pts: insert.pts_time.map(adjust),
+                    duration: insert.break_duration.map(|d| d as f64 / PTS_CLOCK),
+                })
+            }
+            SpliceCommand::TimeSignal { pts_time } => self
+                .segmentation
+                .iter()
+                .find(|d| !d.cancel && SEGMENTATION_STARTS.contains(&d.type_id))
+                .map(|d| CueOut {
+                    event_id: d.event_id,
+                    pts: pts_time.map(adjust),
+                    duration: d.duration.map(|t| t as f64 / PTS_CLOCK),
+                }),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a binary splice_info_section
+///
+/// The CRC_32 trailer is verified; sections with a bad CRC are rejected
+/// because in-band data is untrusted input.
+pub fn parse_splice_info_section(data: &[u8]) -> Result<SpliceInfo> {
+    let mut r = BitReader::new(data);
+
+    if r.read(8)? as u8 != TABLE_ID {
+        return Err(parse_error("table_id is not 0xFC"));
+    }
+    r.skip(4)?; // section_syntax_indicator, private_indicator, sap_type
+    let section_length = r.read(12)? as usize;
+    let total = 3 + section_length;
+    if total > data.len() || section_length < 4 {
+        return Err(parse_error("section_length exceeds available data"));
+    }
+    if crc32_mpeg2(&data[..total]) != 0 {
+        return Err(parse_error("CRC_32 mismatch"));
+    }
+
+    r.skip(8)?; // protocol_version
+    let encrypted = r.read(1)? == 1;
+    r.skip(6)?; // encryption_algorithm
+    let pts_adjustment = r.read(33)?;
+    r.skip(8 + 12)?; // cw_index, tier
+    let command_length = r.read(12)? as usize;
+    let command_type = r.read(8)? as u8;
+
+    if encrypted {
+        return Ok(SpliceInfo {
+            pts_adjustment,
+            encrypted,
+            command: SpliceCommand::Other(command_type),
+            segmentation: Vec::new(),
+        });
+    }
+
+    let command_start = r.byte_pos();
+    let command = match command_type {
+        0x00 => SpliceCommand::SpliceNull,
+        0x05 => SpliceCommand::SpliceInsert(parse_splice_insert(&mut r)?),
+        0x06 => SpliceCommand::TimeSignal {
+            pts_time: parse_splice_time(&mut r)?,
+        },
+        other => SpliceCommand::Other(other),
+    };
+
+    // splice_command_length 0xFFF means "unknown" in legacy encoders —
+    // only trust it to skip commands we did not parse ourselves.
+    if command_length != 0xFFF {
+        r.seek_byte(command_start + command_length)?;
+    } else if matches!(command, SpliceCommand::Other(_)) {
+        return Err(parse_error("unknown command with unspecified length"));
+    }
+
+    let descriptor_loop_length = r.read(16)? as usize;
+    let loop_end = r.byte_pos() + descriptor_loop_length;
+    if loop_end > total - 4 {
+        return Err(parse_error("descriptor loop exceeds section"));
+    }
+
+    let mut segmentation = Vec::new();
+    while r.byte_pos() + 2 <= loop_end {
+        let tag = r.read(8)? as u8;
+        let length = r.read(8)? as usize;
+        let body_start = r.byte_pos();
+        if body_start + length > loop_end {
+            return Err(parse_error("descriptor exceeds descriptor loop"));
+        }
+        if tag == 0x02 && length >= 9 {
+            let identifier = r.read(32)?;
+            if identifier == u32::from_be_bytes(*b"CUEI") as u64 {
+                segmentation.push(parse_segmentation_descriptor(&mut r)?);
+            }
+        }
+        r.seek_byte(body_start + length)?;
+    }
+
+    Ok(SpliceInfo {
+        pts_adjustment,
+        encrypted,
+        command,
+        segmentation,
+    })
+}
+
+/// Parse splice_insert() (SCTE-35 §9.7.3)
+fn parse_splice_insert(r: &mut BitReader) -> Result<SpliceInsert> {
+    let splice_event_id = r.read(32)? as u32;
+    let cancel = r.read(1)? == 1;
+    r.skip(7)?;
+
+    let mut insert = SpliceInsert {
+        splice_event_id,
+        cancel,
+        out_of_network: false,
+        splice_immediate: false,
+        pts_time: None,
+        break_duration: None,
+    };
+    if cancel {
+        return Ok(insert);
+    }
+
+    insert.out_of_network = r.read(1)? == 1;
+    let program_splice = r.read(1)? == 1;
+    let duration_flag = r.read(1)? == 1;
+    insert.splice_immediate = r.read(1)? == 1;
+    r.skip(4)?;
+
+    if program_splice && !insert.splice_immediate {
+        insert.pts_time = parse_splice_time(r)?;
+    }
+    if !program_splice {
+        let component_count = r.read(8)?;
+        for _ in 0..component_count {
+            r.skip(8)?; // component_tag
+            if !insert.splice_immediate {
+                // Component splice times are not used; the first one stands
+                // in for the program splice time.
+                let pts = parse_splice_time(r)?;
+                insert.pts_time = insert.pts_time.or(pts);
+            }
+        }
+    }
+    if duration_flag {
+        r.skip(1 + 6)?; // auto_return, reserved
+        insert.break_duration = Some(r.read(33)?);
+    }
+    // unique_program_id, avail_num, avails_expected follow but are unused
+
+    Ok(insert)
+}
+
+/// Parse splice_time() — returns the PTS if time_specified_flag is set
+fn parse_splice_time(r: &mut BitReader) -> Result<Option<u64>> {
+    if r.read(1)? == 1 {
+        r.skip(6)?;
+        Ok(Some(r.read(33)?))
+    } else {
+        r.skip(7)?;
+        Ok(None)
+    }
+}
+
+/// Parse segmentation_descriptor() after the "CUEI" identifier (§10.3.3)
+fn parse_segmentation_descriptor(r: &mut BitReader) -> Result<SegmentationDescriptor> {
+    let event_id = r.read(32)? as u32;
+    let cancel = r.read(1)? == 1;
+    r.skip(7)?;
+
+    if cancel {
+        return Ok(SegmentationDescriptor {
+            event_id,
+            cancel,
+            type_id: 0,
+            duration: None,
+        });
+    }
+
+    let program_segmentation = r.read(1)? == 1;
+    let duration_flag = r.read(1)? == 1;
+    r.skip(6)?; // delivery_not_restricted_flag + restriction flags / reserved
+
+    if !program_segmentation {
+        let component_count = r.read(8)?;
+        r.skip(component_count * 48)?; // component_tag, reserved, pts_offset
+    }
+    let duration = if duration_flag {
+        Some(r.read(40)?)
+    } else {
+        None
+    };
+    r.skip(8)?; // segmentation_upid_type
+    let upid_length = r.read(8)?;
+    r.skip(upid_length * 8)?;
+    let type_id = r.read(8)? as u8;
+
+    Ok(SegmentationDescriptor {
+        event_id,
+        cancel,
+        type_id,
+        duration,
+    })
+}
+
+fn parse_error(msg: &str) -> Scte35Error {
+    Scte35Error(msg.to_string())
+}
+
+/// CRC-32/MPEG-2 (poly 0x04C11DB7, init 0xFFFFFFFF, no reflection)
+///
+/// Running it over a section including its CRC_32 field yields 0.
+pub fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// MSB-first bit reader over a byte slice
+struct BitReader<'a> {
+    data: &'a [u8],
+    bit_pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, bit_pos: 0 }
+    }
+
+    /// Read up to 64 bits as an unsigned integer
+    fn read(&mut self, bits: u64) -> Result<u64> {
+        let bits = bits as usize;
+        if self.bit_pos + bits > self.data.len() * 8 {
+            return Err(parse_error("unexpected end of section"));
+        }
+        let mut value = 0u64;
+        for _ in 0..bits {
+            let byte = self.data[self.bit_pos / 8];
+            let bit = (byte >> (7 - (self.bit_pos % 8))) & 1;
+            value = (value << 1) | bit as u64;
+            self.bit_pos += 1;
+        }
+        Ok(value)
+    }
+
+    fn skip(&mut self, bits: u64) -> Result<()> {
+        let target = self.bit_pos + bits as usize;
+        if target > self.data.len() * 8 {
+            return Err(parse_error("unexpected end of section"));
+        }
+        self.bit_pos = target;
+        Ok(())
+    }
+
+    fn byte_pos(&self) -> usize {
+        self.bit_pos.div_ceil(8)
+    }
+
+    fn seek_byte(&mut self, pos: usize) -> Result<()> {
+        if pos > self.data.len() {
+            return Err(parse_error("unexpected end of section"));
+        }
+        self.bit_pos = pos * 8;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// MSB-first bit writer used to build test sections
+    #[derive(Default)]
+    pub(crate) struct BitWriter {
+        bytes: Vec<u8>,
+        bit_len: usize,
+    }
+
+    impl BitWriter {
+        pub(crate) fn put(&mut self, bits: usize, value: u64) {
+            for i in (0..bits).rev() {
+                if self.bit_len.is_multiple_of(8) {
+                    self.bytes.push(0);
+                }
+                let bit = ((value >> i) & 1) as u8;
+                let last = self.bytes.len() - 1;
+                self.bytes[last] |= bit << (7 - (self.bit_len % 8));
+                self.bit_len += 1;
+            }
+        }
+
+        pub(crate) fn into_bytes(self) -> Vec<u8> {
+            self.bytes
+        }
+    }
+
+    /// Wrap a command + descriptor loop into a complete section with CRC
+    fn section(command_type: u8, command: &[u8], descriptors: &[u8], pts_adj: u64) -> Vec<u8> {
+        let mut body = BitWriter::default();
+        body.put(8, 0); // protocol_version
+        body.put(1, 0); // encrypted_packet
+        body.put(6, 0); // encryption_algorithm
+        body.put(33, pts_adj);
+        body.put(8, 0); // cw_index
+        body.put(12, 0xFFF); // tier
+        body.put(12, command.len() as u64);
+        body.put(8, command_type as u64);
+        let mut body = body.into_bytes();
+        body.extend_from_slice(command);
+        body.extend_from_slice(&(descriptors.len() as u16).to_be_bytes());
+        body.extend_from_slice(descriptors);
+
+        let section_length = body.len() + 4;
+        let mut out = vec![
+            TABLE_ID,
+            0x30 | ((section_length >> 8) as u8 & 0x0F),
+            section_length as u8,
+        ];
+        out.extend_from_slice(&body);
+        let crc = crc32_mpeg2(&out);
+        out.extend_from_slice(&crc.to_be_bytes());
+        out
+    }
+
+    /// Build a splice_insert section (program splice, optional duration)
+    pub(crate) fn splice_insert_section(event_id: u32, pts: u64, duration_secs: f64) -> Vec<u8> {
+        let mut cmd = BitWriter::default();
+        cmd.put(32, event_id as u64);
+        cmd.put(1, 0); // cancel
+        cmd.put(7, 0x7F);
+        cmd.put(1, 1); // out_of_network
+        cmd.put(1, 1); // program_splice
+        cmd.put(1, 1); // duration_flag
+        cmd.put(1, 0); // splice_immediate
+        cmd.put(4, 0xF);
+        cmd.put(1, 1); // time_specified_flag
+        cmd.put(6, 0x3F);
+        cmd.put(33, pts);
+        cmd.put(1, 1); // auto_return
+        cmd.put(6, 0x3F);
+        cmd.put(33, (duration_secs * PTS_CLOCK) as u64);
+        cmd.put(16, 1); // unique_program_id
+        cmd.put(8, 0); // avail_num
+        cmd.put(8, 0); // avails_expected
+        section(0x05, &cmd.into_bytes(), &[], 0)
+    }
+
+    fn time_signal_section(
+        event_id: u32,
+        pts: u64,
+        type_id: u8,
+        duration_secs: f64,
+        pts_adj: u64,
+    ) -> Vec<u8> {
+        let mut cmd = BitWriter::default();
+        cmd.put(1, 1);
+        cmd.put(6, 0x3F);
+        cmd.put(33, pts);
+
+        let mut desc = BitWriter::default();
+        desc.put(32, u32::from_be_bytes(*b"CUEI") as u64);
+        desc.put(32, event_id as u64);
+        desc.put(1, 0); // cancel
+        desc.put(7, 0x7F);
+        desc.put(1, 1); // program_segmentation_flag
+        desc.put(1, 1); // segmentation_duration_flag
+        desc.put(1, 1); // delivery_not_restricted_flag
+        desc.put(5, 0x1F);
+        desc.put(40, (duration_secs * PTS_CLOCK) as u64);
+        desc.put(8, 0); // upid_type
+        desc.put(8, 0); // upid_length
+        desc.put(8, type_id as u64);
+        desc.put(8, 0); // segment_num
+        desc.put(8, 0); // segments_expected
+        let desc = desc.into_bytes();
+
+        let mut descriptors = vec![0x02, desc.len() as u8];
+        descriptors.extend_from_slice(&desc);
+        section(0x06, &cmd.into_bytes(), &descriptors, pts_adj)
+    }
+
+    #[test]
+    fn test_parse_splice_insert() {
+        let data = splice_insert_section(42, 900_000, 30.0);
+        let info = parse_splice_info_section(&data).unwrap();
+
+        let cue = info.cue_out().unwrap();
+        assert_eq!(cue.event_id, 42);
+        assert_eq!(cue.pts, Some(900_000));
+        assert_eq!(cue.duration, Some(30.0));
+    }
+
+    #[test]
+    fn test_parse_time_signal_placement_opportunity() {
+        let data = time_signal_section(7, 180_000, 0x34, 15.0, 0);
+        let info = parse_splice_info_section(&data).unwrap();
+
+        assert!(matches!(
+            info.command,
+            SpliceCommand::TimeSignal {
+                pts_time: Some(180_000)
+            }
+        ));
+        let cue = info.cue_out().unwrap();
+        assert_eq!(cue.event_id, 7);
+        assert_eq!(cue.duration, Some(15.0));
+    }
+
+    #[test]
+    fn test_time_signal_end_is_not_cue_out() {
+        // 0x35 = Provider Placement Opportunity End
+        let data = time_signal_section(7, 180_000, 0x35, 0.0, 0);
+        let info = parse_splice_info_section(&data).unwrap();
+        assert_eq!(info.cue_out(), None);
+    }
+
+    #[test]
+    fn test_pts_adjustment_wraps() {
+        let data = time_signal_section(9, PTS_MODULO - 10, 0x22, 30.0, 100);
+        let info = parse_splice_info_section(&data).unwrap();
+        assert_eq!(info.pts_adjustment, 100);
+        assert_eq!(info.cue_out().unwrap().pts, Some(90));
+    }
+
+    #[test]
+    fn test_reject_bad_crc() {
+        let mut data = splice_insert_section(1, 0, 30.0);
+        let last = data.len() - 1;
+        data[last] ^= 0xFF;
+        assert!(parse_splice_info_section(&data).is_err());
+    }
+
+    #[test]
+    fn test_reject_truncated_and_wrong_table() {
+        let data = splice_insert_section(1, 0, 30.0);
+        assert!(parse_splice_info_section(&data[..10]).is_err());
+        assert!(parse_splice_info_section(&[0x00, 0x30, 0x00]).is_err());
+        assert!(parse_splice_info_section(&[]).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use dashmap::DashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tracing::{debug, info};
+
+/// Maximum cues retained per stream (oldest dropped first)
+const MAX_CUES_PER_STREAM: usize = 32;
+
+/// Maximum age of a cue before eviction
+const MAX_CUE_AGE: Duration = Duration::from_secs(300);
+
+/// An ad break start signalled in-band (inside media segments)
+#[derive(Debug, Clone, PartialEq)]
+pub struct InbandCue {
+    /// splice_event_id / segmentation_event_id (deduplication key)
+    pub event_id: u32,
+    /// Splice time in seconds on the stream's media timeline
+    pub media_time: f64,
+    /// Break duration in seconds
+    pub duration: f64,
+    /// When the cue was first observed (for TTL-based eviction)
+    received_at: Instant,
+}
+
+impl InbandCue {
+    pub fn new(event_id: u32, media_time: f64, duration: f64) -> Self {
+        Self {
+            event_id,
+            media_time,
+            duration,
+            received_at: Instant::now(),
+        }
+    }
+}
+
+/// A segment's scan, shared by every session that needs it
+type SegmentScan = (Arc<OnceCell<()>>, Instant);
+
+/// Shared per-stream store of in-band SCTE-35 cues
+///
+/// Segment handlers record cues as they proxy media; manifest handlers read
+/// them back on the next refresh and turn them into ad breaks. Streams are
+/// keyed by the origin directory segments are fetched from, so every session
+/// watching the same stream benefits from cues seen by any of them.
+///
+/// For transport streams it also remembers the media time at which scanned
+/// segments start, so cues can be placed on later playlist refreshes without
+/// fetching the same segment again.
+#[derive(Clone, Default)]
+pub struct InbandCueStore {
+    streams: Arc<DashMap<String, Vec<InbandCue>>>,
+    segment_times: Arc<DashMap<String, (f64, Instant)>>,
+    /// Segment scans by URL, started or finished
+    scans: Arc<DashMap<String, SegmentScan>>,
+}
+
+impl InbandCueStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a cue for a stream. Returns `false` if it was already known.
+    pub fn record(&self, stream_key: &str, cue: InbandCue) -> bool {
+        let mut cues = self.streams.entry(stream_key.to_string()).or_default();
+
+        if cues.iter().any(|c| c.event_id == cue.event_id) {
+            debug!(
+                "In-band cue {} already recorded for {}",
+                cue.event_id, stream_key
+            );
+            return false;
+        }
+
+        info!(
+            "In-band cue {} recorded for {}: media_time={}s duration={}s",
+            cue.event_id, stream_key, cue.media_time, cue.duration
+        );
+        cues.push(cue);
+        if cues.len() > MAX_CUES_PER_STREAM {
+            cues.remove(0);
+        }
+        true
+    }
+
+    /// All cues for the stream at `base` or below it, ordered by media time
+    ///
+    /// A manifest only knows its own directory, while segments may live in
+    /// per-representation subdirectories — hence the directory prefix match.
+    pub fn cues_for(&self, base: &str) -> Vec<InbandCue> {
+        let dir = format!("{}/", base.trim_end_matches('/'));
+        let mut cues: Vec<InbandCue> = self
+            .streams
+            .iter()
+            .filter(|e| e.key() == base || e.key().starts_with(&dir))
+            .flat_map(|e| e.value().clone())
+            .collect();
+
+        cues.sort_by(|a, b| a.media_time.total_cmp(&b.media_time));
+        // Renditions of one stream carry the same cue — keep one per event
+        cues.dedup_by_key(|c| c.event_id);
+        cues
+    }
+
+    /// Remember the media time (seconds) at which a scanned segment starts
+    pub fn record_segment_time(&self, segment_url: &str, media_time: f64) {
+        self.segment_times
+            .insert(segment_url.to_string(), (media_time, Instant::now()));
+    }
+
+    /// Media time at which a previously scanned segment starts
+    pub fn segment_time(&self, segment_url: &str) -> Option<f64> {
+        self.segment_times.get(segment_url).map(|e| e.0)
+    }
+
+    /// Run `scan` for a segment unless it was scanned before, by any session
+    ///
+    /// Concurrent callers for one segment share a single scan. A segment is
+    /// scanned once whatever the outcome, so one that cannot be fetched or
+    /// placed is not fetched again on every refresh.
+    pub async fn scan_once<F, Fut>(&self, segment_url: &str, scan: F)
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        // Clone the cell out so no map shard lock is held across the await
+        let cell = self
+            .scans
+            .entry(segment_url.to_string())
+            .or_insert_with(|| (Arc::new(OnceCell::new()), Instant::now()))
+            .0
+            .clone();
+        cell.get_or_init(scan).await;
+    }
+
+    /// Evict cues, segment times and scans older than the maximum age
+    pub fn cleanup(&self) {
+        self.streams.retain(|_, cues| {
+            cues.retain(|c| c.received_at.elapsed() < MAX_CUE_AGE);
+            !cues.is_empty()
+        });
+        self.segment_times
+            .retain(|_, (_, seen)| seen.elapsed() < MAX_CUE_AGE);
+        self.scans
+            .retain(|_, (_, started)| started.elapsed() < MAX_CUE_AGE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_deduplicates_by_event_id() {
+        let store = InbandCueStore::new();
+        assert!(store.record("http://cdn/video", InbandCue::new(1, 10.0, 30.0)));
+        assert!(!store.record("http://cdn/video", InbandCue::new(1, 10.0, 30.0)));
+        assert_eq!(store.cues_for("http://cdn/video").len(), 1);
+    }
+
+    #[test]
+    fn test_cues_for_prefix_merges_renditions() {
+        let store = InbandCueStore::new();
+        store.record("http://cdn/live/video", InbandCue::new(2, 40.0, 15.0));
+        store.record("http://cdn/live/audio", InbandCue::new(2, 40.0, 15.0));
+        store.record("http://cdn/live/video", InbandCue::new(1, 10.0, 30.0));
+        store.record("http://cdn/other", InbandCue::new(3, 5.0, 30.0));
+        store.record("http://cdn/live2", InbandCue::new(4, 5.0, 30.0));
+
+        let cues = store.cues_for("http://cdn/live");
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0].event_id, 1);
+        assert_eq!(cues[1].event_id, 2);
+    }
+
+    #[test]
+    fn test_segment_times() {
+        let store = InbandCueStore::new();
+        assert_eq!(store.segment_time("http://cdn/seg1.ts"), None);
+        store.record_segment_time("http://cdn/seg1.ts", 10.0);
+        assert_eq!(store.segment_time("http://cdn/seg1.ts"), Some(10.0));
+        store.cleanup();
+        assert_eq!(store.segment_time("http://cdn/seg1.ts"), Some(10.0));
+    }
+
+    #[tokio::test]
+    async fn test_segments_are_scanned_once() {
+        let store = InbandCueStore::new();
+        let scans = std::sync::atomic::AtomicUsize::new(0);
+        let scan = || async {
+            scans.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            tokio::task::yield_now().await;
+        };
+
+        // Concurrent sessions share one scan; a later refresh reuses it
+        tokio::join!(
+            store.scan_once("http://cdn/seg1.ts", scan),
+            store.scan_once("http://cdn/seg1.ts", scan),
+        );
+        store.scan_once("http://cdn/seg1.ts", scan).await;
+        assert_eq!(scans.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        store.scan_once("http://cdn/seg2.ts", scan).await;
+        assert_eq!(scans.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_per_stream_bound() {
+        let store = InbandCueStore::new();
+        for i in 0..(MAX_CUES_PER_STREAM as u32 + 5) {
+            store.record("s", InbandCue::new(i, i as f64, 30.0));
+        }
+        let cues = store.cues_for("s");
+        assert_eq!(cues.len(), MAX_CUES_PER_STREAM);
+        assert_eq!(cues[0].event_id, 5);
+    }
+}
@@ -1,4 +1,9 @@
-use crate::{ad::tracking, error::Result, metrics, server::state::AppState};
+use crate::{
+    ad::{provider, tracking, vast::VastErrorCode},
+    error::Result,
+    metrics,
+    server::{handlers::session::check_session, state::AppState},
+};
 use axum::{
     body::Body,
     extract::{Path, State},
@@ -11,8 +16,9 @@ use tracing::{info, warn};
 /// Serve ad segments by proxying from the configured ad source
 ///
 /// The ad_name encodes the break and segment index (e.g. "break-0-seg-3.ts").
-/// We delegate URL resolution to the AdProvider, keeping this handler decoupled
-/// from ad source implementation details.
+/// Breaks decided into the session are resolved from there, so any instance
+/// sharing the session store can serve them; everything else (static ads,
+/// slate) is resolved by the AdProvider.
 ///
 /// Includes 1 retry with 500ms backoff on fetch failure.
 pub async fn serve_ad(
@@ -21,11 +27,24 @@ pub async fn serve_ad(
 ) -> Result<Response> {
     let start = Instant::now();
     info!("Serving ad: {} for session: {}", ad_name, session_id);
+    check_session(&state, &session_id).await?;
 
     // Resolve ad segment with tracking context
-    let resolved = state
-        .ad_provider
-        .resolve_segment_with_tracking(&ad_name, &session_id)
+    let decided = match provider::parse_ad_segment_name(&ad_name) {
+        Some((break_id, index)) => {
+            state
+                .sessions
+                .deliver_ad_segment(&session_id, break_id, index)
+                .await
+        }
+        None => None,
+    };
+    let resolved = decided
+        .or_else(|| {
+            state
+                .ad_provider
+                .resolve_segment_with_tracking(&ad_name, &session_id)
+        })
         .ok_or_else(|| {
             crate::error::RitcherError::InternalError(format!(
                 "Failed to resolve ad segment URL for: {}",
@@ -37,7 +56,24 @@ pub async fn serve_ad(
     if let Some(tracking) = &resolved.tracking {
         // Fire impressions on first segment
         if tracking.segment_index == 0 {
-            tracking::fire_impressions(state.http_client.clone(), &tracking.impression_urls);
+            let ad = &tracking.ad;
+            info!(
+                "Impression for session {}: ad {} (sequence {:?}, creative {}, advertiser {:?}, universal ids {:?})",
+                session_id,
+                ad.ad_id,
+                ad.sequence,
+                ad.creative_id,
+                ad.advertiser,
+                ad.universal_ad_ids
+                    .iter()
+                    .map(|id| format!("{}:{}", id.id_registry, id.value))
+                    .collect::<Vec<_>>()
+            );
+            tracking::fire_impressions(
+                state.http_client.clone(),
+                &tracking.impression_urls,
+                &tracking.macros,
+            );
         }
 
         // Fire quartile events
@@ -46,13 +82,12 @@ pub async fn serve_ad(
             tracking.total_segments,
             &tracking.tracking_events,
         );
-        for event in events {
-            tracking::fire_beacon(
-                state.http_client.clone(),
-                event.url.clone(),
-                event.event.clone(),
-            );
-        }
+        tracking::fire_events(
+            state.http_client.clone(),
+            &events,
+            tracking.ad_duration,
+            &tracking.macros,
+        );
     }
 
     let ad_url = &resolved.url;
@@ -110,11 +145,14 @@ pub async fn serve_ad(
         }
     }
 
-    // Fire error beacon if tracking metadata is present
-    if let Some(tracking) = &resolved.tracking
-        && let Some(error_url) = &tracking.error_url
-    {
-        tracking::fire_error(state.http_client.clone(), error_url);
+    // Report VAST error 405 (media fetch failed) if tracking metadata is present
+    if let Some(tracking) = &resolved.tracking {
+        tracking::fire_errors(
+            state.http_client.clone(),
+            &tracking.error_urls,
+            VastErrorCode::MediaFetchFailed,
+            &tracking.macros,
+        );
     }
 
     metrics::record_request("ad", 502);
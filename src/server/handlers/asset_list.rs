@@ -9,7 +9,13 @@
 //! {"ASSETS": [{"URI": "https://ad-cdn.example.com/ad.m3u8", "DURATION": 30.0}]}
 //! ```
 
-use crate::{error::Result, metrics, server::state::AppState};
+use crate::{
+    ad::decisioning::{self, BreakRequest},
+    error::Result,
+    metrics,
+    server::{handlers::session::join_session, state::AppState},
+    session::{BreakDecision, ViewerContext},
+};
 use axum::{
     Json,
     extract::{Path, Query, State},
@@ -40,12 +46,15 @@ struct Asset {
 ///
 /// Called by the player for each ad break it encounters. Returns the list of
 /// ad creatives (URI + duration) the player should fetch and play inline.
+/// Decided asset lists are kept in the session, so a player retrying against
+/// another instance gets the same ads.
 ///
 /// Query params:
 /// - `dur` — requested ad break duration in seconds (default: 30.0)
 pub async fn serve_asset_list(
     Path((session_id, break_id)): Path<(String, String)>,
     Query(params): Query<HashMap<String, String>>,
+    viewer: ViewerContext,
     State(state): State<AppState>,
 ) -> Result<Response> {
     let start = Instant::now();
@@ -59,7 +68,51 @@ pub async fn serve_asset_list(
         .and_then(|d| d.parse().ok())
         .unwrap_or(30.0);
 
-    let creatives = state.ad_provider.get_ad_creatives(duration, &session_id);
+    // The player fetches asset lists itself, so its context is current
+    let session = join_session(&state, &session_id, viewer).await?;
+
+    // break_id is rendition-independent, so every rendition shares one decision
+    let request = BreakRequest {
+        key: break_id.clone(),
+        duration,
+    };
+    let decided = session
+        .ad_breaks
+        .get(&request.id())
+        .filter(|decision| !decision.assets.is_empty())
+        .map(|decision| decision.assets.clone());
+    let creatives = match decided {
+        Some(creatives) => creatives,
+        None => {
+            let creatives = decisioning::decide_creatives(
+                state.ad_provider.as_ref(),
+                &state.decisions,
+                &request,
+                &session_id,
+                &session.viewer,
+                state.config.ad_decision_timeout(),
+            )
+            .await;
+            // A no-fill or missed deadline may be decided again
+            if creatives.is_empty() {
+                creatives
+            } else {
+                // Another instance may have stored the break's decision first
+                state
+                    .sessions
+                    .record_break_decision(
+                        &session_id,
+                        &request.id(),
+                        BreakDecision::assets(creatives.clone()),
+                        state.config.ad_cache_ttl(),
+                    )
+                    .await
+                    .map(|earlier| earlier.assets)
+                    .filter(|assets| !assets.is_empty())
+                    .unwrap_or(creatives)
+            }
+        }
+    };
 
     let assets: Vec<Asset> = creatives
         .into_iter()
@@ -1,35 +1,143 @@
 use crate::{
-    dash::{cue, interleaver, parser},
-    error::Result,
+    ad::{
+        decisioning::{self, BreakRequest},
+        provider::{self, AdSegment},
+    },
+    config::XlinkResolution,
+    dash::{
+        cue, interleaver, parser, patch,
+        xlink::{self, XlinkPeriod},
+    },
+    error::{Result, RitcherError},
     metrics,
-    server::{state::AppState, url_validation::validate_origin_url},
+    server::{
+        handlers::session::join_session, state::AppState, url_validation::validate_origin_url,
+    },
+    session::{ContentProfile, Session, ViewerContext},
 };
 use axum::{
     extract::{Path, Query, State},
     http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
+use dash_mpd::{MPD, Period};
 use std::collections::HashMap;
 use std::time::Instant;
-use tracing::info;
+use tracing::{info, warn};
+use url::Url;
 
 /// Serve modified DASH manifest with stitched ad Periods
 pub async fn serve_manifest(
     Path(session_id): Path<String>,
     Query(params): Query<HashMap<String, String>>,
+    viewer: ViewerContext,
     State(state): State<AppState>,
 ) -> Result<Response> {
     let start = Instant::now();
     info!("Serving DASH manifest for session: {}", session_id);
 
-    // Get origin URL from query params or fallback to config.
-    // Validate user-supplied origin against SSRF attack vectors.
-    let origin_url: &str = if let Some(origin) = params.get("origin") {
-        validate_origin_url(origin)?;
-        origin.as_str()
-    } else {
-        &state.config.origin_url
-    };
+    let session = join_session(&state, &session_id, viewer).await?;
+    let origin_url = resolve_origin(&session, &params)?;
+    let mpd = stitch_mpd(&state, &session, origin_url, "manifest", start).await?;
+
+    // Step 5: Serialize MPD to XML
+    let mpd_xml = parser::serialize_mpd(&mpd)?;
+
+    metrics::record_request("manifest", 200);
+    metrics::record_duration("manifest", start);
+
+    // Return MPD with proper Content-Type header
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/dash+xml")],
+        mpd_xml,
+    )
+        .into_response())
+}
+
+/// Serve an MPD patch document for a previously served stitched MPD
+///
+/// Clients reach this through the rewritten `PatchLocation`, which carries
+/// the publishTime and fingerprint of the MPD they hold. The current stitched MPD is built
+/// as for a full refresh and diffed against that version. When the old
+/// version is unknown or the change cannot be patched, 404 tells the client
+/// to refetch the full MPD.
+pub async fn serve_manifest_patch(
+    Path(session_id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    viewer: ViewerContext,
+    State(state): State<AppState>,
+) -> Result<Response> {
+    let start = Instant::now();
+    info!("Serving DASH MPD patch for session: {}", session_id);
+
+    let session = join_session(&state, &session_id, viewer).await?;
+    let origin_url = resolve_origin(&session, &params)?;
+    let since = params
+        .get("publishTime")
+        .ok_or_else(|| RitcherError::PatchUnavailable("missing publishTime".to_string()))?;
+    let version = params
+        .get("version")
+        .ok_or_else(|| RitcherError::PatchUnavailable("missing version".to_string()))?;
+
+    let mpd = stitch_mpd(&state, &session, origin_url, "patch", start).await?;
+
+    let old = state
+        .mpd_history
+        .get(&history_key(&session_id, origin_url), since, version)
+        .ok_or_else(|| {
+            metrics::record_request("patch", 404);
+            RitcherError::PatchUnavailable(format!("no stitched MPD published at {}", since))
+        })?;
+    let patch_xml = patch::build_patch(&old, &mpd).ok_or_else(|| {
+        metrics::record_request("patch", 404);
+        RitcherError::PatchUnavailable("stitched MPD changes cannot be patched".to_string())
+    })?;
+
+    metrics::record_request("patch", 200);
+    metrics::record_duration("patch", start);
+
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/dash-patch+xml")],
+        patch_xml,
+    )
+        .into_response())
+}
+
+/// Origin MPD URL from the `origin` query param (SSRF-validated) or session
+fn resolve_origin<'a>(
+    session: &'a Session,
+    params: &'a HashMap<String, String>,
+) -> Result<&'a str> {
+    match params.get("origin") {
+        Some(origin) => {
+            validate_origin_url(origin)?;
+            Ok(origin.as_str())
+        }
+        None => Ok(&session.origin_url),
+    }
+}
+
+/// Key for a session's stitched MPD history of one origin
+fn history_key(session_id: &str, origin_url: &str) -> String {
+    format!("{}|{}", session_id, origin_url)
+}
+
+/// Fetch the origin MPD and run it through the ad insertion pipeline
+///
+/// Shared by the full manifest and patch endpoints so both describe the same
+/// stitched timeline. The result has proxied URLs and, for origins that
+/// offer MPD patching, a `PatchLocation` pointing at the stitcher.
+async fn stitch_mpd(
+    state: &AppState,
+    session: &Session,
+    origin_url: &str,
+    endpoint: &str,
+    start: Instant,
+) -> Result<MPD> {
+    let session_id = session.session_id.as_str();
+    let viewer = &session.viewer;
 
     info!("Fetching MPD from origin: {}", origin_url);
 
@@ -41,14 +149,14 @@ pub async fn serve_manifest(
         .await
         .map_err(|e| {
             metrics::record_origin_error();
-            crate::error::RitcherError::OriginFetchError(e)
+            RitcherError::OriginFetchError(e)
         })?;
 
     if !response.status().is_success() {
         metrics::record_origin_error();
-        metrics::record_request("manifest", 502);
-        metrics::record_duration("manifest", start);
-        return Err(crate::error::RitcherError::OriginFetchError(
+        metrics::record_request(endpoint, 502);
+        metrics::record_duration(endpoint, start);
+        return Err(RitcherError::OriginFetchError(
             response.error_for_status().unwrap_err(),
         ));
     }
@@ -58,35 +166,65 @@ pub async fn serve_manifest(
     // Parse DASH MPD
     let mut mpd = parser::parse_mpd(&content)?;
 
+    // Remember the content's renditions for conditioning this MPD's ads
+    state
+        .sessions
+        .record_content(session_id, ContentProfile::from_mpd(&mpd))
+        .await;
+
     // Extract base URL from origin
     let origin_base = origin_url
         .rsplit_once('/')
         .map(|(base, _)| base)
         .unwrap_or(origin_url);
 
-    // Step 1: Detect ad breaks from EventStream/SCTE-35
-    let ad_breaks = cue::detect_dash_ad_breaks(&mpd);
+    // Step 0: Resolve XLink remote Periods (upstream ad opportunities)
+    resolve_xlink_periods(state, &mut mpd, session_id, viewer, origin_url).await;
+
+    // Step 1: Detect ad breaks from EventStream/SCTE-35, plus in-band
+    // emsg cues recorded while proxying this stream's segments
+    let mut ad_breaks = cue::detect_dash_ad_breaks(&mpd);
+    let inband_cues = state.inband_cues.cues_for(origin_base);
+    if !inband_cues.is_empty() {
+        let inband = cue::inband_ad_breaks(&mpd, &inband_cues, &ad_breaks);
+        ad_breaks.extend(inband);
+        ad_breaks.sort_by(|a, b| {
+            (a.period_index, a.presentation_time)
+                .partial_cmp(&(b.period_index, b.presentation_time))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+    }
 
     if !ad_breaks.is_empty() {
         info!("Detected {} ad break(s)", ad_breaks.len());
         metrics::record_ad_breaks(ad_breaks.len());
 
-        // Step 2: Get ad segments for each break
-        let ad_segments_per_break: Vec<_> = ad_breaks
+        // Step 2: Get ad segments for each break (concurrently, under deadline,
+        // reusing decisions already made for this session)
+        let requests: Vec<BreakRequest> = ad_breaks
             .iter()
-            .map(|ad_break| {
-                state
-                    .ad_provider
-                    .get_ad_segments(ad_break.duration as f32, &session_id)
+            .map(|b| BreakRequest {
+                key: b.break_key(),
+                duration: b.duration as f32,
             })
             .collect();
+        // A break that misses the deadline keeps its content Period
+        let ad_segments_per_break = decisioning::decide_whole_breaks(
+            &state.ad_provider,
+            &state.decisions,
+            &requests,
+            session_id,
+            viewer,
+            state.config.ad_decision_timeout(),
+        )
+        .await;
 
         // Step 3: Interleave ad Periods into MPD
         mpd = interleaver::interleave_ads_mpd(
             mpd,
             &ad_breaks,
             &ad_segments_per_break,
-            &session_id,
+            session_id,
             &state.config.base_url,
         );
     } else {
@@ -94,19 +232,295 @@ pub async fn serve_manifest(
     }
 
     // Step 4: Rewrite URLs to proxy through stitcher
-    parser::rewrite_dash_urls(&mut mpd, &session_id, &state.config.base_url, origin_base)?;
+    parser::rewrite_dash_urls(&mut mpd, session_id, &state.config.base_url, origin_base)?;
 
-    // Step 5: Serialize MPD to XML
-    let mpd_xml = parser::serialize_mpd(&mpd)?;
+    // Step 4b: Point MPD patching at the stitcher — origin patches describe
+    // the unstitched timeline and would undo inserted ad Periods
+    if !mpd.PatchLocation.is_empty() {
+        match (mpd.id.is_some(), patch::publish_time(&mpd)) {
+            (true, Some(published)) => {
+                let version = patch::fingerprint(&mpd);
+                let location = patch_location(
+                    &state.config.base_url,
+                    session_id,
+                    origin_url,
+                    &published,
+                    &version,
+                );
+                for patch_location in &mut mpd.PatchLocation {
+                    patch_location.content = location.clone();
+                }
+                state.mpd_history.record(
+                    &history_key(session_id, origin_url),
+                    &published,
+                    &version,
+                    &mpd,
+                );
+            }
+            _ => {
+                warn!("Origin MPD has PatchLocation but no id/publishTime, dropping it");
+                mpd.PatchLocation.clear();
+            }
+        }
+    }
 
-    metrics::record_request("manifest", 200);
-    metrics::record_duration("manifest", start);
+    Ok(mpd)
+}
 
-    // Return MPD with proper Content-Type header
-    Ok((
-        StatusCode::OK,
-        [(header::CONTENT_TYPE, "application/dash+xml")],
-        mpd_xml,
+/// Stitcher URL of the patch to the stitched MPD `version` published at
+/// `published`
+fn patch_location(
+    base_url: &str,
+    session_id: &str,
+    origin_url: &str,
+    published: &str,
+    version: &str,
+) -> String {
+    let encode = |value: &str| -> String {
+        url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+    };
+    format!(
+        "{}/stitch/{}/manifest.mpp?origin={}&publishTime={}&version={}",
+        base_url,
+        session_id,
+        encode(origin_url),
+        encode(published),
+        encode(version)
     )
-        .into_response())
+}
+
+/// Replace every XLink Period placeholder with concrete Periods
+///
+/// Placeholders are ad opportunities: depending on `XLINK_RESOLUTION` they
+/// are filled from the ad provider (using the placeholder duration) or by
+/// fetching the remote Period, with the other source as fallback.
+/// Placeholders that cannot be resolved are removed, as a player would do
+/// for a failed XLink resolution.
+async fn resolve_xlink_periods(
+    state: &AppState,
+    mpd: &mut MPD,
+    session_id: &str,
+    viewer: &ViewerContext,
+    origin_url: &str,
+) {
+    let placeholders = xlink::detect_xlink_periods(mpd);
+    if placeholders.is_empty() {
+        return;
+    }
+
+    // Provider-first: decide every placeholder with a duration concurrently
+    let mut decided: HashMap<usize, Vec<AdSegment>> = HashMap::new();
+    if state.config.xlink_resolution == XlinkResolution::AdProvider {
+        let wanted: Vec<(usize, BreakRequest)> = placeholders
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| !p.is_resolve_to_zero())
+            .filter_map(|(i, p)| placeholder_request(mpd, p).map(|r| (i, r)))
+            .collect();
+        let requests: Vec<BreakRequest> = wanted.iter().map(|(_, r)| r.clone()).collect();
+        // A placeholder that misses the deadline keeps its fallback
+        let segments = decisioning::decide_whole_breaks(
+            &state.ad_provider,
+            &state.decisions,
+            &requests,
+            session_id,
+            viewer,
+            state.config.ad_decision_timeout(),
+        )
+        .await;
+        decided = wanted.into_iter().map(|(i, _)| i).zip(segments).collect();
+    }
+
+    let mut resolved = Vec::with_capacity(placeholders.len());
+    for (ordinal, placeholder) in placeholders.iter().enumerate() {
+        let periods = if placeholder.is_resolve_to_zero() {
+            metrics::record_xlink_resolution("zero");
+            Vec::new()
+        } else {
+            let result = match state.config.xlink_resolution {
+                XlinkResolution::AdProvider => {
+                    let segments = decided.remove(&ordinal).unwrap_or_default();
+                    match provider_period(state, mpd, placeholder, ordinal, session_id, segments) {
+                        Some(period) => Some(("provider", vec![period])),
+                        None => fetch_remote_periods(state, &placeholder.href, origin_url)
+                            .await
+                            .map(|p| ("remote", p)),
+                    }
+                }
+                XlinkResolution::Remote => {
+                    match fetch_remote_periods(state, &placeholder.href, origin_url).await {
+                        Some(periods) => Some(("remote", periods)),
+                        None => {
+                            let segments = match placeholder_request(mpd, placeholder) {
+                                Some(request) => decisioning::decide_whole_breaks(
+                                    &state.ad_provider,
+                                    &state.decisions,
+                                    &[request],
+                                    session_id,
+                                    viewer,
+                                    state.config.ad_decision_timeout(),
+                                )
+                                .await
+                                .pop()
+                                .unwrap_or_default(),
+                                None => Vec::new(),
+                            };
+                            provider_period(state, mpd, placeholder, ordinal, session_id, segments)
+                                .map(|p| ("provider", vec![p]))
+                        }
+                    }
+                }
+            };
+
+            match result {
+                Some((source, periods)) => {
+                    info!(
+                        "Resolved XLink Period #{} from {} into {} Period(s)",
+                        placeholder.period_index,
+                        source,
+                        periods.len()
+                    );
+                    metrics::record_xlink_resolution(source);
+                    periods
+                }
+                None => {
+                    warn!(
+                        "Could not resolve XLink Period #{} ({}), removing it",
+                        placeholder.period_index, placeholder.href
+                    );
+                    metrics::record_xlink_resolution("failed");
+                    Vec::new()
+                }
+            }
+        };
+        resolved.push((placeholder.period_index, periods));
+    }
+
+    xlink::apply_resolutions(mpd, resolved);
+}
+
+/// Decision request for a placeholder, if it has a usable duration
+fn placeholder_request(mpd: &MPD, placeholder: &XlinkPeriod) -> Option<BreakRequest> {
+    let duration = placeholder.duration.filter(|d| *d > 0.0)?;
+    Some(BreakRequest {
+        key: placeholder.break_key(mpd),
+        duration: duration as f32,
+    })
+}
+
+/// Build an ad Period for a placeholder from decided ad segments
+///
+/// The ad Period mirrors the tracks of the nearest preceding content Period
+/// and keeps the placeholder's id and start so the timeline is unchanged.
+fn provider_period(
+    state: &AppState,
+    mpd: &MPD,
+    placeholder: &XlinkPeriod,
+    ordinal: usize,
+    session_id: &str,
+    ad_segments: Vec<AdSegment>,
+) -> Option<Period> {
+    if ad_segments.is_empty() {
+        return None;
+    }
+
+    let content_adaptations = mpd.periods[..placeholder.period_index]
+        .iter()
+        .rev()
+        .find(|p| p.href.is_none() && !p.adaptations.is_empty())
+        .map(|p| p.adaptations.as_slice())
+        .unwrap_or(&[]);
+
+    let source = &mpd.periods[placeholder.period_index];
+    let break_id = provider::break_id(&placeholder.break_key(mpd));
+    let mut period = interleaver::create_ad_period(
+        &ad_segments,
+        ordinal,
+        &break_id,
+        session_id,
+        &state.config.base_url,
+        content_adaptations,
+    );
+    period.id = Some(
+        source
+            .id
+            .clone()
+            .unwrap_or_else(|| format!("xlink-ad-{}", ordinal)),
+    );
+    period.start = source.start;
+    Some(period)
+}
+
+/// Fetch and parse a remote Period, validating the URL against SSRF
+///
+/// `href` resolves against the MPD URL. Each absolute URL is fetched once
+/// across sessions per cache TTL, with a client that validates every
+/// redirect hop as well.
+async fn fetch_remote_periods(state: &AppState, href: &str, mpd_url: &str) -> Option<Vec<Period>> {
+    let url = match Url::parse(mpd_url).and_then(|base| base.join(href)) {
+        Ok(url) => url,
+        Err(e) => {
+            warn!("Invalid XLink Period URL {}: {}", href, e);
+            return None;
+        }
+    };
+
+    if let Err(e) = validate_origin_url(url.as_str()) {
+        warn!("Refusing to fetch XLink Period {}: {}", url, e);
+        return None;
+    }
+
+    state
+        .xlink_periods
+        .get_or_fetch(url.as_str(), || async {
+            let body = async {
+                let response = state.xlink_client.get(url.clone()).send().await?;
+                let response = response.error_for_status()?;
+                let remote_url = response.url().clone();
+                Ok((remote_url, response.text().await?))
+            }
+            .await
+            .map_err(|e| {
+                metrics::record_origin_error();
+                RitcherError::OriginFetchError(e)
+            });
+
+            // Relative BaseURLs resolve against the final, redirected URL
+            let parsed = body.and_then(|(remote_url, xml)| {
+                let remote_base = remote_url.join(".").unwrap_or(remote_url);
+                xlink::parse_remote_periods(&xml, remote_base.as_str().trim_end_matches('/'))
+            });
+            match parsed {
+                Ok(periods) => Some(periods),
+                Err(e) => {
+                    warn!("Failed to resolve XLink Period {}: {}", url, e);
+                    None
+                }
+            }
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch_location_keeps_origin_query() {
+        let origin = "https://origin.example.com/live/manifest.mpd?token=a&b=c d";
+        let location = patch_location(
+            "http://stitcher",
+            "s1",
+            origin,
+            "2024-01-01T00:00:00+01:00",
+            "0123456789abcdef",
+        );
+        let url = url::Url::parse(&location).unwrap();
+        assert_eq!(url.path(), "/stitch/s1/manifest.mpp");
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(params.len(), 3);
+        assert_eq!(params["origin"], origin);
+        assert_eq!(params["publishTime"], "2024-01-01T00:00:00+01:00");
+        assert_eq!(params["version"], "0123456789abcdef");
+    }
 }
@@ -6,3 +6,4 @@ pub mod manifest;
 pub mod metrics;
 pub mod playlist;
 pub mod segment;
+pub mod session;
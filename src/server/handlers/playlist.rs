@@ -1,37 +1,54 @@
 use crate::{
-    ad::{AdProvider, interleaver},
+    ad::{
+        AdProvider,
+        decisioning::{self, BreakRequest, DecisionCache},
+        interleaver,
+    },
     config::StitchingMode,
     error::Result,
-    hls::{cue, interstitial, parser},
+    hls::{cue, interstitial, parser, ts},
     metrics,
-    server::{state::AppState, url_validation::validate_origin_url},
+    scte35::{InbandCue, splice::PTS_CLOCK},
+    server::{
+        handlers::session::join_session, state::AppState, url_validation::validate_origin_url,
+    },
+    session::{ContentProfile, ViewerContext},
 };
 use axum::{
     extract::{Path, Query, State},
     http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
-use m3u8_rs::Playlist;
+use futures::future::join_all;
+use m3u8_rs::{MediaPlaylist, Playlist};
 use std::collections::HashMap;
-use std::time::Instant;
-use tracing::info;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+use url::Url;
+
+/// Number of live-edge segments scanned for in-band SCTE-35 per refresh
+const INBAND_SCAN_SEGMENTS: usize = 2;
 
 /// Serve modified HLS playlist with stitched ad markers
 pub async fn serve_playlist(
     Path(session_id): Path<String>,
     Query(params): Query<HashMap<String, String>>,
+    viewer: ViewerContext,
     State(state): State<AppState>,
 ) -> Result<Response> {
     let start = Instant::now();
     info!("Serving playlist for session: {}", session_id);
 
-    // Get origin URL from query params or fallback to config.
+    let session = join_session(&state, &session_id, viewer).await?;
+
+    // Get origin URL from query params or fallback to the session's.
     // Validate user-supplied origin against SSRF attack vectors.
     let origin_url: &str = if let Some(origin) = params.get("origin") {
         validate_origin_url(origin)?;
         origin.as_str()
     } else {
-        &state.config.origin_url
+        &session.origin_url
     };
 
     info!("Fetching playlist from origin: {}", origin_url);
@@ -61,6 +78,14 @@ pub async fn serve_playlist(
     // Parse HLS playlist
     let playlist = parser::parse_hls_playlist(&content)?;
 
+    // Remember the content's renditions for conditioning ads of later breaks
+    if let Playlist::MasterPlaylist(master) = &playlist {
+        state
+            .sessions
+            .record_content(&session_id, ContentProfile::from_hls_master(master))
+            .await;
+    }
+
     // Extract base URL from origin
     let origin_base = origin_url
         .rsplit_once('/')
@@ -74,16 +99,31 @@ pub async fn serve_playlist(
         _ => "video",
     };
 
+    // In-band SCTE-35 carried in the transport stream rather than the playlist
+    let (inband_breaks, upcoming_breaks) = match &playlist {
+        Playlist::MediaPlaylist(media) if track_type != "subtitles" => {
+            detect_inband_breaks(&state, media, origin_url, origin_base).await
+        }
+        _ => (Vec::new(), Vec::new()),
+    };
+
     // Process playlist through the ad insertion pipeline
     let modified_playlist = process_playlist(
         playlist,
+        inband_breaks,
+        upcoming_breaks,
         &session_id,
+        &session.viewer,
         &state.config.base_url,
         origin_base,
-        state.ad_provider.as_ref(),
+        &state.ad_provider,
+        &state.decisions,
+        state.config.ad_decision_timeout(),
+        state.config.ad_lookahead(),
         track_type,
         &state.config.stitching_mode,
-    )?;
+    )
+    .await?;
 
     // Serialize to string
     let playlist_str = parser::serialize_playlist(modified_playlist)?;
@@ -100,6 +140,149 @@ pub async fn serve_playlist(
         .into_response())
 }
 
+/// Detect ad breaks signalled only on the SCTE-35 PID of TS segments
+///
+/// Scans the newest live-edge segments not seen before, records their cues
+/// and start PTS in the shared in-band cue store, then maps every cue known
+/// for the stream onto this playlist. Cues splicing beyond the playlist end,
+/// within the look-ahead horizon, are returned as upcoming breaks. Failures
+/// are logged and never fail the playlist request.
+///
+/// The playlist waits for the scans at most the ad decision timeout; a slow
+/// scan carries on in the background and its cues apply from the next
+/// refresh.
+async fn detect_inband_breaks(
+    state: &AppState,
+    playlist: &MediaPlaylist,
+    origin_url: &str,
+    origin_base: &str,
+) -> (Vec<cue::AdBreak>, Vec<cue::UpcomingBreak>) {
+    // VOD has no live edge; fMP4 (EXT-X-MAP) segments carry emsg instead
+    if !state.config.inband_scte35
+        || playlist.end_list
+        || playlist.segments.iter().any(|s| s.map.is_some())
+    {
+        return (Vec::new(), Vec::new());
+    }
+
+    // Segment URIs resolve against the playlist URL (relative, `../`,
+    // root-relative or absolute); unresolvable ones are never scanned
+    let Ok(playlist_url) = Url::parse(origin_url) else {
+        return (Vec::new(), Vec::new());
+    };
+    let segment_urls: Vec<Option<String>> = playlist
+        .segments
+        .iter()
+        .map(|s| playlist_url.join(&s.uri).ok().map(String::from))
+        .collect();
+
+    // Live-edge segments are fetched concurrently, each once across sessions
+    let live_edge = segment_urls.len().saturating_sub(INBAND_SCAN_SEGMENTS);
+    let scans: Vec<_> = segment_urls[live_edge..]
+        .iter()
+        .flatten()
+        .filter(|url| {
+            let allowed = validate_origin_url(url).is_ok();
+            if !allowed {
+                warn!("Skipping in-band SCTE-35 scan of disallowed URL: {}", url);
+            }
+            allowed
+        })
+        .map(|url| {
+            let state = state.clone();
+            let url = url.clone();
+            let origin_base = origin_base.to_string();
+            tokio::spawn(async move {
+                state
+                    .inband_cues
+                    .scan_once(&url, || scan_ts_segment(&state, &url, &origin_base))
+                    .await
+            })
+        })
+        .collect();
+    if tokio::time::timeout(state.config.ad_decision_timeout(), join_all(scans))
+        .await
+        .is_err()
+    {
+        debug!("In-band SCTE-35 scan still running, its cues apply from the next refresh");
+    }
+
+    let Some(anchor) = segment_urls.iter().enumerate().rev().find_map(|(i, url)| {
+        url.as_deref()
+            .and_then(|url| state.inband_cues.segment_time(url))
+            .map(|t| (i, t))
+    }) else {
+        return (Vec::new(), Vec::new());
+    };
+
+    let cues = state.inband_cues.cues_for(origin_base);
+    if cues.is_empty() {
+        return (Vec::new(), Vec::new());
+    }
+    let signalled = cue::detect_ad_breaks(playlist);
+    let horizon = state.config.ad_lookahead().as_secs_f64();
+    let upcoming = if horizon > 0.0 {
+        cue::upcoming_inband_breaks(playlist, &cues, anchor, horizon)
+    } else {
+        Vec::new()
+    };
+    (
+        cue::inband_ad_breaks(playlist, &cues, anchor, &signalled),
+        upcoming,
+    )
+}
+
+/// Fetch one TS segment and record its start time and SCTE-35 cues
+async fn scan_ts_segment(state: &AppState, url: &str, origin_base: &str) {
+    let bytes = match state.http_client.get(url).send().await {
+        Ok(response) if response.status().is_success() => match response.bytes().await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                warn!("Failed to read segment {} for SCTE-35 scan: {}", url, e);
+                return;
+            }
+        },
+        Ok(response) => {
+            warn!(
+                "Segment {} returned status {} during SCTE-35 scan",
+                url,
+                response.status()
+            );
+            return;
+        }
+        Err(e) => {
+            metrics::record_origin_error();
+            warn!("Failed to fetch segment {} for SCTE-35 scan: {}", url, e);
+            return;
+        }
+    };
+
+    let scan = ts::scan_segment(&bytes);
+    let Some(first_pts) = scan.first_pts else {
+        debug!("No PES timestamp in {}, cannot place in-band cues", url);
+        return;
+    };
+    state
+        .inband_cues
+        .record_segment_time(url, first_pts as f64 / PTS_CLOCK);
+
+    for cue_out in scan.cues {
+        let Some(duration) = cue_out.duration else {
+            debug!(
+                "SCTE-35 event {} in {} has no break duration, skipping",
+                cue_out.event_id, url
+            );
+            continue;
+        };
+        // Immediate splices take effect at the start of the segment
+        let pts = cue_out.pts.unwrap_or(first_pts);
+        let cue = InbandCue::new(cue_out.event_id, pts as f64 / PTS_CLOCK, duration);
+        if state.inband_cues.record(origin_base, cue) {
+            metrics::record_inband_cue("ts");
+        }
+    }
+}
+
 /// Process playlist through the ad insertion pipeline
 ///
 /// The `track_type` parameter indicates the media track type:
@@ -111,12 +294,28 @@ pub async fn serve_playlist(
 /// The `stitching_mode` selects the insertion strategy:
 /// - `StitchingMode::Ssai` — replace content segments with ad segments (traditional SSAI)
 /// - `StitchingMode::Sgai` — inject EXT-X-DATERANGE interstitial markers (HLS Interstitials)
-fn process_playlist(
+///
+/// `inband_breaks` are breaks found in-band in the media segments; they are
+/// merged with the breaks signalled by CUE tags and SCTE-35 DateRanges.
+///
+/// Breaks signalled ahead of their splice point — `upcoming_breaks` found
+/// in-band and DateRanges starting within `lookahead` of the playlist end —
+/// are decided in the background, so their pod is ready when the break is
+/// published. In SGAI mode the published breaks are also decided here,
+/// ahead of the player's asset-list request.
+#[allow(clippy::too_many_arguments)]
+async fn process_playlist(
     playlist: Playlist,
+    inband_breaks: Vec<cue::AdBreak>,
+    upcoming_breaks: Vec<cue::UpcomingBreak>,
     session_id: &str,
+    viewer: &ViewerContext,
     base_url: &str,
     origin_base: &str,
-    ad_provider: &dyn AdProvider,
+    ad_provider: &Arc<dyn AdProvider>,
+    decisions: &DecisionCache,
+    decision_timeout: Duration,
+    lookahead: Duration,
     track_type: &str,
     stitching_mode: &StitchingMode,
 ) -> Result<Playlist> {
@@ -137,8 +336,40 @@ fn process_playlist(
         return Ok(playlist);
     };
 
-    // Step 1: Detect ad breaks from CUE tags
-    let ad_breaks = cue::detect_ad_breaks(&media_playlist);
+    // Step 1: Detect ad breaks from CUE tags, plus any found in-band or in
+    // SCTE-35 DateRanges
+    let mut ad_breaks = cue::detect_ad_breaks(&media_playlist);
+    ad_breaks.extend(inband_breaks);
+    let daterange_breaks = cue::daterange_ad_breaks(&media_playlist, &ad_breaks);
+    ad_breaks.extend(daterange_breaks);
+    ad_breaks.sort_by_key(|b| b.start_index);
+
+    // Start deciding breaks known before their first segment is published
+    let mut upcoming = upcoming_breaks;
+    if !lookahead.is_zero() {
+        upcoming.extend(cue::upcoming_daterange_breaks(
+            &media_playlist,
+            lookahead.as_secs_f64(),
+        ));
+    }
+    for upcoming in upcoming {
+        debug!(
+            "Deciding upcoming ad break {} ({}s) {:.1}s ahead of its splice point",
+            upcoming.key, upcoming.duration, upcoming.lead_time
+        );
+        let request = BreakRequest {
+            key: upcoming.key,
+            duration: upcoming.duration,
+        };
+        prefetch(
+            ad_provider,
+            decisions,
+            stitching_mode,
+            session_id,
+            request,
+            viewer,
+        );
+    }
 
     if !ad_breaks.is_empty() {
         info!(
@@ -152,23 +383,81 @@ fn process_playlist(
             StitchingMode::Ssai => {
                 // Step 2: Get ad segments for each break
                 // For audio tracks, the same muxed ad segments are used — the player
-                // demuxes the audio track from the muxed container
-                let ad_segments_per_break: Vec<_> = ad_breaks
+                // demuxes the audio track from the muxed container. Decisions are
+                // keyed by media sequence so every rendition gets the same ads.
+                let requests: Vec<BreakRequest> = ad_breaks
                     .iter()
-                    .map(|ad_break| ad_provider.get_ad_segments(ad_break.duration, session_id))
+                    .map(|b| BreakRequest {
+                        key: cue::break_key(&media_playlist, b),
+                        duration: b.duration,
+                    })
                     .collect();
+                let decided = decisioning::decide_breaks(
+                    ad_provider,
+                    decisions,
+                    &requests,
+                    session_id,
+                    viewer,
+                    decision_timeout,
+                )
+                .await;
+
+                // Step 2b: Breaks that missed their decision budget go out with
+                // slate (or content) for the segments already due; once the
+                // decision lands, ads fill the rest of the break
+                let (ad_breaks, ad_segments_per_break): (Vec<_>, Vec<_>) = ad_breaks
+                    .iter()
+                    .zip(&requests)
+                    .zip(decided)
+                    .map(|((b, request), ads)| {
+                        let late_segments = match &ads {
+                            None => {
+                                let due =
+                                    b.end_index.min(media_playlist.segments.len()) - b.start_index;
+                                decisions.publish_late(session_id, &request.key, due)
+                            }
+                            Some(_) => decisions.late_segments(session_id, &request.key),
+                        };
+                        interleaver::backfill_break(
+                            &media_playlist.segments,
+                            b,
+                            late_segments,
+                            ads,
+                            ad_provider.slate(),
+                            session_id,
+                        )
+                    })
+                    .unzip();
 
                 // Step 3: Interleave ads into playlist
                 media_playlist = interleaver::interleave_ads(
                     media_playlist,
                     &ad_breaks,
                     &ad_segments_per_break,
+                    &requests.iter().map(BreakRequest::id).collect::<Vec<_>>(),
                     session_id,
                     base_url,
                 );
             }
             StitchingMode::Sgai => {
-                // SGAI: inject EXT-X-DATERANGE interstitial markers
+                // SGAI: decide now so the asset list is ready when the player
+                // asks for it, keyed like the asset-list URL
+                for b in &ad_breaks {
+                    let request = BreakRequest {
+                        key: cue::break_key(&media_playlist, b),
+                        duration: b.duration,
+                    };
+                    prefetch(
+                        ad_provider,
+                        decisions,
+                        stitching_mode,
+                        session_id,
+                        request,
+                        viewer,
+                    );
+                }
+
+                // Inject EXT-X-DATERANGE interstitial markers
                 // Ensure PDT is present (required by HLS Interstitials spec)
                 interstitial::ensure_program_date_time(&mut media_playlist);
                 // Inject DateRange tags for each ad break
@@ -196,3 +485,23 @@ fn process_playlist(
     let playlist = Playlist::MediaPlaylist(media_playlist);
     parser::rewrite_content_urls(playlist, session_id, base_url, origin_base)
 }
+
+/// Decide a break in the background with the decision kind the stitching
+/// mode will ask for
+fn prefetch(
+    ad_provider: &Arc<dyn AdProvider>,
+    decisions: &DecisionCache,
+    stitching_mode: &StitchingMode,
+    session_id: &str,
+    request: BreakRequest,
+    viewer: &ViewerContext,
+) {
+    match stitching_mode {
+        StitchingMode::Ssai => {
+            decisions.prefetch_segments(ad_provider.clone(), session_id, request, viewer.clone())
+        }
+        StitchingMode::Sgai => {
+            decisions.prefetch_creatives(ad_provider.clone(), session_id, request, viewer.clone())
+        }
+    }
+}
@@ -1,7 +1,10 @@
 use crate::{
+    dash::emsg,
     error::Result,
     metrics,
-    server::{state::AppState, url_validation::validate_origin_url},
+    server::{
+        handlers::session::check_session, state::AppState, url_validation::validate_origin_url,
+    },
 };
 use axum::{
     body::Body,
@@ -26,6 +29,7 @@ pub async fn serve_segment(
         "Serving segment: {} for session: {}",
         segment_path, session_id
     );
+    check_session(&state, &session_id).await?;
 
     // Get origin base URL from query params or fallback to config.
     // Validate user-supplied origin against SSRF attack vectors.
@@ -56,6 +60,16 @@ pub async fn serve_segment(
 
                 let bytes = response.bytes().await?;
 
+                // In-band SCTE-35: scan fMP4 segments for emsg cues so the
+                // next MPD refresh can stitch breaks not signalled in the MPD
+                if is_fmp4_segment(&segment_path, &content_type) {
+                    for cue in emsg::scte35_cues(&bytes) {
+                        if state.inband_cues.record(origin_base, cue) {
+                            metrics::record_inband_cue("emsg");
+                        }
+                    }
+                }
+
                 metrics::record_request("segment", 200);
                 metrics::record_duration("segment", start);
 
@@ -98,3 +112,12 @@ pub async fn serve_segment(
         last_error.expect("Should have error after all retries failed"),
     ))
 }
+
+/// Whether a proxied segment is fragmented MP4 (CMAF) and may carry emsg boxes
+fn is_fmp4_segment(segment_path: &str, content_type: &str) -> bool {
+    let path = segment_path.split('?').next().unwrap_or(segment_path);
+    content_type.contains("mp4")
+        || [".m4s", ".mp4", ".cmfv", ".cmfa", ".m4v", ".m4a"]
+            .iter()
+            .any(|ext| path.ends_with(ext))
+}
@@ -0,0 +1,456 @@
+//! Session API
+//!
+//! `POST /sessions` creates a session with a server-issued id, so playback
+//! URLs cannot be guessed or shared between viewers. The response carries
+//! the session's playback URLs plus:
+//! - a tracking URL (`GET /sessions/{id}/tracking`) listing the session's
+//!   decided ads with their beacon URLs, for client-side ad reporting
+//! - a reporting URL (`GET /sessions/{id}`) summarizing the session
+//!
+//! With `REQUIRE_SESSIONS=true` the stitch endpoints only serve sessions
+//! created here.
+
+use crate::{
+    ad::macros,
+    error::{Result, RitcherError},
+    metrics,
+    server::{state::AppState, url_validation::validate_origin_url},
+    session::{
+        Session, ViewerContext,
+        viewer::{AD_PARAM_PREFIX, MAX_AD_PARAM_LEN, MAX_AD_PARAMS},
+    },
+};
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Longest accepted channel name
+const MAX_CHANNEL_LEN: usize = 64;
+
+/// Random bytes of a session id
+const SESSION_ID_BYTES: usize = 16;
+
+/// Session creation request
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CreateSessionRequest {
+    /// Origin HLS playlist or DASH MPD (default: `ORIGIN_URL`)
+    pub origin: Option<String>,
+    /// Channel the session plays, for reporting
+    pub channel: Option<String>,
+    /// Ad targeting parameters, with or without the `ads.` prefix
+    #[serde(default)]
+    pub ad_params: HashMap<String, String>,
+    /// Viewer context, for players that do not fetch the stream themselves
+    #[serde(default)]
+    pub viewer: ViewerFields,
+}
+
+/// Viewer context supplied when creating a session
+///
+/// Overrides what the creating request's own headers tell.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ViewerFields {
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub accept_language: Option<String>,
+}
+
+/// Created session and its URLs
+#[derive(Debug, Serialize)]
+pub struct CreateSessionResponse {
+    pub session_id: String,
+    pub hls_url: String,
+    pub dash_url: String,
+    pub tracking_url: String,
+    pub reporting_url: String,
+}
+
+/// Session summary served at the reporting URL
+#[derive(Debug, Serialize)]
+pub struct SessionReport {
+    pub session_id: String,
+    pub channel: Option<String>,
+    pub origin: String,
+    /// Epoch seconds
+    pub created_at: u64,
+    /// Epoch seconds
+    pub last_accessed: u64,
+    pub ad_breaks: Vec<BreakReport>,
+}
+
+/// Delivery summary of one decided break
+#[derive(Debug, Serialize)]
+pub struct BreakReport {
+    pub break_id: String,
+    /// Epoch seconds
+    pub decided_at: u64,
+    /// SSAI ad segments, or SGAI creatives of the asset list
+    pub ads: usize,
+    /// SSAI ad segments delivered to the player
+    pub delivered: usize,
+}
+
+/// Decided ads served at the tracking URL
+#[derive(Debug, Serialize)]
+pub struct TrackingResponse {
+    pub breaks: Vec<TrackedBreak>,
+}
+
+/// Ads of one decided break
+#[derive(Debug, Serialize)]
+pub struct TrackedBreak {
+    pub break_id: String,
+    pub ads: Vec<TrackedAd>,
+}
+
+/// One decided ad with its beacons, macros expanded
+#[derive(Debug, Serialize)]
+pub struct TrackedAd {
+    pub ad_id: String,
+    pub creative_id: String,
+    pub duration: f64,
+    pub impression_urls: Vec<String>,
+    pub tracking_events: Vec<TrackedEvent>,
+}
+
+/// Beacon of one tracking event
+#[derive(Debug, Serialize)]
+pub struct TrackedEvent {
+    pub event: String,
+    pub url: String,
+}
+
+/// Create a session with a server-issued id
+///
+/// Returns `201 Created` with the session's playback, tracking and reporting
+/// URLs, or `400` when the origin, channel, ad parameters or viewer context
+/// are invalid.
+pub async fn create_session(
+    viewer: ViewerContext,
+    State(state): State<AppState>,
+    Json(request): Json<CreateSessionRequest>,
+) -> Result<Response> {
+    let start = Instant::now();
+
+    let origin_url = match request.origin {
+        Some(origin) => {
+            validate_origin_url(&origin)?;
+            origin
+        }
+        None => state.config.origin_url.clone(),
+    };
+    if let Some(channel) = &request.channel {
+        validate_channel(channel)?;
+    }
+    let viewer = session_viewer(
+        viewer,
+        request.viewer,
+        &request.ad_params,
+        &state.config.ad_query_params,
+    )?;
+
+    let mut session = Session::new(new_session_id()?, origin_url);
+    session.channel = request.channel;
+    session.viewer = viewer;
+    let session_id = session.session_id.clone();
+    info!(
+        "Created session {} (channel: {:?}, origin: {})",
+        session_id, session.channel, session.origin_url
+    );
+    state.sessions.create(session).await;
+
+    metrics::record_session("created");
+    metrics::record_request("sessions", 201);
+    metrics::record_duration("sessions", start);
+
+    let base_url = &state.config.base_url;
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateSessionResponse {
+            hls_url: format!("{}/stitch/{}/playlist.m3u8", base_url, session_id),
+            dash_url: format!("{}/stitch/{}/manifest.mpd", base_url, session_id),
+            tracking_url: format!("{}/sessions/{}/tracking", base_url, session_id),
+            reporting_url: format!("{}/sessions/{}", base_url, session_id),
+            session_id,
+        }),
+    )
+        .into_response())
+}
+
+/// Summarize a session and the delivery of its decided breaks
+pub async fn session_report(
+    Path(session_id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<SessionReport>> {
+    let session = state
+        .sessions
+        .get(&session_id)
+        .await
+        .ok_or_else(|| RitcherError::InvalidSessionId(session_id.clone()))?;
+
+    let mut ad_breaks = Vec::with_capacity(session.ad_breaks.len());
+    for (break_id, decision) in &session.ad_breaks {
+        let delivered = state
+            .sessions
+            .delivered_segments(&session_id, break_id)
+            .await;
+        ad_breaks.push(BreakReport {
+            break_id: break_id.clone(),
+            decided_at: epoch_secs(decision.decided_at),
+            ads: decision.len(),
+            delivered: delivered.len(),
+        });
+    }
+
+    Ok(Json(SessionReport {
+        ad_breaks,
+        session_id: session.session_id,
+        channel: session.channel,
+        origin: session.origin_url,
+        created_at: epoch_secs(session.created_at),
+        last_accessed: epoch_secs(session.last_accessed),
+    }))
+}
+
+/// List a session's decided ads with their beacon URLs
+///
+/// Lets players that report ad playback themselves fire the same beacons
+/// the stitcher would, with macros expanded from the ad decision.
+pub async fn session_tracking(
+    Path(session_id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<TrackingResponse>> {
+    let session = state
+        .sessions
+        .get(&session_id)
+        .await
+        .ok_or(RitcherError::InvalidSessionId(session_id))?;
+
+    let breaks = session
+        .ad_breaks
+        .into_iter()
+        .map(|(break_id, decision)| {
+            let segments = decision.segments.into_iter().filter_map(|segment| {
+                let tracking = segment.tracking?;
+                Some(TrackedAd {
+                    ad_id: tracking.ad.ad_id,
+                    creative_id: tracking.ad.creative_id,
+                    duration: f64::from(tracking.ad_duration),
+                    impression_urls: tracking
+                        .impression_urls
+                        .iter()
+                        .map(|url| macros::expand(url, &tracking.macros))
+                        .collect(),
+                    tracking_events: tracking
+                        .tracking_events
+                        .iter()
+                        .map(|event| TrackedEvent {
+                            event: event.event.clone(),
+                            url: macros::expand(&event.url, &tracking.macros),
+                        })
+                        .collect(),
+                })
+            });
+            let assets = decision.assets.into_iter().map(|creative| {
+                let ad = creative.ad.unwrap_or_default();
+                TrackedAd {
+                    ad_id: ad.ad_id,
+                    creative_id: ad.creative_id,
+                    duration: creative.duration,
+                    impression_urls: Vec::new(),
+                    tracking_events: Vec::new(),
+                }
+            });
+            TrackedBreak {
+                break_id,
+                ads: segments.chain(assets).collect(),
+            }
+        })
+        .collect();
+
+    Ok(Json(TrackingResponse { breaks }))
+}
+
+/// The session a stitch request belongs to, updated with its viewer context
+///
+/// Unknown sessions are created on first use, unless `REQUIRE_SESSIONS`
+/// only admits sessions created through `POST /sessions`.
+pub async fn join_session(
+    state: &AppState,
+    session_id: &str,
+    viewer: ViewerContext,
+) -> Result<Session> {
+    if !state.config.require_sessions {
+        return Ok(state
+            .sessions
+            .record_viewer(session_id, &state.config.origin_url, viewer)
+            .await);
+    }
+    match state.sessions.join(session_id, viewer).await {
+        Some(session) => Ok(session),
+        None => Err(reject(session_id)),
+    }
+}
+
+/// Reject segment requests of unknown sessions when sessions are required
+pub async fn check_session(state: &AppState, session_id: &str) -> Result<()> {
+    if state.config.require_sessions && state.sessions.get(session_id).await.is_none() {
+        return Err(reject(session_id));
+    }
+    Ok(())
+}
+
+fn reject(session_id: &str) -> RitcherError {
+    metrics::record_session("rejected");
+    RitcherError::InvalidSessionId(format!("unknown or expired session {}", session_id))
+}
+
+/// A new unguessable session id: 128 random bits, hex encoded
+fn new_session_id() -> Result<String> {
+    let mut bytes = [0u8; SESSION_ID_BYTES];
+    getrandom::fill(&mut bytes)
+        .map_err(|e| RitcherError::InternalError(format!("no randomness for session id: {e}")))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Channel names are short and URL-safe
+fn validate_channel(channel: &str) -> Result<()> {
+    let valid = !channel.is_empty()
+        && channel.len() <= MAX_CHANNEL_LEN
+        && channel
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(RitcherError::InvalidRequest(format!(
+            "channel must be 1-{} letters, digits, '-' or '_'",
+            MAX_CHANNEL_LEN
+        )))
+    }
+}
+
+/// Viewer context of a new session: the request's, overridden by the body
+///
+/// Unlike stitch URL parameters, which are silently dropped, invalid ad
+/// parameters and client IPs are rejected.
+fn session_viewer(
+    mut viewer: ViewerContext,
+    fields: ViewerFields,
+    ad_params: &HashMap<String, String>,
+    allowed_params: &[String],
+) -> Result<ViewerContext> {
+    if ad_params.len() > MAX_AD_PARAMS {
+        return Err(RitcherError::InvalidRequest(format!(
+            "at most {} ad parameters are accepted",
+            MAX_AD_PARAMS
+        )));
+    }
+    let prefixed: HashMap<String, String> = ad_params
+        .iter()
+        .map(|(name, value)| {
+            let name = if name.starts_with(AD_PARAM_PREFIX) {
+                name.clone()
+            } else {
+                format!("{}{}", AD_PARAM_PREFIX, name)
+            };
+            (name, value.clone())
+        })
+        .collect();
+    let selected = ViewerContext::select_ad_params(&prefixed, allowed_params);
+    if let Some(name) = prefixed.keys().find(|name| !selected.contains_key(*name)) {
+        return Err(RitcherError::InvalidRequest(format!(
+            "ad parameter {} is not accepted (allowed names, values up to {} bytes)",
+            name, MAX_AD_PARAM_LEN
+        )));
+    }
+
+    if let Some(ip) = &fields.client_ip
+        && ip.parse::<IpAddr>().is_err()
+    {
+        return Err(RitcherError::InvalidRequest(format!(
+            "client_ip {} is not an IP address",
+            ip
+        )));
+    }
+
+    viewer.merge(ViewerContext {
+        client_ip: fields.client_ip,
+        user_agent: fields.user_agent,
+        accept_language: fields.accept_language,
+        ad_params: selected,
+    });
+    Ok(viewer)
+}
+
+fn epoch_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_ids_are_random_hex() {
+        let first = new_session_id().unwrap();
+        assert_eq!(first.len(), SESSION_ID_BYTES * 2);
+        assert!(first.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(first, new_session_id().unwrap());
+    }
+
+    #[test]
+    fn test_validate_channel() {
+        assert!(validate_channel("news-24_hd").is_ok());
+        assert!(validate_channel("").is_err());
+        assert!(validate_channel("../admin").is_err());
+        assert!(validate_channel(&"a".repeat(MAX_CHANNEL_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_session_viewer_validates_and_overrides() {
+        let request = ViewerContext {
+            client_ip: Some("198.51.100.1".to_string()),
+            user_agent: Some("curl".to_string()),
+            ..Default::default()
+        };
+        let fields = ViewerFields {
+            client_ip: Some("203.0.113.9".to_string()),
+            ..Default::default()
+        };
+        let params = HashMap::from([
+            ("genre".to_string(), "news".to_string()),
+            ("ads.ifa".to_string(), "abc".to_string()),
+        ]);
+        let allowed = vec!["ads.genre".to_string(), "ads.ifa".to_string()];
+        let viewer = session_viewer(request.clone(), fields, &params, &allowed).unwrap();
+        assert_eq!(viewer.client_ip.as_deref(), Some("203.0.113.9"));
+        assert_eq!(viewer.user_agent.as_deref(), Some("curl"));
+        assert_eq!(viewer.ad_param("genre"), Some("news"));
+        assert_eq!(viewer.ad_param("ifa"), Some("abc"));
+
+        // Names outside AD_QUERY_PARAMS and malformed IPs are rejected
+        let allowed = vec!["ads.genre".to_string()];
+        assert!(
+            session_viewer(request.clone(), ViewerFields::default(), &params, &allowed).is_err()
+        );
+        assert!(session_viewer(request.clone(), ViewerFields::default(), &params, &[]).is_err());
+        let bad_ip = ViewerFields {
+            client_ip: Some("not-an-ip".to_string()),
+            ..Default::default()
+        };
+        assert!(session_viewer(request, bad_ip, &HashMap::new(), &[]).is_err());
+    }
+}
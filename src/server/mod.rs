@@ -1,9 +1,13 @@
 pub mod handlers;
 pub mod state;
 pub mod url_validation;
+pub mod viewer;
 
 use crate::config::Config;
-use axum::{Router, routing::get};
+use axum::{
+    Router,
+    routing::{get, post},
+};
 use metrics_exporter_prometheus::PrometheusBuilder;
 use state::AppState;
 use tower_http::cors::CorsLayer;
@@ -36,6 +40,46 @@ pub async fn build_router(config: Config) -> Router {
         }
     });
 
+    // Spawn background task for shared ad decision eviction
+    let cleanup_decisions = state.decisions.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            cleanup_decisions.cleanup();
+        }
+    });
+
+    // Spawn background task for in-band cue eviction
+    let cleanup_cues = state.inband_cues.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            cleanup_cues.cleanup();
+        }
+    });
+
+    // Spawn background task for stitched MPD history eviction
+    let cleanup_mpd_history = state.mpd_history.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            cleanup_mpd_history.cleanup();
+        }
+    });
+
+    // Spawn background task for resolved XLink Period eviction
+    let cleanup_xlink_periods = state.xlink_periods.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            cleanup_xlink_periods.cleanup();
+        }
+    });
+
     let cors = CorsLayer::very_permissive();
 
     Router::new()
@@ -50,6 +94,16 @@ pub async fn build_router(config: Config) -> Router {
             "/demo/manifest.mpd",
             get(handlers::demo::serve_demo_manifest),
         )
+        // Session API: server-issued sessions with playback URLs
+        .route("/sessions", post(handlers::session::create_session))
+        .route(
+            "/sessions/{session_id}",
+            get(handlers::session::session_report),
+        )
+        .route(
+            "/sessions/{session_id}/tracking",
+            get(handlers::session::session_tracking),
+        )
         // Stitcher endpoints
         .route(
             "/stitch/{session_id}/playlist.m3u8",
@@ -59,6 +113,10 @@ pub async fn build_router(config: Config) -> Router {
             "/stitch/{session_id}/manifest.mpd",
             get(handlers::manifest::serve_manifest),
         )
+        .route(
+            "/stitch/{session_id}/manifest.mpp",
+            get(handlers::manifest::serve_manifest_patch),
+        )
         .route(
             "/stitch/{session_id}/segment/{*segment_path}",
             get(handlers::segment::serve_segment),
@@ -121,6 +179,8 @@ pub async fn start(config: Config) -> Result<(), Box<dyn std::error::Error>> {
     );
 
     // Start serving with graceful shutdown
+    // Peer addresses identify viewers not behind a proxy
+    let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
     if let Err(e) = axum::serve(listener, app)
         .with_graceful_shutdown(shutdown_signal())
         .await
@@ -1,13 +1,53 @@
 use crate::{
-    ad::{AdProvider, SlateProvider, StaticAdProvider, VastAdProvider},
+    ad::{
+        AdNormalizer, AdProvider, AdSource, MultiSourceAdProvider, OpenRtbAdProvider,
+        OpenRtbBidder, SlateProvider, StaticAdProvider, VastAdProvider, decisioning::DecisionCache,
+        policy::AdPolicy, slate::SlateReason,
+    },
     config::{AdProviderType, Config, SessionStoreType},
+    dash::{patch::MpdHistory, xlink::RemotePeriodCache},
+    scte35::InbandCueStore,
+    server::url_validation::validate_origin_url,
     session::SessionManager,
 };
-use reqwest::Client;
+use reqwest::{Client, redirect};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tracing::info;
 
+#[cfg(feature = "valkey")]
+use crate::{config::ValkeyMode, session::ValkeyTopology};
+
+/// Valkey deployment to connect the session store to
+#[cfg(feature = "valkey")]
+fn valkey_topology(config: &Config) -> ValkeyTopology {
+    let url = config
+        .valkey_url
+        .as_deref()
+        .expect("VALKEY_URL is required when SESSION_STORE=valkey");
+    let urls = || {
+        url.split(',')
+            .map(str::trim)
+            .filter(|u| !u.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    match config.valkey_mode {
+        ValkeyMode::Standalone => ValkeyTopology::Standalone(url.to_string()),
+        ValkeyMode::Cluster => ValkeyTopology::Cluster(urls()),
+        ValkeyMode::Sentinel => ValkeyTopology::Sentinel {
+            sentinels: urls(),
+            master: config
+                .valkey_sentinel_master
+                .clone()
+                .expect("VALKEY_SENTINEL_MASTER is required when VALKEY_MODE=sentinel"),
+        },
+    }
+}
+
+/// Redirects followed when fetching an XLink Period
+const MAX_XLINK_REDIRECTS: usize = 5;
+
 /// Application state shared across all handlers
 #[derive(Clone)]
 pub struct AppState {
@@ -19,6 +59,16 @@ pub struct AppState {
     pub sessions: SessionManager,
     /// Ad provider for serving ad content (trait object for runtime flexibility)
     pub ad_provider: Arc<dyn AdProvider>,
+    /// Ad decisions per (session, break), shared by all renditions
+    pub decisions: DecisionCache,
+    /// In-band SCTE-35 cues seen in proxied segments, shared per stream
+    pub inband_cues: InbandCueStore,
+    /// Recently served stitched MPDs, for computing MPD patches
+    pub mpd_history: MpdHistory,
+    /// HTTP client for XLink Periods, following only SSRF-safe redirects
+    pub xlink_client: Client,
+    /// Remote XLink Periods resolved per URL, shared by all sessions
+    pub xlink_periods: RemotePeriodCache,
     /// Server start time for uptime tracking
     pub started_at: Instant,
 }
@@ -34,52 +84,176 @@ impl AppState {
             .build()
             .expect("Failed to create HTTP client");
 
+        // XLink hrefs come from the origin MPD: every redirect hop is
+        // validated like the href itself
+        let xlink_client = Client::builder()
+            .timeout(Duration::from_secs(30))
+            .connect_timeout(Duration::from_secs(5))
+            .redirect(redirect::Policy::custom(|attempt| {
+                if attempt.previous().len() >= MAX_XLINK_REDIRECTS {
+                    attempt.error("too many XLink redirects")
+                } else if validate_origin_url(attempt.url().as_str()).is_err() {
+                    attempt.error("XLink redirect to a disallowed URL")
+                } else {
+                    attempt.follow()
+                }
+            }))
+            .build()
+            .expect("Failed to create XLink HTTP client");
+
         let ttl = Duration::from_secs(config.session_ttl_secs);
         let sessions = match config.session_store {
             SessionStoreType::Memory => SessionManager::new_memory(ttl),
             #[cfg(feature = "valkey")]
-            SessionStoreType::Valkey => {
-                let url = config
-                    .valkey_url
-                    .as_deref()
-                    .expect("VALKEY_URL is required when SESSION_STORE=valkey");
-                SessionManager::new_valkey(url, ttl)
-                    .await
-                    .expect("Failed to connect to Valkey")
-            }
+            SessionStoreType::Valkey => SessionManager::new_valkey(&valkey_topology(&config), ttl)
+                .await
+                .expect("Failed to connect to Valkey"),
             #[cfg(not(feature = "valkey"))]
             SessionStoreType::Valkey => {
                 panic!("SESSION_STORE=valkey requires the 'valkey' feature flag");
             }
+            #[cfg(feature = "sqlite")]
+            SessionStoreType::Sqlite => SessionManager::new_sqlite(&config.session_db_path, ttl)
+                .expect("Failed to open the SQLite session store"),
+            #[cfg(not(feature = "sqlite"))]
+            SessionStoreType::Sqlite => {
+                panic!("SESSION_STORE=sqlite requires the 'sqlite' feature flag");
+            }
         };
 
+        // Progressive creatives are packaged by the ad normalizer, when configured
+        let normalizer = config.ad_normalizer_url.as_ref().map(|url| {
+            info!("Ad normalizer: enabled (endpoint: {})", url);
+            AdNormalizer::new(url.clone(), http_client.clone())
+        });
+
         // Create ad provider based on config
         let ad_provider: Arc<dyn AdProvider> = match config.ad_provider_type {
+            AdProviderType::Vast if !config.ad_sources.is_empty() => {
+                let sources = config
+                    .ad_sources
+                    .iter()
+                    .map(|source| {
+                        info!(
+                            "Ad source: {} (endpoint: {}, timeout: {}ms, weight: {})",
+                            source.name, source.url, source.timeout_ms, source.weight
+                        );
+                        let timeout = Duration::from_millis(source.timeout_ms);
+                        let mut provider =
+                            VastAdProvider::new(source.url.clone(), http_client.clone())
+                                .with_timeout(timeout)
+                                .with_pod_constraints(config.pod_constraints())
+                                .with_cache_limits(
+                                    config.ad_cache_ttl(),
+                                    config.ad_cache_max_creatives,
+                                );
+                        if let Some(normalizer) = &normalizer {
+                            provider = provider.with_normalizer(normalizer.clone());
+                        }
+                        AdSource {
+                            name: source.name.clone(),
+                            weight: source.weight,
+                            timeout,
+                            provider,
+                        }
+                    })
+                    .collect();
+                info!(
+                    "Ad provider: {} VAST sources ({:?})",
+                    config.ad_sources.len(),
+                    config.ad_source_strategy
+                );
+
+                let mut provider = MultiSourceAdProvider::new(sources, config.ad_source_strategy)
+                    .with_overrun_tolerance(config.pod_constraints().overrun_tolerance)
+                    .with_policy(AdPolicy::new(config.policy_rules(), sessions.clone()))
+                    .with_sessions(sessions.clone());
+                if let Some(slate) = load_slate(&config, &http_client).await {
+                    provider = provider.with_slate(slate);
+                }
+
+                Arc::new(provider)
+            }
             AdProviderType::Vast => {
                 let endpoint = config
                     .vast_endpoint
                     .as_deref()
-                    .expect("VAST_ENDPOINT is required when AD_PROVIDER_TYPE=vast");
+                    .expect("VAST_ENDPOINT or AD_SOURCES is required when AD_PROVIDER_TYPE=vast");
                 info!("Ad provider: VAST (endpoint: {})", endpoint);
 
-                let mut provider = VastAdProvider::new(endpoint.to_string(), http_client.clone());
+                let mut provider = VastAdProvider::new(endpoint.to_string(), http_client.clone())
+                    .with_pod_constraints(config.pod_constraints())
+                    .with_cache_limits(config.ad_cache_ttl(), config.ad_cache_max_creatives)
+                    .with_sessions(sessions.clone())
+                    .with_policy(AdPolicy::new(config.policy_rules(), sessions.clone()));
+                if let Some(normalizer) = normalizer {
+                    provider = provider.with_normalizer(normalizer);
+                }
 
                 // Configure slate fallback if SLATE_URL is set
-                if let Some(slate_url) = &config.slate_url {
-                    info!(
-                        "Slate fallback: enabled (url: {}, segment duration: {}s)",
-                        slate_url, config.slate_segment_duration
-                    );
-                    provider = provider.with_slate(SlateProvider::new(
-                        slate_url.clone(),
-                        config.slate_segment_duration,
-                    ));
-                } else {
-                    info!("Slate fallback: disabled (no SLATE_URL configured)");
+                if let Some(slate) = load_slate(&config, &http_client).await {
+                    provider = provider.with_slate(slate);
+                }
+
+                Arc::new(provider)
+            }
+            AdProviderType::OpenRtb => {
+                assert!(
+                    !config.openrtb_bidders.is_empty(),
+                    "OPENRTB_BIDDERS is required when AD_PROVIDER_TYPE=openrtb"
+                );
+                let bidders = config
+                    .openrtb_bidders
+                    .iter()
+                    .map(|bidder| {
+                        info!("OpenRTB bidder: {} (endpoint: {})", bidder.name, bidder.url);
+                        OpenRtbBidder {
+                            name: bidder.name.clone(),
+                            url: bidder.url.clone(),
+                        }
+                    })
+                    .collect();
+                info!(
+                    "Ad provider: OpenRTB ({} bidders, tmax: {}ms, floor: {} {})",
+                    config.openrtb_bidders.len(),
+                    config.openrtb_tmax_ms,
+                    config.openrtb_bid_floor,
+                    config.openrtb_currency
+                );
+
+                let mut provider = OpenRtbAdProvider::new(bidders, http_client.clone())
+                    .with_tmax(Duration::from_millis(config.openrtb_tmax_ms))
+                    .with_bid_floor(config.openrtb_bid_floor, config.openrtb_currency.clone())
+                    .with_pod_constraints(config.pod_constraints())
+                    .with_cache_limits(config.ad_cache_ttl(), config.ad_cache_max_creatives)
+                    .with_sessions(sessions.clone())
+                    .with_policy(AdPolicy::new(config.policy_rules(), sessions.clone()));
+                if let Some(domain) = url::Url::parse(&config.base_url)
+                    .ok()
+                    .and_then(|url| url.host_str().map(str::to_string))
+                {
+                    provider = provider.with_site_domain(domain);
+                }
+                if let Some(normalizer) = normalizer {
+                    provider = provider.with_normalizer(normalizer);
+                }
+                if let Some(slate) = load_slate(&config, &http_client).await {
+                    provider = provider.with_slate(slate);
                 }
 
                 Arc::new(provider)
             }
+            AdProviderType::Static if !config.ad_playlists.is_empty() => {
+                info!(
+                    "Ad provider: Static ({} ad playlist(s))",
+                    config.ad_playlists.len()
+                );
+                Arc::new(
+                    StaticAdProvider::load(&config.ad_playlists, &http_client)
+                        .await
+                        .expect("Failed to load AD_PLAYLISTS"),
+                )
+            }
             AdProviderType::Static => {
                 info!(
                     "Ad provider: Static (source: {}, segment duration: {}s)",
@@ -92,12 +266,50 @@ impl AppState {
             }
         };
 
+        let decisions = DecisionCache::new(ttl).with_sessions(sessions.clone());
+
         Self {
             config: Arc::new(config),
             http_client,
             sessions,
             ad_provider,
+            decisions,
+            inband_cues: InbandCueStore::new(),
+            mpd_history: MpdHistory::new(),
+            xlink_client,
+            xlink_periods: RemotePeriodCache::new(),
             started_at: Instant::now(),
         }
     }
 }
+
+/// Load the slate configured by SLATE_URL and its per-reason overrides
+async fn load_slate(config: &Config, http_client: &Client) -> Option<SlateProvider> {
+    let Some(slate_url) = &config.slate_url else {
+        info!("Slate fallback: disabled (no SLATE_URL configured)");
+        return None;
+    };
+    info!(
+        "Slate fallback: enabled (url: {}, segment duration: {}s)",
+        slate_url, config.slate_segment_duration
+    );
+    let mut slate = SlateProvider::load(slate_url, config.slate_segment_duration, http_client)
+        .await
+        .expect("Failed to load SLATE_URL");
+
+    let reason_urls = [
+        (SlateReason::NoFill, &config.slate_url_nofill),
+        (SlateReason::Error, &config.slate_url_error),
+        (SlateReason::Policy, &config.slate_url_policy),
+    ];
+    for (reason, url) in reason_urls {
+        if let Some(url) = url {
+            info!("Slate for {}: {}", reason.as_str(), url);
+            slate = slate
+                .load_reason_slate(reason, url, config.slate_segment_duration, http_client)
+                .await
+                .unwrap_or_else(|e| panic!("Failed to load slate for {}: {}", reason.as_str(), e));
+        }
+    }
+    Some(slate)
+}
@@ -0,0 +1,42 @@
+use crate::server::state::AppState;
+use crate::session::ViewerContext;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+/// Capture the viewer context of a playlist, manifest or asset-list request
+///
+/// The peer address is only known when the server is started with
+/// `into_make_service_with_connect_info`; `X-Forwarded-For` takes
+/// precedence either way.
+impl FromRequestParts<AppState> for ViewerContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let query: HashMap<String, String> = parts
+            .uri
+            .query()
+            .map(|q| {
+                url::form_urlencoded::parse(q.as_bytes())
+                    .into_owned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        Ok(ViewerContext::from_request(
+            &parts.headers,
+            &query,
+            peer,
+            &state.config.ad_query_params,
+        ))
+    }
+}
@@ -0,0 +1,158 @@
+use dash_mpd::MPD;
+use m3u8_rs::MasterPlaylist;
+use serde::{Deserialize, Serialize};
+
+/// Video renditions of the content a session stitches ads into
+///
+/// Captured from the master playlist or MPD so ad creatives can be
+/// conditioned against what the player is actually decoding.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ContentProfile {
+    /// Video codec families of the content (see [`codec_family`])
+    pub video_codecs: Vec<String>,
+    /// Resolutions of the content's video ladder
+    pub resolutions: Vec<(u32, u32)>,
+}
+
+impl ContentProfile {
+    /// Profile of an HLS master playlist's variant streams
+    pub fn from_hls_master(master: &MasterPlaylist) -> Self {
+        let mut profile = Self::default();
+        for variant in master.variants.iter().filter(|v| !v.is_i_frame) {
+            if let Some(codecs) = &variant.codecs {
+                profile.add_codecs(codecs);
+            }
+            if let Some(resolution) = &variant.resolution {
+                profile.add_resolution(resolution.width, resolution.height);
+            }
+        }
+        profile
+    }
+
+    /// Profile of an MPD's video Representations
+    pub fn from_mpd(mpd: &MPD) -> Self {
+        let mut profile = Self::default();
+        for adaptation in mpd.periods.iter().flat_map(|p| &p.adaptations) {
+            let is_video = adaptation.contentType.as_deref() == Some("video")
+                || adaptation
+                    .mimeType
+                    .as_deref()
+                    .is_some_and(|m| m.starts_with("video/"));
+            for representation in &adaptation.representations {
+                let video = is_video
+                    || representation
+                        .mimeType
+                        .as_deref()
+                        .is_some_and(|m| m.starts_with("video/"));
+                if !video {
+                    continue;
+                }
+                if let Some(codecs) = representation
+                    .codecs
+                    .as_ref()
+                    .or(adaptation.codecs.as_ref())
+                {
+                    profile.add_codecs(codecs);
+                }
+                let width = representation.width.or(adaptation.width);
+                let height = representation.height.or(adaptation.height);
+                if let (Some(width), Some(height)) = (width, height) {
+                    profile.add_resolution(width, height);
+                }
+            }
+        }
+        profile
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.video_codecs.is_empty() && self.resolutions.is_empty()
+    }
+
+    /// Add the video codecs of a CODECS / `@codecs` list
+    fn add_codecs(&mut self, codecs: &str) {
+        for family in codecs.split(',').filter_map(codec_family) {
+            if !self.video_codecs.iter().any(|c| c == family) {
+                self.video_codecs.push(family.to_string());
+            }
+        }
+    }
+
+    fn add_resolution(&mut self, width: u64, height: u64) {
+        let resolution = (width as u32, height as u32);
+        if !self.resolutions.contains(&resolution) {
+            self.resolutions.push(resolution);
+        }
+    }
+}
+
+/// Video codec family of an RFC 6381 codec string or a VAST codec name
+///
+/// Returns `None` for audio and unknown codecs.
+pub fn codec_family(codec: &str) -> Option<&'static str> {
+    let codec = codec.trim().to_lowercase();
+    let fourcc = codec.split('.').next().unwrap_or_default();
+    match fourcc {
+        "avc1" | "avc3" | "h264" | "avc" => Some("avc"),
+        "hvc1" | "hev1" | "h265" | "hevc" => Some("hevc"),
+        "av01" | "av1" => Some("av1"),
+        "vp09" | "vp9" => Some("vp9"),
+        "vp8" => Some("vp8"),
+        _ if codec == "h.264" => Some("avc"),
+        _ if codec == "h.265" => Some("hevc"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_from_hls_master() {
+        let master = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=2000000,RESOLUTION=1280x720,CODECS=\"avc1.64001f,mp4a.40.2\"
+720p.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=5000000,RESOLUTION=1920x1080,CODECS=\"avc1.640028,mp4a.40.2\"
+1080p.m3u8
+#EXT-X-I-FRAME-STREAM-INF:BANDWIDTH=200000,RESOLUTION=640x360,CODECS=\"hvc1.1.6.L93.B0\",URI=\"iframe.m3u8\"
+";
+        let Ok((_, m3u8_rs::Playlist::MasterPlaylist(master))) =
+            m3u8_rs::parse_playlist(master.as_bytes())
+        else {
+            panic!("expected a master playlist");
+        };
+        let profile = ContentProfile::from_hls_master(&master);
+        assert_eq!(profile.video_codecs, vec!["avc"]);
+        assert_eq!(profile.resolutions, vec![(1280, 720), (1920, 1080)]);
+    }
+
+    #[test]
+    fn test_profile_from_mpd_ignores_audio() {
+        let mpd = crate::dash::parser::parse_mpd(
+            r#"<?xml version="1.0"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static" mediaPresentationDuration="PT30S">
+  <Period id="p0">
+    <AdaptationSet contentType="video" mimeType="video/mp4" codecs="hev1.1.6.L120.90">
+      <Representation id="v1" bandwidth="3000000" width="1920" height="1080"/>
+      <Representation id="v2" bandwidth="1000000" width="960" height="540"/>
+    </AdaptationSet>
+    <AdaptationSet contentType="audio" mimeType="audio/mp4">
+      <Representation id="a1" bandwidth="128000" codecs="mp4a.40.2"/>
+    </AdaptationSet>
+  </Period>
+</MPD>"#,
+        )
+        .unwrap();
+        let profile = ContentProfile::from_mpd(&mpd);
+        assert_eq!(profile.video_codecs, vec!["hevc"]);
+        assert_eq!(profile.resolutions, vec![(1920, 1080), (960, 540)]);
+    }
+
+    #[test]
+    fn test_codec_family() {
+        assert_eq!(codec_family("avc1.64001f"), Some("avc"));
+        assert_eq!(codec_family("H.264"), Some("avc"));
+        assert_eq!(codec_family("hvc1.1.6.L93.B0"), Some("hevc"));
+        assert_eq!(codec_family("mp4a.40.2"), None);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::ad::provider::{AdCreative, AdSegment, ResolvedSegment};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+/// Ads decided for one break of a session
+///
+/// Stored with the session so whichever instance receives a segment or
+/// asset-list request serves the same ads and fires tracking only once.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BreakDecision {
+    pub decided_at: SystemTime,
+    /// SSAI segments by index: source URL and tracking context
+    #[serde(default)]
+    pub segments: Vec<ResolvedSegment>,
+    /// SGAI creatives of the break's asset list
+    #[serde(default)]
+    pub assets: Vec<AdCreative>,
+    /// SSAI segments as published in manifests, slate included
+    #[serde(default)]
+    pub published: Vec<AdSegment>,
+}
+
+impl BreakDecision {
+    /// Decision of an SSAI break, served segment by segment
+    pub fn segments(segments: Vec<ResolvedSegment>) -> Self {
+        Self {
+            decided_at: SystemTime::now(),
+            segments,
+            assets: Vec::new(),
+            published: Vec::new(),
+        }
+    }
+
+    /// Decision of an SGAI break, served as an asset list
+    pub fn assets(assets: Vec<AdCreative>) -> Self {
+        Self {
+            decided_at: SystemTime::now(),
+            segments: Vec::new(),
+            assets,
+            published: Vec::new(),
+        }
+    }
+
+    /// Attach the segments published for the break
+    ///
+    /// Tracking stays with `segments`; manifests only need names and
+    /// durations.
+    pub fn with_published(mut self, published: &[AdSegment]) -> Self {
+        self.published = published
+            .iter()
+            .map(|segment| AdSegment {
+                tracking: None,
+                ..segment.clone()
+            })
+            .collect();
+        self
+    }
+
+    /// Number of creatives the decision holds
+    pub fn len(&self) -> usize {
+        self.segments.len() + self.assets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the decision is older than `max_age`
+    pub fn is_expired(&self, max_age: Duration) -> bool {
+        self.decided_at
+            .elapsed()
+            .is_ok_and(|elapsed| elapsed >= max_age)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ad::provider::AdTrackingInfo;
+
+    #[test]
+    fn test_decision_survives_the_store() {
+        let segment = |url: &str| ResolvedSegment {
+            url: url.to_string(),
+            tracking: Some(AdTrackingInfo::default()),
+        };
+        let decision = BreakDecision::segments(vec![segment("a.ts"), segment("b.ts")]);
+
+        let json = serde_json::to_string(&decision).unwrap();
+        let restored: BreakDecision = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, decision);
+        assert!(!restored.is_expired(Duration::from_secs(60)));
+        assert!(restored.is_expired(Duration::ZERO));
+    }
+}
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, SystemTime};
+
+/// Ads decided for a session, for frequency capping and separation
+///
+/// Entries are policy keys (see [`crate::ad::policy`]): one per creative,
+/// plus advertiser and category keys when those are kept apart.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AdHistory {
+    /// Policy keys of the session's most recent pods, oldest first
+    pub recent_pods: VecDeque<Vec<String>>,
+    /// How often each creative was decided in the session
+    pub creative_counts: BTreeMap<String, u32>,
+}
+
+impl AdHistory {
+    /// Remember a decided pod, keeping the `keep` most recent pods
+    pub fn record_pod(&mut self, pod_keys: Vec<String>, creatives: &[String], keep: usize) {
+        for creative in creatives {
+            *self.creative_counts.entry(creative.clone()).or_default() += 1;
+        }
+        self.recent_pods.push_back(pod_keys);
+        while self.recent_pods.len() > keep {
+            self.recent_pods.pop_front();
+        }
+    }
+}
+
+/// Creatives decided for one device or household within a capping window
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct DeviceAdCounts {
+    pub counts: HashMap<String, u32>,
+    pub expires_at: SystemTime,
+}
+
+impl DeviceAdCounts {
+    /// Start a window of `window` from now
+    pub fn new(window: Duration) -> Self {
+        Self {
+            counts: HashMap::new(),
+            expires_at: SystemTime::now() + window,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        SystemTime::now() >= self.expires_at
+    }
+
+    /// Time left in the window, the TTL of the stored counts
+    pub fn remaining(&self) -> Duration {
+        self.expires_at
+            .duration_since(SystemTime::now())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_pod_counts_creatives_and_keeps_recent_pods() {
+        let mut history = AdHistory::default();
+        history.record_pod(vec!["a".into()], &["a".into()], 2);
+        history.record_pod(vec!["b".into()], &["b".into()], 2);
+        history.record_pod(vec!["a".into()], &["a".into()], 2);
+
+        assert_eq!(history.creative_counts["a"], 2);
+        assert_eq!(history.recent_pods, [vec!["b"], vec!["a"]]);
+    }
+}
@@ -1,13 +1,36 @@
-use dashmap::DashMap;
+use crate::ad::provider::ResolvedSegment;
+use crate::session::history::{AdHistory, DeviceAdCounts};
+use crate::session::store::{MemoryStore, SessionStore};
+use crate::session::{BreakDecision, ContentProfile, ViewerContext};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
+use tracing::{error, warn};
 
+#[cfg(any(feature = "valkey", feature = "sqlite"))]
+use crate::error::Result;
+#[cfg(feature = "sqlite")]
+use crate::session::sqlite::SqliteStore;
 #[cfg(feature = "valkey")]
-use tracing::{error, info};
+use crate::session::valkey::{ValkeyStore, ValkeyTopology};
 
-#[cfg(feature = "valkey")]
-use redis::aio::ConnectionManager;
+/// Store key prefix of sessions
+const SESSION_KEY_PREFIX: &str = "session:";
+
+/// Store key prefix of cross-session ad counts
+const DEVICE_KEY_PREFIX: &str = "freq:";
+
+/// Store key prefix of the segments delivered from a session's break
+const DELIVERED_KEY_PREFIX: &str = "delivered:";
+
+/// Attempts of a read-modify-write before giving up on contention
+const MAX_UPDATE_ATTEMPTS: usize = 8;
+
+/// Requests that bring nothing new only refresh a session idle for this
+/// fraction of its TTL, so most requests need no write
+const TOUCH_INTERVAL_DIVISOR: u32 = 10;
 
 /// Session data stored for each active session
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -18,9 +41,76 @@ pub struct Session {
     pub created_at: SystemTime,
     #[serde(with = "epoch_secs")]
     pub last_accessed: SystemTime,
+    /// Viewer context captured from the session's requests
+    #[serde(default)]
+    pub viewer: ViewerContext,
+    /// Ads decided for the session, for frequency caps and separation
+    #[serde(default)]
+    pub ad_history: AdHistory,
+    /// Video renditions of the session's content, for ad conditioning
+    #[serde(default)]
+    pub content: ContentProfile,
+    /// Ads decided for the session's breaks, by break id
+    #[serde(default)]
+    pub ad_breaks: BTreeMap<String, BreakDecision>,
+    /// Channel the session was created for, if any
+    #[serde(default)]
+    pub channel: Option<String>,
+}
+
+impl Session {
+    /// A new session of `origin_url`, accessed now
+    pub fn new(session_id: String, origin_url: String) -> Self {
+        let now = SystemTime::now();
+        Self {
+            session_id,
+            origin_url,
+            created_at: now,
+            last_accessed: now,
+            viewer: ViewerContext::default(),
+            ad_history: AdHistory::default(),
+            content: ContentProfile::default(),
+            ad_breaks: BTreeMap::new(),
+            channel: None,
+        }
+    }
+
+    /// Whether the session went unused for `ttl`
+    pub fn is_expired(&self, ttl: Duration) -> bool {
+        SystemTime::now()
+            .duration_since(self.last_accessed)
+            .is_ok_and(|idle| idle >= ttl)
+    }
+
+    /// Merge a request's viewer context and mark the session accessed
+    ///
+    /// Returns whether the session changed: a request with no new viewer
+    /// context only refreshes a session idle for at least `touch_interval`.
+    fn visit(&mut self, viewer: ViewerContext, touch_interval: Duration) -> bool {
+        let mut merged = self.viewer.clone();
+        merged.merge(viewer);
+        let idle = SystemTime::now()
+            .duration_since(self.last_accessed)
+            .unwrap_or_default();
+        if merged == self.viewer && idle < touch_interval {
+            return false;
+        }
+        self.viewer = merged;
+        self.last_accessed = SystemTime::now();
+        true
+    }
+
+    /// Store a break's decision, dropping decisions older than `max_age`
+    fn record_break(&mut self, break_id: &str, decision: BreakDecision, max_age: Duration) {
+        self.ad_breaks
+            .retain(|_, decision| !decision.is_expired(max_age));
+        self.ad_breaks.insert(break_id.to_string(), decision);
+    }
 }
 
-/// Serde helper: SystemTime ↔ u64 epoch seconds
+/// Serde helper: SystemTime ↔ epoch seconds, with sub-second precision
+///
+/// Whole seconds, as written by earlier versions, are read as well.
 mod epoch_secs {
     use serde::{Deserialize, Deserializer, Serializer};
     use std::time::{Duration, SystemTime, UNIX_EPOCH};
@@ -32,258 +122,467 @@ mod epoch_secs {
         let secs = time
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
-            .as_secs();
-        serializer.serialize_u64(secs)
+            .as_secs_f64();
+        serializer.serialize_f64(secs)
     }
 
     pub fn deserialize<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
     where
         D: Deserializer<'de>,
     {
-        let secs = u64::deserialize(deserializer)?;
-        Ok(UNIX_EPOCH + Duration::from_secs(secs))
+        let secs = f64::deserialize(deserializer)?;
+        Ok(UNIX_EPOCH + Duration::try_from_secs_f64(secs).unwrap_or_default())
     }
 }
 
-/// Internal storage backend
-#[derive(Clone)]
-enum Backend {
-    Memory {
-        sessions: Arc<DashMap<String, Session>>,
-    },
-    #[cfg(feature = "valkey")]
-    Valkey {
-        conn: ConnectionManager,
-        key_prefix: String,
-    },
+/// Key of a session in the store
+fn session_key(session_id: &str) -> String {
+    format!("{}{}", SESSION_KEY_PREFIX, session_id)
+}
+
+/// Key of a device's cross-session ad counts in the store
+fn device_key(device_id: &str) -> String {
+    format!("{}{}", DEVICE_KEY_PREFIX, device_id)
 }
 
-/// Session manager — same public API regardless of backend
+/// Key of the segments delivered from one of a session's breaks
+fn delivered_key(session_id: &str, break_id: &str) -> String {
+    format!("{}{}:{}", DELIVERED_KEY_PREFIX, session_id, break_id)
+}
+
+/// Session manager — same public API regardless of store
 #[derive(Clone)]
 pub struct SessionManager {
-    backend: Backend,
+    store: Arc<dyn SessionStore>,
     ttl: Duration,
 }
 
 impl SessionManager {
+    /// Create a session manager over any [`SessionStore`]
+    pub fn new(store: Arc<dyn SessionStore>, ttl: Duration) -> Self {
+        Self { store, ttl }
+    }
+
     /// Create an in-memory session manager (default)
     pub fn new_memory(ttl: Duration) -> Self {
-        Self {
-            backend: Backend::Memory {
-                sessions: Arc::new(DashMap::new()),
-            },
-            ttl,
-        }
+        Self::new(Arc::new(MemoryStore::new()), ttl)
     }
 
     /// Create a Valkey-backed session manager
     #[cfg(feature = "valkey")]
-    pub async fn new_valkey(url: &str, ttl: Duration) -> Result<Self, redis::RedisError> {
-        let client = redis::Client::open(url)?;
-        let conn = ConnectionManager::new(client).await?;
-        info!("Connected to Valkey at {}", url);
-        Ok(Self {
-            backend: Backend::Valkey {
-                conn,
-                key_prefix: "ritcher:session".to_string(),
-            },
+    pub async fn new_valkey(topology: &ValkeyTopology, ttl: Duration) -> Result<Self> {
+        Ok(Self::new(
+            Arc::new(ValkeyStore::connect(topology).await?),
             ttl,
-        })
+        ))
     }
 
-    /// Get or create a session
-    pub async fn get_or_create(&self, session_id: String, origin_url: String) -> Session {
-        match &self.backend {
-            Backend::Memory { sessions } => sessions
-                .entry(session_id.clone())
-                .or_insert_with(|| {
-                    let now = SystemTime::now();
-                    Session {
-                        session_id: session_id.clone(),
-                        origin_url,
-                        created_at: now,
-                        last_accessed: now,
-                    }
-                })
-                .clone(),
-            #[cfg(feature = "valkey")]
-            Backend::Valkey { conn, key_prefix } => {
-                let key = format!("{}:{}", key_prefix, session_id);
-                let mut conn = conn.clone();
-                // Try to get existing session
-                if let Ok(Some(json)) = redis::cmd("GET")
-                    .arg(&key)
-                    .query_async::<Option<String>>(&mut conn)
-                    .await
-                {
-                    if let Ok(session) = serde_json::from_str::<Session>(&json) {
-                        return session;
-                    }
+    /// Create a session manager persisted in a SQLite database
+    #[cfg(feature = "sqlite")]
+    pub fn new_sqlite(path: &str, ttl: Duration) -> Result<Self> {
+        Ok(Self::new(Arc::new(SqliteStore::open(path)?), ttl))
+    }
+
+    /// Read-modify-write a stored value
+    ///
+    /// `apply` gets the current value (`None` if absent) and returns its
+    /// result and whether it changed the value. Changed values are written
+    /// with compare-and-swap, retrying from a fresh read when another
+    /// writer got there first. Returns `None` if the store failed or holds
+    /// a value that does not deserialize, which is never overwritten.
+    async fn update<T, R>(
+        &self,
+        key: &str,
+        ttl: impl Fn(&T) -> Duration,
+        mut apply: impl FnMut(&mut Option<T>) -> (R, bool),
+    ) -> Option<R>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let current = match self.store.get(key).await {
+                Ok(current) => current,
+                Err(e) => {
+                    error!("Failed to read {}: {}", key, e);
+                    return None;
                 }
-                // Create new session
-                let now = SystemTime::now();
-                let session = Session {
-                    session_id: session_id.clone(),
-                    origin_url,
-                    created_at: now,
-                    last_accessed: now,
-                };
-                if let Ok(json) = serde_json::to_string(&session) {
-                    let ttl_secs = self.ttl.as_secs();
-                    if let Err(e) = redis::cmd("SET")
-                        .arg(&key)
-                        .arg(&json)
-                        .arg("EX")
-                        .arg(ttl_secs)
-                        .query_async::<()>(&mut conn)
-                        .await
-                    {
-                        error!("Failed to store session in Valkey: {}", e);
-                    }
+            };
+            let version = current.as_ref().map(|c| c.version);
+            let parsed = current.map(|c| serde_json::from_str(&c.value)).transpose();
+            let mut value = match parsed {
+                Ok(value) => value,
+                Err(e) => {
+                    error!("Failed to deserialize {}, leaving it unchanged: {}", key, e);
+                    return None;
+                }
+            };
+            let (result, changed) = apply(&mut value);
+            let Some(value) = value.filter(|_| changed) else {
+                return Some(result);
+            };
+            let json = match serde_json::to_string(&value) {
+                Ok(json) => json,
+                Err(e) => {
+                    error!("Failed to serialize {}: {}", key, e);
+                    return None;
+                }
+            };
+            match self
+                .store
+                .compare_and_swap(key, version, json, ttl(&value))
+                .await
+            {
+                Ok(true) => return Some(result),
+                Ok(false) => continue,
+                Err(e) => {
+                    error!("Failed to write {}: {}", key, e);
+                    return None;
                 }
-                session
             }
         }
+        warn!(
+            "Gave up writing {} after {} conflicting updates",
+            key, MAX_UPDATE_ATTEMPTS
+        );
+        None
     }
 
-    /// Update last accessed time for a session
-    pub async fn touch(&self, session_id: &str) {
-        match &self.backend {
-            Backend::Memory { sessions } => {
-                if let Some(mut session) = sessions.get_mut(session_id) {
-                    session.last_accessed = SystemTime::now();
+    /// Read-modify-write a session; expired sessions read as absent
+    async fn update_session<R>(
+        &self,
+        session_id: &str,
+        mut apply: impl FnMut(&mut Option<Session>) -> (R, bool),
+    ) -> Option<R> {
+        self.update(
+            &session_key(session_id),
+            |_| self.ttl,
+            |session: &mut Option<Session>| {
+                if session.as_ref().is_some_and(|s| s.is_expired(self.ttl)) {
+                    *session = None;
                 }
+                apply(session)
+            },
+        )
+        .await
+    }
+
+    /// Get or create a session
+    pub async fn get_or_create(&self, session_id: String, origin_url: String) -> Session {
+        let new_session = || Session::new(session_id.clone(), origin_url.clone());
+        self.update_session(&session_id, |session| match session {
+            Some(session) => (session.clone(), false),
+            None => (session.insert(new_session()).clone(), true),
+        })
+        .await
+        .unwrap_or_else(new_session)
+    }
+
+    /// Record a request's viewer context, creating the session if needed
+    ///
+    /// The context is merged into what earlier requests captured (see
+    /// [`ViewerContext::merge`]) and the session is touched. The session is
+    /// only written when the context changed or the session needs touching
+    /// to stay alive. Returns the updated session.
+    pub async fn record_viewer(
+        &self,
+        session_id: &str,
+        origin_url: &str,
+        viewer: ViewerContext,
+    ) -> Session {
+        let new_session = || Session::new(session_id.to_string(), origin_url.to_string());
+        let touch_interval = self.touch_interval();
+        self.update_session(session_id, |session| match session {
+            Some(session) => {
+                let changed = session.visit(viewer.clone(), touch_interval);
+                (session.clone(), changed)
             }
-            #[cfg(feature = "valkey")]
-            Backend::Valkey { conn, key_prefix } => {
-                let key = format!("{}:{}", key_prefix, session_id);
-                let mut conn = conn.clone();
-                let json: Option<String> =
-                    match redis::cmd("GET").arg(&key).query_async(&mut conn).await {
-                        Ok(v) => v,
-                        Err(e) => {
-                            error!("Valkey GET failed in touch: {}", e);
-                            return;
-                        }
-                    };
-                if let Some(json) = json {
-                    if let Ok(mut session) = serde_json::from_str::<Session>(&json) {
-                        session.last_accessed = SystemTime::now();
-                        if let Ok(updated) = serde_json::to_string(&session) {
-                            let ttl_secs = self.ttl.as_secs();
-                            if let Err(e) = redis::cmd("SET")
-                                .arg(&key)
-                                .arg(&updated)
-                                .arg("EX")
-                                .arg(ttl_secs)
-                                .query_async::<()>(&mut conn)
-                                .await
-                            {
-                                error!("Valkey SET failed in touch: {}", e);
-                            }
-                        }
-                    }
+            None => {
+                let session = session.insert(new_session());
+                session.visit(viewer.clone(), touch_interval);
+                (session.clone(), true)
+            }
+        })
+        .await
+        .unwrap_or_else(|| {
+            let mut session = new_session();
+            session.visit(viewer, touch_interval);
+            session
+        })
+    }
+
+    /// Idle time after which a request touches an otherwise unchanged session
+    fn touch_interval(&self) -> Duration {
+        self.ttl / TOUCH_INTERVAL_DIVISOR
+    }
+
+    /// Store a newly created session, such as one issued by the session API
+    pub async fn create(&self, session: Session) {
+        let key = session_key(&session.session_id);
+        match serde_json::to_string(&session) {
+            Ok(json) => {
+                if let Err(e) = self.store.put(&key, json, self.ttl).await {
+                    error!("Failed to store session {}: {}", session.session_id, e);
                 }
             }
+            Err(e) => error!("Failed to serialize session: {}", e),
         }
     }
 
-    /// Get a session by ID
-    pub async fn get(&self, session_id: &str) -> Option<Session> {
-        match &self.backend {
-            Backend::Memory { sessions } => sessions.get(session_id).map(|s| s.clone()),
-            #[cfg(feature = "valkey")]
-            Backend::Valkey { conn, key_prefix } => {
-                let key = format!("{}:{}", key_prefix, session_id);
-                let mut conn = conn.clone();
-                match redis::cmd("GET")
-                    .arg(&key)
-                    .query_async::<Option<String>>(&mut conn)
-                    .await
-                {
-                    Ok(Some(json)) => serde_json::from_str(&json).ok(),
-                    Ok(None) => None,
-                    Err(e) => {
-                        error!("Valkey GET failed: {}", e);
-                        None
-                    }
-                }
+    /// Record a request's viewer context in an existing session
+    ///
+    /// Like [`record_viewer`](Self::record_viewer), but unknown and expired
+    /// sessions are not created: `None` is returned instead.
+    pub async fn join(&self, session_id: &str, viewer: ViewerContext) -> Option<Session> {
+        let touch_interval = self.touch_interval();
+        self.update_session(session_id, |session| match session {
+            Some(session) => {
+                let changed = session.visit(viewer.clone(), touch_interval);
+                (Some(session.clone()), changed)
             }
+            None => (None, false),
+        })
+        .await
+        .flatten()
+    }
+
+    /// Record the content profile of a session's master playlist or MPD
+    ///
+    /// Empty profiles and unknown sessions are ignored.
+    pub async fn record_content(&self, session_id: &str, content: ContentProfile) {
+        if content.is_empty() {
+            return;
         }
+        self.update_session(session_id, |session| match session {
+            Some(session) if session.content != content => {
+                session.content = content.clone();
+                ((), true)
+            }
+            _ => ((), false),
+        })
+        .await;
     }
 
-    /// Remove expired sessions (no-op for Valkey — TTL is native)
-    pub async fn cleanup_expired(&self) {
-        match &self.backend {
-            Backend::Memory { sessions } => {
-                let now = SystemTime::now();
-                sessions.retain(|_, session| {
-                    if let Ok(elapsed) = now.duration_since(session.last_accessed) {
-                        elapsed < self.ttl
-                    } else {
-                        true
-                    }
+    /// Ads decided so far in a session (empty for unknown sessions)
+    pub async fn ad_history(&self, session_id: &str) -> AdHistory {
+        self.get(session_id)
+            .await
+            .map(|session| session.ad_history)
+            .unwrap_or_default()
+    }
+
+    /// Record a decided pod in the session's ad history
+    ///
+    /// `pod_keys` are kept for the `keep` most recent pods; `creatives` are
+    /// counted against the session frequency cap. Unknown sessions are
+    /// ignored.
+    pub async fn record_ad_pod(
+        &self,
+        session_id: &str,
+        pod_keys: Vec<String>,
+        creatives: &[String],
+        keep: usize,
+    ) {
+        self.update_session(session_id, |session| match session {
+            Some(session) => {
+                session
+                    .ad_history
+                    .record_pod(pod_keys.clone(), creatives, keep);
+                ((), true)
+            }
+            None => ((), false),
+        })
+        .await;
+    }
+
+    /// Store the ads decided for one of a session's breaks, unless the
+    /// break already has a decision
+    ///
+    /// The first decision stored wins, so every instance serves the same
+    /// ads. Returns the earlier decision when there is one (`decision` is
+    /// then dropped). Decisions older than `max_age` are dropped. Unknown
+    /// sessions are ignored.
+    pub async fn record_break_decision(
+        &self,
+        session_id: &str,
+        break_id: &str,
+        decision: BreakDecision,
+        max_age: Duration,
+    ) -> Option<BreakDecision> {
+        self.update_session(session_id, |session| {
+            let Some(session) = session else {
+                return (None, false);
+            };
+            let earlier = session
+                .ad_breaks
+                .get(break_id)
+                .filter(|earlier| !earlier.is_expired(max_age));
+            match earlier {
+                Some(earlier) => (Some(earlier.clone()), false),
+                None => {
+                    session.record_break(break_id, decision.clone(), max_age);
+                    (None, true)
+                }
+            }
+        })
+        .await
+        .flatten()
+    }
+
+    /// The ads decided for one of a session's breaks
+    pub async fn break_decision(&self, session_id: &str, break_id: &str) -> Option<BreakDecision> {
+        self.get(session_id)
+            .await
+            .and_then(|mut session| session.ad_breaks.remove(break_id))
+    }
+
+    /// Resolve a segment of a decided break and mark it delivered
+    ///
+    /// Tracking is only returned the first time a segment is delivered, by
+    /// whichever instance serves it. Deliveries are recorded under their own
+    /// key, so the segment is served even when recording fails — without
+    /// tracking, rather than risk firing it twice.
+    pub async fn deliver_ad_segment(
+        &self,
+        session_id: &str,
+        break_id: &str,
+        index: usize,
+    ) -> Option<ResolvedSegment> {
+        let mut session = self.get(session_id).await?;
+        let segment = session
+            .ad_breaks
+            .remove(break_id)?
+            .segments
+            .into_iter()
+            .nth(index)?;
+        let first = segment.tracking.is_some()
+            && self
+                .update(
+                    &delivered_key(session_id, break_id),
+                    |_| self.ttl,
+                    |delivered: &mut Option<BTreeSet<usize>>| {
+                        let first = delivered.get_or_insert_default().insert(index);
+                        (first, first)
+                    },
+                )
+                .await
+                .unwrap_or_else(|| {
+                    warn!(
+                        "Serving segment {} of break {} without tracking: delivery not recorded for session {}",
+                        index, break_id, session_id
+                    );
+                    false
                 });
+        Some(ResolvedSegment {
+            url: segment.url,
+            tracking: segment.tracking.filter(|_| first),
+        })
+    }
+
+    /// Indices of the segments delivered from one of a session's breaks
+    pub async fn delivered_segments(&self, session_id: &str, break_id: &str) -> BTreeSet<usize> {
+        let key = delivered_key(session_id, break_id);
+        match self.store.get(&key).await {
+            Ok(current) => current
+                .and_then(|c| serde_json::from_str(&c.value).ok())
+                .unwrap_or_default(),
+            Err(e) => {
+                error!("Failed to read {}: {}", key, e);
+                BTreeSet::new()
             }
-            #[cfg(feature = "valkey")]
-            Backend::Valkey { .. } => {
-                // Valkey handles TTL natively via EXPIRE — nothing to do
+        }
+    }
+
+    /// Creatives decided for a device or household in its current window
+    pub async fn device_ad_counts(&self, device_id: &str) -> HashMap<String, u32> {
+        let key = device_key(device_id);
+        match self.store.get(&key).await {
+            Ok(current) => current
+                .and_then(|c| serde_json::from_str::<DeviceAdCounts>(&c.value).ok())
+                .filter(|d| !d.is_expired())
+                .map(|d| d.counts)
+                .unwrap_or_default(),
+            Err(e) => {
+                error!("Failed to read {}: {}", key, e);
+                HashMap::new()
             }
         }
     }
 
-    /// Get the count of active sessions
-    pub async fn session_count(&self) -> usize {
-        match &self.backend {
-            Backend::Memory { sessions } => sessions.len(),
-            #[cfg(feature = "valkey")]
-            Backend::Valkey { conn, key_prefix } => {
-                let pattern = format!("{}:*", key_prefix);
-                let mut conn = conn.clone();
-                // NOTE: KEYS is O(N) — acceptable for health endpoint at current scale.
-                // Replace with SCAN or atomic counter if session volume exceeds ~10k.
-                match redis::cmd("KEYS")
-                    .arg(&pattern)
-                    .query_async::<Vec<String>>(&mut conn)
-                    .await
-                {
-                    Ok(keys) => keys.len(),
-                    Err(e) => {
-                        error!("Valkey KEYS failed in session_count: {}", e);
-                        0
-                    }
+    /// Count decided creatives against a device or household
+    ///
+    /// Counts are shared by all of the device's sessions and reset `window`
+    /// after the first counted creative.
+    pub async fn count_device_ads(&self, device_id: &str, creatives: &[String], window: Duration) {
+        self.update(
+            &device_key(device_id),
+            DeviceAdCounts::remaining,
+            |entry: &mut Option<DeviceAdCounts>| {
+                let counts = match entry {
+                    Some(counts) if !counts.is_expired() => counts,
+                    _ => entry.insert(DeviceAdCounts::new(window)),
+                };
+                for creative in creatives {
+                    *counts.counts.entry(creative.clone()).or_default() += 1;
                 }
+                ((), true)
+            },
+        )
+        .await;
+    }
+
+    /// Update last accessed time for a session
+    pub async fn touch(&self, session_id: &str) {
+        self.update_session(session_id, |session| match session {
+            Some(session) => {
+                session.last_accessed = SystemTime::now();
+                ((), true)
+            }
+            None => ((), false),
+        })
+        .await;
+    }
+
+    /// Get a session by ID (expired sessions are not returned)
+    pub async fn get(&self, session_id: &str) -> Option<Session> {
+        let key = session_key(session_id);
+        match self.store.get(&key).await {
+            Ok(current) => current
+                .and_then(|c| serde_json::from_str::<Session>(&c.value).ok())
+                .filter(|s| !s.is_expired(self.ttl)),
+            Err(e) => {
+                error!("Failed to read {}: {}", key, e);
+                None
             }
         }
     }
 
+    /// Remove expired sessions and device counts (no-op for stores with
+    /// native TTL, such as Valkey)
+    pub async fn cleanup_expired(&self) {
+        if let Err(e) = self.store.cleanup().await {
+            error!("Session store cleanup failed: {}", e);
+        }
+    }
+
+    /// Get the count of active sessions
+    pub async fn session_count(&self) -> usize {
+        self.store
+            .count(SESSION_KEY_PREFIX)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to count sessions: {}", e);
+                0
+            })
+    }
+
     /// Remove a specific session
     pub async fn remove(&self, session_id: &str) -> Option<Session> {
-        match &self.backend {
-            Backend::Memory { sessions } => sessions.remove(session_id).map(|(_, session)| session),
-            #[cfg(feature = "valkey")]
-            Backend::Valkey { conn, key_prefix } => {
-                let key = format!("{}:{}", key_prefix, session_id);
-                let mut conn = conn.clone();
-                // GET then DEL
-                let json: Option<String> =
-                    match redis::cmd("GET").arg(&key).query_async(&mut conn).await {
-                        Ok(v) => v,
-                        Err(e) => {
-                            error!("Valkey GET failed in remove: {}", e);
-                            return None;
-                        }
-                    };
-                if json.is_some() {
-                    if let Err(e) = redis::cmd("DEL")
-                        .arg(&key)
-                        .query_async::<()>(&mut conn)
-                        .await
-                    {
-                        error!("Valkey DEL failed in remove: {}", e);
-                    }
-                }
-                json.and_then(|j| serde_json::from_str(&j).ok())
+        let key = session_key(session_id);
+        match self.store.delete(&key).await {
+            Ok(json) => json.and_then(|json| serde_json::from_str(&json).ok()),
+            Err(e) => {
+                error!("Failed to remove {}: {}", key, e);
+                None
             }
         }
     }
@@ -292,6 +591,46 @@ impl SessionManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::{Result, RitcherError};
+    use crate::session::store::Versioned;
+    use futures::future::BoxFuture;
+
+    /// Store that reads from `inner` but fails every write
+    struct ReadOnlyStore {
+        inner: Arc<MemoryStore>,
+    }
+
+    fn write_failed<T>() -> Result<T> {
+        Err(RitcherError::SessionStoreError("read-only".to_string()))
+    }
+
+    impl SessionStore for ReadOnlyStore {
+        fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Versioned>>> {
+            self.inner.get(key)
+        }
+
+        fn put<'a>(&'a self, _: &'a str, _: String, _: Duration) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async { write_failed() })
+        }
+
+        fn compare_and_swap<'a>(
+            &'a self,
+            _: &'a str,
+            _: Option<u64>,
+            _: String,
+            _: Duration,
+        ) -> BoxFuture<'a, Result<bool>> {
+            Box::pin(async { write_failed() })
+        }
+
+        fn delete<'a>(&'a self, _: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+            Box::pin(async { write_failed() })
+        }
+
+        fn scan<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+            self.inner.scan(prefix)
+        }
+    }
 
     #[tokio::test]
     async fn test_session_creation() {
@@ -320,6 +659,212 @@ mod tests {
         assert!(updated_session.last_accessed > initial_time);
     }
 
+    #[tokio::test]
+    async fn test_record_viewer_creates_and_merges() {
+        let manager = SessionManager::new_memory(Duration::from_secs(300));
+        let first = ViewerContext {
+            user_agent: Some("hls.js".to_string()),
+            ad_params: [("ads.genre".to_string(), "news".to_string())].into(),
+            ..Default::default()
+        };
+        let session = manager
+            .record_viewer("viewer1", "https://example.com", first)
+            .await;
+        assert_eq!(session.viewer.ad_param("genre"), Some("news"));
+
+        // A variant playlist request carries no ads.* parameters
+        let variant = ViewerContext {
+            client_ip: Some("203.0.113.7".to_string()),
+            ..Default::default()
+        };
+        manager
+            .record_viewer("viewer1", "https://example.com", variant)
+            .await;
+        let session = manager.get("viewer1").await.unwrap();
+        assert_eq!(session.viewer.ad_param("genre"), Some("news"));
+        assert_eq!(session.viewer.client_ip.as_deref(), Some("203.0.113.7"));
+        assert_eq!(manager.session_count().await, 1);
+
+        // A request with nothing new leaves a recently touched session alone
+        let version = || async { manager.store.get("session:viewer1").await.unwrap() };
+        let before = version().await.unwrap().version;
+        manager
+            .record_viewer("viewer1", "https://example.com", session.viewer.clone())
+            .await;
+        assert_eq!(version().await.unwrap().version, before);
+    }
+
+    #[tokio::test]
+    async fn test_record_viewer_touches_idle_sessions() {
+        let manager = SessionManager::new_memory(Duration::from_secs(300));
+        let mut idle = Session::new("viewer1".to_string(), "https://example.com".to_string());
+        idle.last_accessed -= Duration::from_secs(60);
+        manager.create(idle.clone()).await;
+
+        let touched = manager
+            .record_viewer("viewer1", "https://example.com", ViewerContext::default())
+            .await;
+        assert!(touched.last_accessed > idle.last_accessed);
+        let stored = manager.get("viewer1").await.unwrap();
+        assert!(stored.last_accessed > idle.last_accessed);
+    }
+
+    #[tokio::test]
+    async fn test_ad_counts_per_session_and_device() {
+        let manager = SessionManager::new_memory(Duration::from_secs(300));
+        manager
+            .get_or_create("s1".to_string(), "https://example.com".to_string())
+            .await;
+        let creatives = vec!["creative:ad-id:A1".to_string()];
+        manager
+            .record_ad_pod("s1", creatives.clone(), &creatives, 1)
+            .await;
+        // Unknown sessions keep no history
+        manager
+            .record_ad_pod("missing", creatives.clone(), &creatives, 1)
+            .await;
+
+        let history = manager.ad_history("s1").await;
+        assert_eq!(history.creative_counts["creative:ad-id:A1"], 1);
+        assert!(manager.ad_history("missing").await.recent_pods.is_empty());
+
+        let window = Duration::from_secs(60);
+        manager.count_device_ads("hh-1", &creatives, window).await;
+        manager.count_device_ads("hh-1", &creatives, window).await;
+        assert_eq!(
+            manager.device_ad_counts("hh-1").await["creative:ad-id:A1"],
+            2
+        );
+
+        // An elapsed window starts counting afresh
+        manager
+            .count_device_ads("hh-2", &creatives, Duration::ZERO)
+            .await;
+        assert!(manager.device_ad_counts("hh-2").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_break_decisions_are_kept_with_the_session() {
+        let manager = SessionManager::new_memory(Duration::from_secs(300));
+        manager
+            .get_or_create("s1".to_string(), "https://example.com".to_string())
+            .await;
+        let segment = ResolvedSegment {
+            url: "https://ads.example.com/a.ts".to_string(),
+            tracking: Some(Default::default()),
+        };
+        let max_age = Duration::from_secs(60);
+        let decision = BreakDecision::segments(vec![segment]);
+        assert!(
+            manager
+                .record_break_decision("s1", "b1", decision.clone(), max_age)
+                .await
+                .is_none()
+        );
+        // The first decision of a break is kept
+        let earlier = manager
+            .record_break_decision("s1", "b1", BreakDecision::assets(Vec::new()), max_age)
+            .await;
+        assert_eq!(earlier, Some(decision));
+
+        let first = manager.deliver_ad_segment("s1", "b1", 0).await.unwrap();
+        assert_eq!(first.url, "https://ads.example.com/a.ts");
+        assert!(first.tracking.is_some());
+        let again = manager.deliver_ad_segment("s1", "b1", 0).await.unwrap();
+        assert!(again.tracking.is_none());
+        assert_eq!(manager.delivered_segments("s1", "b1").await.len(), 1);
+        assert!(manager.deliver_ad_segment("s1", "b2", 0).await.is_none());
+        assert!(manager.deliver_ad_segment("s2", "b1", 0).await.is_none());
+
+        // Recording a break drops decisions older than max_age
+        manager
+            .record_break_decision(
+                "s1",
+                "b2",
+                BreakDecision::assets(Vec::new()),
+                Duration::ZERO,
+            )
+            .await;
+        assert!(manager.break_decision("s1", "b1").await.is_none());
+        assert!(manager.break_decision("s1", "b2").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_values_are_not_overwritten() {
+        let ttl = Duration::from_secs(300);
+        let store = Arc::new(MemoryStore::new());
+        store
+            .put("session:s1", "{not a session".to_string(), ttl)
+            .await
+            .unwrap();
+        let manager = SessionManager::new(store.clone(), ttl);
+
+        manager.touch("s1").await;
+        let session = manager
+            .record_viewer("s1", "https://example.com", ViewerContext::default())
+            .await;
+        assert_eq!(session.session_id, "s1");
+        assert_eq!(
+            store.get("session:s1").await.unwrap().unwrap().value,
+            "{not a session"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delivery_is_served_when_recording_fails() {
+        let ttl = Duration::from_secs(300);
+        let store = Arc::new(MemoryStore::new());
+        let manager = SessionManager::new(store.clone(), ttl);
+        manager
+            .get_or_create("s1".to_string(), "https://example.com".to_string())
+            .await;
+        let segment = ResolvedSegment {
+            url: "https://ads.example.com/a.ts".to_string(),
+            tracking: Some(Default::default()),
+        };
+        manager
+            .record_break_decision("s1", "b1", BreakDecision::segments(vec![segment]), ttl)
+            .await;
+
+        let failing = SessionManager::new(Arc::new(ReadOnlyStore { inner: store }), ttl);
+        let served = failing.deliver_ad_segment("s1", "b1", 0).await.unwrap();
+        assert_eq!(served.url, "https://ads.example.com/a.ts");
+        assert!(served.tracking.is_none());
+
+        // The delivery was not recorded, so its tracking is still due
+        let first = manager.deliver_ad_segment("s1", "b1", 0).await.unwrap();
+        assert!(first.tracking.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_join_only_admits_live_sessions() {
+        let manager = SessionManager::new_memory(Duration::from_secs(300));
+        let mut issued = Session::new("issued".to_string(), "https://example.com".to_string());
+        issued.channel = Some("news".to_string());
+        manager.create(issued).await;
+
+        let viewer = ViewerContext {
+            user_agent: Some("AppleCoreMedia".to_string()),
+            ..Default::default()
+        };
+        let session = manager.join("issued", viewer.clone()).await.unwrap();
+        assert_eq!(session.channel.as_deref(), Some("news"));
+        assert_eq!(session.viewer.user_agent.as_deref(), Some("AppleCoreMedia"));
+        assert!(manager.join("guessed", viewer.clone()).await.is_none());
+        assert_eq!(manager.session_count().await, 1);
+
+        // Expired sessions are gone even before cleanup runs
+        let expired = SessionManager::new_memory(Duration::ZERO);
+        expired
+            .create(Session::new(
+                "old".to_string(),
+                "https://example.com".to_string(),
+            ))
+            .await;
+        assert!(expired.join("old", viewer).await.is_none());
+        assert!(expired.get("old").await.is_none());
+    }
+
     #[tokio::test]
     async fn test_session_removal() {
         let manager = SessionManager::new_memory(Duration::from_secs(300));
@@ -1,3 +1,21 @@
+pub mod content;
+pub mod decisions;
+pub mod history;
 pub mod manager;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod store;
+#[cfg(feature = "valkey")]
+pub mod valkey;
+pub mod viewer;
 
-pub use manager::SessionManager;
+pub use content::ContentProfile;
+pub use decisions::BreakDecision;
+pub use history::AdHistory;
+pub use manager::{Session, SessionManager};
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+pub use store::{MemoryStore, SessionStore, Versioned};
+#[cfg(feature = "valkey")]
+pub use valkey::{ValkeyStore, ValkeyTopology};
+pub use viewer::ViewerContext;
@@ -0,0 +1,239 @@
+use super::store::{SessionStore, Versioned};
+use crate::error::{Result, RitcherError};
+use futures::future::BoxFuture;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Embedded SQLite store: sessions survive restarts of a single instance
+///
+/// Versions come from a database-wide counter, so a key recreated after
+/// expiry or deletion never reuses a version a reader may still hold.
+#[derive(Clone)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// Open (or create) the database at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let conn = Connection::open(path).map_err(store_error)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             CREATE TABLE IF NOT EXISTS entries (
+                 key TEXT PRIMARY KEY,
+                 value TEXT NOT NULL,
+                 version INTEGER NOT NULL,
+                 expires_at INTEGER NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS versions (
+                 id INTEGER PRIMARY KEY CHECK (id = 0),
+                 last INTEGER NOT NULL
+             );
+             INSERT OR IGNORE INTO versions (id, last)
+                 SELECT 0, COALESCE(MAX(version), 0) FROM entries;",
+        )
+        .map_err(store_error)?;
+        info!("Opened SQLite session store at {}", path.display());
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Run `f` on the connection without blocking the async runtime
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection, i64) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn
+                .lock()
+                .map_err(|_| RitcherError::SessionStoreError("SQLite: lock poisoned".into()))?;
+            f(&conn, now_millis()).map_err(store_error)
+        })
+        .await
+        .map_err(|e| RitcherError::SessionStoreError(format!("SQLite: {}", e)))?
+    }
+}
+
+fn store_error(e: rusqlite::Error) -> RitcherError {
+    RitcherError::SessionStoreError(format!("SQLite: {}", e))
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+fn expires_at(now: i64, ttl: Duration) -> i64 {
+    now.saturating_add(ttl.as_millis() as i64)
+}
+
+/// Take the next version from the database-wide counter
+fn next_version(conn: &Connection) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "UPDATE versions SET last = last + 1 WHERE id = 0 RETURNING last",
+        [],
+        |row| row.get(0),
+    )
+}
+
+impl SessionStore for SqliteStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Versioned>>> {
+        let key = key.to_string();
+        Box::pin(self.with_conn(move |conn, now| {
+            conn.query_row(
+                "SELECT value, version FROM entries WHERE key = ?1 AND expires_at > ?2",
+                params![key, now],
+                |row| {
+                    Ok(Versioned {
+                        value: row.get(0)?,
+                        version: row.get(1)?,
+                    })
+                },
+            )
+            .optional()
+        }))
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: String, ttl: Duration) -> BoxFuture<'a, Result<()>> {
+        let key = key.to_string();
+        Box::pin(self.with_conn(move |conn, now| {
+            let tx = conn.unchecked_transaction()?;
+            let version = next_version(&tx)?;
+            tx.execute(
+                "INSERT INTO entries (key, value, version, expires_at) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (key) DO UPDATE SET
+                     value = excluded.value,
+                     version = excluded.version,
+                     expires_at = excluded.expires_at",
+                params![key, value, version, expires_at(now, ttl)],
+            )?;
+            tx.commit()
+        }))
+    }
+
+    fn compare_and_swap<'a>(
+        &'a self,
+        key: &'a str,
+        expected: Option<u64>,
+        value: String,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<bool>> {
+        let key = key.to_string();
+        Box::pin(self.with_conn(move |conn, now| {
+            let expires = expires_at(now, ttl);
+            let tx = conn.unchecked_transaction()?;
+            let version = next_version(&tx)?;
+            let written = match expected {
+                Some(expected) => tx.execute(
+                    "UPDATE entries SET value = ?2, version = ?3, expires_at = ?4
+                     WHERE key = ?1 AND version = ?5 AND expires_at > ?6",
+                    params![key, value, version, expires, expected, now],
+                )?,
+                // Absent: no row, or only an expired one
+                None => tx.execute(
+                    "INSERT INTO entries (key, value, version, expires_at) VALUES (?1, ?2, ?3, ?4)
+                     ON CONFLICT (key) DO UPDATE SET
+                         value = excluded.value,
+                         version = excluded.version,
+                         expires_at = excluded.expires_at
+                     WHERE entries.expires_at <= ?5",
+                    params![key, value, version, expires, now],
+                )?,
+            };
+            tx.commit()?;
+            Ok(written == 1)
+        }))
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        let key = key.to_string();
+        Box::pin(self.with_conn(move |conn, now| {
+            let deleted: Option<(String, i64)> = conn
+                .query_row(
+                    "DELETE FROM entries WHERE key = ?1 RETURNING value, expires_at",
+                    params![key],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+            Ok(deleted
+                .filter(|(_, expires_at)| *expires_at > now)
+                .map(|(value, _)| value))
+        }))
+    }
+
+    fn scan<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+        let prefix = prefix.to_string();
+        Box::pin(self.with_conn(move |conn, now| {
+            let mut stmt = conn.prepare(
+                "SELECT key FROM entries
+                 WHERE substr(key, 1, length(?1)) = ?1 AND expires_at > ?2",
+            )?;
+            stmt.query_map(params![prefix, now], |row| row.get(0))?
+                .collect()
+        }))
+    }
+
+    fn count<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<usize>> {
+        let prefix = prefix.to_string();
+        Box::pin(self.with_conn(move |conn, now| {
+            conn.query_row(
+                "SELECT COUNT(*) FROM entries
+                 WHERE substr(key, 1, length(?1)) = ?1 AND expires_at > ?2",
+                params![prefix, now],
+                |row| row.get(0),
+            )
+        }))
+    }
+
+    fn cleanup(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(self.with_conn(|conn, now| {
+            conn.execute("DELETE FROM entries WHERE expires_at <= ?1", params![now])
+                .map(|_| ())
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::store::tests::exercise_store;
+
+    fn temp_db(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("ritcher-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let path = temp_db("store");
+        exercise_store(&SqliteStore::open(&path).unwrap()).await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store_survives_reopening() {
+        let path = temp_db("reopen");
+        let ttl = Duration::from_secs(60);
+        SqliteStore::open(&path)
+            .unwrap()
+            .put("session:a", "kept".into(), ttl)
+            .await
+            .unwrap();
+
+        let reopened = SqliteStore::open(&path).unwrap();
+        let read = reopened.get("session:a").await.unwrap().unwrap();
+        assert_eq!(read.value, "kept");
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::error::Result;
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use futures::future::BoxFuture;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// A stored value with the version of its last write
+#[derive(Debug, Clone, PartialEq)]
+pub struct Versioned {
+    pub value: String,
+    /// Changes with every write; passed back to
+    /// [`compare_and_swap`](SessionStore::compare_and_swap)
+    pub version: u64,
+}
+
+/// Key-value storage behind [`SessionManager`](super::SessionManager)
+///
+/// Values are serialized sessions and device ad counts. Every write carries
+/// a TTL after which the key reads as absent. Updates go through
+/// [`compare_and_swap`](Self::compare_and_swap), so instances sharing a
+/// store never overwrite each other's changes.
+pub trait SessionStore: Send + Sync {
+    /// Read a live key
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Versioned>>>;
+
+    /// Write a key unconditionally
+    fn put<'a>(&'a self, key: &'a str, value: String, ttl: Duration) -> BoxFuture<'a, Result<()>>;
+
+    /// Write a key only if it is still at `expected` (`None`: absent)
+    ///
+    /// Returns whether the value was written.
+    fn compare_and_swap<'a>(
+        &'a self,
+        key: &'a str,
+        expected: Option<u64>,
+        value: String,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<bool>>;
+
+    /// Remove a key, returning its live value
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>>>;
+
+    /// Live keys starting with `prefix`
+    fn scan<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>>;
+
+    /// Number of live keys starting with `prefix`
+    fn count<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<usize>> {
+        Box::pin(async move { Ok(self.scan(prefix).await?.len()) })
+    }
+
+    /// Drop expired keys, for stores without native expiry
+    fn cleanup(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// One key of the [`MemoryStore`]
+struct Record {
+    value: String,
+    version: u64,
+    expires_at: Instant,
+}
+
+impl Record {
+    fn is_live(&self) -> bool {
+        Instant::now() < self.expires_at
+    }
+}
+
+/// In-process store (DashMap), the default
+#[derive(Default)]
+pub struct MemoryStore {
+    records: DashMap<String, Record>,
+    /// Store-wide, so a key recreated after expiry never reuses a version
+    versions: AtomicU64,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(&self, value: String, ttl: Duration) -> Record {
+        Record {
+            value,
+            version: self.versions.fetch_add(1, Ordering::Relaxed) + 1,
+            expires_at: Instant::now() + ttl,
+        }
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Versioned>>> {
+        Box::pin(async move {
+            Ok(self
+                .records
+                .get(key)
+                .filter(|record| record.is_live())
+                .map(|record| Versioned {
+                    value: record.value.clone(),
+                    version: record.version,
+                }))
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: String, ttl: Duration) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.records
+                .insert(key.to_string(), self.record(value, ttl));
+            Ok(())
+        })
+    }
+
+    fn compare_and_swap<'a>(
+        &'a self,
+        key: &'a str,
+        expected: Option<u64>,
+        value: String,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            match self.records.entry(key.to_string()) {
+                Entry::Occupied(mut entry) => {
+                    let current = Some(entry.get())
+                        .filter(|record| record.is_live())
+                        .map(|record| record.version);
+                    if current != expected {
+                        return Ok(false);
+                    }
+                    entry.insert(self.record(value, ttl));
+                }
+                Entry::Vacant(entry) => {
+                    if expected.is_some() {
+                        return Ok(false);
+                    }
+                    entry.insert(self.record(value, ttl));
+                }
+            }
+            Ok(true)
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async move {
+            Ok(self
+                .records
+                .remove(key)
+                .filter(|(_, record)| record.is_live())
+                .map(|(_, record)| record.value))
+        })
+    }
+
+    fn scan<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            Ok(self
+                .records
+                .iter()
+                .filter(|record| record.key().starts_with(prefix) && record.is_live())
+                .map(|record| record.key().clone())
+                .collect())
+        })
+    }
+
+    fn cleanup(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.records.retain(|_, record| record.is_live());
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Contract every store implementation must meet
+    pub(crate) async fn exercise_store(store: &dyn SessionStore) {
+        let ttl = Duration::from_secs(60);
+        assert!(store.get("session:a").await.unwrap().is_none());
+
+        // Creating requires the key to be absent
+        assert!(
+            store
+                .compare_and_swap("session:a", None, "1".into(), ttl)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !store
+                .compare_and_swap("session:a", None, "2".into(), ttl)
+                .await
+                .unwrap()
+        );
+
+        // Updating requires the version that was read
+        let read = store.get("session:a").await.unwrap().unwrap();
+        assert_eq!(read.value, "1");
+        assert!(
+            store
+                .compare_and_swap("session:a", Some(read.version), "2".into(), ttl)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !store
+                .compare_and_swap("session:a", Some(read.version), "3".into(), ttl)
+                .await
+                .unwrap()
+        );
+        assert_eq!(store.get("session:a").await.unwrap().unwrap().value, "2");
+
+        store.put("session:b", "b".into(), ttl).await.unwrap();
+        store.put("freq:hh", "{}".into(), ttl).await.unwrap();
+        let mut keys = store.scan("session:").await.unwrap();
+        keys.sort();
+        assert_eq!(keys, ["session:a", "session:b"]);
+        assert_eq!(store.count("session:").await.unwrap(), 2);
+
+        assert_eq!(
+            store.delete("session:b").await.unwrap().as_deref(),
+            Some("b")
+        );
+        assert!(store.delete("session:b").await.unwrap().is_none());
+
+        // Expired keys read as absent and can be created again
+        store
+            .put("session:old", "old".into(), Duration::ZERO)
+            .await
+            .unwrap();
+        assert!(store.get("session:old").await.unwrap().is_none());
+        assert_eq!(store.count("session:").await.unwrap(), 1);
+        assert!(
+            store
+                .compare_and_swap("session:old", None, "new".into(), ttl)
+                .await
+                .unwrap()
+        );
+        store.cleanup().await.unwrap();
+        assert_eq!(store.count("session:").await.unwrap(), 2);
+
+        // A key recreated after expiry or deletion never reuses a version
+        store.put("session:c", "1".into(), ttl).await.unwrap();
+        let stale = store.get("session:c").await.unwrap().unwrap().version;
+        store
+            .put("session:c", "2".into(), Duration::ZERO)
+            .await
+            .unwrap();
+        store.cleanup().await.unwrap();
+        assert!(
+            store
+                .compare_and_swap("session:c", None, "3".into(), ttl)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !store
+                .compare_and_swap("session:c", Some(stale), "4".into(), ttl)
+                .await
+                .unwrap()
+        );
+        let stale = store.get("session:c").await.unwrap().unwrap().version;
+        store.delete("session:c").await.unwrap();
+        assert!(
+            store
+                .compare_and_swap("session:c", None, "5".into(), ttl)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !store
+                .compare_and_swap("session:c", Some(stale), "6".into(), ttl)
+                .await
+                .unwrap()
+        );
+        assert_eq!(store.get("session:c").await.unwrap().unwrap().value, "5");
+    }
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        exercise_store(&MemoryStore::new()).await;
+    }
+}
@@ -0,0 +1,550 @@
+use super::store::{SessionStore, Versioned};
+use crate::error::{Result, RitcherError};
+use crate::metrics;
+use futures::future::{BoxFuture, join_all};
+use redis::aio::{ConnectionLike, MultiplexedConnection};
+use redis::cluster::ClusterClient;
+use redis::cluster_async::ClusterConnection;
+use redis::sentinel::{SentinelClient, SentinelServerType};
+use redis::{Cmd, ErrorKind, Pipeline, RedisError, RedisFuture, RedisResult, Script, Value};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Keys are hashes of the value (`v`) and its version (`n`). Versions are
+/// taken from the shard's counter (KEYS[2]), so a key recreated after it
+/// expired or was deleted never reuses one. The key (ARGV[4]) is indexed in
+/// KEYS[3] until it expires (ARGV[3]) by the same script, and its kind
+/// (ARGV[5]) is added to the shard's kinds (KEYS[4]); a zero TTL (ARGV[2])
+/// leaves it expired right away.
+const PUT_SCRIPT: &str = r#"
+if ARGV[2] == '0' then
+  redis.call('DEL', KEYS[1])
+  redis.call('ZREM', KEYS[3], ARGV[4])
+  return
+end
+local version = redis.call('INCR', KEYS[2])
+redis.call('HSET', KEYS[1], 'v', ARGV[1], 'n', version)
+redis.call('PEXPIRE', KEYS[1], ARGV[2])
+redis.call('ZADD', KEYS[3], ARGV[3], ARGV[4])
+redis.call('SADD', KEYS[4], ARGV[5])
+"#;
+
+/// Write only if the version is still ARGV[1] (empty: key absent)
+const CAS_SCRIPT: &str = r#"
+local current = redis.call('HGET', KEYS[1], 'n')
+if (current or '') ~= ARGV[1] then
+  return 0
+end
+if ARGV[3] == '0' then
+  redis.call('DEL', KEYS[1])
+  redis.call('ZREM', KEYS[3], ARGV[5])
+  return 1
+end
+local version = redis.call('INCR', KEYS[2])
+redis.call('HSET', KEYS[1], 'v', ARGV[2], 'n', version)
+redis.call('PEXPIRE', KEYS[1], ARGV[3])
+redis.call('ZADD', KEYS[3], ARGV[4], ARGV[5])
+redis.call('SADD', KEYS[4], ARGV[6])
+return 1
+"#;
+
+const DELETE_SCRIPT: &str = r#"
+local value = redis.call('HGET', KEYS[1], 'v')
+redis.call('DEL', KEYS[1])
+redis.call('ZREM', KEYS[2], ARGV[1])
+return value
+"#;
+
+/// How to reach a Valkey deployment
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValkeyTopology {
+    /// A single server (or a proxy in front of several)
+    Standalone(String),
+    /// Valkey Cluster, from one or more seed nodes
+    Cluster(Vec<String>),
+    /// Primary/replica set whose primary is looked up from Sentinels
+    Sentinel {
+        sentinels: Vec<String>,
+        master: String,
+    },
+}
+
+/// Opens connections of a topology
+enum Connector {
+    Standalone(redis::Client),
+    Cluster(ClusterClient),
+    Sentinel(Mutex<SentinelClient>),
+}
+
+impl Connector {
+    fn new(topology: &ValkeyTopology) -> RedisResult<Self> {
+        Ok(match topology {
+            ValkeyTopology::Standalone(url) => Self::Standalone(redis::Client::open(url.as_str())?),
+            ValkeyTopology::Cluster(nodes) => Self::Cluster(ClusterClient::new(nodes.clone())?),
+            ValkeyTopology::Sentinel { sentinels, master } => {
+                Self::Sentinel(Mutex::new(SentinelClient::build(
+                    sentinels.clone(),
+                    master.clone(),
+                    None,
+                    SentinelServerType::Master,
+                )?))
+            }
+        })
+    }
+
+    async fn connect(&self) -> RedisResult<Connection> {
+        Ok(match self {
+            Self::Standalone(client) => {
+                Connection::Single(client.get_multiplexed_async_connection().await?)
+            }
+            // The cluster connection follows slot migrations and failovers itself
+            Self::Cluster(client) => Connection::Cluster(client.get_async_connection().await?),
+            // Asks the Sentinels for the current primary
+            Self::Sentinel(client) => {
+                Connection::Single(client.lock().await.get_async_connection().await?)
+            }
+        })
+    }
+}
+
+/// An open connection of any topology
+#[derive(Clone)]
+enum Connection {
+    Single(MultiplexedConnection),
+    Cluster(ClusterConnection),
+}
+
+impl ConnectionLike for Connection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        match self {
+            Self::Single(conn) => conn.req_packed_command(cmd),
+            Self::Cluster(conn) => conn.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        match self {
+            Self::Single(conn) => conn.req_packed_commands(cmd, offset, count),
+            Self::Cluster(conn) => conn.req_packed_commands(cmd, offset, count),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            Self::Single(conn) => conn.get_db(),
+            Self::Cluster(conn) => conn.get_db(),
+        }
+    }
+}
+
+/// Whether an error means the connection must be re-established
+///
+/// `READONLY` is what a former primary answers after a Sentinel failover.
+fn is_connection_error(e: &RedisError) -> bool {
+    e.is_io_error()
+        || e.is_connection_dropped()
+        || e.is_connection_refusal()
+        || e.is_unrecoverable_error()
+        || e.kind() == ErrorKind::ReadOnly
+}
+
+/// Kind of a store key (`session`, `freq`): the part before the first `:`
+fn key_kind(key: &str) -> &str {
+    key.split_once(':').map_or(key, |(kind, _)| kind)
+}
+
+/// Shards the keys are spread over
+///
+/// A shard is a hash tag: its keys, version counter, indexes and set of
+/// indexed kinds share a cluster slot, so one script can update them
+/// together.
+const SHARDS: u64 = 16;
+
+/// Shard of a store key (FNV-1a, stable across instances and builds)
+fn shard(key: &str) -> u64 {
+    key.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    }) % SHARDS
+}
+
+/// Valkey/Redis store, shared by all instances
+///
+/// Every write is a single Lua script on one shard, so it is atomic and
+/// routes to one slot in a cluster. Live keys are also indexed in a sorted
+/// set per shard and key kind, scored by expiry, which counts and lists
+/// them without `KEYS` or a cluster-wide `SCAN`; sharding the index keeps
+/// it from concentrating every write on one node.
+///
+/// The `ritcher:{N}:` layout replaced an unsharded one. Keys of the earlier
+/// layout are not read: sessions stored before an upgrade are dropped and
+/// expire on their own.
+#[derive(Clone)]
+pub struct ValkeyStore {
+    connector: Arc<Connector>,
+    conn: Arc<Mutex<Option<Connection>>>,
+    key_prefix: String,
+    put_script: Arc<Script>,
+    cas_script: Arc<Script>,
+    delete_script: Arc<Script>,
+}
+
+impl ValkeyStore {
+    pub async fn connect(topology: &ValkeyTopology) -> Result<Self> {
+        let connector = Connector::new(topology).map_err(store_error)?;
+        let conn = connector.connect().await.map_err(store_error)?;
+        info!("Connected to Valkey ({:?})", topology);
+        Ok(Self {
+            connector: Arc::new(connector),
+            conn: Arc::new(Mutex::new(Some(conn))),
+            key_prefix: "ritcher:".to_string(),
+            put_script: Arc::new(Script::new(PUT_SCRIPT)),
+            cas_script: Arc::new(Script::new(CAS_SCRIPT)),
+            delete_script: Arc::new(Script::new(DELETE_SCRIPT)),
+        })
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{{{}}}:{}", self.key_prefix, shard(key), key)
+    }
+
+    /// Version counter of `key`'s shard
+    fn versions(&self, key: &str) -> String {
+        format!("{}{{{}}}:versions", self.key_prefix, shard(key))
+    }
+
+    /// Sorted set indexing the keys of `key`'s shard and kind
+    fn index(&self, key: &str) -> String {
+        self.shard_index(shard(key), key_kind(key))
+    }
+
+    /// Sorted set indexing the keys of one shard and kind
+    fn shard_index(&self, shard: u64, kind: &str) -> String {
+        format!("{}{{{}}}:index:{}", self.key_prefix, shard, kind)
+    }
+
+    /// Set of the kinds indexed in `key`'s shard
+    fn kinds(&self, key: &str) -> String {
+        self.shard_kinds(shard(key))
+    }
+
+    /// Set of the kinds indexed in one shard, whose indexes `cleanup` prunes
+    fn shard_kinds(&self, shard: u64) -> String {
+        format!("{}{{{}}}:kinds", self.key_prefix, shard)
+    }
+
+    /// The open connection, reconnecting if the last one failed
+    async fn connection(&self) -> RedisResult<Connection> {
+        let mut conn = self.conn.lock().await;
+        if let Some(conn) = conn.as_ref() {
+            return Ok(conn.clone());
+        }
+        let reconnected = self.connector.connect().await;
+        metrics::record_valkey_reconnection(if reconnected.is_ok() { "ok" } else { "error" });
+        let reconnected = reconnected?;
+        info!("Reconnected to Valkey");
+        Ok(conn.insert(reconnected).clone())
+    }
+
+    /// Run one operation, timing it and dropping a broken connection
+    ///
+    /// Operations are not retried: a write may have been applied before the
+    /// connection failed. The next operation reconnects.
+    async fn run<T, F, Fut>(&self, op: &'static str, f: F) -> Result<T>
+    where
+        F: FnOnce(Connection) -> Fut,
+        Fut: Future<Output = RedisResult<T>>,
+    {
+        let start = Instant::now();
+        let result = match self.connection().await {
+            Ok(conn) => f(conn).await,
+            Err(e) => Err(e),
+        };
+        metrics::record_valkey_command(op, start, result.is_ok());
+        if let Err(e) = &result
+            && is_connection_error(e)
+        {
+            warn!("Valkey connection lost ({}), reconnecting", e);
+            *self.conn.lock().await = None;
+        }
+        result.map_err(store_error)
+    }
+
+    /// Run `f` on the index of every shard for `prefix`'s kind
+    async fn each_index<T, F, Fut>(&self, op: &'static str, prefix: &str, f: F) -> Result<Vec<T>>
+    where
+        F: Fn(Connection, String) -> Fut,
+        Fut: Future<Output = RedisResult<T>>,
+    {
+        let kind = key_kind(prefix);
+        let pending = (0..SHARDS).map(|shard| {
+            let index = self.shard_index(shard, kind);
+            self.run(op, |conn| f(conn, index))
+        });
+        join_all(pending).await.into_iter().collect()
+    }
+}
+
+fn store_error(e: RedisError) -> RitcherError {
+    RitcherError::SessionStoreError(format!("Valkey: {}", e))
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Index score of a key written now with `ttl`
+fn expires_at(ttl: Duration) -> u64 {
+    now_millis().saturating_add(ttl.as_millis() as u64)
+}
+
+/// PEXPIRE argument; the scripts expire a key at once for zero
+fn ttl_millis(ttl: Duration) -> u64 {
+    ttl.as_millis() as u64
+}
+
+impl SessionStore for ValkeyStore {
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<Versioned>>> {
+        Box::pin(async move {
+            let key = self.key(key);
+            let (value, version): (Option<String>, Option<u64>) = self
+                .run("get", |mut conn| async move {
+                    redis::cmd("HMGET")
+                        .arg(key)
+                        .arg("v")
+                        .arg("n")
+                        .query_async(&mut conn)
+                        .await
+                })
+                .await?;
+            Ok(value
+                .zip(version)
+                .map(|(value, version)| Versioned { value, version }))
+        })
+    }
+
+    fn put<'a>(&'a self, key: &'a str, value: String, ttl: Duration) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let stored = self.key(key);
+            let versions = self.versions(key);
+            let index = self.index(key);
+            let kinds = self.kinds(key);
+            self.run("put", |mut conn| async move {
+                self.put_script
+                    .key(stored)
+                    .key(versions)
+                    .key(index)
+                    .key(kinds)
+                    .arg(value)
+                    .arg(ttl_millis(ttl))
+                    .arg(expires_at(ttl))
+                    .arg(key)
+                    .arg(key_kind(key))
+                    .invoke_async::<()>(&mut conn)
+                    .await
+            })
+            .await
+        })
+    }
+
+    fn compare_and_swap<'a>(
+        &'a self,
+        key: &'a str,
+        expected: Option<u64>,
+        value: String,
+        ttl: Duration,
+    ) -> BoxFuture<'a, Result<bool>> {
+        Box::pin(async move {
+            let stored = self.key(key);
+            let versions = self.versions(key);
+            let index = self.index(key);
+            let kinds = self.kinds(key);
+            let expected = expected.map(|v| v.to_string()).unwrap_or_default();
+            let swapped: i32 = self
+                .run("compare_and_swap", |mut conn| async move {
+                    self.cas_script
+                        .key(stored)
+                        .key(versions)
+                        .key(index)
+                        .key(kinds)
+                        .arg(expected)
+                        .arg(value)
+                        .arg(ttl_millis(ttl))
+                        .arg(expires_at(ttl))
+                        .arg(key)
+                        .arg(key_kind(key))
+                        .invoke_async(&mut conn)
+                        .await
+                })
+                .await?;
+            Ok(swapped == 1)
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>>> {
+        Box::pin(async move {
+            let stored = self.key(key);
+            let index = self.index(key);
+            self.run("delete", |mut conn| async move {
+                self.delete_script
+                    .key(stored)
+                    .key(index)
+                    .arg(key)
+                    .invoke_async(&mut conn)
+                    .await
+            })
+            .await
+        })
+    }
+
+    fn scan<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            let live = format!("({}", now_millis());
+            let shards: Vec<Vec<String>> = self
+                .each_index("scan", prefix, |mut conn, index| {
+                    let live = live.clone();
+                    async move {
+                        redis::cmd("ZRANGEBYSCORE")
+                            .arg(index)
+                            .arg(live)
+                            .arg("+inf")
+                            .query_async(&mut conn)
+                            .await
+                    }
+                })
+                .await?;
+            Ok(shards
+                .into_iter()
+                .flatten()
+                .filter(|k| k.starts_with(prefix))
+                .collect())
+        })
+    }
+
+    fn count<'a>(&'a self, prefix: &'a str) -> BoxFuture<'a, Result<usize>> {
+        Box::pin(async move {
+            // Whole kinds are counted by the index; narrower prefixes are listed
+            if prefix != format!("{}:", key_kind(prefix)) {
+                return Ok(self.scan(prefix).await?.len());
+            }
+            let live = format!("({}", now_millis());
+            let shards: Vec<usize> = self
+                .each_index("count", prefix, |mut conn, index| {
+                    let live = live.clone();
+                    async move {
+                        redis::cmd("ZCOUNT")
+                            .arg(index)
+                            .arg(live)
+                            .arg("+inf")
+                            .query_async(&mut conn)
+                            .await
+                    }
+                })
+                .await?;
+            Ok(shards.into_iter().sum())
+        })
+    }
+
+    /// Keys expire natively; only index entries of expired keys are dropped
+    ///
+    /// The kinds come from each shard's set in the store, so indexes written
+    /// by any instance are pruned, not just this one's.
+    fn cleanup(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let now = now_millis();
+            let pending = (0..SHARDS).map(|shard| async move {
+                let kinds: Vec<String> = self
+                    .run("cleanup", |mut conn| async move {
+                        redis::cmd("SMEMBERS")
+                            .arg(self.shard_kinds(shard))
+                            .query_async(&mut conn)
+                            .await
+                    })
+                    .await?;
+                for kind in kinds {
+                    let index = self.shard_index(shard, &kind);
+                    self.run("cleanup", |mut conn| async move {
+                        redis::cmd("ZREMRANGEBYSCORE")
+                            .arg(index)
+                            .arg("-inf")
+                            .arg(now)
+                            .query_async::<()>(&mut conn)
+                            .await
+                    })
+                    .await?;
+                }
+                Ok(())
+            });
+            join_all(pending).await.into_iter().collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::store::tests::exercise_store;
+
+    #[test]
+    fn test_keys_are_indexed_by_kind() {
+        assert_eq!(key_kind("session:abc"), "session");
+        assert_eq!(key_kind("freq:hh-1"), "freq");
+        assert_eq!(key_kind("session:"), "session");
+        assert_eq!(ttl_millis(Duration::from_micros(999)), 0);
+    }
+
+    /// Contract test against a live server
+    #[tokio::test]
+    #[ignore = "needs a Valkey/Redis server at VALKEY_TEST_URL"]
+    async fn test_valkey_store() {
+        let url = std::env::var("VALKEY_TEST_URL")
+            .expect("VALKEY_TEST_URL must point at a Valkey/Redis server");
+        let mut store = ValkeyStore::connect(&ValkeyTopology::Standalone(url))
+            .await
+            .unwrap();
+        // Keys of this run only, so counts are not thrown off by others
+        store.key_prefix = format!("ritcher-test:{}:{}:", std::process::id(), now_millis());
+        exercise_store(&store).await;
+    }
+
+    #[test]
+    fn test_keys_are_spread_over_shards() {
+        let shards: std::collections::HashSet<u64> =
+            (0..200).map(|i| shard(&format!("session:{}", i))).collect();
+        assert_eq!(shards.len() as u64, SHARDS);
+        // Fixed by the hash, so every instance agrees on it
+        assert_eq!(shard("session:abc"), 9);
+    }
+
+    #[test]
+    fn test_failover_errors_drop_the_connection() {
+        let readonly = RedisError::from((ErrorKind::ReadOnly, "READONLY"));
+        assert!(is_connection_error(&readonly));
+        let io = RedisError::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe));
+        assert!(is_connection_error(&io));
+        let script = RedisError::from((ErrorKind::ResponseError, "ERR"));
+        assert!(!is_connection_error(&script));
+    }
+
+    #[test]
+    fn test_topologies_are_validated_without_connecting() {
+        let standalone = ValkeyTopology::Standalone("redis://127.0.0.1:6379".to_string());
+        assert!(Connector::new(&standalone).is_ok());
+        let sentinel = ValkeyTopology::Sentinel {
+            sentinels: vec!["redis://127.0.0.1:26379".to_string()],
+            master: "mymaster".to_string(),
+        };
+        assert!(Connector::new(&sentinel).is_ok());
+        assert!(Connector::new(&ValkeyTopology::Standalone("not a url".to_string())).is_err());
+    }
+}
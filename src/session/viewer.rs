@@ -0,0 +1,213 @@
+use axum::http::{HeaderMap, header};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+
+/// Prefix of stitch URL query parameters forwarded to ad decisioning
+pub const AD_PARAM_PREFIX: &str = "ads.";
+
+/// Most `ads.*` parameters kept per viewer
+pub const MAX_AD_PARAMS: usize = 32;
+
+/// Longest `ads.*` parameter value kept, in bytes
+pub const MAX_AD_PARAM_LEN: usize = 512;
+
+/// What the stitcher knows about the viewer behind a session
+///
+/// Captured from playlist, manifest and asset-list requests and stored in
+/// the session, so every ad decision of the session can be targeted —
+/// including those made for variant playlists whose URLs carry no
+/// `ads.*` parameters.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ViewerContext {
+    /// Client IP, from the first `X-Forwarded-For` hop or the peer address
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub accept_language: Option<String>,
+    /// `ads.*` query parameters, keyed by their full name (e.g. `ads.genre`)
+    pub ad_params: BTreeMap<String, String>,
+}
+
+impl ViewerContext {
+    /// Capture viewer context from one request
+    ///
+    /// # Arguments
+    /// * `headers` - Request headers
+    /// * `query` - Query parameters of the stitch URL
+    /// * `peer` - Address of the TCP peer, if known
+    /// * `allowed_params` - `ads.*` parameter names to keep; empty keeps none
+    pub fn from_request(
+        headers: &HeaderMap,
+        query: &HashMap<String, String>,
+        peer: Option<IpAddr>,
+        allowed_params: &[String],
+    ) -> Self {
+        let header_value = |name: header::HeaderName| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+        };
+
+        let forwarded_ip = headers
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+
+        Self {
+            client_ip: forwarded_ip.or(peer).map(|ip| ip.to_string()),
+            user_agent: header_value(header::USER_AGENT),
+            accept_language: header_value(header::ACCEPT_LANGUAGE),
+            ad_params: Self::select_ad_params(query, allowed_params),
+        }
+    }
+
+    /// The `ads.*` parameters ad decisioning may see
+    ///
+    /// Only parameters on the allowlist are kept, and only with values of at
+    /// most [`MAX_AD_PARAM_LEN`] bytes; an empty allowlist keeps none. Of
+    /// those, the first [`MAX_AD_PARAMS`] by name are kept.
+    pub fn select_ad_params(
+        params: &HashMap<String, String>,
+        allowed_params: &[String],
+    ) -> BTreeMap<String, String> {
+        let mut selected: BTreeMap<String, String> = params
+            .iter()
+            .filter(|(name, value)| {
+                name.starts_with(AD_PARAM_PREFIX)
+                    && name.len() > AD_PARAM_PREFIX.len()
+                    && value.len() <= MAX_AD_PARAM_LEN
+                    && allowed_params.contains(name)
+            })
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        while selected.len() > MAX_AD_PARAMS {
+            selected.pop_last();
+        }
+        selected
+    }
+
+    /// Fold a newer request's context into this one
+    ///
+    /// Values present in `newer` win; parameters it lacks are kept, since
+    /// variant and rendition URLs do not repeat the viewer's `ads.*`
+    /// parameters.
+    pub fn merge(&mut self, newer: ViewerContext) {
+        if newer.client_ip.is_some() {
+            self.client_ip = newer.client_ip;
+        }
+        if newer.user_agent.is_some() {
+            self.user_agent = newer.user_agent;
+        }
+        if newer.accept_language.is_some() {
+            self.accept_language = newer.accept_language;
+        }
+        self.ad_params.extend(newer.ad_params);
+        while self.ad_params.len() > MAX_AD_PARAMS {
+            self.ad_params.pop_last();
+        }
+    }
+
+    /// Value of an `ads.*` parameter, by name without the prefix
+    pub fn ad_param(&self, name: &str) -> Option<&str> {
+        self.ad_params
+            .get(&format!("{}{}", AD_PARAM_PREFIX, name))
+            .map(String::as_str)
+    }
+
+    /// Primary language from Accept-Language (e.g. `sv` for `sv-SE,en;q=0.8`)
+    pub fn language(&self) -> Option<String> {
+        self.accept_language
+            .as_deref()?
+            .split(',')
+            .next()
+            .and_then(|tag| tag.split(';').next())
+            .and_then(|tag| tag.trim().split('-').next())
+            .filter(|lang| !lang.is_empty() && *lang != "*")
+            .map(str::to_lowercase)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_request_prefers_forwarded_for() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "203.0.113.7, 10.0.0.1".parse().unwrap());
+        headers.insert(header::USER_AGENT, "AppleCoreMedia/1.0".parse().unwrap());
+        headers.insert(header::ACCEPT_LANGUAGE, "sv-SE,en;q=0.8".parse().unwrap());
+        let peer = Some("10.0.0.1".parse().unwrap());
+
+        let viewer = ViewerContext::from_request(&headers, &HashMap::new(), peer, &[]);
+        assert_eq!(viewer.client_ip.as_deref(), Some("203.0.113.7"));
+        assert_eq!(viewer.user_agent.as_deref(), Some("AppleCoreMedia/1.0"));
+        assert_eq!(viewer.language().as_deref(), Some("sv"));
+
+        // Unparseable forwarded address falls back to the peer
+        headers.insert("x-forwarded-for", "unknown".parse().unwrap());
+        let viewer = ViewerContext::from_request(&headers, &HashMap::new(), peer, &[]);
+        assert_eq!(viewer.client_ip.as_deref(), Some("10.0.0.1"));
+    }
+
+    #[test]
+    fn test_from_request_keeps_whitelisted_ad_params() {
+        let params = query(&[
+            ("origin", "https://example.com/live.m3u8"),
+            ("ads.genre", "sports"),
+            ("ads.ifa", "abc"),
+            ("ads.", "empty-name"),
+        ]);
+        // Without an allowlist nothing reaches ad decisioning
+        let none = ViewerContext::from_request(&HeaderMap::new(), &params, None, &[]);
+        assert!(none.ad_params.is_empty());
+
+        let allowed = vec!["ads.genre".to_string(), "ads.".to_string()];
+        let some = ViewerContext::from_request(&HeaderMap::new(), &params, None, &allowed);
+        assert_eq!(some.ad_params.len(), 1);
+        assert_eq!(some.ad_param("genre"), Some("sports"));
+        assert_eq!(some.ad_param("ifa"), None);
+    }
+
+    #[test]
+    fn test_select_ad_params_caps_by_name() {
+        let names: Vec<String> = (0..MAX_AD_PARAMS + 8)
+            .map(|i| format!("ads.p{:03}", i))
+            .collect();
+        let params: HashMap<String, String> =
+            names.iter().map(|n| (n.clone(), "v".to_string())).collect();
+
+        // The same parameters are kept whatever the map's iteration order
+        let selected = ViewerContext::select_ad_params(&params, &names);
+        assert_eq!(selected.len(), MAX_AD_PARAMS);
+        assert!(selected.keys().eq(names[..MAX_AD_PARAMS].iter()));
+    }
+
+    #[test]
+    fn test_merge_keeps_params_from_earlier_requests() {
+        let mut viewer = ViewerContext {
+            client_ip: Some("203.0.113.7".to_string()),
+            ad_params: BTreeMap::from([("ads.genre".to_string(), "sports".to_string())]),
+            ..Default::default()
+        };
+        viewer.merge(ViewerContext {
+            client_ip: Some("203.0.113.8".to_string()),
+            user_agent: Some("hls.js".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(viewer.client_ip.as_deref(), Some("203.0.113.8"));
+        assert_eq!(viewer.user_agent.as_deref(), Some("hls.js"));
+        assert_eq!(viewer.ad_param("genre"), Some("sports"));
+    }
+}
@@ -9,9 +9,14 @@
 //! SSRF validator correctly blocks). Config-sourced origins are operator-trusted
 //! and not subject to user-supplied origin validation.
 
-use ritcher::config::{AdProviderType, Config, SessionStoreType, StitchingMode};
+use ritcher::ad::AdSourceStrategy;
+use ritcher::ad::conditioning::ConditioningAction;
+use ritcher::config::{
+    AdProviderType, Config, SessionStoreType, StitchingMode, ValkeyMode, XlinkResolution,
+};
 use ritcher::server::build_router;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 
 // ── Test server helpers ───────────────────────────────────────────────────────
 
@@ -21,12 +26,21 @@ use std::net::SocketAddr;
 /// `origin_url` to point to the server's own demo endpoint. This avoids
 /// user-supplied `?origin=` params (which the SSRF validator would block).
 async fn start_server(mode: StitchingMode, origin_path: &str) -> SocketAddr {
+    start_server_with(mode, origin_path, |_| {}).await
+}
+
+/// Like `start_server`, with the config adjusted by `configure` before start.
+async fn start_server_with(
+    mode: StitchingMode,
+    origin_path: &str,
+    configure: impl FnOnce(&mut Config),
+) -> SocketAddr {
     let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
         .await
         .expect("Failed to bind test server");
     let addr = listener.local_addr().unwrap();
 
-    let config = Config {
+    let mut config = Config {
         port: 0,
         base_url: format!("http://{}", addr),
         origin_url: format!("http://{}{}", addr, origin_path),
@@ -35,13 +49,45 @@ async fn start_server(mode: StitchingMode, origin_path: &str) -> SocketAddr {
         ad_provider_type: AdProviderType::Static,
         ad_source_url: "https://hls.src.tedm.io/content/ts_h264_480p_1s".to_string(),
         ad_segment_duration: 1.0,
+        ad_playlists: Vec::new(),
         vast_endpoint: None,
+        ad_sources: Vec::new(),
+        ad_source_strategy: AdSourceStrategy::Waterfall,
+        openrtb_bidders: Vec::new(),
+        openrtb_tmax_ms: 500,
+        openrtb_bid_floor: 0.0,
+        openrtb_currency: "USD".to_string(),
+        ad_decision_timeout_ms: 3000,
+        ad_lookahead_secs: 30,
+        ad_cache_ttl_secs: 300,
+        ad_cache_max_creatives: 10_000,
+        pod_max_ads: 10,
+        pod_min_ad_duration: 0.0,
+        freq_cap_session: 0,
+        freq_cap_device: 0,
+        freq_cap_window_secs: 86400,
+        separate_advertisers: true,
+        separate_categories: true,
+        separation_breaks: 1,
+        creative_conditioning: ConditioningAction::Warn,
+        ad_normalizer_url: None,
         slate_url: None,
         slate_segment_duration: 1.0,
+        slate_url_nofill: None,
+        slate_url_error: None,
+        slate_url_policy: None,
         session_store: SessionStoreType::Memory,
         valkey_url: None,
+        valkey_mode: ValkeyMode::Standalone,
+        valkey_sentinel_master: None,
+        session_db_path: "ritcher-sessions.db".to_string(),
         session_ttl_secs: 300,
+        require_sessions: false,
+        inband_scte35: false,
+        xlink_resolution: XlinkResolution::AdProvider,
+        ad_query_params: Vec::new(),
     };
+    configure(&mut config);
 
     let app = build_router(config).await;
 
@@ -281,3 +327,120 @@ async fn ssai_mode_unchanged() {
         body
     );
 }
+
+#[tokio::test]
+async fn vast_request_carries_viewer_context() {
+    // Mock ad server that records request URIs and has no ads to offer
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let recorded = requests.clone();
+    let ad_server = axum::Router::new().fallback(move |uri: axum::http::Uri| {
+        recorded.lock().unwrap().push(uri.to_string());
+        async { r#"<VAST version="4.2"></VAST>"# }
+    });
+    let ad_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let ad_addr = ad_listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(ad_listener, ad_server).await.unwrap() });
+
+    let addr = start_server_with(StitchingMode::Ssai, "/demo/playlist.m3u8", |config| {
+        config.ad_provider_type = AdProviderType::Vast;
+        config.vast_endpoint = Some(format!(
+            "http://{}/vast?dur=[DURATION]&genre=[ads.genre]&ip=[DEVICEIP]&ua=[CLIENTUA]",
+            ad_addr
+        ));
+        config.ad_query_params = vec!["ads.genre".to_string()];
+    })
+    .await;
+
+    let resp = reqwest::Client::new()
+        .get(format!(
+            "http://{}/stitch/viewer-1/playlist.m3u8?ads.genre=sports&ads.other=x",
+            addr
+        ))
+        .header("User-Agent", "e2e-player/1.0")
+        .header("X-Forwarded-For", "203.0.113.7")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+
+    let requests = requests.lock().unwrap();
+    assert_eq!(
+        requests.as_slice(),
+        ["/vast?dur=30&genre=sports&ip=203.0.113.7&ua=e2e-player%2F1.0"]
+    );
+}
+
+#[tokio::test]
+async fn session_api_issues_required_sessions() {
+    let addr = start_server_with(StitchingMode::Ssai, "/demo/playlist.m3u8", |config| {
+        config.require_sessions = true;
+        config.ad_query_params = vec!["ads.genre".to_string()];
+    })
+    .await;
+    let client = reqwest::Client::new();
+
+    // Made-up session ids are rejected
+    let resp = client
+        .get(format!("http://{}/stitch/guessed/playlist.m3u8", addr))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 404);
+
+    let resp = client
+        .post(format!("http://{}/sessions", addr))
+        .json(&serde_json::json!({
+            "channel": "news",
+            "ad_params": {"genre": "news"},
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 201);
+    let created: serde_json::Value = resp.json().await.unwrap();
+    let session_id = created["session_id"].as_str().unwrap();
+    assert_eq!(session_id.len(), 32);
+    let hls_url = created["hls_url"].as_str().unwrap();
+    assert_eq!(
+        hls_url,
+        format!("http://{}/stitch/{}/playlist.m3u8", addr, session_id)
+    );
+    assert!(
+        created["dash_url"]
+            .as_str()
+            .unwrap()
+            .ends_with("/manifest.mpd")
+    );
+
+    // The issued session plays its origin without an origin parameter
+    let resp = client.get(hls_url).send().await.unwrap();
+    assert_eq!(resp.status(), 200);
+    assert!(resp.text().await.unwrap().contains("#EXTM3U"));
+
+    let report: serde_json::Value = client
+        .get(created["reporting_url"].as_str().unwrap())
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["channel"], "news");
+    let resp = client
+        .get(created["tracking_url"].as_str().unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    let tracking: serde_json::Value = resp.json().await.unwrap();
+    assert!(tracking["breaks"].is_array());
+
+    // Invalid requests create no session
+    let resp = client
+        .post(format!("http://{}/sessions", addr))
+        .json(&serde_json::json!({"channel": "../admin"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), 400);
+}